@@ -0,0 +1,436 @@
+use super::pulse::{Pulse, PulseChannel};
+use super::triangle::Triangle;
+use super::noise::Noise;
+use super::dmc::Dmc;
+use crate::nes::savestate::{StateWriter, StateReader};
+use crate::nes::region::Region;
+
+pub const PULSE_1_START: u16 = 0x4000;
+pub const PULSE_1_END: u16 = 0x4003;
+pub const PULSE_2_START: u16 = 0x4004;
+pub const PULSE_2_END: u16 = 0x4007;
+pub const TRIANGLE_START: u16 = 0x4008;
+pub const TRIANGLE_END: u16 = 0x400B;
+pub const NOISE_START: u16 = 0x400C;
+pub const NOISE_END: u16 = 0x400F;
+pub const DMC_START: u16 = 0x4010;
+pub const DMC_END: u16 = 0x4013;
+pub const STATUS: u16 = 0x4015;
+pub const FRAME_COUNTER: u16 = 0x4017;
+
+const STATUS_PULSE_1: u8 = 1 << 0;
+const STATUS_PULSE_2: u8 = 1 << 1;
+const STATUS_TRIANGLE: u8 = 1 << 2;
+const STATUS_NOISE: u8 = 1 << 3;
+const STATUS_DMC: u8 = 1 << 4;
+const STATUS_FRAME_IRQ: u8 = 1 << 6;
+const STATUS_DMC_IRQ: u8 = 1 << 7;
+
+const FRAME_COUNTER_FIVE_STEP: u8 = 1 << 7;
+const FRAME_COUNTER_IRQ_INHIBIT: u8 = 1 << 6;
+
+// Frame counter steps in cpu cycles since the sequence started
+#[derive(Clone, Copy)]
+struct FrameTimings {
+    step_1: u32,
+    step_2: u32,
+    step_3: u32,
+    irq_start: u32,
+    step_4: u32,
+    step_5: u32,
+    four_step_length: u32,
+    five_step_length: u32,
+}
+
+const NTSC_FRAME_TIMINGS: FrameTimings = FrameTimings {
+    step_1: 7457,
+    step_2: 14913,
+    step_3: 22371,
+    irq_start: 29828,
+    step_4: 29829,
+    step_5: 37281,
+    four_step_length: 29830,
+    five_step_length: 37282,
+};
+
+const PAL_FRAME_TIMINGS: FrameTimings = FrameTimings {
+    step_1: 8313,
+    step_2: 16627,
+    step_3: 24939,
+    irq_start: 33252,
+    step_4: 33253,
+    step_5: 41565,
+    four_step_length: 33254,
+    five_step_length: 41566,
+};
+
+/// The audio half of the 2A03, clocked by the bus once per cpu cycle.
+#[derive(Clone)]
+pub struct Apu {
+    // One sample is produced per cpu cycle, so this is the sample rate too
+    clock_rate: u32,
+    frame_timings: FrameTimings,
+
+    pulse_1: Pulse,
+    pulse_2: Pulse,
+    triangle: Triangle,
+    noise: Noise,
+    dmc: Dmc,
+
+    five_step_mode: bool,
+    irq_inhibit: bool,
+    frame_irq: bool,
+    frame_cycle: u32,
+    // Cycles until a write to $4017 resets the sequence
+    frame_reset_delay: Option<u8>,
+
+    // The pulse timers run at half the cpu clock
+    odd_cycle: bool,
+
+    samples: Vec<f32>,
+}
+
+impl Default for Apu {
+    fn default() -> Apu {
+        Apu::new()
+    }
+}
+
+impl Apu {
+    pub fn new() -> Apu {
+        Apu {
+            clock_rate: Region::Ntsc.get_cpu_clock_rate(),
+            frame_timings: NTSC_FRAME_TIMINGS,
+            pulse_1: Pulse::new(PulseChannel::One),
+            pulse_2: Pulse::new(PulseChannel::Two),
+            triangle: Triangle::new(),
+            noise: Noise::new(),
+            dmc: Dmc::new(),
+            five_step_mode: false,
+            irq_inhibit: false,
+            frame_irq: false,
+            frame_cycle: 0,
+            frame_reset_delay: None,
+            odd_cycle: false,
+            samples: Vec::new(),
+        }
+    }
+
+    pub fn set_region(&mut self, region: Region) {
+        self.clock_rate = region.get_cpu_clock_rate();
+        self.frame_timings = if region.has_pal_apu() { PAL_FRAME_TIMINGS } else { NTSC_FRAME_TIMINGS };
+        self.noise.set_region(region);
+        self.dmc.set_region(region);
+    }
+
+    pub fn get_sample_rate(&self) -> u32 {
+        self.clock_rate
+    }
+
+    pub fn write_register(&mut self, address: u16, data: u8) {
+        match address {
+            PULSE_1_START..=PULSE_1_END => self.pulse_1.write_register(address - PULSE_1_START, data),
+            PULSE_2_START..=PULSE_2_END => self.pulse_2.write_register(address - PULSE_2_START, data),
+            TRIANGLE_START..=TRIANGLE_END => self.triangle.write_register(address - TRIANGLE_START, data),
+            NOISE_START..=NOISE_END => self.noise.write_register(address - NOISE_START, data),
+            DMC_START..=DMC_END => self.dmc.write_register(address - DMC_START, data),
+            STATUS => {
+                self.pulse_1.set_enabled(data & STATUS_PULSE_1 > 0);
+                self.pulse_2.set_enabled(data & STATUS_PULSE_2 > 0);
+                self.triangle.set_enabled(data & STATUS_TRIANGLE > 0);
+                self.noise.set_enabled(data & STATUS_NOISE > 0);
+                self.dmc.set_enabled(data & STATUS_DMC > 0);
+            }
+            FRAME_COUNTER => {
+                self.five_step_mode = data & FRAME_COUNTER_FIVE_STEP > 0;
+                self.irq_inhibit = data & FRAME_COUNTER_IRQ_INHIBIT > 0;
+                if self.irq_inhibit {
+                    self.frame_irq = false;
+                }
+
+                // The sequence restarts 3 or 4 cycles later, depending on which half of the apu cycle it is
+                self.frame_reset_delay = Some(if self.odd_cycle { 4 } else { 3 });
+            }
+            _ => {}
+        }
+    }
+
+    // $4015, reading clears the frame IRQ
+    pub fn read_status(&mut self) -> u8 {
+        let status = self.peek_status();
+        self.frame_irq = false;
+
+        status
+    }
+
+    pub fn peek_status(&self) -> u8 {
+        let mut status = 0;
+
+        if self.pulse_1.is_active() { status |= STATUS_PULSE_1; }
+        if self.pulse_2.is_active() { status |= STATUS_PULSE_2; }
+        if self.triangle.is_active() { status |= STATUS_TRIANGLE; }
+        if self.noise.is_active() { status |= STATUS_NOISE; }
+        if self.dmc.is_active() { status |= STATUS_DMC; }
+        if self.frame_irq { status |= STATUS_FRAME_IRQ; }
+        if self.dmc.get_irq() { status |= STATUS_DMC_IRQ; }
+
+        status
+    }
+
+    pub fn get_irq_signal(&self) -> bool {
+        self.frame_irq || self.dmc.get_irq()
+    }
+
+    // The DMC reads its samples through the bus, which stalls the cpu while doing so
+    pub fn get_dmc_fetch_address(&self) -> Option<u16> {
+        self.dmc.get_fetch_address()
+    }
+
+    pub fn fill_dmc_sample_buffer(&mut self, data: u8) {
+        self.dmc.fill_sample_buffer(data);
+    }
+
+    pub fn tick(&mut self) {
+        self._clock_frame_counter();
+
+        self.triangle.clock_timer();
+        self.noise.clock_timer();
+        self.dmc.clock_timer();
+        if self.odd_cycle {
+            self.pulse_1.clock_timer();
+            self.pulse_2.clock_timer();
+        }
+        self.odd_cycle = !self.odd_cycle;
+
+        // About a second of samples is kept when nobody takes them, the rest is dropped
+        if self.samples.len() < self.clock_rate as usize {
+            let sample = self._mix();
+            self.samples.push(sample);
+        }
+    }
+
+    // Returns the samples produced since the last call, between 0.0 and 1.0
+    pub fn take_samples(&mut self) -> Vec<f32> {
+        std::mem::take(&mut self.samples)
+    }
+
+    fn _clock_frame_counter(&mut self) {
+        if let Some(delay) = self.frame_reset_delay {
+            if delay > 1 {
+                self.frame_reset_delay = Some(delay - 1);
+            } else {
+                self.frame_reset_delay = None;
+                self.frame_cycle = 0;
+
+                // The five step mode clocks everything as soon as it is selected
+                if self.five_step_mode {
+                    self._clock_quarter_frame();
+                    self._clock_half_frame();
+                }
+                return;
+            }
+        }
+
+        self.frame_cycle += 1;
+
+        let timings = self.frame_timings;
+        match (self.five_step_mode, self.frame_cycle) {
+            (_, cycle) if cycle == timings.step_1 || cycle == timings.step_3 => {
+                self._clock_quarter_frame();
+            }
+            (_, cycle) if cycle == timings.step_2 => {
+                self._clock_quarter_frame();
+                self._clock_half_frame();
+            }
+            // The IRQ flag is raised on the last three cycles of the four step sequence
+            (false, cycle) if cycle == timings.irq_start => {
+                self._set_frame_irq();
+            }
+            (false, cycle) if cycle == timings.step_4 => {
+                self._clock_quarter_frame();
+                self._clock_half_frame();
+                self._set_frame_irq();
+            }
+            (false, cycle) if cycle == timings.four_step_length => {
+                self._set_frame_irq();
+                self.frame_cycle = 0;
+            }
+            (true, cycle) if cycle == timings.step_5 => {
+                self._clock_quarter_frame();
+                self._clock_half_frame();
+            }
+            (true, cycle) if cycle == timings.five_step_length => {
+                self.frame_cycle = 0;
+            }
+            _ => {}
+        }
+    }
+
+    fn _set_frame_irq(&mut self) {
+        if !self.irq_inhibit {
+            self.frame_irq = true;
+        }
+    }
+
+    fn _clock_quarter_frame(&mut self) {
+        self.pulse_1.clock_quarter_frame();
+        self.pulse_2.clock_quarter_frame();
+        self.triangle.clock_quarter_frame();
+        self.noise.clock_quarter_frame();
+    }
+
+    fn _clock_half_frame(&mut self) {
+        self.pulse_1.clock_half_frame();
+        self.pulse_2.clock_half_frame();
+        self.triangle.clock_half_frame();
+        self.noise.clock_half_frame();
+    }
+
+    // The non-linear mixer of the NES, approximated as described on the nesdev wiki
+    fn _mix(&self) -> f32 {
+        let pulse = (self.pulse_1.output() + self.pulse_2.output()) as f32;
+        let triangle = self.triangle.output() as f32;
+        let noise = self.noise.output() as f32;
+        let dmc = self.dmc.output() as f32;
+
+        let pulse_out = if pulse > 0.0 {
+            95.88 / (8128.0 / pulse + 100.0)
+        } else {
+            0.0
+        };
+
+        let tnd = triangle / 8227.0 + noise / 12241.0 + dmc / 22638.0;
+        let tnd_out = if tnd > 0.0 {
+            159.79 / (1.0 / tnd + 100.0)
+        } else {
+            0.0
+        };
+
+        pulse_out + tnd_out
+    }
+
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        self.pulse_1.save_state(writer);
+        self.pulse_2.save_state(writer);
+        self.triangle.save_state(writer);
+        self.noise.save_state(writer);
+        self.dmc.save_state(writer);
+
+        writer.write_bool(self.five_step_mode);
+        writer.write_bool(self.irq_inhibit);
+        writer.write_bool(self.frame_irq);
+        writer.write_u32(self.frame_cycle);
+        writer.write_u8(self.frame_reset_delay.unwrap_or(0));
+        writer.write_bool(self.odd_cycle);
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<(), String> {
+        self.pulse_1.load_state(reader)?;
+        self.pulse_2.load_state(reader)?;
+        self.triangle.load_state(reader)?;
+        self.noise.load_state(reader)?;
+        self.dmc.load_state(reader)?;
+
+        self.five_step_mode = reader.read_bool()?;
+        self.irq_inhibit = reader.read_bool()?;
+        self.frame_irq = reader.read_bool()?;
+        self.frame_cycle = reader.read_u32()?;
+        self.frame_reset_delay = match reader.read_u8()? {
+            0 => None,
+            delay => Some(delay),
+        };
+        self.odd_cycle = reader.read_bool()?;
+
+        if self.frame_cycle >= self.frame_timings.five_step_length {
+            return Err(format!("Invalid frame counter position in save state: {}", self.frame_cycle));
+        }
+
+        // Whatever was buffered belongs to the timeline that was left
+        self.samples.clear();
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Apu, STATUS, FRAME_COUNTER, STATUS_FRAME_IRQ, STATUS_PULSE_1, NTSC_FRAME_TIMINGS};
+    use crate::nes::region::Region;
+
+    const FOUR_STEP_LENGTH: u32 = NTSC_FRAME_TIMINGS.four_step_length;
+
+    fn run(apu: &mut Apu, cycles: u32) {
+        for _i in 0..cycles {
+            apu.tick();
+        }
+    }
+
+    #[test]
+    fn test_frame_irq() {
+        let mut apu = Apu::new();
+        run(&mut apu, FOUR_STEP_LENGTH);
+        assert!(apu.get_irq_signal());
+
+        // Reading $4015 acknowledges it
+        assert_eq!(STATUS_FRAME_IRQ, apu.read_status() & STATUS_FRAME_IRQ);
+        assert!(!apu.get_irq_signal());
+
+        // Neither the inhibited nor the five step sequence raise it
+        apu.write_register(FRAME_COUNTER, 0x40);
+        run(&mut apu, FOUR_STEP_LENGTH * 2);
+        assert!(!apu.get_irq_signal());
+
+        apu.write_register(FRAME_COUNTER, 0x80);
+        run(&mut apu, FOUR_STEP_LENGTH * 2);
+        assert!(!apu.get_irq_signal());
+    }
+
+    #[test]
+    fn test_pal_frame_irq() {
+        let mut apu = Apu::new();
+        apu.set_region(Region::Pal);
+        assert_eq!(1_662_607, apu.get_sample_rate());
+
+        run(&mut apu, FOUR_STEP_LENGTH);
+        assert!(!apu.get_irq_signal());
+        run(&mut apu, 33_254 - FOUR_STEP_LENGTH);
+        assert!(apu.get_irq_signal());
+    }
+
+    #[test]
+    fn test_length_counter() {
+        let mut apu = Apu::new();
+        apu.write_register(STATUS, STATUS_PULSE_1);
+        // Halt off; length index 1 is 254, index 0 is 10
+        apu.write_register(0x4000, 0x00);
+        apu.write_register(0x4003, 0x00);
+        assert_eq!(STATUS_PULSE_1, apu.peek_status() & STATUS_PULSE_1);
+
+        // Clocked twice per four step sequence
+        run(&mut apu, FOUR_STEP_LENGTH * 5);
+        assert_eq!(0, apu.peek_status() & STATUS_PULSE_1);
+
+        // Disabling the channel clears it right away
+        apu.write_register(0x4003, 0x08);
+        assert_eq!(STATUS_PULSE_1, apu.peek_status() & STATUS_PULSE_1);
+        apu.write_register(STATUS, 0);
+        assert_eq!(0, apu.peek_status() & STATUS_PULSE_1);
+    }
+
+    #[test]
+    fn test_samples() {
+        let mut apu = Apu::new();
+        apu.write_register(STATUS, STATUS_PULSE_1);
+        // 50% duty, constant volume 15, period $100
+        apu.write_register(0x4000, 0xBF);
+        apu.write_register(0x4002, 0x00);
+        apu.write_register(0x4003, 0x01);
+
+        run(&mut apu, 1000);
+        let samples = apu.take_samples();
+        assert_eq!(1000, samples.len());
+        assert!(samples.iter().any(|sample| *sample > 0.1));
+        assert!(samples.iter().all(|sample| (0.0..=1.0).contains(sample)));
+        assert!(apu.take_samples().is_empty());
+    }
+}
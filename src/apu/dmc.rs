@@ -0,0 +1,258 @@
+use crate::nes::savestate::{StateWriter, StateReader};
+use crate::nes::region::Region;
+
+// Timer periods in cpu cycles
+const RATE_TABLE: [u16; 16] = [
+    428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106, 84, 72, 54,
+];
+const PAL_RATE_TABLE: [u16; 16] = [
+    398, 354, 316, 298, 276, 236, 210, 198, 176, 148, 132, 118, 98, 78, 66, 50,
+];
+
+const SAMPLE_ADDRESS_START: u16 = 0xC000;
+// Sample addresses past $FFFF wrap around to here
+const SAMPLE_ADDRESS_WRAP: u16 = 0x8000;
+
+// Plays 1-bit delta encoded samples that it reads from PRG through the bus, stalling the cpu for each byte
+#[derive(Clone)]
+pub struct Dmc {
+    irq_enabled: bool,
+    looping: bool,
+    rate_table: &'static [u16; 16],
+    period: u16,
+    timer: u16,
+
+    sample_address: u16,
+    sample_length: u16,
+    current_address: u16,
+    bytes_remaining: u16,
+    sample_buffer: Option<u8>,
+
+    shift_register: u8,
+    bits_remaining: u8,
+    silence: bool,
+    output_level: u8,
+
+    irq: bool,
+}
+
+impl Dmc {
+    pub fn new() -> Dmc {
+        Dmc {
+            irq_enabled: false,
+            looping: false,
+            rate_table: &RATE_TABLE,
+            period: RATE_TABLE[0],
+            timer: 0,
+            sample_address: SAMPLE_ADDRESS_START,
+            sample_length: 1,
+            current_address: SAMPLE_ADDRESS_START,
+            bytes_remaining: 0,
+            sample_buffer: None,
+            shift_register: 0,
+            bits_remaining: 8,
+            silence: true,
+            output_level: 0,
+            irq: false,
+        }
+    }
+
+    // $4010-$4013
+    pub fn write_register(&mut self, register: u16, data: u8) {
+        match register {
+            0 => {
+                // IL-- RRRR
+                self.irq_enabled = data & 0x80 > 0;
+                self.looping = data & 0x40 > 0;
+                self.period = self.rate_table[(data & 0x0F) as usize];
+
+                if !self.irq_enabled {
+                    self.irq = false;
+                }
+            }
+            1 => {
+                // -DDD DDDD
+                self.output_level = data & 0x7F;
+            }
+            2 => {
+                self.sample_address = SAMPLE_ADDRESS_START + data as u16 * 64;
+            }
+            3 => {
+                self.sample_length = data as u16 * 16 + 1;
+            }
+            _ => unreachable!()
+        }
+    }
+
+    // Enabling restarts the sample only if it had finished, disabling stops it after the buffered byte
+    pub fn set_region(&mut self, region: Region) {
+        self.rate_table = if region.has_pal_apu() { &PAL_RATE_TABLE } else { &RATE_TABLE };
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.irq = false;
+
+        if !enabled {
+            self.bytes_remaining = 0;
+        } else if self.bytes_remaining == 0 {
+            self._restart();
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.bytes_remaining > 0
+    }
+
+    pub fn get_irq(&self) -> bool {
+        self.irq
+    }
+
+    fn _restart(&mut self) {
+        self.current_address = self.sample_address;
+        self.bytes_remaining = self.sample_length;
+    }
+
+    // The address the next sample byte has to be read from, when the buffer needs filling
+    pub fn get_fetch_address(&self) -> Option<u16> {
+        if self.sample_buffer.is_none() && self.bytes_remaining > 0 {
+            Some(self.current_address)
+        } else {
+            None
+        }
+    }
+
+    // Called by the bus with the byte it read from get_fetch_address
+    pub fn fill_sample_buffer(&mut self, data: u8) {
+        self.sample_buffer = Some(data);
+
+        self.current_address = match self.current_address {
+            0xFFFF => SAMPLE_ADDRESS_WRAP,
+            address => address + 1,
+        };
+        self.bytes_remaining -= 1;
+
+        if self.bytes_remaining == 0 {
+            if self.looping {
+                self._restart();
+            } else if self.irq_enabled {
+                self.irq = true;
+            }
+        }
+    }
+
+    // Clocked every cpu cycle, the rate table is in cpu cycles
+    pub fn clock_timer(&mut self) {
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+        self.timer = self.period - 1;
+
+        if !self.silence {
+            if self.shift_register & 1 > 0 {
+                if self.output_level <= 125 {
+                    self.output_level += 2;
+                }
+            } else if self.output_level >= 2 {
+                self.output_level -= 2;
+            }
+        }
+        self.shift_register >>= 1;
+
+        self.bits_remaining -= 1;
+        if self.bits_remaining == 0 {
+            self.bits_remaining = 8;
+
+            match self.sample_buffer.take() {
+                Some(sample) => {
+                    self.silence = false;
+                    self.shift_register = sample;
+                }
+                None => self.silence = true,
+            }
+        }
+    }
+
+    pub fn output(&self) -> u8 {
+        self.output_level
+    }
+
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bool(self.irq_enabled);
+        writer.write_bool(self.looping);
+        writer.write_u16(self.period);
+        writer.write_u16(self.timer);
+
+        writer.write_u16(self.sample_address);
+        writer.write_u16(self.sample_length);
+        writer.write_u16(self.current_address);
+        writer.write_u16(self.bytes_remaining);
+        writer.write_bool(self.sample_buffer.is_some());
+        writer.write_u8(self.sample_buffer.unwrap_or(0));
+
+        writer.write_u8(self.shift_register);
+        writer.write_u8(self.bits_remaining);
+        writer.write_bool(self.silence);
+        writer.write_u8(self.output_level);
+
+        writer.write_bool(self.irq);
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<(), String> {
+        self.irq_enabled = reader.read_bool()?;
+        self.looping = reader.read_bool()?;
+        self.period = reader.read_u16()?.max(1);
+        self.timer = reader.read_u16()?;
+
+        self.sample_address = reader.read_u16()?;
+        self.sample_length = reader.read_u16()?;
+        self.current_address = reader.read_u16()?;
+        self.bytes_remaining = reader.read_u16()?;
+        let buffered = reader.read_bool()?;
+        let sample = reader.read_u8()?;
+        self.sample_buffer = if buffered { Some(sample) } else { None };
+
+        self.shift_register = reader.read_u8()?;
+        self.bits_remaining = reader.read_u8()?.clamp(1, 8);
+        self.silence = reader.read_bool()?;
+        self.output_level = reader.read_u8()? & 0x7F;
+
+        self.irq = reader.read_bool()?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Dmc;
+
+    #[test]
+    fn test_sample_fetch() {
+        let mut dmc = Dmc::new();
+        // IRQ on, no loop; sample at $FFC0, 81 bytes
+        dmc.write_register(0, 0x80);
+        dmc.write_register(2, 0xFF);
+        dmc.write_register(3, 0x05);
+        dmc.set_enabled(true);
+
+        for i in 0..81u16 {
+            assert!(!dmc.get_irq());
+            // Wraps from $FFFF to $8000
+            let expected = if i < 0x40 { 0xFFC0 + i } else { 0x8000 + i - 0x40 };
+            assert_eq!(Some(expected), dmc.get_fetch_address());
+
+            dmc.fill_sample_buffer(0xFF);
+            assert_eq!(None, dmc.get_fetch_address());
+
+            // Play the buffered byte
+            for _j in 0..8 * 428 {
+                dmc.clock_timer();
+            }
+        }
+
+        assert!(dmc.get_irq());
+        assert!(!dmc.is_active());
+        assert!(dmc.output() > 0);
+    }
+}
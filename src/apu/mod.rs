@@ -0,0 +1,7 @@
+pub mod apu;
+
+mod envelope;
+mod pulse;
+mod triangle;
+mod noise;
+mod dmc;
@@ -0,0 +1,150 @@
+use super::envelope::{Envelope, LengthCounter};
+use crate::nes::savestate::{StateWriter, StateReader};
+use crate::nes::region::Region;
+
+// Timer periods in cpu cycles
+const PERIOD_TABLE: [u16; 16] = [
+    4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068,
+];
+const PAL_PERIOD_TABLE: [u16; 16] = [
+    4, 8, 14, 30, 60, 88, 118, 148, 188, 236, 354, 472, 708, 944, 1890, 3778,
+];
+
+#[derive(Clone)]
+pub struct Noise {
+    envelope: Envelope,
+    length_counter: LengthCounter,
+
+    // Mode 1 takes the feedback from bit 6 instead of bit 1, giving a short metallic sounding sequence
+    mode: bool,
+    period_table: &'static [u16; 16],
+    period: u16,
+    timer: u16,
+    shift_register: u16,
+}
+
+impl Noise {
+    pub fn new() -> Noise {
+        Noise {
+            envelope: Envelope::new(),
+            length_counter: LengthCounter::new(),
+            mode: false,
+            period_table: &PERIOD_TABLE,
+            period: PERIOD_TABLE[0],
+            timer: 0,
+            shift_register: 1,
+        }
+    }
+
+    // $400C-$400F, $400D is unused
+    pub fn write_register(&mut self, register: u16, data: u8) {
+        match register {
+            0 => {
+                // --LC VVVV
+                self.envelope.write_control(data);
+                self.length_counter.set_halted(data & 0x20 > 0);
+            }
+            1 => {}
+            2 => {
+                // M--- PPPP
+                self.mode = data & 0x80 > 0;
+                self.period = self.period_table[(data & 0x0F) as usize];
+            }
+            3 => {
+                // LLLL L---
+                self.length_counter.load(data);
+                self.envelope.restart();
+            }
+            _ => unreachable!()
+        }
+    }
+
+    pub fn set_region(&mut self, region: Region) {
+        self.period_table = if region.has_pal_apu() { &PAL_PERIOD_TABLE } else { &PERIOD_TABLE };
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.length_counter.set_enabled(enabled);
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.length_counter.is_active()
+    }
+
+    // Clocked every cpu cycle, the period table is in cpu cycles
+    pub fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.period - 1;
+
+            let tap = if self.mode { 6 } else { 1 };
+            let feedback = (self.shift_register ^ (self.shift_register >> tap)) & 1;
+            self.shift_register = (self.shift_register >> 1) | (feedback << 14);
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    pub fn clock_quarter_frame(&mut self) {
+        self.envelope.clock();
+    }
+
+    pub fn clock_half_frame(&mut self) {
+        self.length_counter.clock();
+    }
+
+    pub fn output(&self) -> u8 {
+        if self.shift_register & 1 > 0 || !self.length_counter.is_active() {
+            0
+        } else {
+            self.envelope.output()
+        }
+    }
+
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        self.envelope.save_state(writer);
+        self.length_counter.save_state(writer);
+
+        writer.write_bool(self.mode);
+        writer.write_u16(self.period);
+        writer.write_u16(self.timer);
+        writer.write_u16(self.shift_register);
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<(), String> {
+        self.envelope.load_state(reader)?;
+        self.length_counter.load_state(reader)?;
+
+        self.mode = reader.read_bool()?;
+        self.period = reader.read_u16()?.max(1);
+        self.timer = reader.read_u16()?;
+        self.shift_register = reader.read_u16()?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Noise;
+
+    fn sequence_length(mode: bool) -> usize {
+        let mut noise = Noise::new();
+        noise.mode = mode;
+        noise.period = 1;
+
+        let start = noise.shift_register;
+        for i in 1..=0x8000 {
+            noise.clock_timer();
+            if noise.shift_register == start {
+                return i;
+            }
+        }
+        panic!("The shift register never repeated");
+    }
+
+    #[test]
+    fn test_sequence_length() {
+        assert_eq!(32767, sequence_length(false));
+        assert_eq!(93, sequence_length(true));
+    }
+}
@@ -0,0 +1,231 @@
+use super::envelope::{Envelope, LengthCounter};
+use crate::nes::savestate::{StateWriter, StateReader};
+
+const DUTY_TABLE: [[u8; 8]; 4] = [
+    [0, 1, 0, 0, 0, 0, 0, 0], // 12.5%
+    [0, 1, 1, 0, 0, 0, 0, 0], // 25%
+    [0, 1, 1, 1, 1, 0, 0, 0], // 50%
+    [1, 0, 0, 1, 1, 1, 1, 1], // 25% negated
+];
+
+// Periods below this, or sweeping above MAX_PERIOD, mute the channel
+const MIN_PERIOD: u16 = 8;
+const MAX_PERIOD: u16 = 0x7FF;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum PulseChannel {
+    // The sweep of pulse 1 negates with one's complement, pulse 2 with two's complement
+    One,
+    Two,
+}
+
+#[derive(Clone)]
+pub struct Pulse {
+    channel: PulseChannel,
+
+    envelope: Envelope,
+    length_counter: LengthCounter,
+
+    duty: u8,
+    sequence_step: u8,
+
+    period: u16,
+    timer: u16,
+
+    sweep_enabled: bool,
+    sweep_period: u8,
+    sweep_negate: bool,
+    sweep_shift: u8,
+    sweep_divider: u8,
+    sweep_reload: bool,
+}
+
+impl Pulse {
+    pub fn new(channel: PulseChannel) -> Pulse {
+        Pulse {
+            channel,
+            envelope: Envelope::new(),
+            length_counter: LengthCounter::new(),
+            duty: 0,
+            sequence_step: 0,
+            period: 0,
+            timer: 0,
+            sweep_enabled: false,
+            sweep_period: 0,
+            sweep_negate: false,
+            sweep_shift: 0,
+            sweep_divider: 0,
+            sweep_reload: false,
+        }
+    }
+
+    // Register 0-3 of the channel, $4000-$4003 or $4004-$4007
+    pub fn write_register(&mut self, register: u16, data: u8) {
+        match register {
+            0 => {
+                // DDLC VVVV
+                self.duty = data >> 6;
+                self.envelope.write_control(data);
+                self.length_counter.set_halted(data & 0x20 > 0);
+            }
+            1 => {
+                // EPPP NSSS
+                self.sweep_enabled = data & 0x80 > 0;
+                self.sweep_period = (data >> 4) & 0x07;
+                self.sweep_negate = data & 0x08 > 0;
+                self.sweep_shift = data & 0x07;
+                self.sweep_reload = true;
+            }
+            2 => {
+                self.period = (self.period & 0x0700) | data as u16;
+            }
+            3 => {
+                // LLLL LHHH
+                self.period = (self.period & 0x00FF) | (((data & 0x07) as u16) << 8);
+                self.length_counter.load(data);
+                self.sequence_step = 0;
+                self.envelope.restart();
+            }
+            _ => unreachable!()
+        }
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.length_counter.set_enabled(enabled);
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.length_counter.is_active()
+    }
+
+    // Clocked every other cpu cycle
+    pub fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.period;
+            self.sequence_step = (self.sequence_step + 1) % 8;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    pub fn clock_quarter_frame(&mut self) {
+        self.envelope.clock();
+    }
+
+    pub fn clock_half_frame(&mut self) {
+        self.length_counter.clock();
+
+        if self.sweep_divider == 0 && self.sweep_enabled && self.sweep_shift > 0 && !self._is_muted() {
+            self.period = self._sweep_target_period();
+        }
+
+        if self.sweep_divider == 0 || self.sweep_reload {
+            self.sweep_divider = self.sweep_period;
+            self.sweep_reload = false;
+        } else {
+            self.sweep_divider -= 1;
+        }
+    }
+
+    fn _sweep_target_period(&self) -> u16 {
+        let change = self.period >> self.sweep_shift;
+
+        if self.sweep_negate {
+            match self.channel {
+                PulseChannel::One => self.period.saturating_sub(change + 1),
+                PulseChannel::Two => self.period.saturating_sub(change),
+            }
+        } else {
+            self.period + change
+        }
+    }
+
+    // The sweep unit mutes the channel even when it is disabled
+    fn _is_muted(&self) -> bool {
+        self.period < MIN_PERIOD || self._sweep_target_period() > MAX_PERIOD
+    }
+
+    pub fn output(&self) -> u8 {
+        if DUTY_TABLE[self.duty as usize][self.sequence_step as usize] == 0
+            || !self.length_counter.is_active()
+            || self._is_muted() {
+            0
+        } else {
+            self.envelope.output()
+        }
+    }
+
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        self.envelope.save_state(writer);
+        self.length_counter.save_state(writer);
+
+        writer.write_u8(self.duty);
+        writer.write_u8(self.sequence_step);
+        writer.write_u16(self.period);
+        writer.write_u16(self.timer);
+
+        writer.write_bool(self.sweep_enabled);
+        writer.write_u8(self.sweep_period);
+        writer.write_bool(self.sweep_negate);
+        writer.write_u8(self.sweep_shift);
+        writer.write_u8(self.sweep_divider);
+        writer.write_bool(self.sweep_reload);
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<(), String> {
+        self.envelope.load_state(reader)?;
+        self.length_counter.load_state(reader)?;
+
+        self.duty = reader.read_u8()? & 0x03;
+        self.sequence_step = reader.read_u8()? % 8;
+        self.period = reader.read_u16()? & MAX_PERIOD;
+        self.timer = reader.read_u16()?;
+
+        self.sweep_enabled = reader.read_bool()?;
+        self.sweep_period = reader.read_u8()?;
+        self.sweep_negate = reader.read_bool()?;
+        self.sweep_shift = reader.read_u8()? & 0x07;
+        self.sweep_divider = reader.read_u8()?;
+        self.sweep_reload = reader.read_bool()?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Pulse, PulseChannel};
+
+    #[test]
+    fn test_sweep_negate() {
+        // Period $100, shift 1, negate
+        let mut one = Pulse::new(PulseChannel::One);
+        let mut two = Pulse::new(PulseChannel::Two);
+
+        for pulse in [&mut one, &mut two].iter_mut() {
+            pulse.write_register(1, 0b1000_1001);
+            pulse.write_register(2, 0x00);
+            pulse.write_register(3, 0x01);
+        }
+
+        assert_eq!(0x7F, one._sweep_target_period());
+        assert_eq!(0x80, two._sweep_target_period());
+    }
+
+    #[test]
+    fn test_sweep_mutes_high_periods() {
+        let mut pulse = Pulse::new(PulseChannel::One);
+        pulse.set_enabled(true);
+        // Constant volume 15, 50% duty
+        pulse.write_register(0, 0b1011_1111);
+        // Sweep disabled, shift 1: $600 + $300 overflows
+        pulse.write_register(1, 0b0000_0001);
+        pulse.write_register(2, 0x00);
+        pulse.write_register(3, 0x06);
+
+        for _i in 0..16 {
+            assert_eq!(0, pulse.output());
+            pulse.clock_timer();
+        }
+    }
+}
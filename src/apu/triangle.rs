@@ -0,0 +1,130 @@
+use super::envelope::LengthCounter;
+use crate::nes::savestate::{StateWriter, StateReader};
+
+const SEQUENCE: [u8; 32] = [
+    15, 14, 13, 12, 11, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1, 0,
+    0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15,
+];
+
+#[derive(Clone)]
+pub struct Triangle {
+    length_counter: LengthCounter,
+
+    // Doubles as the length counter halt flag
+    control: bool,
+    linear_reload_value: u8,
+    linear_counter: u8,
+    linear_reload: bool,
+
+    period: u16,
+    timer: u16,
+    sequence_step: u8,
+}
+
+impl Triangle {
+    pub fn new() -> Triangle {
+        Triangle {
+            length_counter: LengthCounter::new(),
+            control: false,
+            linear_reload_value: 0,
+            linear_counter: 0,
+            linear_reload: false,
+            period: 0,
+            timer: 0,
+            sequence_step: 0,
+        }
+    }
+
+    // $4008-$400B, $4009 is unused
+    pub fn write_register(&mut self, register: u16, data: u8) {
+        match register {
+            0 => {
+                // CRRR RRRR
+                self.control = data & 0x80 > 0;
+                self.linear_reload_value = data & 0x7F;
+                self.length_counter.set_halted(self.control);
+            }
+            1 => {}
+            2 => {
+                self.period = (self.period & 0x0700) | data as u16;
+            }
+            3 => {
+                // LLLL LHHH
+                self.period = (self.period & 0x00FF) | (((data & 0x07) as u16) << 8);
+                self.length_counter.load(data);
+                self.linear_reload = true;
+            }
+            _ => unreachable!()
+        }
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.length_counter.set_enabled(enabled);
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.length_counter.is_active()
+    }
+
+    // Clocked every cpu cycle, unlike the other channels
+    pub fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.period;
+
+            if self.length_counter.is_active() && self.linear_counter > 0 {
+                self.sequence_step = (self.sequence_step + 1) % 32;
+            }
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    pub fn clock_quarter_frame(&mut self) {
+        if self.linear_reload {
+            self.linear_counter = self.linear_reload_value;
+        } else if self.linear_counter > 0 {
+            self.linear_counter -= 1;
+        }
+
+        if !self.control {
+            self.linear_reload = false;
+        }
+    }
+
+    pub fn clock_half_frame(&mut self) {
+        self.length_counter.clock();
+    }
+
+    // A stopped triangle keeps outputting the step it stopped at, which avoids a pop
+    pub fn output(&self) -> u8 {
+        SEQUENCE[self.sequence_step as usize]
+    }
+
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        self.length_counter.save_state(writer);
+
+        writer.write_bool(self.control);
+        writer.write_u8(self.linear_reload_value);
+        writer.write_u8(self.linear_counter);
+        writer.write_bool(self.linear_reload);
+
+        writer.write_u16(self.period);
+        writer.write_u16(self.timer);
+        writer.write_u8(self.sequence_step);
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<(), String> {
+        self.length_counter.load_state(reader)?;
+
+        self.control = reader.read_bool()?;
+        self.linear_reload_value = reader.read_u8()?;
+        self.linear_counter = reader.read_u8()?;
+        self.linear_reload = reader.read_bool()?;
+
+        self.period = reader.read_u16()? & 0x07FF;
+        self.timer = reader.read_u16()?;
+        self.sequence_step = reader.read_u8()? % 32;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,3 @@
+pub mod output;
+pub mod resampler;
+pub mod wav;
@@ -0,0 +1,67 @@
+use super::resampler::Resampler;
+
+pub const DEFAULT_SAMPLE_RATE: u32 = 48_000;
+pub const SUPPORTED_SAMPLE_RATES: [u32; 2] = [44_100, 48_000];
+
+// Somewhere the resampled audio goes, mono samples in -1..1 at the rate the sink was opened with
+pub trait AudioSink {
+    fn get_sample_rate(&self) -> u32;
+
+    fn write(&mut self, samples: &[f32]) -> Result<(), String>;
+
+    // Samples written but not played yet, for sinks that play in real time
+    fn get_queued_samples(&self) -> u32 {
+        0
+    }
+
+    // Called once after the last write
+    fn finish(&mut self) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+/// Takes the samples of the apu at their native rate and writes them resampled to a sink
+pub struct AudioOutput {
+    resampler: Resampler,
+    sink: Box<dyn AudioSink>,
+    buffer: Vec<f32>,
+}
+
+impl AudioOutput {
+    pub fn new(input_rate: u32, sink: Box<dyn AudioSink>) -> AudioOutput {
+        AudioOutput {
+            resampler: Resampler::new(input_rate, sink.get_sample_rate()),
+            sink,
+            buffer: Vec::new(),
+        }
+    }
+
+    pub fn get_sample_rate(&self) -> u32 {
+        self.resampler.get_output_rate()
+    }
+
+    pub fn push(&mut self, samples: &[f32]) -> Result<(), String> {
+        self.buffer.clear();
+        self.resampler.process(samples, &mut self.buffer);
+
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        self.sink.write(&self.buffer)
+    }
+
+    pub fn get_queued_samples(&self) -> u32 {
+        self.sink.get_queued_samples()
+    }
+
+    pub fn finish(&mut self) -> Result<(), String> {
+        self.sink.finish()
+    }
+}
+
+pub fn parse_sample_rate(value: &str) -> Result<u32, String> {
+    match value.parse::<u32>() {
+        Ok(rate) if SUPPORTED_SAMPLE_RATES.contains(&rate) => Ok(rate),
+        _ => Err(format!("Unsupported sample rate {}, expected one of {:?}", value, SUPPORTED_SAMPLE_RATES)),
+    }
+}
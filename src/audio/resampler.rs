@@ -0,0 +1,216 @@
+use std::f64::consts::{PI, FRAC_1_SQRT_2};
+
+// The analog filters of the NES output stage: two high-passes that remove the DC offset of the mixer, and a low-pass
+const HIGH_PASS_1_HZ: f64 = 90.0;
+const HIGH_PASS_2_HZ: f64 = 440.0;
+const LOW_PASS_HZ: f64 = 14_000.0;
+
+// The band limit is placed below the nyquist frequency of the output rate to leave room for the roll off
+const BAND_LIMIT: f64 = 0.4;
+
+// Q of the sections of a 6th order Butterworth low-pass
+const BUTTERWORTH_Q: [f64; 3] = [0.5176, FRAC_1_SQRT_2, 1.9319];
+
+#[derive(Clone)]
+struct FirstOrderFilter {
+    a: f64,
+    high_pass: bool,
+    previous_input: f64,
+    previous_output: f64,
+}
+
+impl FirstOrderFilter {
+    fn new(cutoff: f64, sample_rate: f64, high_pass: bool) -> FirstOrderFilter {
+        let rc = 1.0 / (2.0 * PI * cutoff);
+        let dt = 1.0 / sample_rate;
+        let a = if high_pass { rc / (rc + dt) } else { dt / (rc + dt) };
+
+        FirstOrderFilter { a, high_pass, previous_input: 0.0, previous_output: 0.0 }
+    }
+
+    fn process(&mut self, input: f64) -> f64 {
+        let output = if self.high_pass {
+            self.a * (self.previous_output + input - self.previous_input)
+        } else {
+            self.previous_output + self.a * (input - self.previous_output)
+        };
+
+        self.previous_input = input;
+        self.previous_output = output;
+
+        output
+    }
+}
+
+// A low-pass section from the RBJ audio EQ cookbook, in direct form I
+#[derive(Clone)]
+struct Biquad {
+    b0: f64,
+    b1: f64,
+    b2: f64,
+    a1: f64,
+    a2: f64,
+
+    x1: f64,
+    x2: f64,
+    y1: f64,
+    y2: f64,
+}
+
+impl Biquad {
+    fn low_pass(cutoff: f64, sample_rate: f64, q: f64) -> Biquad {
+        let w0 = 2.0 * PI * cutoff / sample_rate;
+        let alpha = w0.sin() / (2.0 * q);
+        let cos_w0 = w0.cos();
+        let a0 = 1.0 + alpha;
+
+        Biquad {
+            b0: (1.0 - cos_w0) / 2.0 / a0,
+            b1: (1.0 - cos_w0) / a0,
+            b2: (1.0 - cos_w0) / 2.0 / a0,
+            a1: -2.0 * cos_w0 / a0,
+            a2: (1.0 - alpha) / a0,
+            x1: 0.0,
+            x2: 0.0,
+            y1: 0.0,
+            y2: 0.0,
+        }
+    }
+
+    fn process(&mut self, input: f64) -> f64 {
+        let output = self.b0 * input + self.b1 * self.x1 + self.b2 * self.x2 - self.a1 * self.y1 - self.a2 * self.y2;
+
+        self.x2 = self.x1;
+        self.x1 = input;
+        self.y2 = self.y1;
+        self.y1 = output;
+
+        output
+    }
+}
+
+/// Converts the samples of the apu, one per cpu cycle, to an output rate such as 44.1 or 48 kHz.
+///
+/// The input goes through the filters of the NES output stage and a low-pass below the nyquist frequency
+/// of the output rate, so that the high harmonics of the square waves don't alias. The filtered signal
+/// is then sampled at the output rate with linear interpolation.
+#[derive(Clone)]
+pub struct Resampler {
+    output_rate: u32,
+
+    analog_filters: [FirstOrderFilter; 3],
+    band_limit: [Biquad; 3],
+
+    // Input samples per output sample
+    step: f64,
+    // Position of the next output sample, relative to the previous input sample
+    position: f64,
+    previous: f64,
+}
+
+impl Resampler {
+    pub fn new(input_rate: u32, output_rate: u32) -> Resampler {
+        let input_rate_f = input_rate as f64;
+        let cutoff = output_rate as f64 * BAND_LIMIT;
+
+        Resampler {
+            output_rate,
+            analog_filters: [
+                FirstOrderFilter::new(HIGH_PASS_1_HZ, input_rate_f, true),
+                FirstOrderFilter::new(HIGH_PASS_2_HZ, input_rate_f, true),
+                FirstOrderFilter::new(LOW_PASS_HZ, input_rate_f, false),
+            ],
+            band_limit: [
+                Biquad::low_pass(cutoff, input_rate_f, BUTTERWORTH_Q[0]),
+                Biquad::low_pass(cutoff, input_rate_f, BUTTERWORTH_Q[1]),
+                Biquad::low_pass(cutoff, input_rate_f, BUTTERWORTH_Q[2]),
+            ],
+            step: input_rate_f / output_rate as f64,
+            position: 0.0,
+            previous: 0.0,
+        }
+    }
+
+    pub fn get_output_rate(&self) -> u32 {
+        self.output_rate
+    }
+
+    // Appends the resampled input to output, roughly input.len() * output_rate / input_rate samples
+    pub fn process(&mut self, input: &[f32], output: &mut Vec<f32>) {
+        for sample in input {
+            let mut filtered = *sample as f64;
+            for filter in self.analog_filters.iter_mut() {
+                filtered = filter.process(filtered);
+            }
+            for filter in self.band_limit.iter_mut() {
+                filtered = filter.process(filtered);
+            }
+
+            // Output samples that fall between the previous input sample and this one
+            while self.position <= 1.0 {
+                let interpolated = self.previous + (filtered - self.previous) * self.position;
+                output.push(interpolated as f32);
+                self.position += self.step;
+            }
+
+            self.position -= 1.0;
+            self.previous = filtered;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Resampler;
+
+    const INPUT_RATE: u32 = 1_789_773;
+
+    // Peak amplitude of a resampled sine, after the filters have settled
+    fn resampled_amplitude(frequency: f64, output_rate: u32) -> f32 {
+        let mut resampler = Resampler::new(INPUT_RATE, output_rate);
+
+        let input: Vec<f32> = (0..INPUT_RATE / 4)
+            .map(|i| 0.5 * (2.0 * std::f64::consts::PI * frequency * i as f64 / INPUT_RATE as f64).sin() as f32)
+            .collect();
+
+        let mut output = Vec::new();
+        resampler.process(&input, &mut output);
+
+        output[output.len() / 2..].iter().fold(0.0, |peak, sample| sample.abs().max(peak))
+    }
+
+    #[test]
+    fn test_output_length() {
+        for rate in [44_100, 48_000].iter() {
+            let mut resampler = Resampler::new(INPUT_RATE, *rate);
+            let mut output = Vec::new();
+
+            // In chunks like a frame at a time
+            for _i in 0..60 {
+                resampler.process(&vec![0.0; INPUT_RATE as usize / 60], &mut output);
+            }
+
+            assert!((output.len() as i64 - *rate as i64).abs() <= 2, "{} samples at {}", output.len(), rate);
+        }
+    }
+
+    #[test]
+    fn test_band_limit() {
+        // Passes the audible range, with the 14 kHz low-pass of the NES taking a bit off the top
+        assert!(resampled_amplitude(1000.0, 48_000) > 0.45);
+        assert!(resampled_amplitude(8000.0, 48_000) > 0.35);
+
+        // Frequencies that would alias are filtered before resampling
+        assert!(resampled_amplitude(40_000.0, 48_000) < 0.01);
+        assert!(resampled_amplitude(100_000.0, 44_100) < 0.001);
+    }
+
+    #[test]
+    fn test_removes_dc() {
+        let mut resampler = Resampler::new(INPUT_RATE, 48_000);
+        let mut output = Vec::new();
+        resampler.process(&vec![0.8; INPUT_RATE as usize / 10], &mut output);
+
+        assert!(output.last().unwrap().abs() < 0.01);
+    }
+}
@@ -0,0 +1,131 @@
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+
+use super::output::AudioSink;
+
+const HEADER_SIZE: u32 = 44;
+const BITS_PER_SAMPLE: u16 = 16;
+const CHANNELS: u16 = 1;
+
+// Offsets of the sizes that are only known once everything is written
+const RIFF_SIZE_OFFSET: u64 = 4;
+const DATA_SIZE_OFFSET: u64 = 40;
+
+/// Records the audio to a 16 bit mono PCM wav file
+pub struct WavWriter {
+    writer: BufWriter<File>,
+    sample_rate: u32,
+    data_size: u32,
+    finished: bool,
+}
+
+impl WavWriter {
+    pub fn create(path: &str, sample_rate: u32) -> Result<WavWriter, String> {
+        let file = File::create(path).map_err(|e| format!("Could not create {}: {}", path, e))?;
+
+        let mut writer = WavWriter { writer: BufWriter::new(file), sample_rate, data_size: 0, finished: false };
+        writer._write_header().map_err(|e| format!("Could not write {}: {}", path, e))?;
+
+        Ok(writer)
+    }
+
+    fn _write_header(&mut self) -> std::io::Result<()> {
+        let block_align = CHANNELS * BITS_PER_SAMPLE / 8;
+        let byte_rate = self.sample_rate * block_align as u32;
+
+        let w = &mut self.writer;
+        w.write_all(b"RIFF")?;
+        w.write_all(&(HEADER_SIZE - 8 + self.data_size).to_le_bytes())?;
+        w.write_all(b"WAVE")?;
+
+        w.write_all(b"fmt ")?;
+        w.write_all(&16u32.to_le_bytes())?;
+        // PCM
+        w.write_all(&1u16.to_le_bytes())?;
+        w.write_all(&CHANNELS.to_le_bytes())?;
+        w.write_all(&self.sample_rate.to_le_bytes())?;
+        w.write_all(&byte_rate.to_le_bytes())?;
+        w.write_all(&block_align.to_le_bytes())?;
+        w.write_all(&BITS_PER_SAMPLE.to_le_bytes())?;
+
+        w.write_all(b"data")?;
+        w.write_all(&self.data_size.to_le_bytes())?;
+
+        Ok(())
+    }
+
+    fn _finish(&mut self) -> std::io::Result<()> {
+        self.writer.seek(SeekFrom::Start(RIFF_SIZE_OFFSET))?;
+        self.writer.write_all(&(HEADER_SIZE - 8 + self.data_size).to_le_bytes())?;
+        self.writer.seek(SeekFrom::Start(DATA_SIZE_OFFSET))?;
+        self.writer.write_all(&self.data_size.to_le_bytes())?;
+        self.writer.flush()
+    }
+}
+
+impl AudioSink for WavWriter {
+    fn get_sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn write(&mut self, samples: &[f32]) -> Result<(), String> {
+        for sample in samples {
+            let value = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+            self.writer.write_all(&value.to_le_bytes()).map_err(|e| format!("Could not write audio: {}", e))?;
+        }
+        self.data_size = self.data_size.saturating_add(samples.len() as u32 * 2);
+
+        Ok(())
+    }
+
+    // Fills in the sizes in the header
+    fn finish(&mut self) -> Result<(), String> {
+        self.finished = true;
+        self._finish().map_err(|e| format!("Could not write audio: {}", e))
+    }
+}
+
+impl Drop for WavWriter {
+    fn drop(&mut self) {
+        if !self.finished {
+            let _ = self._finish();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::WavWriter;
+    use crate::audio::output::AudioSink;
+
+    #[test]
+    fn test_header() {
+        let path = std::env::temp_dir().join(format!("cnese_test_{}.wav", std::process::id()));
+        let path = path.to_str().unwrap();
+
+        let mut writer = WavWriter::create(path, 44_100).unwrap();
+        writer.write(&[0.0, 1.0, -1.0, 2.0]).unwrap();
+        writer.write(&[0.5]).unwrap();
+        writer.finish().unwrap();
+        drop(writer);
+
+        let data = std::fs::read(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        let u32_at = |offset: usize| u32::from_le_bytes([data[offset], data[offset + 1], data[offset + 2], data[offset + 3]]);
+        let i16_at = |offset: usize| i16::from_le_bytes([data[offset], data[offset + 1]]);
+
+        assert_eq!(44 + 10, data.len());
+        assert_eq!(b"RIFF", &data[0..4]);
+        assert_eq!(data.len() as u32 - 8, u32_at(4));
+        assert_eq!(b"WAVEfmt ", &data[8..16]);
+        assert_eq!(44_100, u32_at(24));
+        assert_eq!(88_200, u32_at(28));
+        assert_eq!(b"data", &data[36..40]);
+        assert_eq!(10, u32_at(40));
+
+        // Clamped to the 16 bit range
+        assert_eq!(vec![0, i16::MAX, -i16::MAX, i16::MAX, i16::MAX / 2],
+                   (0..5).map(|i| i16_at(44 + i * 2)).collect::<Vec<_>>());
+    }
+}
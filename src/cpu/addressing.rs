@@ -1,35 +1,35 @@
 use super::state::State;
 use super::databus::Databus;
 
-pub type AddressingModeFn = fn(state: &State, bus: &dyn Databus, operand: u16) -> u16;
+pub type AddressingModeFn = fn(state: &State, bus: &mut dyn Databus, operand: u16) -> u16;
 
-pub const DO_NOTHING: AddressingModeFn = |_state: &State, _bus: &dyn Databus, _operand: u16| -> u16 { 0 };
-pub const IMMEDIATE: AddressingModeFn = |_state: &State, _bus: &dyn Databus, operand: u16| -> u16 { operand };
+pub const DO_NOTHING: AddressingModeFn = |_state: &State, _bus: &mut dyn Databus, _operand: u16| -> u16 { 0 };
+pub const IMMEDIATE: AddressingModeFn = |_state: &State, _bus: &mut dyn Databus, operand: u16| -> u16 { operand };
 
-pub const RELATIVE: AddressingModeFn = |state: &State, _bus: &dyn Databus, operand: u16| -> u16 {
+pub const RELATIVE: AddressingModeFn = |state: &State, _bus: &mut dyn Databus, operand: u16| -> u16 {
     state.calculate_relative_pc(operand as i8)
 };
 
-pub const ABSOLUTE: AddressingModeFn = |_state: &State, _bus: &dyn Databus, operand: u16| -> u16 { operand };
-pub const ABSOLUTE_INDEXED_X: AddressingModeFn = |state: &State, _bus: &dyn Databus, operand: u16| -> u16 {
+pub const ABSOLUTE: AddressingModeFn = |_state: &State, _bus: &mut dyn Databus, operand: u16| -> u16 { operand };
+pub const ABSOLUTE_INDEXED_X: AddressingModeFn = |state: &State, _bus: &mut dyn Databus, operand: u16| -> u16 {
     operand.wrapping_add(state.x as u16)
 };
 
-pub const ABSOLUTE_INDEXED_Y: AddressingModeFn = |state: &State, _bus: &dyn Databus, operand: u16| -> u16 {
+pub const ABSOLUTE_INDEXED_Y: AddressingModeFn = |state: &State, _bus: &mut dyn Databus, operand: u16| -> u16 {
     operand.wrapping_add(state.y as u16)
 };
 
-pub const ZEROPAGE: AddressingModeFn = |_state: &State, _bus: &dyn Databus, operand: u16| -> u16 { operand };
+pub const ZEROPAGE: AddressingModeFn = |_state: &State, _bus: &mut dyn Databus, operand: u16| -> u16 { operand };
 
-pub const ZEROPAGE_INDEXED_X: AddressingModeFn = |state: &State, _bus: &dyn Databus, operand: u16| -> u16 {
+pub const ZEROPAGE_INDEXED_X: AddressingModeFn = |state: &State, _bus: &mut dyn Databus, operand: u16| -> u16 {
     ((operand as u8).wrapping_add(state.x)) as u16
 };
 
-pub const ZEROPAGE_INDEXED_Y: AddressingModeFn = |state: &State, _bus: &dyn Databus, operand: u16| -> u16 {
+pub const ZEROPAGE_INDEXED_Y: AddressingModeFn = |state: &State, _bus: &mut dyn Databus, operand: u16| -> u16 {
     ((operand as u8).wrapping_add(state.y)) as u16
 };
 
-pub const INDIRECT: AddressingModeFn = |_state: &State, bus: &dyn Databus, operand: u16| -> u16 {
+pub const INDIRECT: AddressingModeFn = |_state: &State, bus: &mut dyn Databus, operand: u16| -> u16 {
     let addr = operand as u16;
 
     let lo = bus.read(addr);
@@ -43,7 +43,7 @@ pub const INDIRECT: AddressingModeFn = |_state: &State, bus: &dyn Databus, opera
     ((hi as u16) << 8) + lo as u16
 };
 
-pub const INDEXED_INDIRECT_X: AddressingModeFn = |state: &State, bus: &dyn Databus, operand: u16| -> u16 {
+pub const INDEXED_INDIRECT_X: AddressingModeFn = |state: &State, bus: &mut dyn Databus, operand: u16| -> u16 {
     let addr = ((operand as u8).wrapping_add(state.x));
 
     let lo = bus.read(addr as u16);
@@ -52,13 +52,13 @@ pub const INDEXED_INDIRECT_X: AddressingModeFn = |state: &State, bus: &dyn Datab
     ((hi as u16) << 8) + lo as u16
 };
 
-pub const INDIRECT_INDEXED_Y: AddressingModeFn = |state: &State, bus: &dyn Databus, operand: u16| -> u16 {
+pub const INDIRECT_INDEXED_Y: AddressingModeFn = |state: &State, bus: &mut dyn Databus, operand: u16| -> u16 {
     let addr = operand as u8;
 
     let lo = bus.read(addr as u16);
     let hi = bus.read(addr.wrapping_add(1) as u16);
 
-    ((hi as u16) << 8) + lo as u16 + state.y as u16
+    (((hi as u16) << 8) + lo as u16).wrapping_add(state.y as u16)
 };
 
 
@@ -81,7 +81,7 @@ pub enum AddressingMode {
 }
 
 impl AddressingMode {
-    pub fn eval(&self, state: &State, bus: &dyn Databus, operand: u16) -> u16 {
+    pub fn eval(&self, state: &State, bus: &mut dyn Databus, operand: u16) -> u16 {
         self.get_fn()(state, bus, operand)
     }
 
@@ -90,7 +90,11 @@ impl AddressingMode {
             // TODO This is probably incorrect! See implementations of the addressing modes
             AddressingMode::AbsoluteIndexedX => _crossing_page(operand, state.x),
             AddressingMode::AbsoluteIndexedY => _crossing_page(operand, state.y),
-            AddressingMode::IndirectIndexedY => _crossing_page(bus.read_u16(operand), state.y),
+            AddressingMode::IndirectIndexedY => {
+                let lo = bus.peek(operand & 0xff);
+                let hi = bus.peek((operand as u8).wrapping_add(1) as u16);
+                _crossing_page(((hi as u16) << 8) + lo as u16, state.y)
+            }
             _ => false
         }
     }
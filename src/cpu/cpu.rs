@@ -1,7 +1,10 @@
+use std::fmt;
+
 use super::state::State;
 use super::databus::Databus;
 use super::instruction;
 use crate::cpu::instruction::Instruction;
+use crate::nes::savestate::{StateWriter, StateReader};
 
 pub const NMI_VECTOR_ADDRESS: u16 = 0xFFFA;
 pub const RES_VECTOR_ADDRESS: u16 = 0xFFFC;
@@ -9,11 +12,39 @@ pub const IRQ_VECTOR_ADDRESS: u16 = 0xFFFE;
 
 pub const STACK_OFFSET: u16 = 0x0100;
 
+/// What the cpu does when it fetches a JAM (KIL) or otherwise unknown opcode.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum IllegalOpcodePolicy {
+    /// Lock up like the real hardware. Only a reset brings the cpu back.
+    Halt,
+    /// Skip the opcode as if it was a one byte NOP.
+    Nop,
+    /// Refuse to execute the opcode and report it to the caller.
+    Error,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum CpuError {
+    IllegalOpcode { pc: u16, opcode: u8 },
+}
+
+impl fmt::Display for CpuError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CpuError::IllegalOpcode { pc, opcode } => {
+                write!(f, "Illegal opcode ${:02X} at PC=${:04X}", opcode, pc)
+            }
+        }
+    }
+}
+
+#[derive(Clone)]
 pub struct Cpu {
     state: State,
 
     next_instruction: Instruction,
     next_instruction_cost: u8,
+    last_instruction: Instruction,
     unspent_cycles: u8,
 
     cycle_count: u32,
@@ -21,7 +52,63 @@ pub struct Cpu {
 
     irq: bool,
     nmi: bool,
-    nmi_seen_hi: bool
+    nmi_seen_hi: bool,
+
+    illegal_opcode_policy: IllegalOpcodePolicy,
+    halted: bool,
+
+    // In cycle accurate mode every bus access of an instruction is made on its own cycle. The whole
+    // instruction still runs on the first tick, so cycle_debt holds the cycles the bus is ahead of the cpu.
+    cycle_accurate: bool,
+    cycle_debt: u8,
+
+    // Cycles the cpu has to sit out while DMA uses the bus
+    stall_cycles: u16,
+    stalled: bool,
+}
+
+// Clocks the bus before every access and counts the cycles spent
+struct ClockedDatabus<'a> {
+    bus: &'a mut dyn Databus,
+    cycles: u8,
+}
+
+impl<'a> ClockedDatabus<'a> {
+    fn new(bus: &'a mut dyn Databus) -> ClockedDatabus<'a> {
+        ClockedDatabus { bus, cycles: 0 }
+    }
+
+    fn _clock(&mut self) {
+        self.bus.tick();
+        self.cycles += 1;
+    }
+}
+
+impl<'a> Databus for ClockedDatabus<'a> {
+    fn read(&mut self, address: u16) -> u8 {
+        self._clock();
+        self.bus.read(address)
+    }
+
+    fn read_u16(&mut self, address: u16) -> u16 {
+        let lo = self.read(address);
+        let hi = self.read(address.wrapping_add(1));
+
+        ((hi as u16) << 8) + lo as u16
+    }
+
+    fn write(&mut self, address: u16, data: u8) {
+        self._clock();
+        self.bus.write(address, data);
+    }
+
+    fn peek(&self, address: u16) -> u8 {
+        self.bus.peek(address)
+    }
+
+    fn tick(&mut self) {
+        self._clock();
+    }
 }
 
 impl Cpu {
@@ -30,6 +117,7 @@ impl Cpu {
             state: State::new(),
             next_instruction: instruction::DUMMY_INSTRUCTION,
             next_instruction_cost: 0,
+            last_instruction: instruction::DUMMY_INSTRUCTION,
             unspent_cycles: 0,
 
             cycle_count: 0,
@@ -37,10 +125,29 @@ impl Cpu {
 
             irq: true,
             nmi: true,
-            nmi_seen_hi: true
+            nmi_seen_hi: true,
+
+            illegal_opcode_policy: IllegalOpcodePolicy::Halt,
+            halted: false,
+
+            cycle_accurate: true,
+            cycle_debt: 0,
+
+            stall_cycles: 0,
+            stalled: false,
         }
     }
 
+    pub fn set_cycle_accurate(&mut self, cycle_accurate: bool) {
+        self.cycle_accurate = cycle_accurate;
+        self.unspent_cycles = 0;
+        self.cycle_debt = 0;
+    }
+
+    pub fn set_illegal_opcode_policy(&mut self, policy: IllegalOpcodePolicy) {
+        self.illegal_opcode_policy = policy;
+    }
+
     pub fn set_irq_lo(&mut self) { self.irq = false;}
     pub fn set_irq_hi(&mut self) { self.irq = true;}
     pub fn set_nmi_hi(&mut self) {
@@ -49,8 +156,9 @@ impl Cpu {
     }
     pub fn set_nmi_lo(&mut self) { self.nmi = false; }
 
-    pub fn reset(&mut self, bus: &dyn Databus) {
+    pub fn reset(&mut self, bus: &mut dyn Databus) {
         self.state.clear();
+        self.halted = false;
 
         let pc = bus.read_u16(RES_VECTOR_ADDRESS);
         self.state.set_next_pc(pc);
@@ -59,46 +167,190 @@ impl Cpu {
         self._load_next_instruction(bus);
 
         self.unspent_cycles = 0;
+        self.cycle_debt = 0;
 
         #[cfg(debug_assertions)] {
             println!("Reset NES, PC=${:x}", pc);
         }
     }
 
-    pub fn tick(&mut self, bus: &mut dyn Databus) {
-        self.unspent_cycles += 1;
+    // The illegal opcode policy is a setting rather than state, it is left as it is
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        self.state.save_state(writer);
+
+        self.next_instruction.save_state(writer);
+        writer.write_u8(self.next_instruction_cost);
+        self.last_instruction.save_state(writer);
+        writer.write_u8(self.unspent_cycles);
+
+        writer.write_u32(self.cycle_count);
+        writer.write_u32(self.instruction_count);
+
+        writer.write_bool(self.irq);
+        writer.write_bool(self.nmi);
+        writer.write_bool(self.nmi_seen_hi);
+        writer.write_bool(self.halted);
+
+        writer.write_bool(self.cycle_accurate);
+        writer.write_u8(self.cycle_debt);
+
+        writer.write_u16(self.stall_cycles);
+        writer.write_bool(self.stalled);
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<(), String> {
+        self.state.load_state(reader)?;
+
+        self.next_instruction = Instruction::load_state(reader)?;
+        self.next_instruction_cost = reader.read_u8()?;
+        self.last_instruction = Instruction::load_state(reader)?;
+        self.unspent_cycles = reader.read_u8()?;
+
+        self.cycle_count = reader.read_u32()?;
+        self.instruction_count = reader.read_u32()?;
+
+        self.irq = reader.read_bool()?;
+        self.nmi = reader.read_bool()?;
+        self.nmi_seen_hi = reader.read_bool()?;
+        self.halted = reader.read_bool()?;
+
+        self.cycle_accurate = reader.read_bool()?;
+        self.cycle_debt = reader.read_u8()?;
+
+        self.stall_cycles = reader.read_u16()?;
+        self.stalled = reader.read_bool()?;
+
+        Ok(())
+    }
+
+    pub fn tick(&mut self, bus: &mut dyn Databus) -> Result<(), CpuError> {
+        // Stalls wait for the cycles the bus is already ahead by
+        self.stalled = self.stall_cycles > 0 && self.cycle_debt == 0;
+
+        if self.stalled {
+            self.stall_cycles -= 1;
+            bus.tick();
+        } else if self.cycle_accurate {
+            self._tick_cycle_accurate(bus)?;
+        } else {
+            self._tick_fast(bus)?;
+        }
+
         self.cycle_count += 1;
 
-        if self.unspent_cycles >= self.next_instruction_cost {
-            self.unspent_cycles -= self.next_instruction_cost;
+        Ok(())
+    }
+
+    // Runs until the current instruction has finished, returns the number of cycles spent
+    pub fn tick_instruction(&mut self, bus: &mut dyn Databus) -> Result<u8, CpuError> {
+        let mut cycles = 0;
 
-            self._execute_next_instruction(bus);
+        loop {
+            self.tick(bus)?;
+            cycles += 1;
 
+            if self.halted || self.is_at_instruction_boundary() {
+                return Ok(cycles);
+            }
         }
     }
 
-    pub fn tick_instruction(&mut self, bus: &mut dyn Databus) -> u8 {
-        let cycles = self.next_instruction_cost;
-        self.cycle_count += cycles as u32;
-        self._execute_next_instruction(bus);
+    // True when the last tick finished an instruction, i.e. the next tick starts a new one
+    pub fn is_at_instruction_boundary(&self) -> bool {
+        !self.stalled && self.unspent_cycles == 0 && self.cycle_debt == 0
+    }
+
+    // Halts the cpu for the given number of cycles, from the next tick on
+    pub fn stall(&mut self, cycles: u16) {
+        self.stall_cycles += cycles;
+    }
+
+    fn _tick_fast(&mut self, bus: &mut dyn Databus) -> Result<(), CpuError> {
+        if self.halted {
+            // A jammed cpu does nothing but let time pass
+        } else if self.unspent_cycles + 1 >= self.next_instruction_cost {
+            let cost = self.next_instruction_cost;
+            self._execute_next_instruction(bus)?;
+            self.unspent_cycles = self.unspent_cycles + 1 - cost;
+        } else {
+            self.unspent_cycles += 1;
+        }
+
+        bus.tick();
 
-        cycles
+        Ok(())
     }
 
+    fn _tick_cycle_accurate(&mut self, bus: &mut dyn Databus) -> Result<(), CpuError> {
+        if self.cycle_debt > 0 {
+            // The bus has already been clocked for this cycle
+            self.cycle_debt -= 1;
+        } else if self.halted {
+            bus.tick();
+        } else {
+            let mut clocked_bus = ClockedDatabus::new(bus);
+            self._execute_next_instruction(&mut clocked_bus)?;
+
+            match clocked_bus.cycles {
+                0 => bus.tick(), // Jammed without touching the bus
+                cycles => self.cycle_debt = cycles - 1,
+            }
+        }
+
+        Ok(())
+    }
     pub fn get_state(&self) -> &State { &self.state }
+    pub fn get_last_instruction(&self) -> &Instruction { &self.last_instruction }
     pub fn get_cycle_count(&self) -> u32 { self.cycle_count }
     pub fn get_instruction_count(&self) -> u32 { self.instruction_count }
+    pub fn is_halted(&self) -> bool { self.halted }
+
+    pub fn _execute_next_instruction(&mut self, bus: &mut dyn Databus) -> Result<(), CpuError> {
+        if self.cycle_accurate && !self.next_instruction.is_interrupt() {
+            // Interrupts are polled again now that the previous instruction has really finished
+            self._load_next_instruction(bus);
+        }
 
-    pub fn _execute_next_instruction(&mut self, bus: &mut dyn Databus) {
-        let instruction = &self.next_instruction;
+        let instruction = self.next_instruction;
 
-        self.state.set_next_pc(self.state.calculate_relative_pc(instruction.get_size() as i8));
-        instruction.execute(&mut self.state, bus);
+        if instruction.is_illegal() {
+            match self.illegal_opcode_policy {
+                IllegalOpcodePolicy::Halt => {
+                    self.halted = true;
+                    return Ok(());
+                }
+                IllegalOpcodePolicy::Error => {
+                    return Err(CpuError::IllegalOpcode {
+                        pc: self.state.get_pc(),
+                        opcode: instruction.get_opcode_byte(),
+                    });
+                }
+                IllegalOpcodePolicy::Nop => {}
+            }
+        }
+
+        if self.cycle_accurate {
+            let instruction = if instruction.is_interrupt() {
+                instruction
+            } else {
+                instruction::decode_instruction(bus, self.state.get_pc())
+            };
+
+            self.state.set_next_pc(self.state.calculate_relative_pc(instruction.get_size() as i8));
+            instruction.execute_cycle_accurate(&mut self.state, bus);
+            self.last_instruction = instruction;
+        } else {
+            self.state.set_next_pc(self.state.calculate_relative_pc(instruction.get_size() as i8));
+            instruction.execute(&mut self.state, bus);
+            self.last_instruction = instruction;
+        }
         self.state.update_pc();
 
         self._load_next_instruction(bus);
 
         self.instruction_count += 1;
+
+        Ok(())
     }
 
     pub fn _load_next_instruction(&mut self, bus: &dyn Databus) {
@@ -108,7 +360,7 @@ impl Cpu {
         } else if !self.irq && !self.state.get_status_field(super::state::SR_MASK_INTERRUPT) { // IRQ
             self.next_instruction = instruction::IRQ_INSTRUCTION;
         } else {
-            self.next_instruction = instruction::decode_instruction(bus, self.state.get_pc());
+            self.next_instruction = instruction::peek_instruction(bus, self.state.get_pc());
         }
 
         self.next_instruction_cost = self.next_instruction.calculate_cycle_cost(self.get_state(), bus);
@@ -116,3 +368,86 @@ impl Cpu {
 
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::{Cpu, CpuError, IllegalOpcodePolicy, RES_VECTOR_ADDRESS};
+    use crate::cpu::databus::tests::TestBus;
+
+    fn setup(program: &[u8]) -> (Cpu, TestBus) {
+        let mut bus = TestBus::new();
+        bus.load(0x8000, program);
+        bus.load(RES_VECTOR_ADDRESS, &[0x00, 0x80]);
+
+        let mut cpu = Cpu::new();
+        cpu.reset(&mut bus);
+
+        (cpu, bus)
+    }
+
+    fn run(cpu: &mut Cpu, bus: &mut TestBus, cycles: usize) -> Result<(), CpuError> {
+        for _i in 0..cycles {
+            cpu.tick(bus)?;
+        }
+        Ok(())
+    }
+
+    // INX, JAM, INX
+    const JAM_PROGRAM: [u8; 3] = [0xe8, 0x02, 0xe8];
+
+    #[test]
+    fn test_illegal_opcode_halt() {
+        let (mut cpu, mut bus) = setup(&JAM_PROGRAM);
+        cpu.set_illegal_opcode_policy(IllegalOpcodePolicy::Halt);
+
+        run(&mut cpu, &mut bus, 20).unwrap();
+        assert!(cpu.is_halted());
+        assert_eq!(1, cpu.get_state().x);
+        assert_eq!(0x8001, cpu.get_state().get_pc());
+        assert_eq!(20, cpu.get_cycle_count());
+
+        cpu.reset(&mut bus);
+        assert!(!cpu.is_halted());
+    }
+
+    #[test]
+    fn test_illegal_opcode_nop() {
+        let (mut cpu, mut bus) = setup(&JAM_PROGRAM);
+        cpu.set_illegal_opcode_policy(IllegalOpcodePolicy::Nop);
+
+        run(&mut cpu, &mut bus, 6).unwrap();
+        assert!(!cpu.is_halted());
+        assert_eq!(2, cpu.get_state().x);
+        assert_eq!(0x8003, cpu.get_state().get_pc());
+    }
+
+    #[test]
+    fn test_illegal_opcode_error() {
+        let (mut cpu, mut bus) = setup(&JAM_PROGRAM);
+        cpu.set_illegal_opcode_policy(IllegalOpcodePolicy::Error);
+
+        let result = run(&mut cpu, &mut bus, 20);
+        assert_eq!(Err(CpuError::IllegalOpcode { pc: 0x8001, opcode: 0x02 }), result);
+        assert_eq!(1, cpu.get_state().x);
+        assert_eq!(0x8001, cpu.get_state().get_pc());
+
+        // The error sticks until the cpu is reset
+        assert!(cpu.tick(&mut bus).is_err());
+    }
+
+    #[test]
+    fn test_tick_instruction() {
+        // LDA $0200,X crossing a page, INX, JMP $8000
+        let program = [0xa2, 0xff, 0xbd, 0x01, 0x02, 0xe8, 0x4c, 0x00, 0x80];
+
+        for &cycle_accurate in &[true, false] {
+            let (mut cpu, mut bus) = setup(&program);
+            cpu.set_cycle_accurate(cycle_accurate);
+
+            let cycles: Vec<u8> = (0..4).map(|_| cpu.tick_instruction(&mut bus).unwrap()).collect();
+            assert_eq!(vec![2, 5, 2, 3], cycles);
+            assert_eq!(12, cpu.get_cycle_count());
+            assert_eq!(0x8000, cpu.get_state().get_pc());
+        }
+    }
+}
@@ -1,5 +1,57 @@
 pub trait Databus {
-    fn read(&self, address: u16) -> u8;
-    fn read_u16(&self, address: u16) -> u16;
+    fn read(&mut self, address: u16) -> u8;
+    fn read_u16(&mut self, address: u16) -> u16;
     fn write(&mut self, address: u16, data: u8);
-}
\ No newline at end of file
+
+    /// Reads without any side effects, e.g. for disassembly and debug views.
+    fn peek(&self, address: u16) -> u8;
+
+    /// Advances everything else connected to the bus by one cpu cycle.
+    fn tick(&mut self) {}
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::Databus;
+
+    /// Flat 64 KiB of RAM for exercising the cpu without a NES around it.
+    pub struct TestBus {
+        pub memory: Vec<u8>,
+        /// Number of reads and writes made through the bus.
+        pub accesses: u32,
+    }
+
+    impl TestBus {
+        pub fn new() -> TestBus {
+            TestBus { memory: vec![0; 0x10000], accesses: 0 }
+        }
+
+        pub fn load(&mut self, address: u16, data: &[u8]) {
+            let start = address as usize;
+            self.memory[start..start + data.len()].copy_from_slice(data);
+        }
+    }
+
+    impl Databus for TestBus {
+        fn read(&mut self, address: u16) -> u8 {
+            self.accesses += 1;
+            self.memory[address as usize]
+        }
+
+        fn read_u16(&mut self, address: u16) -> u16 {
+            let lo = self.read(address);
+            let hi = self.read(address.wrapping_add(1));
+
+            ((hi as u16) << 8) + lo as u16
+        }
+
+        fn write(&mut self, address: u16, data: u8) {
+            self.accesses += 1;
+            self.memory[address as usize] = data;
+        }
+
+        fn peek(&self, address: u16) -> u8 {
+            self.memory[address as usize]
+        }
+    }
+}
@@ -4,6 +4,13 @@ use super::state;
 use super::addressing::AddressingMode;
 use super::cpu;
 use crate::cpu::state::{Status, SR_MASK_BREAK, SR_MASK_B_FLAG};
+use crate::nes::savestate::{StateWriter, StateReader};
+
+// How an instruction is stored in a save state, decoded ones are looked up again from the opcode byte
+const SAVED_DECODED: u8 = 0;
+const SAVED_DUMMY: u8 = 1;
+const SAVED_IRQ: u8 = 2;
+const SAVED_NMI: u8 = 3;
 
 #[allow(non_camel_case_types)]
 #[derive(Clone, Copy, PartialEq)]
@@ -80,6 +87,30 @@ enum Operation {
     TXS,
     TYA,
 
+    // Undocumented
+    ALR,
+    ANC,
+    ARR,
+    AXS,
+    DCP,
+    ISC,
+    LAS,
+    LAX_IMM,
+    LAX_MEM,
+    NOP_IMM,
+    NOP_MEM,
+    RLA,
+    RRA,
+    SAX,
+    SHA,
+    SHX,
+    SHY,
+    SLO,
+    SRE,
+    TAS,
+    XAA,
+    JAM,
+
     UNKNOWN,
     INTERNAL_IRQ,
     INTERNAL_NMI,
@@ -160,10 +191,50 @@ impl Operation {
             Operation::TXS => "TXS",
             Operation::TYA => "TYA",
 
+            Operation::ALR => "ALR",
+            Operation::ANC => "ANC",
+            Operation::ARR => "ARR",
+            Operation::AXS => "AXS",
+            Operation::DCP => "DCP",
+            Operation::ISC => "ISC",
+            Operation::LAS => "LAS",
+            Operation::LAX_IMM => "LAX",
+            Operation::LAX_MEM => "LAX",
+            Operation::NOP_IMM => "NOP",
+            Operation::NOP_MEM => "NOP",
+            Operation::RLA => "RLA",
+            Operation::RRA => "RRA",
+            Operation::SAX => "SAX",
+            Operation::SHA => "SHA",
+            Operation::SHX => "SHX",
+            Operation::SHY => "SHY",
+            Operation::SLO => "SLO",
+            Operation::SRE => "SRE",
+            Operation::TAS => "TAS",
+            Operation::XAA => "XAA",
+            Operation::JAM => "JAM",
+
             _ => "##"
         }
     }
 
+    fn is_read_modify_write(&self) -> bool {
+        matches!(self,
+            Operation::ASL_MEM
+            | Operation::DEC
+            | Operation::INC
+            | Operation::LSR_MEM
+            | Operation::ROL_MEM
+            | Operation::ROR_MEM
+            | Operation::DCP
+            | Operation::ISC
+            | Operation::RLA
+            | Operation::RRA
+            | Operation::SLO
+            | Operation::SRE
+        )
+    }
+
     fn get_fn(&self) -> OperationFn {
         match *self {
             Operation::ADC_IMM => ADC_IMM,
@@ -238,7 +309,31 @@ impl Operation {
             Operation::TXS => TXS,
             Operation::TYA => TYA,
 
-            Operation::UNKNOWN => NOT_IMPLEMENTED,
+            Operation::ALR => ALR,
+            Operation::ANC => ANC,
+            Operation::ARR => ARR,
+            Operation::AXS => AXS,
+            Operation::DCP => DCP,
+            Operation::ISC => ISC,
+            Operation::LAS => LAS,
+            Operation::LAX_IMM => LAX_IMM,
+            Operation::LAX_MEM => LAX_MEM,
+            Operation::NOP_IMM => NOP_IMM,
+            Operation::NOP_MEM => NOP_MEM,
+            Operation::RLA => RLA,
+            Operation::RRA => RRA,
+            Operation::SAX => SAX,
+            Operation::SHA => SHA,
+            Operation::SHX => SHX,
+            Operation::SHY => SHY,
+            Operation::SLO => SLO,
+            Operation::SRE => SRE,
+            Operation::TAS => TAS,
+            Operation::XAA => XAA,
+
+            // What happens on an illegal opcode is decided by the cpu, see IllegalOpcodePolicy
+            Operation::JAM => NOP,
+            Operation::UNKNOWN => NOP,
             Operation::INTERNAL_IRQ => INTERNAL_IRQ_FN,
             Operation::INTERNAL_NMI => INTERNAL_NMI_FN,
         }
@@ -247,10 +342,6 @@ impl Operation {
 
 type OperationFn = fn(state: &mut State, bus: &mut dyn Databus, operand: u16);
 
-const NOT_IMPLEMENTED: OperationFn = |_state: &mut State, _bus: &mut dyn Databus, _operand: u16| {
-    println!("Not Implemented!");
-};
-
 const ADC_IMM: OperationFn = |state: &mut State, _bus: &mut dyn Databus, operand: u16| {
     _adc(state, operand as u8);
 };
@@ -690,6 +781,156 @@ const TYA: OperationFn = |state: &mut State, _bus: &mut dyn Databus, _operand: u
     state.set_status_field(state::SR_MASK_ZERO, state.acc == 0);
 };
 
+// Undocumented operations
+
+// The "magic" constant of the unstable XAA and LAX #imm opcodes differs between chips.
+// $FF makes them behave like the stable AND/LDA combinations most NES software expects.
+const UNSTABLE_MAGIC: u8 = 0xFF;
+
+const ALR: OperationFn = |state: &mut State, _bus: &mut dyn Databus, operand: u16| {
+    let value = state.acc & operand as u8;
+    state.acc = _lsr(state, value);
+};
+
+const ANC: OperationFn = |state: &mut State, _bus: &mut dyn Databus, operand: u16| {
+    state.acc &= operand as u8;
+
+    state.set_status_field(state::SR_MASK_NEGATIVE, state.acc >= 128);
+    state.set_status_field(state::SR_MASK_ZERO, state.acc == 0);
+    state.set_status_field(state::SR_MASK_CARRY, state.acc >= 128);
+};
+
+const ARR: OperationFn = |state: &mut State, _bus: &mut dyn Databus, operand: u16| {
+    let carry = if state.get_status_field(state::SR_MASK_CARRY) { 0x80 } else { 0 };
+    state.acc = ((state.acc & operand as u8) >> 1) | carry;
+
+    let bit_6 = (state.acc & 0x40) > 0;
+    let bit_5 = (state.acc & 0x20) > 0;
+
+    state.set_status_field(state::SR_MASK_NEGATIVE, state.acc >= 128);
+    state.set_status_field(state::SR_MASK_ZERO, state.acc == 0);
+    state.set_status_field(state::SR_MASK_CARRY, bit_6);
+    state.set_status_field(state::SR_MASK_OVERFLOW, bit_6 ^ bit_5);
+};
+
+const AXS: OperationFn = |state: &mut State, _bus: &mut dyn Databus, operand: u16| {
+    let value = state.acc & state.x;
+    _compare(state, operand as u8, value);
+    state.x = value.wrapping_sub(operand as u8);
+};
+
+const DCP: OperationFn = |state: &mut State, bus: &mut dyn Databus, operand: u16| {
+    let value = bus.read(operand).wrapping_sub(1);
+    bus.write(operand, value);
+
+    _compare(state, value, state.acc);
+};
+
+const ISC: OperationFn = |state: &mut State, bus: &mut dyn Databus, operand: u16| {
+    let value = bus.read(operand).wrapping_add(1);
+    bus.write(operand, value);
+
+    _sbc(state, value);
+};
+
+const LAS: OperationFn = |state: &mut State, bus: &mut dyn Databus, operand: u16| {
+    let value = bus.read(operand) & state.stack_pointer;
+    state.acc = value;
+    state.x = value;
+    state.stack_pointer = value;
+
+    state.set_status_field(state::SR_MASK_NEGATIVE, value >= 128);
+    state.set_status_field(state::SR_MASK_ZERO, value == 0);
+};
+
+const LAX_IMM: OperationFn = |state: &mut State, _bus: &mut dyn Databus, operand: u16| {
+    state.acc = (state.acc | UNSTABLE_MAGIC) & operand as u8;
+    state.x = state.acc;
+
+    state.set_status_field(state::SR_MASK_NEGATIVE, state.acc >= 128);
+    state.set_status_field(state::SR_MASK_ZERO, state.acc == 0);
+};
+
+const LAX_MEM: OperationFn = |state: &mut State, bus: &mut dyn Databus, operand: u16| {
+    state.acc = bus.read(operand);
+    state.x = state.acc;
+
+    state.set_status_field(state::SR_MASK_NEGATIVE, state.acc >= 128);
+    state.set_status_field(state::SR_MASK_ZERO, state.acc == 0);
+};
+
+const NOP_IMM: OperationFn = |_state: &mut State, _bus: &mut dyn Databus, _operand: u16| {};
+
+const NOP_MEM: OperationFn = |_state: &mut State, bus: &mut dyn Databus, operand: u16| {
+    // The value is discarded but the read still happens on the bus
+    bus.read(operand);
+};
+
+const RLA: OperationFn = |state: &mut State, bus: &mut dyn Databus, operand: u16| {
+    let value = _rol(state, bus.read(operand));
+    bus.write(operand, value);
+
+    state.acc &= value;
+
+    state.set_status_field(state::SR_MASK_NEGATIVE, state.acc >= 128);
+    state.set_status_field(state::SR_MASK_ZERO, state.acc == 0);
+};
+
+const RRA: OperationFn = |state: &mut State, bus: &mut dyn Databus, operand: u16| {
+    let value = _ror(state, bus.read(operand));
+    bus.write(operand, value);
+
+    _adc(state, value);
+};
+
+const SAX: OperationFn = |state: &mut State, bus: &mut dyn Databus, operand: u16| {
+    bus.write(operand, state.acc & state.x);
+};
+
+const SHA: OperationFn = |state: &mut State, bus: &mut dyn Databus, operand: u16| {
+    _store_and_high_byte(bus, operand, state.y, state.acc & state.x);
+};
+
+const SHX: OperationFn = |state: &mut State, bus: &mut dyn Databus, operand: u16| {
+    _store_and_high_byte(bus, operand, state.y, state.x);
+};
+
+const SHY: OperationFn = |state: &mut State, bus: &mut dyn Databus, operand: u16| {
+    _store_and_high_byte(bus, operand, state.x, state.y);
+};
+
+const SLO: OperationFn = |state: &mut State, bus: &mut dyn Databus, operand: u16| {
+    let value = _asl(state, bus.read(operand));
+    bus.write(operand, value);
+
+    state.acc |= value;
+
+    state.set_status_field(state::SR_MASK_NEGATIVE, state.acc >= 128);
+    state.set_status_field(state::SR_MASK_ZERO, state.acc == 0);
+};
+
+const SRE: OperationFn = |state: &mut State, bus: &mut dyn Databus, operand: u16| {
+    let value = _lsr(state, bus.read(operand));
+    bus.write(operand, value);
+
+    state.acc ^= value;
+
+    state.set_status_field(state::SR_MASK_NEGATIVE, state.acc >= 128);
+    state.set_status_field(state::SR_MASK_ZERO, state.acc == 0);
+};
+
+const TAS: OperationFn = |state: &mut State, bus: &mut dyn Databus, operand: u16| {
+    state.stack_pointer = state.acc & state.x;
+    _store_and_high_byte(bus, operand, state.y, state.stack_pointer);
+};
+
+const XAA: OperationFn = |state: &mut State, _bus: &mut dyn Databus, operand: u16| {
+    state.acc = (state.acc | UNSTABLE_MAGIC) & state.x & operand as u8;
+
+    state.set_status_field(state::SR_MASK_NEGATIVE, state.acc >= 128);
+    state.set_status_field(state::SR_MASK_ZERO, state.acc == 0);
+};
+
 const INTERNAL_IRQ_FN: OperationFn = |state: &mut State, bus: &mut dyn Databus, _operand: u16| {
     _handle_interrupt(state, bus, cpu::IRQ_VECTOR_ADDRESS, false);
 };
@@ -888,6 +1129,112 @@ lazy_static! {
         opcodes[0x9a] = Opcode::new(Operation::TXS, AddressingMode::Implied, 1, 2, false);
         opcodes[0x98] = Opcode::new(Operation::TYA, AddressingMode::Implied, 1, 2, false);
 
+        // Undocumented opcodes
+        opcodes[0x4b] = Opcode::new(Operation::ALR, AddressingMode::Immediate, 2, 2, false);
+
+        opcodes[0x0b] = Opcode::new(Operation::ANC, AddressingMode::Immediate, 2, 2, false);
+        opcodes[0x2b] = Opcode::new(Operation::ANC, AddressingMode::Immediate, 2, 2, false);
+
+        opcodes[0x6b] = Opcode::new(Operation::ARR, AddressingMode::Immediate, 2, 2, false);
+
+        opcodes[0xcb] = Opcode::new(Operation::AXS, AddressingMode::Immediate, 2, 2, false);
+
+        opcodes[0xc7] = Opcode::new(Operation::DCP, AddressingMode::Zeropage, 2, 5, false);
+        opcodes[0xd7] = Opcode::new(Operation::DCP, AddressingMode::ZeropageIndexedX, 2, 6, false);
+        opcodes[0xcf] = Opcode::new(Operation::DCP, AddressingMode::Absolute, 3, 6, false);
+        opcodes[0xdf] = Opcode::new(Operation::DCP, AddressingMode::AbsoluteIndexedX, 3, 7, false);
+        opcodes[0xdb] = Opcode::new(Operation::DCP, AddressingMode::AbsoluteIndexedY, 3, 7, false);
+        opcodes[0xc3] = Opcode::new(Operation::DCP, AddressingMode::IndexedIndirectX, 2, 8, false);
+        opcodes[0xd3] = Opcode::new(Operation::DCP, AddressingMode::IndirectIndexedY, 2, 8, false);
+
+        opcodes[0xe7] = Opcode::new(Operation::ISC, AddressingMode::Zeropage, 2, 5, false);
+        opcodes[0xf7] = Opcode::new(Operation::ISC, AddressingMode::ZeropageIndexedX, 2, 6, false);
+        opcodes[0xef] = Opcode::new(Operation::ISC, AddressingMode::Absolute, 3, 6, false);
+        opcodes[0xff] = Opcode::new(Operation::ISC, AddressingMode::AbsoluteIndexedX, 3, 7, false);
+        opcodes[0xfb] = Opcode::new(Operation::ISC, AddressingMode::AbsoluteIndexedY, 3, 7, false);
+        opcodes[0xe3] = Opcode::new(Operation::ISC, AddressingMode::IndexedIndirectX, 2, 8, false);
+        opcodes[0xf3] = Opcode::new(Operation::ISC, AddressingMode::IndirectIndexedY, 2, 8, false);
+
+        opcodes[0xbb] = Opcode::new(Operation::LAS, AddressingMode::AbsoluteIndexedY, 3, 4, true);
+
+        opcodes[0xab] = Opcode::new(Operation::LAX_IMM, AddressingMode::Immediate, 2, 2, false);
+        opcodes[0xa7] = Opcode::new(Operation::LAX_MEM, AddressingMode::Zeropage, 2, 3, false);
+        opcodes[0xb7] = Opcode::new(Operation::LAX_MEM, AddressingMode::ZeropageIndexedY, 2, 4, false);
+        opcodes[0xaf] = Opcode::new(Operation::LAX_MEM, AddressingMode::Absolute, 3, 4, false);
+        opcodes[0xbf] = Opcode::new(Operation::LAX_MEM, AddressingMode::AbsoluteIndexedY, 3, 4, true);
+        opcodes[0xa3] = Opcode::new(Operation::LAX_MEM, AddressingMode::IndexedIndirectX, 2, 6, false);
+        opcodes[0xb3] = Opcode::new(Operation::LAX_MEM, AddressingMode::IndirectIndexedY, 2, 5, true);
+
+        for opcode in [0x1a, 0x3a, 0x5a, 0x7a, 0xda, 0xfa].iter() {
+            opcodes[*opcode] = Opcode::new(Operation::NOP, AddressingMode::Implied, 1, 2, false);
+        }
+        for opcode in [0x80, 0x82, 0x89, 0xc2, 0xe2].iter() {
+            opcodes[*opcode] = Opcode::new(Operation::NOP_IMM, AddressingMode::Immediate, 2, 2, false);
+        }
+        for opcode in [0x04, 0x44, 0x64].iter() {
+            opcodes[*opcode] = Opcode::new(Operation::NOP_MEM, AddressingMode::Zeropage, 2, 3, false);
+        }
+        for opcode in [0x14, 0x34, 0x54, 0x74, 0xd4, 0xf4].iter() {
+            opcodes[*opcode] = Opcode::new(Operation::NOP_MEM, AddressingMode::ZeropageIndexedX, 2, 4, false);
+        }
+        opcodes[0x0c] = Opcode::new(Operation::NOP_MEM, AddressingMode::Absolute, 3, 4, false);
+        for opcode in [0x1c, 0x3c, 0x5c, 0x7c, 0xdc, 0xfc].iter() {
+            opcodes[*opcode] = Opcode::new(Operation::NOP_MEM, AddressingMode::AbsoluteIndexedX, 3, 4, true);
+        }
+
+        opcodes[0x27] = Opcode::new(Operation::RLA, AddressingMode::Zeropage, 2, 5, false);
+        opcodes[0x37] = Opcode::new(Operation::RLA, AddressingMode::ZeropageIndexedX, 2, 6, false);
+        opcodes[0x2f] = Opcode::new(Operation::RLA, AddressingMode::Absolute, 3, 6, false);
+        opcodes[0x3f] = Opcode::new(Operation::RLA, AddressingMode::AbsoluteIndexedX, 3, 7, false);
+        opcodes[0x3b] = Opcode::new(Operation::RLA, AddressingMode::AbsoluteIndexedY, 3, 7, false);
+        opcodes[0x23] = Opcode::new(Operation::RLA, AddressingMode::IndexedIndirectX, 2, 8, false);
+        opcodes[0x33] = Opcode::new(Operation::RLA, AddressingMode::IndirectIndexedY, 2, 8, false);
+
+        opcodes[0x67] = Opcode::new(Operation::RRA, AddressingMode::Zeropage, 2, 5, false);
+        opcodes[0x77] = Opcode::new(Operation::RRA, AddressingMode::ZeropageIndexedX, 2, 6, false);
+        opcodes[0x6f] = Opcode::new(Operation::RRA, AddressingMode::Absolute, 3, 6, false);
+        opcodes[0x7f] = Opcode::new(Operation::RRA, AddressingMode::AbsoluteIndexedX, 3, 7, false);
+        opcodes[0x7b] = Opcode::new(Operation::RRA, AddressingMode::AbsoluteIndexedY, 3, 7, false);
+        opcodes[0x63] = Opcode::new(Operation::RRA, AddressingMode::IndexedIndirectX, 2, 8, false);
+        opcodes[0x73] = Opcode::new(Operation::RRA, AddressingMode::IndirectIndexedY, 2, 8, false);
+
+        opcodes[0x87] = Opcode::new(Operation::SAX, AddressingMode::Zeropage, 2, 3, false);
+        opcodes[0x97] = Opcode::new(Operation::SAX, AddressingMode::ZeropageIndexedY, 2, 4, false);
+        opcodes[0x8f] = Opcode::new(Operation::SAX, AddressingMode::Absolute, 3, 4, false);
+        opcodes[0x83] = Opcode::new(Operation::SAX, AddressingMode::IndexedIndirectX, 2, 6, false);
+
+        opcodes[0xeb] = Opcode::new(Operation::SBC_IMM, AddressingMode::Immediate, 2, 2, false);
+
+        opcodes[0x9f] = Opcode::new(Operation::SHA, AddressingMode::AbsoluteIndexedY, 3, 5, false);
+        opcodes[0x93] = Opcode::new(Operation::SHA, AddressingMode::IndirectIndexedY, 2, 6, false);
+        opcodes[0x9e] = Opcode::new(Operation::SHX, AddressingMode::AbsoluteIndexedY, 3, 5, false);
+        opcodes[0x9c] = Opcode::new(Operation::SHY, AddressingMode::AbsoluteIndexedX, 3, 5, false);
+
+        opcodes[0x07] = Opcode::new(Operation::SLO, AddressingMode::Zeropage, 2, 5, false);
+        opcodes[0x17] = Opcode::new(Operation::SLO, AddressingMode::ZeropageIndexedX, 2, 6, false);
+        opcodes[0x0f] = Opcode::new(Operation::SLO, AddressingMode::Absolute, 3, 6, false);
+        opcodes[0x1f] = Opcode::new(Operation::SLO, AddressingMode::AbsoluteIndexedX, 3, 7, false);
+        opcodes[0x1b] = Opcode::new(Operation::SLO, AddressingMode::AbsoluteIndexedY, 3, 7, false);
+        opcodes[0x03] = Opcode::new(Operation::SLO, AddressingMode::IndexedIndirectX, 2, 8, false);
+        opcodes[0x13] = Opcode::new(Operation::SLO, AddressingMode::IndirectIndexedY, 2, 8, false);
+
+        opcodes[0x47] = Opcode::new(Operation::SRE, AddressingMode::Zeropage, 2, 5, false);
+        opcodes[0x57] = Opcode::new(Operation::SRE, AddressingMode::ZeropageIndexedX, 2, 6, false);
+        opcodes[0x4f] = Opcode::new(Operation::SRE, AddressingMode::Absolute, 3, 6, false);
+        opcodes[0x5f] = Opcode::new(Operation::SRE, AddressingMode::AbsoluteIndexedX, 3, 7, false);
+        opcodes[0x5b] = Opcode::new(Operation::SRE, AddressingMode::AbsoluteIndexedY, 3, 7, false);
+        opcodes[0x43] = Opcode::new(Operation::SRE, AddressingMode::IndexedIndirectX, 2, 8, false);
+        opcodes[0x53] = Opcode::new(Operation::SRE, AddressingMode::IndirectIndexedY, 2, 8, false);
+
+        opcodes[0x9b] = Opcode::new(Operation::TAS, AddressingMode::AbsoluteIndexedY, 3, 5, false);
+
+        opcodes[0x8b] = Opcode::new(Operation::XAA, AddressingMode::Immediate, 2, 2, false);
+
+        // JAM locks up the cpu. The cycle count is only used when the opcode is treated as a NOP
+        for opcode in [0x02, 0x12, 0x22, 0x32, 0x42, 0x52, 0x62, 0x72, 0x92, 0xb2, 0xd2, 0xf2].iter() {
+            opcodes[*opcode] = Opcode::new(Operation::JAM, AddressingMode::Implied, 1, 2, false);
+        }
+
         opcodes
     };
 }
@@ -945,12 +1292,13 @@ impl Opcode {
 #[derive(Clone, Copy)]
 pub struct Instruction {
     opcode: Opcode,
+    opcode_byte: u8,
     operand: u16,
 }
 
 impl Instruction {
-    fn new(opcode: Opcode, operand: u16) -> Instruction {
-        Instruction { opcode, operand }
+    fn new(opcode: Opcode, opcode_byte: u8, operand: u16) -> Instruction {
+        Instruction { opcode, opcode_byte, operand }
     }
 
     pub fn execute(&self, state: &mut State, bus: &mut dyn Databus) {
@@ -959,6 +1307,80 @@ impl Instruction {
         self.opcode.operation.get_fn()(state, bus, evalued_operand);
     }
 
+    // Executes the instruction with every bus access of the real 6502, dummy reads and writes included,
+    // so that a bus which is clocked per access sees them on the correct cycle.
+    // The opcode and operand must already have been fetched with decode_instruction.
+    pub fn execute_cycle_accurate(&self, state: &mut State, bus: &mut dyn Databus) {
+        let operation = self.opcode.operation;
+        let mode = self.opcode.mode;
+
+        if self.is_interrupt() {
+            // The opcode fetch is replaced by two reads of PC
+            bus.read(state.get_pc());
+            bus.read(state.get_pc());
+        } else if self.opcode.size == 1 {
+            // The byte after a single byte opcode is read and thrown away
+            bus.read(state.get_pc().wrapping_add(1));
+        }
+
+        match mode {
+            // The base address is read while the index is being added
+            AddressingMode::ZeropageIndexedX
+            | AddressingMode::ZeropageIndexedY
+            | AddressingMode::IndexedIndirectX => {
+                bus.read(self.operand & 0xff);
+            }
+            _ => {}
+        }
+
+        let address = mode.eval(state, bus, self.operand);
+
+        match mode {
+            AddressingMode::AbsoluteIndexedX
+            | AddressingMode::AbsoluteIndexedY
+            | AddressingMode::IndirectIndexedY => {
+                // The first read happens before the carry is added to the high byte. Reads only pay for
+                // it when a page is crossed (that is the page boundary penalty), writes always do.
+                let index = if mode == AddressingMode::AbsoluteIndexedX { state.x } else { state.y };
+                let base = address.wrapping_sub(index as u16);
+                let unfixed_address = (base & 0xff00) | (address & 0x00ff);
+
+                if unfixed_address != address || !self.opcode.page_boundary_penalty {
+                    bus.read(unfixed_address);
+                }
+            }
+            _ => {}
+        }
+
+        match operation {
+            Operation::JSR | Operation::PLA | Operation::PLP | Operation::RTI | Operation::RTS => {
+                bus.read(cpu::STACK_OFFSET + state.stack_pointer as u16);
+            }
+            _ => {}
+        }
+
+        if self.opcode.is_branch() && self.opcode.will_branch(state) {
+            let next_pc = state.get_next_pc();
+            bus.read(next_pc);
+
+            if (next_pc & 0xff00) != (address & 0xff00) {
+                bus.read((next_pc & 0xff00) | (address & 0x00ff));
+            }
+        }
+
+        if operation.is_read_modify_write() {
+            let mut rmw_bus = ReadModifyWriteDatabus { bus, last_read: 0 };
+            operation.get_fn()(state, &mut rmw_bus, address);
+        } else {
+            operation.get_fn()(state, bus, address);
+        }
+
+        if operation == Operation::RTS {
+            // Reads the pulled address while incrementing it
+            bus.read(state.get_next_pc().wrapping_sub(1));
+        }
+    }
+
     pub fn calculate_cycle_cost(&self, state: &State, bus: &dyn Databus) -> u8 {
         let mut cost = self.opcode.cycles;
 
@@ -985,24 +1407,115 @@ impl Instruction {
         self.opcode.size
     }
 
+    pub fn get_opcode_byte(&self) -> u8 {
+        self.opcode_byte
+    }
+
+    pub fn is_interrupt(&self) -> bool {
+        self.is_irq() || self.is_nmi()
+    }
+
+    pub fn is_irq(&self) -> bool {
+        self.opcode.operation == Operation::INTERNAL_IRQ
+    }
+
+    pub fn is_nmi(&self) -> bool {
+        self.opcode.operation == Operation::INTERNAL_NMI
+    }
+
+    pub fn is_illegal(&self) -> bool {
+        self.opcode.operation == Operation::JAM || self.opcode.operation == Operation::UNKNOWN
+    }
+
     pub fn format(&self) -> String {
         format!("{} {}", self.opcode.operation.as_str(), self.opcode.mode.format(self.operand))
     }
+
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        let kind = match self.opcode.operation {
+            Operation::INTERNAL_IRQ => SAVED_IRQ,
+            Operation::INTERNAL_NMI => SAVED_NMI,
+            _ if self.opcode.size == 0 => SAVED_DUMMY,
+            _ => SAVED_DECODED,
+        };
+
+        writer.write_u8(kind);
+        writer.write_u8(self.opcode_byte);
+        writer.write_u16(self.operand);
+    }
+
+    pub fn load_state(reader: &mut StateReader) -> Result<Instruction, String> {
+        let kind = reader.read_u8()?;
+        let opcode_byte = reader.read_u8()?;
+        let operand = reader.read_u16()?;
+
+        match kind {
+            SAVED_DECODED => Ok(Instruction::new(OPCODE_SET[opcode_byte as usize], opcode_byte, operand)),
+            SAVED_DUMMY => Ok(DUMMY_INSTRUCTION),
+            SAVED_IRQ => Ok(IRQ_INSTRUCTION),
+            SAVED_NMI => Ok(NMI_INSTRUCTION),
+            _ => Err(format!("Invalid instruction in save state: {}", kind)),
+        }
+    }
 }
 
 
-pub fn decode_instruction(bus: &dyn Databus, address: u16) -> Instruction {
-    let opcode = OPCODE_SET[bus.read(address) as usize];
+// Fetches the instruction at address through the bus, one read per byte
+pub fn decode_instruction(bus: &mut dyn Databus, address: u16) -> Instruction {
+    _decode(address, |address| bus.read(address))
+}
 
-    let operand;
-    match opcode.size {
-        1 => operand = 0,
-        2 => operand = bus.read(address + 1) as u16,
-        3 => operand = bus.read_u16(address + 1),
+// Decodes the instruction at address without causing any side effects on the bus
+pub fn peek_instruction(bus: &dyn Databus, address: u16) -> Instruction {
+    _decode(address, |address| bus.peek(address))
+}
+
+fn _decode<F: FnMut(u16) -> u8>(address: u16, mut read: F) -> Instruction {
+    let opcode_byte = read(address);
+    let opcode = OPCODE_SET[opcode_byte as usize];
+
+    let operand = match opcode.size {
+        1 => 0,
+        2 => read(address.wrapping_add(1)) as u16,
+        3 => {
+            let lo = read(address.wrapping_add(1)) as u16;
+            let hi = read(address.wrapping_add(2)) as u16;
+            (hi << 8) + lo
+        }
         _ => unreachable!()
+    };
+
+    Instruction::new(opcode, opcode_byte, operand)
+}
+
+// Read-modify-write instructions write the unmodified value back before writing the result
+struct ReadModifyWriteDatabus<'a> {
+    bus: &'a mut dyn Databus,
+    last_read: u8,
+}
+
+impl<'a> Databus for ReadModifyWriteDatabus<'a> {
+    fn read(&mut self, address: u16) -> u8 {
+        self.last_read = self.bus.read(address);
+        self.last_read
     }
 
-    Instruction::new(opcode, operand)
+    fn read_u16(&mut self, address: u16) -> u16 {
+        self.bus.read_u16(address)
+    }
+
+    fn write(&mut self, address: u16, data: u8) {
+        self.bus.write(address, self.last_read);
+        self.bus.write(address, data);
+    }
+
+    fn peek(&self, address: u16) -> u8 {
+        self.bus.peek(address)
+    }
+
+    fn tick(&mut self) {
+        self.bus.tick();
+    }
 }
 
 pub static DUMMY_INSTRUCTION: Instruction = Instruction {
@@ -1013,6 +1526,7 @@ pub static DUMMY_INSTRUCTION: Instruction = Instruction {
         cycles: 0,
         page_boundary_penalty: false,
     },
+    opcode_byte: 0,
     operand: 0,
 };
 
@@ -1024,6 +1538,7 @@ pub static IRQ_INSTRUCTION: Instruction = Instruction {
         cycles: 7,
         page_boundary_penalty: false,
     },
+    opcode_byte: 0,
     operand: 0,
 };
 
@@ -1035,6 +1550,7 @@ pub static NMI_INSTRUCTION: Instruction = Instruction {
         cycles: 7,
         page_boundary_penalty: false,
     },
+    opcode_byte: 0,
     operand: 0,
 };
 
@@ -1071,6 +1587,62 @@ fn _sbc(state: &mut State, operand: u8) {
     _adc(state, !operand)
 }
 
+fn _asl(state: &mut State, value: u8) -> u8 {
+    let result = value << 1;
+
+    state.set_status_field(state::SR_MASK_CARRY, (value & 0x80) > 0);
+    state.set_status_field(state::SR_MASK_NEGATIVE, result >= 128);
+    state.set_status_field(state::SR_MASK_ZERO, result == 0);
+
+    result
+}
+
+fn _lsr(state: &mut State, value: u8) -> u8 {
+    let result = value >> 1;
+
+    state.set_status_field(state::SR_MASK_CARRY, (value & 1) > 0);
+    state.set_status_field(state::SR_MASK_NEGATIVE, false);
+    state.set_status_field(state::SR_MASK_ZERO, result == 0);
+
+    result
+}
+
+fn _rol(state: &mut State, value: u8) -> u8 {
+    let result = (value << 1) | state.get_status_field(state::SR_MASK_CARRY) as u8;
+
+    state.set_status_field(state::SR_MASK_CARRY, (value & 0x80) > 0);
+    state.set_status_field(state::SR_MASK_NEGATIVE, result >= 128);
+    state.set_status_field(state::SR_MASK_ZERO, result == 0);
+
+    result
+}
+
+fn _ror(state: &mut State, value: u8) -> u8 {
+    let carry = if state.get_status_field(state::SR_MASK_CARRY) { 0x80 } else { 0 };
+    let result = (value >> 1) | carry;
+
+    state.set_status_field(state::SR_MASK_CARRY, (value & 1) > 0);
+    state.set_status_field(state::SR_MASK_NEGATIVE, result >= 128);
+    state.set_status_field(state::SR_MASK_ZERO, result == 0);
+
+    result
+}
+
+// SHA, SHX, SHY and TAS store the value ANDed with the high byte of the base address plus one.
+// When the indexing crosses a page the stored value also replaces the high byte of the target address.
+fn _store_and_high_byte(bus: &mut dyn Databus, address: u16, index: u8, value: u8) {
+    let base = address.wrapping_sub(index as u16);
+    let data = value & ((base >> 8) as u8).wrapping_add(1);
+
+    let target = if (base & 0xff00) != (address & 0xff00) {
+        ((data as u16) << 8) | (address & 0xff)
+    } else {
+        address
+    };
+
+    bus.write(target, data);
+}
+
 fn _compare(state: &mut State, mem: u8, operand: u8) {
     let sum = operand.wrapping_sub(mem);
     state.set_status_field(state::SR_MASK_NEGATIVE, sum >= 128);
@@ -1099,7 +1671,7 @@ fn _push_pc_to_stack(state: &mut State, bus: &mut dyn Databus, pc: u16) {
     _push_stack(state, bus, pc_lo);
 }
 
-fn _pull_pc_from_stack(state: &mut State, bus: &dyn Databus) {
+fn _pull_pc_from_stack(state: &mut State, bus: &mut dyn Databus) {
     let pc_lo = _pull_stack(state, bus);
     let pc_hi = _pull_stack(state, bus);
 
@@ -1112,14 +1684,508 @@ fn _push_stack(state: &mut State, bus: &mut dyn Databus, data: u8) {
     state.dec_sp();
 }
 
-fn _pull_status_from_stack(state: &mut State, bus: &dyn Databus) -> u8 {
+fn _pull_status_from_stack(state: &mut State, bus: &mut dyn Databus) -> u8 {
     _pull_stack(state, bus) & !(state::SR_MASK_B_FLAG | state::SR_MASK_BREAK)
 }
 
-fn _pull_stack(state: &mut State, bus: &dyn Databus) -> u8 {
+fn _pull_stack(state: &mut State, bus: &mut dyn Databus) -> u8 {
     state.inc_sp();
     let data = bus.read(cpu::STACK_OFFSET + state.stack_pointer as u16);
 
     data
 }
 
+#[cfg(test)]
+mod tests {
+    use super::{decode_instruction, Instruction, Operation, OPCODE_SET};
+    use crate::cpu::addressing::AddressingMode;
+    use crate::cpu::databus::tests::TestBus;
+    use crate::cpu::state;
+    use crate::cpu::state::State;
+
+    const PROGRAM_START: u16 = 0x0600;
+
+    fn execute(state: &mut State, bus: &mut TestBus, program: &[u8]) -> Instruction {
+        bus.load(PROGRAM_START, program);
+
+        state.set_next_pc(PROGRAM_START);
+        state.update_pc();
+
+        let instruction = decode_instruction(bus, PROGRAM_START);
+        state.set_next_pc(state.calculate_relative_pc(instruction.get_size() as i8));
+        instruction.execute(state, bus);
+        state.update_pc();
+
+        instruction
+    }
+
+    #[test]
+    fn test_undocumented_opcode_table() {
+        use AddressingMode::*;
+
+        let expected = [
+            // Opcode, addressing mode, size, cycles, page boundary penalty
+            (0x4b, Immediate, 2, 2, false),
+            (0x0b, Immediate, 2, 2, false),
+            (0x2b, Immediate, 2, 2, false),
+            (0x6b, Immediate, 2, 2, false),
+            (0xcb, Immediate, 2, 2, false),
+            (0x8b, Immediate, 2, 2, false),
+            (0xab, Immediate, 2, 2, false),
+            (0xeb, Immediate, 2, 2, false),
+            (0xa7, Zeropage, 2, 3, false),
+            (0xb7, ZeropageIndexedY, 2, 4, false),
+            (0xaf, Absolute, 3, 4, false),
+            (0xbf, AbsoluteIndexedY, 3, 4, true),
+            (0xa3, IndexedIndirectX, 2, 6, false),
+            (0xb3, IndirectIndexedY, 2, 5, true),
+            (0x87, Zeropage, 2, 3, false),
+            (0x97, ZeropageIndexedY, 2, 4, false),
+            (0x8f, Absolute, 3, 4, false),
+            (0x83, IndexedIndirectX, 2, 6, false),
+            (0xbb, AbsoluteIndexedY, 3, 4, true),
+            (0x9f, AbsoluteIndexedY, 3, 5, false),
+            (0x93, IndirectIndexedY, 2, 6, false),
+            (0x9e, AbsoluteIndexedY, 3, 5, false),
+            (0x9c, AbsoluteIndexedX, 3, 5, false),
+            (0x9b, AbsoluteIndexedY, 3, 5, false),
+            (0x0c, Absolute, 3, 4, false),
+        ];
+
+        for (opcode, mode, size, cycles, penalty) in expected.iter() {
+            let entry = OPCODE_SET[*opcode];
+            assert!(entry.mode == *mode, "opcode ${:02X}", opcode);
+            assert_eq!(*size, entry.size, "opcode ${:02X}", opcode);
+            assert_eq!(*cycles, entry.cycles, "opcode ${:02X}", opcode);
+            assert_eq!(*penalty, entry.page_boundary_penalty, "opcode ${:02X}", opcode);
+        }
+
+        // SLO, RLA, SRE, RRA, DCP and ISC share the same layout
+        for base in [0x03, 0x23, 0x43, 0x63, 0xc3, 0xe3].iter() {
+            let rmw = [
+                (0x00, IndexedIndirectX, 2, 8),
+                (0x04, Zeropage, 2, 5),
+                (0x0c, Absolute, 3, 6),
+                (0x10, IndirectIndexedY, 2, 8),
+                (0x14, ZeropageIndexedX, 2, 6),
+                (0x18, AbsoluteIndexedY, 3, 7),
+                (0x1c, AbsoluteIndexedX, 3, 7),
+            ];
+
+            for (offset, mode, size, cycles) in rmw.iter() {
+                let opcode = base + offset;
+                let entry = OPCODE_SET[opcode];
+                assert!(entry.mode == *mode, "opcode ${:02X}", opcode);
+                assert_eq!(*size, entry.size, "opcode ${:02X}", opcode);
+                assert_eq!(*cycles, entry.cycles, "opcode ${:02X}", opcode);
+                assert!(!entry.page_boundary_penalty, "opcode ${:02X}", opcode);
+            }
+        }
+
+        let nops = [
+            (0x1a, Implied, 1, 2, false),
+            (0x80, Immediate, 2, 2, false),
+            (0x04, Zeropage, 2, 3, false),
+            (0x14, ZeropageIndexedX, 2, 4, false),
+            (0x0c, Absolute, 3, 4, false),
+            (0x1c, AbsoluteIndexedX, 3, 4, true),
+        ];
+        for (opcode, mode, size, cycles, penalty) in nops.iter() {
+            let entry = OPCODE_SET[*opcode];
+            assert!(entry.mode == *mode, "opcode ${:02X}", opcode);
+            assert_eq!(*size, entry.size, "opcode ${:02X}", opcode);
+            assert_eq!(*cycles, entry.cycles, "opcode ${:02X}", opcode);
+            assert_eq!(*penalty, entry.page_boundary_penalty, "opcode ${:02X}", opcode);
+        }
+    }
+
+    #[test]
+    fn test_only_jam_opcodes_are_illegal() {
+        let jam = [0x02, 0x12, 0x22, 0x32, 0x42, 0x52, 0x62, 0x72, 0x92, 0xb2, 0xd2, 0xf2];
+
+        for opcode in 0..256 {
+            assert!(OPCODE_SET[opcode].operation != Operation::UNKNOWN, "opcode ${:02X}", opcode);
+
+            let illegal = OPCODE_SET[opcode].operation == Operation::JAM;
+            assert_eq!(jam.contains(&opcode), illegal, "opcode ${:02X}", opcode);
+        }
+    }
+
+    #[test]
+    fn test_lax() {
+        let mut state = State::new();
+        let mut bus = TestBus::new();
+
+        bus.memory[0x10] = 0x80;
+        execute(&mut state, &mut bus, &[0xa7, 0x10]);
+        assert_eq!(0x80, state.acc);
+        assert_eq!(0x80, state.x);
+        assert!(state.get_status_field(state::SR_MASK_NEGATIVE));
+        assert!(!state.get_status_field(state::SR_MASK_ZERO));
+        assert_eq!(PROGRAM_START + 2, state.get_pc());
+
+        state.y = 0x01;
+        bus.memory[0x1235] = 0x00;
+        execute(&mut state, &mut bus, &[0xbf, 0x34, 0x12]);
+        assert_eq!(0x00, state.acc);
+        assert_eq!(0x00, state.x);
+        assert!(state.get_status_field(state::SR_MASK_ZERO));
+    }
+
+    #[test]
+    fn test_sax() {
+        let mut state = State::new();
+        let mut bus = TestBus::new();
+
+        state.acc = 0b1100_1100;
+        state.x = 0b1010_1010;
+        execute(&mut state, &mut bus, &[0x8f, 0x00, 0x02]);
+        assert_eq!(0b1000_1000, bus.memory[0x200]);
+
+        state.y = 0x05;
+        execute(&mut state, &mut bus, &[0x97, 0xfe]);
+        assert_eq!(0b1000_1000, bus.memory[0x03]);
+    }
+
+    #[test]
+    fn test_dcp() {
+        let mut state = State::new();
+        let mut bus = TestBus::new();
+
+        state.acc = 0x40;
+        bus.memory[0x20] = 0x41;
+        execute(&mut state, &mut bus, &[0xc7, 0x20]);
+        assert_eq!(0x40, bus.memory[0x20]);
+        assert!(state.get_status_field(state::SR_MASK_ZERO));
+        assert!(state.get_status_field(state::SR_MASK_CARRY));
+
+        execute(&mut state, &mut bus, &[0xc7, 0x20]);
+        assert_eq!(0x3f, bus.memory[0x20]);
+        assert!(!state.get_status_field(state::SR_MASK_ZERO));
+        assert!(state.get_status_field(state::SR_MASK_CARRY));
+    }
+
+    #[test]
+    fn test_isc() {
+        let mut state = State::new();
+        let mut bus = TestBus::new();
+
+        state.acc = 0x10;
+        state.set_status_field(state::SR_MASK_CARRY, true);
+        bus.memory[0x0300] = 0x04;
+        execute(&mut state, &mut bus, &[0xef, 0x00, 0x03]);
+        assert_eq!(0x05, bus.memory[0x0300]);
+        assert_eq!(0x0b, state.acc);
+        assert!(state.get_status_field(state::SR_MASK_CARRY));
+    }
+
+    #[test]
+    fn test_slo() {
+        let mut state = State::new();
+        let mut bus = TestBus::new();
+
+        state.acc = 0x01;
+        bus.memory[0x30] = 0x81;
+        execute(&mut state, &mut bus, &[0x07, 0x30]);
+        assert_eq!(0x02, bus.memory[0x30]);
+        assert_eq!(0x03, state.acc);
+        assert!(state.get_status_field(state::SR_MASK_CARRY));
+    }
+
+    #[test]
+    fn test_rla() {
+        let mut state = State::new();
+        let mut bus = TestBus::new();
+
+        state.acc = 0xff;
+        state.set_status_field(state::SR_MASK_CARRY, true);
+        bus.memory[0x30] = 0x40;
+        execute(&mut state, &mut bus, &[0x27, 0x30]);
+        assert_eq!(0x81, bus.memory[0x30]);
+        assert_eq!(0x81, state.acc);
+        assert!(!state.get_status_field(state::SR_MASK_CARRY));
+        assert!(state.get_status_field(state::SR_MASK_NEGATIVE));
+    }
+
+    #[test]
+    fn test_sre() {
+        let mut state = State::new();
+        let mut bus = TestBus::new();
+
+        state.acc = 0x0f;
+        bus.memory[0x30] = 0x03;
+        execute(&mut state, &mut bus, &[0x47, 0x30]);
+        assert_eq!(0x01, bus.memory[0x30]);
+        assert_eq!(0x0e, state.acc);
+        assert!(state.get_status_field(state::SR_MASK_CARRY));
+    }
+
+    #[test]
+    fn test_rra() {
+        let mut state = State::new();
+        let mut bus = TestBus::new();
+
+        state.acc = 0x10;
+        bus.memory[0x30] = 0x03;
+        execute(&mut state, &mut bus, &[0x67, 0x30]);
+        // ROR leaves carry set which ADC then adds
+        assert_eq!(0x01, bus.memory[0x30]);
+        assert_eq!(0x12, state.acc);
+        assert!(!state.get_status_field(state::SR_MASK_CARRY));
+    }
+
+    #[test]
+    fn test_anc() {
+        let mut state = State::new();
+        let mut bus = TestBus::new();
+
+        state.acc = 0xf0;
+        execute(&mut state, &mut bus, &[0x0b, 0x80]);
+        assert_eq!(0x80, state.acc);
+        assert!(state.get_status_field(state::SR_MASK_CARRY));
+        assert!(state.get_status_field(state::SR_MASK_NEGATIVE));
+
+        execute(&mut state, &mut bus, &[0x2b, 0x00]);
+        assert!(!state.get_status_field(state::SR_MASK_CARRY));
+        assert!(state.get_status_field(state::SR_MASK_ZERO));
+    }
+
+    #[test]
+    fn test_alr() {
+        let mut state = State::new();
+        let mut bus = TestBus::new();
+
+        state.acc = 0xff;
+        execute(&mut state, &mut bus, &[0x4b, 0x03]);
+        assert_eq!(0x01, state.acc);
+        assert!(state.get_status_field(state::SR_MASK_CARRY));
+        assert!(!state.get_status_field(state::SR_MASK_NEGATIVE));
+    }
+
+    #[test]
+    fn test_arr() {
+        let mut state = State::new();
+        let mut bus = TestBus::new();
+
+        state.acc = 0xff;
+        state.set_status_field(state::SR_MASK_CARRY, true);
+        execute(&mut state, &mut bus, &[0x6b, 0xc0]);
+        assert_eq!(0xe0, state.acc);
+        assert!(state.get_status_field(state::SR_MASK_CARRY));
+        assert!(!state.get_status_field(state::SR_MASK_OVERFLOW));
+        assert!(state.get_status_field(state::SR_MASK_NEGATIVE));
+
+        state.acc = 0xff;
+        state.set_status_field(state::SR_MASK_CARRY, false);
+        execute(&mut state, &mut bus, &[0x6b, 0x40]);
+        assert_eq!(0x20, state.acc);
+        assert!(!state.get_status_field(state::SR_MASK_CARRY));
+        assert!(state.get_status_field(state::SR_MASK_OVERFLOW));
+    }
+
+    #[test]
+    fn test_axs() {
+        let mut state = State::new();
+        let mut bus = TestBus::new();
+
+        state.acc = 0x0f;
+        state.x = 0x3c;
+        execute(&mut state, &mut bus, &[0xcb, 0x02]);
+        assert_eq!(0x0a, state.x);
+        assert_eq!(0x0f, state.acc);
+        assert!(state.get_status_field(state::SR_MASK_CARRY));
+
+        execute(&mut state, &mut bus, &[0xcb, 0x0b]);
+        assert_eq!(0xff, state.x);
+        assert!(!state.get_status_field(state::SR_MASK_CARRY));
+        assert!(state.get_status_field(state::SR_MASK_NEGATIVE));
+    }
+
+    #[test]
+    fn test_sbc_undocumented() {
+        let mut state = State::new();
+        let mut bus = TestBus::new();
+
+        state.acc = 0x05;
+        state.set_status_field(state::SR_MASK_CARRY, true);
+        execute(&mut state, &mut bus, &[0xeb, 0x03]);
+        assert_eq!(0x02, state.acc);
+    }
+
+    #[test]
+    fn test_multi_byte_nops() {
+        let mut state = State::new();
+        let mut bus = TestBus::new();
+
+        state.acc = 0x12;
+        state.x = 0x34;
+        let status = state.get_status_ref().get_as_u8();
+
+        for program in [&[0x1a][..], &[0x80, 0xff], &[0x04, 0x10], &[0x14, 0x10], &[0x0c, 0x00, 0x02], &[0x1c, 0x00, 0x02]].iter() {
+            let instruction = execute(&mut state, &mut bus, program);
+            assert_eq!(PROGRAM_START + program.len() as u16, state.get_pc());
+            assert_eq!(program.len() as u8, instruction.get_size());
+            assert_eq!("NOP", instruction.opcode.operation.as_str());
+        }
+
+        assert_eq!(0x12, state.acc);
+        assert_eq!(0x34, state.x);
+        assert_eq!(status, state.get_status_ref().get_as_u8());
+    }
+
+    #[test]
+    fn test_nop_page_penalty() {
+        let mut state = State::new();
+        let mut bus = TestBus::new();
+
+        state.x = 0x01;
+        let instruction = execute(&mut state, &mut bus, &[0x1c, 0xff, 0x02]);
+        assert_eq!(5, instruction.calculate_cycle_cost(&state, &bus));
+
+        let instruction = execute(&mut state, &mut bus, &[0x1c, 0x00, 0x02]);
+        assert_eq!(4, instruction.calculate_cycle_cost(&state, &bus));
+    }
+
+    #[test]
+    fn test_shx_shy() {
+        let mut state = State::new();
+        let mut bus = TestBus::new();
+
+        state.x = 0xff;
+        state.y = 0x01;
+        execute(&mut state, &mut bus, &[0x9e, 0x00, 0x02]);
+        assert_eq!(0x03, bus.memory[0x0201]);
+
+        state.x = 0x01;
+        state.y = 0xff;
+        execute(&mut state, &mut bus, &[0x9c, 0x00, 0x04]);
+        assert_eq!(0x05, bus.memory[0x0401]);
+
+        // Crossing a page replaces the high byte of the target address with the stored value
+        state.x = 0x01;
+        state.y = 0x03;
+        execute(&mut state, &mut bus, &[0x9c, 0xff, 0x04]);
+        assert_eq!(0x01, bus.memory[0x0100]);
+    }
+
+    #[test]
+    fn test_sha_tas() {
+        let mut state = State::new();
+        let mut bus = TestBus::new();
+
+        state.acc = 0xff;
+        state.x = 0xf3;
+        state.y = 0x02;
+        execute(&mut state, &mut bus, &[0x9f, 0x00, 0x07]);
+        assert_eq!(0x00, bus.memory[0x0702]);
+
+        bus.memory[0x40] = 0x00;
+        bus.memory[0x41] = 0x02;
+        execute(&mut state, &mut bus, &[0x93, 0x40]);
+        assert_eq!(0x03, bus.memory[0x0202]);
+
+        execute(&mut state, &mut bus, &[0x9b, 0x00, 0x02]);
+        assert_eq!(0xf3, state.stack_pointer);
+        assert_eq!(0x03, bus.memory[0x0202]);
+    }
+
+    #[test]
+    fn test_las() {
+        let mut state = State::new();
+        let mut bus = TestBus::new();
+
+        state.stack_pointer = 0xf0;
+        state.y = 0x10;
+        bus.memory[0x0310] = 0x9f;
+        execute(&mut state, &mut bus, &[0xbb, 0x00, 0x03]);
+        assert_eq!(0x90, state.acc);
+        assert_eq!(0x90, state.x);
+        assert_eq!(0x90, state.stack_pointer);
+        assert!(state.get_status_field(state::SR_MASK_NEGATIVE));
+    }
+
+    #[test]
+    fn test_xaa_lax_immediate() {
+        let mut state = State::new();
+        let mut bus = TestBus::new();
+
+        state.acc = 0x00;
+        state.x = 0x0f;
+        execute(&mut state, &mut bus, &[0x8b, 0x3c]);
+        assert_eq!(0x0c, state.acc);
+
+        execute(&mut state, &mut bus, &[0xab, 0x81]);
+        assert_eq!(0x81, state.acc);
+        assert_eq!(0x81, state.x);
+        assert!(state.get_status_field(state::SR_MASK_NEGATIVE));
+    }
+
+    #[test]
+    fn test_cycle_accurate_access_count() {
+        // Operand lo $10 makes branches cross into the next page, $80 keeps them on the same page
+        for &operand_lo in &[0x10u8, 0x80] {
+            for &index in &[0x10u8, 0xff] {
+                for &status in &[0x00u8, 0xff] {
+                    for opcode_byte in 0..=0xffu8 {
+                        if OPCODE_SET[opcode_byte as usize].operation == Operation::JAM {
+                            continue;
+                        }
+
+                        let mut bus = TestBus::new();
+                        let pc = 0x06f0;
+                        bus.load(pc, &[opcode_byte, operand_lo, 0x12]);
+                        bus.load(operand_lo as u16, &[0xf0, 0x12]);
+
+                        let mut state = State::new();
+                        state.x = index;
+                        state.y = index;
+                        state.stack_pointer = 0xfd;
+                        state.set_status(state::Status::from_u8(status));
+                        state.set_next_pc(pc);
+                        state.update_pc();
+
+                        let instruction = decode_instruction(&mut bus, pc);
+                        state.set_next_pc(state.calculate_relative_pc(instruction.get_size() as i8));
+                        let cost = instruction.calculate_cycle_cost(&state, &bus);
+
+                        instruction.execute_cycle_accurate(&mut state, &mut bus);
+
+                        assert_eq!(cost as u32, bus.accesses, "opcode ${:02X} {}", opcode_byte, instruction.format());
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_cycle_accurate_interrupt_access_count() {
+        for instruction in &[super::IRQ_INSTRUCTION, super::NMI_INSTRUCTION] {
+            let mut bus = TestBus::new();
+            let mut state = State::new();
+            state.stack_pointer = 0xfd;
+
+            instruction.execute_cycle_accurate(&mut state, &mut bus);
+
+            assert_eq!(7, bus.accesses);
+        }
+    }
+
+    #[test]
+    fn test_cycle_accurate_read_modify_write() {
+        let mut bus = TestBus::new();
+        let mut state = State::new();
+        bus.memory[0x10] = 0x41;
+
+        // INC $10 writes the old value before the incremented one
+        bus.load(PROGRAM_START, &[0xe6, 0x10]);
+        state.set_next_pc(PROGRAM_START);
+        state.update_pc();
+
+        let instruction = decode_instruction(&mut bus, PROGRAM_START);
+        state.set_next_pc(state.calculate_relative_pc(instruction.get_size() as i8));
+        instruction.execute_cycle_accurate(&mut state, &mut bus);
+
+        assert_eq!(0x42, bus.memory[0x10]);
+        assert_eq!(5, bus.accesses);
+    }
+}
@@ -1,3 +1,5 @@
+use crate::nes::savestate::{StateWriter, StateReader};
+
 pub const SR_MASK_NEGATIVE: u8 = 1 << 7;
 pub const SR_MASK_OVERFLOW: u8 = 1 << 6;
 pub const SR_MASK_B_FLAG: u8 = 1 << 5;
@@ -36,6 +38,7 @@ impl Status {
     }
 }
 
+#[derive(Clone)]
 pub struct State {
     pub acc: u8,
     pub x: u8,
@@ -107,6 +110,28 @@ impl State {
     pub fn set_status(&mut self, status: Status) {
         self.status = status;
     }
+
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u8(self.acc);
+        writer.write_u8(self.x);
+        writer.write_u8(self.y);
+        writer.write_u8(self.stack_pointer);
+        writer.write_u16(self.program_counter);
+        writer.write_u16(self.next_pc);
+        writer.write_u8(self.status.get_as_u8());
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<(), String> {
+        self.acc = reader.read_u8()?;
+        self.x = reader.read_u8()?;
+        self.y = reader.read_u8()?;
+        self.stack_pointer = reader.read_u8()?;
+        self.program_counter = reader.read_u16()?;
+        self.next_pc = reader.read_u16()?;
+        self.status = Status::from_u8(reader.read_u8()?);
+
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -0,0 +1,176 @@
+use crate::cpu::state::State;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Operand {
+    Acc,
+    X,
+    Y,
+    StackPointer,
+    Status,
+    Pc,
+    Value(u16),
+}
+
+impl Operand {
+    fn parse(text: &str) -> Result<Operand, String> {
+        let text = text.trim().to_uppercase();
+
+        let operand = match text.as_str() {
+            "A" => Operand::Acc,
+            "X" => Operand::X,
+            "Y" => Operand::Y,
+            "S" | "SP" => Operand::StackPointer,
+            "P" => Operand::Status,
+            "PC" => Operand::Pc,
+            _ => {
+                let value = if let Some(hex) = text.strip_prefix('$') {
+                    u16::from_str_radix(hex, 16)
+                } else if let Some(hex) = text.strip_prefix("0X") {
+                    u16::from_str_radix(hex, 16)
+                } else {
+                    text.parse::<u16>()
+                };
+
+                Operand::Value(value.map_err(|_| format!("Invalid operand '{}'", text))?)
+            }
+        };
+
+        Ok(operand)
+    }
+
+    fn evaluate(&self, state: &State) -> u16 {
+        match *self {
+            Operand::Acc => state.acc as u16,
+            Operand::X => state.x as u16,
+            Operand::Y => state.y as u16,
+            Operand::StackPointer => state.stack_pointer as u16,
+            Operand::Status => state.get_status_ref().get_as_u8() as u16,
+            Operand::Pc => state.get_pc(),
+            Operand::Value(value) => value,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Comparison {
+    Equal,
+    NotEqual,
+    LessOrEqual,
+    GreaterOrEqual,
+    Less,
+    Greater,
+}
+
+// Two character operators first so that "<=" isn't taken for "<"
+const COMPARISONS: [(&str, Comparison); 6] = [
+    ("==", Comparison::Equal),
+    ("!=", Comparison::NotEqual),
+    ("<=", Comparison::LessOrEqual),
+    (">=", Comparison::GreaterOrEqual),
+    ("<", Comparison::Less),
+    (">", Comparison::Greater),
+];
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+struct Term {
+    left: Operand,
+    comparison: Comparison,
+    right: Operand,
+}
+
+impl Term {
+    fn parse(text: &str) -> Result<Term, String> {
+        for (symbol, comparison) in COMPARISONS.iter() {
+            if let Some(index) = text.find(symbol) {
+                return Ok(Term {
+                    left: Operand::parse(&text[..index])?,
+                    comparison: *comparison,
+                    right: Operand::parse(&text[index + symbol.len()..])?,
+                });
+            }
+        }
+
+        Err(format!("Missing comparison in '{}'", text.trim()))
+    }
+
+    fn evaluate(&self, state: &State) -> bool {
+        let left = self.left.evaluate(state);
+        let right = self.right.evaluate(state);
+
+        match self.comparison {
+            Comparison::Equal => left == right,
+            Comparison::NotEqual => left != right,
+            Comparison::LessOrEqual => left <= right,
+            Comparison::GreaterOrEqual => left >= right,
+            Comparison::Less => left < right,
+            Comparison::Greater => left > right,
+        }
+    }
+}
+
+/// A condition on the cpu registers, e.g. `A == $40 && X > 3`.
+///
+/// Registers are A, X, Y, S (or SP), P and PC. Values are decimal or hex with a `$` or `0x` prefix.
+/// Comparisons are joined with `&&` and `||`, where `&&` binds tighter. Parentheses are not supported.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Condition {
+    // The condition holds when all terms of any group hold
+    groups: Vec<Vec<Term>>,
+    source: String,
+}
+
+impl Condition {
+    pub fn parse(source: &str) -> Result<Condition, String> {
+        let mut groups = Vec::new();
+
+        for group in source.split("||") {
+            let terms = group.split("&&")
+                .map(Term::parse)
+                .collect::<Result<Vec<Term>, String>>()?;
+
+            groups.push(terms);
+        }
+
+        Ok(Condition { groups, source: source.trim().to_string() })
+    }
+
+    pub fn evaluate(&self, state: &State) -> bool {
+        self.groups.iter().any(|terms| terms.iter().all(|term| term.evaluate(state)))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.source
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Condition;
+    use crate::cpu::state::State;
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(Condition::parse("").is_err());
+        assert!(Condition::parse("A").is_err());
+        assert!(Condition::parse("A == Q").is_err());
+        assert!(Condition::parse("A == $40 &&").is_err());
+        assert!(Condition::parse("X > $10000").is_err());
+    }
+
+    #[test]
+    fn test_evaluate() {
+        let mut state = State::new();
+        state.acc = 0x40;
+        state.x = 4;
+
+        assert!(Condition::parse("A == $40 && X > 3").unwrap().evaluate(&state));
+        assert!(Condition::parse("a==0x40&&x>=4").unwrap().evaluate(&state));
+        assert!(!Condition::parse("A == $40 && X < 3").unwrap().evaluate(&state));
+        assert!(Condition::parse("A != 64 || X <= 4").unwrap().evaluate(&state));
+        assert!(!Condition::parse("Y > 0 || A == X").unwrap().evaluate(&state));
+
+        state.set_next_pc(0x8000);
+        state.update_pc();
+        assert!(Condition::parse("PC == $8000 && $3 < X").unwrap().evaluate(&state));
+    }
+}
@@ -0,0 +1,397 @@
+use crate::cpu::instruction::Instruction;
+use crate::cpu::state::State;
+use super::condition::Condition;
+
+pub const ACCESS_READ: u8 = 1 << 0;
+pub const ACCESS_WRITE: u8 = 1 << 1;
+pub const ACCESS_EXECUTE: u8 = 1 << 2;
+
+const PPU_REGISTER_START: u16 = 0x2000;
+const PPU_REGISTER_END: u16 = 0x3FFF;
+const PPU_REGISTER_COUNT: u16 = 8;
+
+/// A read or write the cpu made on the bus.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct BusAccess {
+    pub address: u16,
+    pub data: u8,
+    pub write: bool,
+}
+
+impl BusAccess {
+    fn matches(&self, access: u8) -> bool {
+        if self.write {
+            access & ACCESS_WRITE > 0
+        } else {
+            access & ACCESS_READ > 0
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum BreakKind {
+    /// Breaks before the instruction at the address is executed.
+    Pc(u16),
+    /// Breaks on accesses to the addresses from start to end (inclusive). Access is a mask of the
+    /// ACCESS_ constants, an execute access is an instruction starting in the range. Reads include
+    /// the opcode fetches and dummy reads of a cycle accurate cpu.
+    Watch { start: u16, end: u16, access: u8 },
+    /// Breaks on accesses to a PPU register (0-7) or any of its mirrors.
+    PpuRegister { register: u8, access: u8 },
+    /// Breaks after the cpu has entered the NMI handler.
+    Nmi,
+    /// Breaks after the cpu has entered the IRQ handler.
+    Irq,
+    /// Breaks before every instruction. Together with a condition it breaks as soon as the condition holds.
+    Instruction,
+}
+
+#[derive(Clone, Debug)]
+pub struct Breakpoint {
+    id: usize,
+    kind: BreakKind,
+    condition: Option<Condition>,
+    enabled: bool,
+    hit_count: u32,
+}
+
+impl Breakpoint {
+    pub fn get_id(&self) -> usize { self.id }
+    pub fn is_enabled(&self) -> bool { self.enabled }
+    pub fn get_hit_count(&self) -> u32 { self.hit_count }
+
+    pub fn format(&self) -> String {
+        let kind = match self.kind {
+            BreakKind::Pc(address) => format!("PC ${:04X}", address),
+            BreakKind::Watch { start, end, access } => {
+                format!("{} ${:04X}-${:04X}", _format_access(access), start, end)
+            }
+            BreakKind::PpuRegister { register, access } => {
+                format!("{} PPU ${:04X}", _format_access(access), PPU_REGISTER_START + register as u16)
+            }
+            BreakKind::Nmi => "NMI".to_string(),
+            BreakKind::Irq => "IRQ".to_string(),
+            BreakKind::Instruction => "ANY".to_string(),
+        };
+
+        match &self.condition {
+            Some(condition) => format!("{} IF {}", kind, condition.as_str()),
+            None => kind,
+        }
+    }
+
+    fn _access_hit(&self, accesses: &[BusAccess], access: u8, address_matches: impl Fn(u16) -> bool) -> Option<BusAccess> {
+        accesses.iter()
+            .find(|bus_access| bus_access.matches(access) && address_matches(bus_access.address))
+            .copied()
+    }
+
+    // Returns whether the breakpoint hits, along with the bus access that triggered it
+    fn _check(&self, state: &State, executed: &Instruction, accesses: &[BusAccess]) -> Option<Option<BusAccess>> {
+        let pc = state.get_pc();
+
+        let hit = match self.kind {
+            BreakKind::Pc(address) => {
+                if pc == address { Some(None) } else { None }
+            }
+            BreakKind::Watch { start, end, access } => {
+                let in_range = |address: u16| (start..=end).contains(&address);
+
+                if access & ACCESS_EXECUTE > 0 && in_range(pc) {
+                    Some(None)
+                } else {
+                    self._access_hit(accesses, access, in_range).map(Some)
+                }
+            }
+            BreakKind::PpuRegister { register, access } => {
+                let is_register = |address: u16| {
+                    (PPU_REGISTER_START..=PPU_REGISTER_END).contains(&address)
+                        && address % PPU_REGISTER_COUNT == register as u16
+                };
+
+                self._access_hit(accesses, access, is_register).map(Some)
+            }
+            BreakKind::Nmi => {
+                if executed.is_nmi() { Some(None) } else { None }
+            }
+            BreakKind::Irq => {
+                if executed.is_irq() { Some(None) } else { None }
+            }
+            BreakKind::Instruction => Some(None),
+        };
+
+        match &self.condition {
+            Some(condition) if !condition.evaluate(state) => None,
+            _ => hit,
+        }
+    }
+}
+
+fn _format_access(access: u8) -> String {
+    let mut text = String::new();
+    text.push(if access & ACCESS_READ > 0 { 'R' } else { '-' });
+    text.push(if access & ACCESS_WRITE > 0 { 'W' } else { '-' });
+    text.push(if access & ACCESS_EXECUTE > 0 { 'X' } else { '-' });
+    text
+}
+
+/// Why the debugger stopped the emulation.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct BreakHit {
+    /// Id of the breakpoint that hit.
+    pub id: usize,
+    /// PC of the next instruction to execute.
+    pub pc: u16,
+    /// The bus access that triggered a watchpoint.
+    pub access: Option<BusAccess>,
+}
+
+/// Breakpoints and watchpoints, checked by the NES each time the cpu finishes an instruction.
+#[derive(Clone)]
+pub struct Debugger {
+    breakpoints: Vec<Breakpoint>,
+    next_id: usize,
+
+    pending_break: Option<BreakHit>,
+    last_hit: Option<BreakHit>,
+
+    // Cached so the NES doesn't have to look through the breakpoints every cycle
+    active: bool,
+    watching_bus: bool,
+}
+
+impl Default for Debugger {
+    fn default() -> Debugger {
+        Debugger::new()
+    }
+}
+
+impl Debugger {
+    pub fn new() -> Debugger {
+        Debugger {
+            breakpoints: Vec::new(),
+            next_id: 1,
+            pending_break: None,
+            last_hit: None,
+            active: false,
+            watching_bus: false,
+        }
+    }
+
+    // Returns the id of the new breakpoint
+    pub fn add_breakpoint(&mut self, kind: BreakKind, condition: Option<Condition>) -> usize {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        self.breakpoints.push(Breakpoint { id, kind, condition, enabled: true, hit_count: 0 });
+        self._update_flags();
+
+        id
+    }
+
+    pub fn remove_breakpoint(&mut self, id: usize) -> bool {
+        let count = self.breakpoints.len();
+        self.breakpoints.retain(|breakpoint| breakpoint.id != id);
+        self._update_flags();
+
+        self.breakpoints.len() != count
+    }
+
+    pub fn set_enabled(&mut self, id: usize, enabled: bool) -> bool {
+        let found = match self.breakpoints.iter_mut().find(|breakpoint| breakpoint.id == id) {
+            Some(breakpoint) => {
+                breakpoint.enabled = enabled;
+                true
+            }
+            None => false
+        };
+        self._update_flags();
+
+        found
+    }
+
+    // Adds an unconditional breakpoint at the address, or removes it if there is one
+    pub fn toggle_pc_breakpoint(&mut self, address: u16) {
+        let existing = self.breakpoints.iter()
+            .find(|breakpoint| breakpoint.kind == BreakKind::Pc(address) && breakpoint.condition.is_none())
+            .map(|breakpoint| breakpoint.id);
+
+        match existing {
+            Some(id) => { self.remove_breakpoint(id); }
+            None => { self.add_breakpoint(BreakKind::Pc(address), None); }
+        }
+    }
+
+    pub fn get_breakpoints(&self) -> &[Breakpoint] { &self.breakpoints }
+    pub fn get_last_hit(&self) -> Option<BreakHit> { self.last_hit }
+    pub fn is_active(&self) -> bool { self.active }
+    pub fn is_watching_bus(&self) -> bool { self.watching_bus }
+
+    pub fn has_pc_breakpoint(&self, address: u16) -> bool {
+        self.breakpoints.iter().any(|breakpoint| breakpoint.enabled && breakpoint.kind == BreakKind::Pc(address))
+    }
+
+    // Called when the cpu has finished the executed instruction, accesses are the bus accesses it made
+    pub fn check(&mut self, state: &State, executed: &Instruction, accesses: &[BusAccess]) {
+        let mut hit = None;
+
+        for breakpoint in self.breakpoints.iter_mut().filter(|breakpoint| breakpoint.enabled) {
+            if let Some(access) = breakpoint._check(state, executed, accesses) {
+                breakpoint.hit_count += 1;
+
+                if hit.is_none() {
+                    hit = Some(BreakHit { id: breakpoint.id, pc: state.get_pc(), access });
+                }
+            }
+        }
+
+        if hit.is_some() {
+            self.last_hit = hit;
+
+            if self.pending_break.is_none() {
+                self.pending_break = hit;
+            }
+        }
+    }
+
+    // Returns the break the emulation should stop for, if any
+    pub fn take_break(&mut self) -> Option<BreakHit> {
+        self.pending_break.take()
+    }
+
+    fn _update_flags(&mut self) {
+        let breakpoints = &self.breakpoints;
+        let enabled = || breakpoints.iter().filter(|breakpoint| breakpoint.enabled);
+
+        let active = enabled().count() > 0;
+        let watching_bus = enabled().any(|breakpoint| match breakpoint.kind {
+            BreakKind::Watch { access, .. } => access & (ACCESS_READ | ACCESS_WRITE) > 0,
+            BreakKind::PpuRegister { .. } => true,
+            _ => false
+        });
+
+        self.active = active;
+        self.watching_bus = watching_bus;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Debugger, BreakKind, BreakHit, BusAccess, ACCESS_READ, ACCESS_WRITE, ACCESS_EXECUTE};
+    use crate::cpu::instruction::{DUMMY_INSTRUCTION, NMI_INSTRUCTION};
+    use crate::cpu::state::State;
+    use crate::debugger::condition::Condition;
+
+    fn state_at(pc: u16) -> State {
+        let mut state = State::new();
+        state.set_next_pc(pc);
+        state.update_pc();
+        state
+    }
+
+    fn read(address: u16) -> BusAccess {
+        BusAccess { address, data: 0, write: false }
+    }
+
+    fn write(address: u16) -> BusAccess {
+        BusAccess { address, data: 0x40, write: true }
+    }
+
+    #[test]
+    fn test_pc_breakpoint() {
+        let mut debugger = Debugger::new();
+        assert!(!debugger.is_active());
+        let id = debugger.add_breakpoint(BreakKind::Pc(0x8001), None);
+        assert!(debugger.is_active());
+        assert!(!debugger.is_watching_bus());
+
+        debugger.check(&state_at(0x8000), &DUMMY_INSTRUCTION, &[]);
+        assert_eq!(None, debugger.take_break());
+
+        debugger.check(&state_at(0x8001), &DUMMY_INSTRUCTION, &[]);
+        assert_eq!(Some(BreakHit { id, pc: 0x8001, access: None }), debugger.take_break());
+        assert_eq!(None, debugger.take_break());
+        assert_eq!(1, debugger.get_breakpoints()[0].get_hit_count());
+
+        // Disabled breakpoints don't hit, nor count
+        debugger.set_enabled(id, false);
+        assert!(!debugger.is_active());
+        debugger.check(&state_at(0x8001), &DUMMY_INSTRUCTION, &[]);
+        assert_eq!(None, debugger.take_break());
+        assert_eq!(1, debugger.get_breakpoints()[0].get_hit_count());
+
+        debugger.toggle_pc_breakpoint(0x8002);
+        assert!(debugger.has_pc_breakpoint(0x8002));
+        debugger.toggle_pc_breakpoint(0x8002);
+        assert!(!debugger.has_pc_breakpoint(0x8002));
+    }
+
+    #[test]
+    fn test_watch() {
+        let mut debugger = Debugger::new();
+        let id = debugger.add_breakpoint(BreakKind::Watch { start: 0x0300, end: 0x03ff, access: ACCESS_WRITE }, None);
+        assert!(debugger.is_watching_bus());
+
+        debugger.check(&state_at(0x8000), &DUMMY_INSTRUCTION, &[read(0x0300), write(0x0400), write(0x02ff)]);
+        assert_eq!(None, debugger.take_break());
+
+        debugger.check(&state_at(0x8000), &DUMMY_INSTRUCTION, &[read(0x8000), write(0x03ff)]);
+        assert_eq!(Some(BreakHit { id, pc: 0x8000, access: Some(write(0x03ff)) }), debugger.take_break());
+
+        // Execute only, hits on the pc without needing the bus
+        debugger.remove_breakpoint(id);
+        let id = debugger.add_breakpoint(BreakKind::Watch { start: 0x8000, end: 0x80ff, access: ACCESS_EXECUTE }, None);
+        assert!(!debugger.is_watching_bus());
+        debugger.check(&state_at(0x8100), &DUMMY_INSTRUCTION, &[read(0x8000)]);
+        assert_eq!(None, debugger.take_break());
+        debugger.check(&state_at(0x80ff), &DUMMY_INSTRUCTION, &[]);
+        assert_eq!(Some(BreakHit { id, pc: 0x80ff, access: None }), debugger.take_break());
+    }
+
+    #[test]
+    fn test_ppu_register_watch() {
+        let mut debugger = Debugger::new();
+        debugger.add_breakpoint(BreakKind::PpuRegister { register: 2, access: ACCESS_READ }, None);
+        assert!(debugger.is_watching_bus());
+
+        debugger.check(&state_at(0x8000), &DUMMY_INSTRUCTION, &[read(0x2003), write(0x2002), read(0x4002)]);
+        assert_eq!(None, debugger.take_break());
+
+        // Mirrors hit too
+        debugger.check(&state_at(0x8000), &DUMMY_INSTRUCTION, &[read(0x3ffa)]);
+        assert_eq!(Some(read(0x3ffa)), debugger.take_break().unwrap().access);
+    }
+
+    #[test]
+    fn test_condition() {
+        let mut debugger = Debugger::new();
+        let id = debugger.add_breakpoint(BreakKind::Instruction, Some(Condition::parse("A == $40 && X > 3").unwrap()));
+
+        let mut state = state_at(0x8000);
+        state.acc = 0x40;
+        state.x = 3;
+        debugger.check(&state, &DUMMY_INSTRUCTION, &[]);
+        assert_eq!(None, debugger.take_break());
+
+        state.x = 4;
+        debugger.check(&state, &DUMMY_INSTRUCTION, &[]);
+        assert_eq!(Some(id), debugger.take_break().map(|hit| hit.id));
+        assert_eq!("ANY IF A == $40 && X > 3", debugger.get_breakpoints()[0].format());
+    }
+
+    #[test]
+    fn test_interrupt_and_first_hit() {
+        let mut debugger = Debugger::new();
+        let nmi_id = debugger.add_breakpoint(BreakKind::Nmi, None);
+        let any_id = debugger.add_breakpoint(BreakKind::Instruction, None);
+
+        debugger.check(&state_at(0x8100), &DUMMY_INSTRUCTION, &[]);
+        assert_eq!(Some(any_id), debugger.take_break().map(|hit| hit.id));
+
+        // The first breakpoint wins, but all of them count the hit
+        debugger.check(&state_at(0x8100), &NMI_INSTRUCTION, &[]);
+        assert_eq!(Some(nmi_id), debugger.take_break().map(|hit| hit.id));
+        assert_eq!(Some(nmi_id), debugger.get_last_hit().map(|hit| hit.id));
+        assert_eq!(2, debugger.get_breakpoints()[1].get_hit_count());
+    }
+}
@@ -0,0 +1,48 @@
+use sdl2::audio::{AudioQueue, AudioSpecDesired};
+use sdl2::AudioSubsystem;
+
+use crate::audio::output::AudioSink;
+
+// Samples per callback of the device, small enough to keep the latency down
+const DEVICE_BUFFER_SAMPLES: u16 = 1024;
+
+/// Plays the audio through an SDL queue, which the main loop keeps filled to pace the emulation
+pub struct SdlAudioSink {
+    queue: AudioQueue<f32>,
+}
+
+impl SdlAudioSink {
+    pub fn open(audio_subsystem: &AudioSubsystem, sample_rate: u32) -> Result<SdlAudioSink, String> {
+        let desired = AudioSpecDesired {
+            freq: Some(sample_rate as i32),
+            channels: Some(1),
+            samples: Some(DEVICE_BUFFER_SAMPLES),
+        };
+
+        let queue = AudioQueue::<f32>::open_queue(audio_subsystem, None, &desired)?;
+        if queue.spec().freq != sample_rate as i32 {
+            return Err(format!("Audio device opened at {} Hz instead of {} Hz", queue.spec().freq, sample_rate));
+        }
+        queue.resume();
+
+        Ok(SdlAudioSink { queue })
+    }
+}
+
+impl AudioSink for SdlAudioSink {
+    fn get_sample_rate(&self) -> u32 {
+        self.queue.spec().freq as u32
+    }
+
+    fn write(&mut self, samples: &[f32]) -> Result<(), String> {
+        if self.queue.queue(samples) {
+            Ok(())
+        } else {
+            Err(sdl2::get_error())
+        }
+    }
+
+    fn get_queued_samples(&self) -> u32 {
+        self.queue.size() / std::mem::size_of::<f32>() as u32
+    }
+}
@@ -8,18 +8,27 @@ use sdl2::video::Window;
 use sdl2::pixels::Color;
 use std::time::Duration;
 
+use crate::audio::output::AudioOutput;
+use crate::gfx::audio::SdlAudioSink;
 use crate::gfx::ui::window::window;
 use crate::gfx::ui::window::window::CneseWindow;
 use crate::gfx::ui::font::Font;
 
-use crate::nes::nes::NES;
+use crate::nes::nes::{NES, StopReason};
+use crate::nes::battery::BatterySave;
 
 static SCREEN_WIDTH: u32 = 1400;
 static SCREEN_HEIGHT: u32 = 800;
 
-static FRAMERATE: u32 = 60;
-static FRAMETIME_NANO: u64 = 1_000_000_000 / FRAMERATE as u64;
-static TICKS_PER_FRAME: usize = 15000;
+// The number keys select the slot that F5 saves to and F9 loads from
+static STATE_SLOT_KEYS: [Keycode; 10] = [
+    Keycode::Num0, Keycode::Num1, Keycode::Num2, Keycode::Num3, Keycode::Num4,
+    Keycode::Num5, Keycode::Num6, Keycode::Num7, Keycode::Num8, Keycode::Num9,
+];
+
+// While running, the audio queue is kept at about this many milliseconds, which is the pacing instead of the frame time
+// of the region
+static AUDIO_LATENCY_MS: u32 = 50;
 
 static BACKGROUND_COLOR: (u8, u8, u8, u8) = (128, 128, 128, 255);
 static TEXT_COLOR: (u8, u8, u8, u8) = (255, 255, 255, 255);
@@ -40,7 +49,29 @@ fn render(canvas: &mut Canvas<Window>,
     Ok(())
 }
 
-pub fn run(nes: &mut NES) -> Result<(), String> {
+// Quick save states are kept next to the rom, one file per slot
+fn state_slot_path(rom_path: &str, slot: u8) -> String {
+    format!("{}.ss{}", rom_path, slot)
+}
+
+fn quick_save(nes: &NES, rom_path: &str, slot: u8) -> Result<(), String> {
+    let path = state_slot_path(rom_path, slot);
+    std::fs::write(&path, nes.save_state()).map_err(|e| format!("Unable to write {}: {}", path, e))
+}
+
+fn quick_load(nes: &mut NES, rom_path: &str, slot: u8) -> Result<(), String> {
+    let path = state_slot_path(rom_path, slot);
+    let data = std::fs::read(&path).map_err(|e| format!("Unable to read {}: {}", path, e))?;
+    nes.load_state(&data)
+}
+
+fn open_audio(sdl_context: &sdl2::Sdl, nes: &NES, sample_rate: u32) -> Result<AudioOutput, String> {
+    let sink = SdlAudioSink::open(&sdl_context.audio()?, sample_rate)?;
+    Ok(AudioOutput::new(nes.get_apu().get_sample_rate(), Box::new(sink)))
+}
+
+// Without a sample rate, or if the audio device can't be opened, runs silent and paced by a timer
+pub fn run(nes: &mut NES, rom_path: &str, sample_rate: Option<u32>, battery: &mut BatterySave) -> Result<(), String> {
     let (deassembled_instructions, instruction_offset) = nes.deassemble_prg();
 
     println!("inst {:04X}", instruction_offset);
@@ -100,6 +131,11 @@ pub fn run(nes: &mut NES) -> Result<(), String> {
     // ram_window.set_active(true);
     windows.push(&mut ram_window);
 
+    let mut breakpoint_window = window::create_breakpoint_window(&font, &dark_font, 8);
+    breakpoint_window.set_pos(330, 600);
+    breakpoint_window.set_active(true);
+    windows.push(&mut breakpoint_window);
+
     let mut framerate_counter = window::create_framerate_window(&font);
     framerate_counter.set_pos(5, 5);
     framerate_counter.set_active(true);
@@ -116,10 +152,22 @@ pub fn run(nes: &mut NES) -> Result<(), String> {
     windows.push(&mut framebuffer);
 
 
+    let mut audio = match sample_rate.map(|rate| open_audio(&sdl_context, nes, rate)) {
+        Some(Ok(audio)) => Some(audio),
+        Some(Err(e)) => {
+            println!("Audio disabled: {}", e);
+            None
+        }
+        None => None,
+    };
+
     let mut event_pump = sdl_context.event_pump()?;
     let timer = sdl_context.timer()?;
-    let mut framerate = FRAMERATE;
+    // PAL and Dendy run at 50 frames per second
+    let frametime_nano = (1_000_000_000.0 / nes.get_region().get_frame_rate()) as u64;
+    let mut framerate = nes.get_region().get_frame_rate().round() as u32;
     let mut running = false;
+    let mut state_slot = 1;
 
     render(&mut canvas, &mut windows, nes)?;
 
@@ -134,19 +182,52 @@ pub fn run(nes: &mut NES) -> Result<(), String> {
                     running = !running;
                 }
                 Event::KeyDown { keycode: Some(Keycode::Comma), .. } => {
-                    nes.tick();
+                    if let Err(e) = nes.tick() {
+                        println!("{}", e);
+                    }
                     render(&mut canvas, &mut windows, nes)?;
                 }
                 Event::KeyDown { keycode: Some(Keycode::Period), .. } => {
-                    nes.tick_cpu_instruction();
+                    if let Err(e) = nes.tick_cpu_instruction() {
+                        println!("{}", e);
+                    }
                     render(&mut canvas, &mut windows, nes)?;
                 }
                 Event::KeyDown { keycode : Some(Keycode::L), .. } => {
-                    // TODO Should be one line precisely
-                    for _i in 0..115 {
-                        nes.tick();
+                    if let Err(e) = nes.tick_scanline() {
+                        println!("{}", e);
+                    }
+                }
+                Event::KeyDown { keycode : Some(Keycode::V), .. } => {
+                    if let Err(e) = nes.tick_vblank() {
+                        println!("{}", e);
+                    }
+                }
+                Event::KeyDown { keycode : Some(Keycode::F), .. } => {
+                    if let Err(e) = nes.tick_frame() {
+                        println!("{}", e);
                     }
                 }
+                Event::KeyDown { keycode: Some(Keycode::B), .. } => {
+                    let pc = nes.get_cpu().get_state().get_pc();
+                    nes.get_debugger_mut().toggle_pc_breakpoint(pc);
+                }
+                Event::KeyDown { keycode: Some(Keycode::F5), .. } => {
+                    match quick_save(nes, rom_path, state_slot) {
+                        Ok(()) => println!("Saved state to slot {}", state_slot),
+                        Err(e) => println!("{}", e),
+                    }
+                }
+                Event::KeyDown { keycode: Some(Keycode::F9), .. } => {
+                    match quick_load(nes, rom_path, state_slot) {
+                        Ok(()) => println!("Loaded state from slot {}", state_slot),
+                        Err(e) => println!("{}", e),
+                    }
+                }
+                Event::KeyDown { keycode: Some(keycode), .. } if STATE_SLOT_KEYS.contains(&keycode) => {
+                    state_slot = STATE_SLOT_KEYS.iter().position(|key| *key == keycode).unwrap() as u8;
+                    println!("State slot {}", state_slot);
+                }
                 Event::KeyDown { keycode: Some(Keycode::I), repeat: false, .. } => {
                     nes.set_irq_lo();
                 }
@@ -164,23 +245,54 @@ pub fn run(nes: &mut NES) -> Result<(), String> {
         }
 
         if running {
-            for _i in 0..TICKS_PER_FRAME {
-                let frame_ready = nes.tick();
-                // if frame_ready {
-                //     render(&mut canvas, &mut windows, nes)?;
-                // }
+            match nes.tick_frame() {
+                Ok(step) => {
+                    if let StopReason::Break(hit) = step.reason {
+                        println!("Breakpoint {} hit at PC=${:04X}", hit.id, hit.pc);
+                        running = false;
+                    }
+                    if let Err(e) = battery.update(nes) {
+                        println!("{}", e);
+                    }
+                }
+                Err(e) => {
+                    println!("{}", e);
+                    running = false;
+                }
             }
         }
 
         render(&mut canvas, &mut windows, nes)?;
         nes.set_actual_framerate(framerate);
 
-        let sleep_time_nano = FRAMETIME_NANO as i64 - (timer.performance_counter() - time) as i64;
-        if sleep_time_nano < 0 {
-            framerate = 1_000_000_000 / (-sleep_time_nano + FRAMETIME_NANO as i64) as u32;
-        } else {
-            framerate = FRAMERATE;
-            std::thread::sleep(Duration::from_nanos(sleep_time_nano as u64));
+        let samples = nes.take_audio_samples();
+        if let Some(audio) = &mut audio {
+            if let Err(e) = audio.push(&samples) {
+                println!("{}", e);
+            }
+        }
+
+        match &audio {
+            // The device plays at a fixed rate, so waiting for the queue to drain keeps the emulation at full
+            // speed without the queue ever running dry
+            Some(audio) if running => {
+                let latency_samples = audio.get_sample_rate() * AUDIO_LATENCY_MS / 1000;
+                while audio.get_queued_samples() > latency_samples {
+                    std::thread::sleep(Duration::from_millis(1));
+                }
+
+                let elapsed = (timer.performance_counter() - time).max(1);
+                framerate = (timer.performance_frequency() / elapsed) as u32;
+            }
+            _ => {
+                let sleep_time_nano = frametime_nano as i64 - (timer.performance_counter() - time) as i64;
+                if sleep_time_nano < 0 {
+                    framerate = 1_000_000_000 / (-sleep_time_nano + frametime_nano as i64) as u32;
+                } else {
+                    framerate = nes.get_region().get_frame_rate().round() as u32;
+                    std::thread::sleep(Duration::from_nanos(sleep_time_nano as u64));
+                }
+            }
         }
     }
 
@@ -1,4 +1,9 @@
+#[cfg(feature = "gui")]
 pub mod main;
+#[cfg(feature = "gui")]
+pub mod audio;
 pub mod palette;
+#[cfg(feature = "gui")]
 mod ui;
-mod render;
\ No newline at end of file
+#[cfg(feature = "gui")]
+mod render;
@@ -1,6 +1,52 @@
+use crate::ppu::ppu::{PIXEL_COLOR_MASK, PIXEL_EMPHASIS_SHIFT};
+
 pub const NTSC_2C02: [(u8, u8, u8); 64] = [
     (84, 84, 84), (0, 30, 116), (8, 16, 144), (48, 0, 136), (68, 0, 100), (92, 0, 48), (84, 4, 0), (60, 24, 0), (32, 42, 0), (8, 58, 0), (0, 64, 0), (0, 60, 0), (0, 50, 60), (0, 0, 0), (0, 0, 0), (0, 0, 0),
     (152, 150, 152), (8, 76, 196), (48, 50, 236), (92, 30, 228), (136, 20, 176), (160, 20, 100), (152, 34, 32), (120, 60, 0), (84, 90, 0), (40, 114, 0), (8, 124, 0), (0, 118, 40), (0, 102, 120), (0, 0, 0), (0, 0, 0), (0, 0, 0),
     (236, 238, 236), (76, 154, 236), (120, 124, 236), (176, 98, 236), (228, 84, 236), (236, 88, 180), (236, 106, 100), (212, 136, 32), (160, 170, 0), (116, 196, 0), (76, 208, 32), (56, 204, 108), (56, 180, 204), (60, 60, 60), (0, 0, 0), (0, 0, 0),
     (236, 238, 236), (168, 204, 236), (188, 188, 236), (212, 178, 236), (236, 174, 236), (236, 174, 212), (236, 180, 176), (228, 196, 144), (204, 210, 120), (180, 222, 120), (168, 226, 144), (152, 226, 180), (160, 214, 228), (160, 162, 160), (0, 0, 0), (0, 0, 0)
-];
\ No newline at end of file
+];
+// Each emphasis bit darkens the two other color channels
+const EMPHASIS_ATTENUATION: f32 = 0.816_328;
+const EMPHASIS_COMBINATIONS: usize = 8;
+
+// The system palette with the PPUMASK emphasis bits applied, red in bit 0, green in bit 1 and blue in bit 2
+pub fn emphasized_palette(emphasis: u8) -> [(u8, u8, u8); 64] {
+    let mut palette = NTSC_2C02;
+    if emphasis == 0 {
+        return palette;
+    }
+
+    let attenuate = |channel: u8, bit: u8| {
+        if emphasis & !bit > 0 {
+            (channel as f32 * EMPHASIS_ATTENUATION) as u8
+        } else {
+            channel
+        }
+    };
+
+    for color in palette.iter_mut() {
+        let (r, g, b) = *color;
+        *color = (attenuate(r, 0b001), attenuate(g, 0b010), attenuate(b, 0b100));
+    }
+
+    palette
+}
+
+// Converts the color indices of the ppu framebuffer to 24 bit RGB
+pub fn framebuffer_to_rgb(framebuffer: &[u16]) -> Vec<u8> {
+    let mut palettes = [NTSC_2C02; EMPHASIS_COMBINATIONS];
+    for (emphasis, palette) in palettes.iter_mut().enumerate().skip(1) {
+        *palette = emphasized_palette(emphasis as u8);
+    }
+
+    let mut rgb = Vec::with_capacity(framebuffer.len() * 3);
+
+    for val in framebuffer {
+        let emphasis = (*val >> PIXEL_EMPHASIS_SHIFT) as usize % EMPHASIS_COMBINATIONS;
+        let (r, g, b) = palettes[emphasis][(*val & PIXEL_COLOR_MASK) as usize];
+        rgb.extend_from_slice(&[r, g, b]);
+    }
+
+    rgb
+}
@@ -24,6 +24,7 @@ static REGISTER_WINDOW_WIDTH: u32 = 300;
 const PPU_WINDOW_WIDTH: u32 = 300;
 
 static MEMORY_WINDOW_WIDTH: u32 = 440;
+const BREAKPOINT_WINDOW_WIDTH: u32 = 440;
 
 pub struct InstructionWindow<'a> {
     instructions: Vec<Instruction>,
@@ -121,6 +122,13 @@ impl<'a> RenderableWindow for InstructionWindow<'a> {
                                     y + i as i32 * ROW_OFFSET + FRAME_PADDING,
                                     ">",
                 )?;
+            } else if nes.get_debugger().has_pc_breakpoint(memory_addr as u16) {
+                render::render_text(canvas,
+                                    self.secondary_font,
+                                    x + FRAME_PADDING,
+                                    y + i as i32 * ROW_OFFSET + FRAME_PADDING,
+                                    "*",
+                )?;
             }
 
             render::render_text(canvas,
@@ -169,6 +177,14 @@ impl<'a> RenderableWindow for FramerateCounter<'a> {
                                   format!("Instructions: {}", nes.get_cpu().get_instruction_count()).as_str(),
         )?;
 
+        if nes.get_cpu().is_halted() {
+            render::render_text_small(canvas, self.font, x + 360, y, "CPU JAMMED")?;
+        } else if let Some(hit) = nes.get_debugger().get_last_hit() {
+            render::render_text_small(canvas, self.font, x + 360, y,
+                                      format!("Break #{} at ${:04X}", hit.id, hit.pc).as_str(),
+            )?;
+        }
+
         Ok(())
     }
 }
@@ -224,7 +240,7 @@ impl<'a> RenderableWindow for MemoryWindow<'a> {
         while i < self.height && (i + 1) * 16 <= self.data_size {
             let mut row = vec![0 as u8; 16];
             for j in 0..16 {
-                row[j] = bus.read(self.data_start + (i * 16) as u16 + j as u16)
+                row[j] = bus.peek(self.data_start + (i * 16) as u16 + j as u16)
             }
 
             render::render_text_small(canvas,
@@ -253,6 +269,67 @@ impl<'a> RenderableWindow for MemoryWindow<'a> {
     }
 }
 
+pub struct BreakpointWindow<'a> {
+    font: &'a Font<'a>,
+    secondary_font: &'a Font<'a>,
+    height: usize,
+}
+
+impl<'a> BreakpointWindow<'a> {
+    pub fn new(font: &'a Font<'a>,
+               secondary_font: &'a Font<'a>,
+               height: usize) -> BreakpointWindow<'a> {
+        BreakpointWindow { font, secondary_font, height }
+    }
+}
+
+impl<'a> RenderableWindow for BreakpointWindow<'a> {
+    fn render(&mut self,
+              canvas: &mut Canvas<Window>,
+              x: i32,
+              y: i32,
+              nes: &NES) -> Result<(), String> {
+        const TEXT_ID_OFFSET: i32 = 16;
+        const TEXT_BREAKPOINT_OFFSET: i32 = 64;
+
+        let debugger = nes.get_debugger();
+        let last_hit_id = debugger.get_last_hit().map(|hit| hit.id);
+
+        render::window(canvas,
+                       x,
+                       y,
+                       BREAKPOINT_WINDOW_WIDTH,
+                       self.height as u32 * ROW_OFFSET as u32 + (FRAME_PADDING as u32 * 2),
+                       Color::from(FRAME_BORDER_COLOR),
+                       Color::from(FRAME_BACKGROUND_COLOR))?;
+
+        for (i, breakpoint) in debugger.get_breakpoints().iter().take(self.height).enumerate() {
+            let row_y = y + i as i32 * ROW_OFFSET + FRAME_PADDING;
+            let font = if breakpoint.is_enabled() { self.font } else { self.secondary_font };
+
+            if last_hit_id == Some(breakpoint.get_id()) {
+                render::render_text(canvas, self.font, x + FRAME_PADDING, row_y, ">")?;
+            }
+
+            render::render_text(canvas,
+                                self.secondary_font,
+                                x + TEXT_ID_OFFSET + FRAME_PADDING,
+                                row_y,
+                                format!("{:2}", breakpoint.get_id()).as_str(),
+            )?;
+
+            render::render_text(canvas,
+                                font,
+                                x + TEXT_BREAKPOINT_OFFSET + FRAME_PADDING,
+                                row_y,
+                                format!("{} ({})", breakpoint.format(), breakpoint.get_hit_count()).as_str(),
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
 pub struct RegisterWindow<'a> {
     font: &'a Font<'a>,
     secondary_font: &'a Font<'a>,
@@ -379,17 +456,21 @@ impl<'a> RenderableWindow for PpuWindow<'a> {
               nes: &NES) -> Result<(), String> {
 
         let ppuctrl = nes.get_ppu().get_ppuctrl();
-        let ppumask = nes.get_ppu().get_ppuctrl();
+        let ppumask = nes.get_ppu().get_ppumask();
         let ppustatus = nes.get_ppu().get_ppustatus();
         let oamaddr = nes.get_ppu().get_oamaddr();
-        let ppuscroll = nes.get_ppu().get_ppuscroll();
-        let ppuaddr = nes.get_ppu().get_ppuaddr();
+        let v = nes.get_ppu().get_v();
+        let t = nes.get_ppu().get_t();
+        let fine_x = nes.get_ppu().get_fine_x();
+        let write_toggle = nes.get_ppu().get_write_toggle();
+        let scanline = nes.get_ppu().get_scanline();
+        let scanline_cycle = nes.get_ppu().get_scanline_cycle();
 
         render::window(canvas,
                        x,
                        y,
                        PPU_WINDOW_WIDTH,
-                       (FRAME_PADDING * 2 + (ROW_OFFSET * 6)) as u32,
+                       (FRAME_PADDING * 2 + (ROW_OFFSET * 8)) as u32,
                        Color::from(FRAME_BORDER_COLOR),
                        Color::from(FRAME_BACKGROUND_COLOR),
         )?;
@@ -432,6 +513,18 @@ impl<'a> RenderableWindow for PpuWindow<'a> {
                             y + FRAME_PADDING + ROW_OFFSET * 2,
                             format!("          ${:02X}", ppustatus).as_str(),
         )?;
+        // The flags after the value, lit when set
+        let flags = [(nes.get_ppu().is_vblank(), "              V"),
+                     (nes.get_ppu().is_sprite_0_hit(), "               S"),
+                     (nes.get_ppu().is_sprite_overflow(), "                O")];
+        for (set, text) in flags {
+            render::render_text(canvas,
+                                if set { self.font } else { self.secondary_font },
+                                x + FRAME_PADDING,
+                                y + FRAME_PADDING + ROW_OFFSET * 2,
+                                text,
+            )?;
+        }
 
         render::render_text(canvas,
                             self.font,
@@ -450,26 +543,52 @@ impl<'a> RenderableWindow for PpuWindow<'a> {
                             self.font,
                             x + FRAME_PADDING,
                             y + FRAME_PADDING + ROW_OFFSET * 4,
-                            "PPUSCROLL:",
+                            "V:",
         )?;
         render::render_text(canvas,
                             self.secondary_font,
                             x + FRAME_PADDING,
                             y + FRAME_PADDING + ROW_OFFSET * 4,
-                            format!("          ${:04X}", ppuscroll).as_str(),
+                            format!("          ${:04X}", v).as_str(),
         )?;
 
         render::render_text(canvas,
                             self.font,
                             x + FRAME_PADDING,
                             y + FRAME_PADDING + ROW_OFFSET * 5,
-                            "PPUADDR:",
+                            "T:",
         )?;
         render::render_text(canvas,
                             self.secondary_font,
                             x + FRAME_PADDING,
                             y + FRAME_PADDING + ROW_OFFSET * 5,
-                            format!("          ${:04X}", ppuaddr).as_str(),
+                            format!("          ${:04X}", t).as_str(),
+        )?;
+
+        render::render_text(canvas,
+                            self.font,
+                            x + FRAME_PADDING,
+                            y + FRAME_PADDING + ROW_OFFSET * 6,
+                            "X, W:",
+        )?;
+        render::render_text(canvas,
+                            self.secondary_font,
+                            x + FRAME_PADDING,
+                            y + FRAME_PADDING + ROW_OFFSET * 6,
+                            format!("          {}, {}", fine_x, write_toggle as u8).as_str(),
+        )?;
+
+        render::render_text(canvas,
+                            self.font,
+                            x + FRAME_PADDING,
+                            y + FRAME_PADDING + ROW_OFFSET * 7,
+                            "SCANLINE:",
+        )?;
+        render::render_text(canvas,
+                            self.secondary_font,
+                            x + FRAME_PADDING,
+                            y + FRAME_PADDING + ROW_OFFSET * 7,
+                            format!("          {},{}", scanline, scanline_cycle).as_str(),
         )?;
 
         Ok(())
@@ -33,7 +33,7 @@ impl<'a> PatternTableWindow<'a> {
     }
 
     fn _update_texture(&mut self, nes: &NES, pattern_table_index: u8) -> Result<(), String>{
-        let pixel_data = nes.get_ppu().patterntable_to_texture_data(pattern_table_index);
+        let pixel_data = nes.get_ppu().patterntable_to_texture_data(nes.get_cartridge(), pattern_table_index);
         let mut texture_rgb_data = [0 as u8; (128 * 128 * 3) as usize];
 
         for (i, val) in pixel_data.iter().enumerate() {
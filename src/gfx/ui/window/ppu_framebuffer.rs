@@ -8,7 +8,7 @@ use crate::gfx::render;
 use super::window::RenderableWindow;
 
 use crate::ppu::ppu;
-use crate::ppu::ppu::{FRAMEBUFFER_WIDTH, FRAMEBUFFER_HEIGHT};
+use crate::ppu::ppu::FRAMEBUFFER_WIDTH;
 use crate::gfx::palette;
 
 pub struct FramebufferWindow<'a> {
@@ -30,14 +30,7 @@ impl<'a> FramebufferWindow<'a> {
     }
 
     fn _update_texture(&mut self, nes: &NES) -> Result<(), String> {
-        let pixel_data = nes.get_ppu().get_framebuffer();
-        let mut texture_rgb_data = [0 as u8; (FRAMEBUFFER_WIDTH * FRAMEBUFFER_HEIGHT * 3) as usize];
-        for (i, val) in pixel_data.iter().enumerate() {
-            let (r, g, b) = palette::NTSC_2C02[*val as usize];
-            texture_rgb_data[i * 3] = r;
-            texture_rgb_data[i * 3 + 1] = g;
-            texture_rgb_data[i * 3 + 2] = b;
-        }
+        let texture_rgb_data = palette::framebuffer_to_rgb(nes.get_ppu().get_framebuffer());
         self.texture.update(None, &texture_rgb_data, (FRAMEBUFFER_WIDTH * 3) as usize).map_err(|e| e.to_string())?;
 
         Ok(())
@@ -50,7 +43,7 @@ impl<'a> RenderableWindow for FramebufferWindow<'a> {
               x: i32,
               y: i32,
               nes: &NES) -> Result<(), String> {
-        self._update_texture(nes);
+        self._update_texture(nes)?;
         render::textured_window(canvas, x, y, self.width, self.height, &self.texture)?;
 
         Ok(())
@@ -36,6 +36,14 @@ pub fn create_memory_window<'a>(font: &'a Font<'a>,
     CneseWindow::new(Box::new(memory_window))
 }
 
+pub fn create_breakpoint_window<'a>(font: &'a Font<'a>,
+                                    secondary_font: &'a Font<'a>,
+                                    height: usize) -> CneseWindow<'a> {
+    let breakpoint_window = debug::BreakpointWindow::new(font, secondary_font, height);
+
+    CneseWindow::new(Box::new(breakpoint_window))
+}
+
 pub fn create_framerate_window<'a>(font: &'a Font<'a>) -> CneseWindow<'a> {
     let counter = debug::FramerateCounter::new(font);
     CneseWindow::new(Box::new(counter))
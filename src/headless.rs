@@ -0,0 +1,138 @@
+use std::fs;
+
+use crate::audio::output::AudioOutput;
+use crate::audio::wav::WavWriter;
+use crate::cpu::databus::Databus;
+use crate::debugger::debugger::BreakKind;
+use crate::gfx::palette;
+use crate::nes::battery::BatterySave;
+use crate::nes::nes::{NES, StopReason};
+use crate::nes::region::Region;
+use crate::ppu::ppu::{FRAMEBUFFER_WIDTH, FRAMEBUFFER_HEIGHT};
+use crate::util::image;
+
+const RAM_SIZE: u16 = 0x0800;
+const RAM_DUMP_ROW_SIZE: u16 = 16;
+
+const DOTS_PER_SCANLINE: i64 = 341;
+// What the last instruction of a frame may run past it
+const FRAME_OVERRUN_CYCLES: u64 = 10;
+
+/// What to run without a window and what to write when done.
+/// The run ends at whichever limit comes first.
+pub struct HeadlessOptions {
+    pub frames: Option<u64>,
+    pub cycles: Option<u64>,
+    pub until_pc: Option<u16>,
+
+    /// The framebuffer is written as a PNG, or as a PPM if the path ends with .ppm.
+    pub screenshot_path: Option<String>,
+    /// The 2KB internal RAM as a hex dump.
+    pub ram_dump_path: Option<String>,
+    /// Everything the apu played, resampled to sample_rate.
+    pub wav_path: Option<String>,
+    pub sample_rate: u32,
+}
+
+pub fn run(nes: &mut NES, options: &HeadlessOptions, battery: &mut BatterySave) -> Result<(), String> {
+    if options.frames.is_none() && options.cycles.is_none() && options.until_pc.is_none() {
+        return Err("--headless needs --frames, --cycles or --until-pc".to_string());
+    }
+
+    let until_pc_id = options.until_pc
+        .map(|pc| nes.get_debugger_mut().add_breakpoint(BreakKind::Pc(pc), None));
+
+    let mut audio = match &options.wav_path {
+        Some(path) => Some(AudioOutput::new(nes.get_apu().get_sample_rate(), Box::new(WavWriter::create(path, options.sample_rate)?))),
+        None => None,
+    };
+    // Drops what was played before the run
+    nes.take_audio_samples();
+
+    let mut frames = 0;
+    let mut cycles = 0;
+
+    let stop = loop {
+        if options.frames.is_some_and(|limit| frames >= limit) {
+            break format!("{} frames", frames);
+        }
+        if options.cycles.is_some_and(|limit| cycles >= limit) {
+            break format!("{} cycles", cycles);
+        }
+
+        // Steps a frame at a time, unless the cycle limit is closer than the end of the frame
+        let step = match options.cycles {
+            Some(limit) if limit - cycles < get_max_cycles_per_frame(nes.get_region()) => {
+                nes.tick_master_cycles((limit - cycles) * nes.get_region().get_master_cycles_per_cpu_cycle() as u64)
+            }
+            _ => nes.tick_frame()
+        }.map_err(|e| e.to_string())?;
+
+        cycles += step.cycles;
+
+        let samples = nes.take_audio_samples();
+        if let Some(audio) = &mut audio {
+            audio.push(&samples)?;
+        }
+
+        match step.reason {
+            StopReason::FrameDone => {
+                frames += 1;
+                battery.update(nes)?;
+            }
+            StopReason::Break(hit) if Some(hit.id) == until_pc_id => {
+                break format!("reaching PC=${:04X}", hit.pc);
+            }
+            _ => {}
+        }
+    };
+
+    println!("Stopped after {}: {} frames, {} cycles, PC=${:04X}",
+             stop, frames, cycles, nes.get_cpu().get_state().get_pc());
+
+    if let Some(audio) = &mut audio {
+        audio.finish()?;
+    }
+    if let Some(path) = &options.screenshot_path {
+        write_screenshot(nes, path)?;
+    }
+    if let Some(path) = &options.ram_dump_path {
+        write_ram_dump(nes, path)?;
+    }
+
+    Ok(())
+}
+
+// NTSC frames take 29780.5 cpu cycles, PAL ones 33247.5 and Dendy ones 35464
+fn get_max_cycles_per_frame(region: Region) -> u64 {
+    let master_cycles = region.get_scanline_count() as i64 * DOTS_PER_SCANLINE * region.get_master_cycles_per_ppu_dot();
+    (master_cycles / region.get_master_cycles_per_cpu_cycle()) as u64 + FRAME_OVERRUN_CYCLES
+}
+
+pub fn write_screenshot(nes: &NES, path: &str) -> Result<(), String> {
+    let rgb = palette::framebuffer_to_rgb(nes.get_ppu().get_framebuffer());
+
+    if path.ends_with(".ppm") {
+        image::write_ppm(path, FRAMEBUFFER_WIDTH, FRAMEBUFFER_HEIGHT, &rgb)
+    } else {
+        image::write_png(path, FRAMEBUFFER_WIDTH, FRAMEBUFFER_HEIGHT, &rgb)
+    }
+}
+
+pub fn write_ram_dump(nes: &NES, path: &str) -> Result<(), String> {
+    fs::write(path, format_ram_dump(nes.get_databus())).map_err(|e| format!("Unable to write {}: {}", path, e))
+}
+
+fn format_ram_dump(bus: &dyn Databus) -> String {
+    let mut dump = String::new();
+
+    for row in (0..RAM_SIZE).step_by(RAM_DUMP_ROW_SIZE as usize) {
+        dump.push_str(format!("{:04X}:", row).as_str());
+        for address in row..row + RAM_DUMP_ROW_SIZE {
+            dump.push_str(format!(" {:02X}", bus.peek(address)).as_str());
+        }
+        dump.push('\n');
+    }
+
+    dump
+}
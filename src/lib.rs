@@ -0,0 +1,18 @@
+#[macro_use]
+extern crate lazy_static;
+
+pub mod cpu;
+pub mod nes;
+pub mod util;
+pub mod gfx;
+pub mod ppu;
+pub mod apu;
+pub mod audio;
+pub mod debugger;
+pub mod headless;
+
+pub use nes::nes::NES;
+pub use nes::cartridge::cartridge::Cartridge;
+pub use cpu::cpu::Cpu;
+pub use ppu::ppu::Ppu;
+pub use apu::apu::Apu;
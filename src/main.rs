@@ -1,47 +1,249 @@
-#[macro_use]
-extern crate lazy_static;
+use cnese::NES;
+use cnese::nes::ines;
+use cnese::nes::cartridge::cartridge::{self, Cartridge};
+use cnese::nes::loaderror::LoadError;
+use cnese::nes::gamedb::GameDb;
+use cnese::nes::rominfo::RomInfo;
+use cnese::nes::region::Region;
+use cnese::nes::battery::{BatterySave, DEFAULT_SAVE_INTERVAL_SECONDS};
+use cnese::cpu::cpu::IllegalOpcodePolicy;
+use cnese::debugger::condition::Condition;
+use cnese::debugger::debugger::{Debugger, BreakKind, ACCESS_READ, ACCESS_WRITE};
+use cnese::headless::{self, HeadlessOptions};
+use cnese::audio::output::{self, DEFAULT_SAMPLE_RATE};
+use cnese::util;
 
-mod cpu;
-mod nes;
-mod util;
-mod gfx;
-mod ppu;
-
-use nes::nes::NES;
-use nes::ines;
-use nes::cartridge::cartridge;
+use std::time::Duration;
 
 
 fn main() {
     println!("CNESE");
 
     let args: Vec<String> = std::env::args().collect();
-    let path = &args[1];
+    let path = match args.get(1) {
+        Some(path) => path,
+        None => {
+            println!("Usage: cnese ROM [options]");
+            std::process::exit(1);
+        }
+    };
 
-    let mut cartridge = Option::None;
+    let illegal_opcode_policy = match args.iter().position(|arg| arg == "--illegal-opcodes") {
+        Some(i) => match args.get(i + 1).map(|s| s.as_str()) {
+            Some("halt") => IllegalOpcodePolicy::Halt,
+            Some("nop") => IllegalOpcodePolicy::Nop,
+            Some("error") => IllegalOpcodePolicy::Error,
+            _ => {
+                println!("--illegal-opcodes expects one of: halt, nop, error");
+                return;
+            }
+        },
+        None => IllegalOpcodePolicy::Halt,
+    };
 
-    if path.ends_with("bin") {
-        let rom = util::file::read_file(path);
+    // Trades the per-cycle bus accesses for speed
+    let fast_cpu = args.iter().any(|arg| arg == "--fast-cpu");
 
-        cartridge = Option::Some(cartridge::create_cartridge_from_raw(&rom)
-            .map_err(|e| println!("Failed to parse RAW image {}", e))
-            .unwrap());
-    } else if path.ends_with("nes") {
-        cartridge = Option::Some(ines::open_ines(path)
-            .map_err(|e| println!("Failed to parse iNES file {}", e))
-            .unwrap());
+    // --region ntsc, pal or dendy overrides the one in the header
+    let region_override = match args.iter().position(|arg| arg == "--region") {
+        Some(i) => match args.get(i + 1).ok_or("--region expects a value".to_string()).and_then(|name| Region::parse(name)) {
+            Ok(region) => Some(region),
+            Err(e) => {
+                println!("{}", e);
+                return;
+            }
+        },
+        None => None,
+    };
+
+    let sample_rate = match parse_sample_rate(&args) {
+        Ok(rate) => rate,
+        Err(e) => {
+            println!("{}", e);
+            return;
+        }
+    };
+    // --save-interval SECONDS writes the battery backed RAM while running, 0 only on exit
+    let save_interval = match args.iter().position(|arg| arg == "--save-interval") {
+        Some(i) => match args.get(i + 1).and_then(|text| text.parse::<u64>().ok()) {
+            Some(seconds) => seconds,
+            None => {
+                println!("--save-interval expects a number of seconds");
+                return;
+            }
+        },
+        None => DEFAULT_SAVE_INTERVAL_SECONDS,
+    };
+
+    // The gui plays audio unless --no-audio is given
+    let audio_sample_rate = if args.iter().any(|arg| arg == "--no-audio") { None } else { Some(sample_rate) };
+
+    let headless_options = if args.iter().any(|arg| arg == "--headless") {
+        match parse_headless_options(&args) {
+            Ok(options) => Some(options),
+            Err(e) => {
+                println!("{}", e);
+                return;
+            }
+        }
+    } else {
+        None
+    };
+
+    // --game-db FILE adds to the built-in game database
+    let mut game_db = GameDb::embedded();
+    if let Some(i) = args.iter().position(|arg| arg == "--game-db") {
+        if let Err(e) = args.get(i + 1).ok_or("--game-db expects a file".to_string()).and_then(|file| game_db.merge_file(file)) {
+            println!("{}", e);
+            return;
+        }
     }
 
+    let cartridge = if path.ends_with("bin") {
+        Some(open_raw(path).map(|cartridge| (cartridge, RomInfo::default())))
+    } else if path.ends_with("nes") {
+        Some(ines::open_ines(path, &game_db))
+    } else {
+        None
+    };
+
     match cartridge {
         None => {
             println!("No valid cartridge. Exiting..");
             return;
         },
-        Some(c) => {
+        Some(Err(e)) => {
+            println!("Failed to load {}: {}", path, e);
+            std::process::exit(1);
+        },
+        Some(Ok((c, info))) => {
             let mut nes = NES::new(c);
+            nes.set_region(region_override.unwrap_or(info.get_region()));
+            nes.set_illegal_opcode_policy(illegal_opcode_policy);
+            nes.set_cycle_accurate(!fast_cpu);
+
+            if let Err(e) = add_breakpoints(&args, nes.get_debugger_mut()) {
+                println!("{}", e);
+                return;
+            }
+
+            let interval = Some(Duration::from_secs(save_interval)).filter(|interval| !interval.is_zero());
+            let mut battery = BatterySave::new(path, interval);
+            if let Err(e) = battery.load(&mut nes) {
+                println!("{}", e);
+                return;
+            }
+            ines::load_trainer(nes.get_cartridge_mut(), &info);
+
             nes.reset();
-            let _result = gfx::main::run(&mut nes).unwrap();
+
+            let result = match &headless_options {
+                Some(options) => headless::run(&mut nes, options, &mut battery),
+                None => run_gui(&mut nes, path, audio_sample_rate, &mut battery),
+            };
+
+            // Also after an error, the game may have saved before it
+            if let Err(e) = battery.write(&nes) {
+                println!("{}", e);
+            }
+
+            if let Err(e) = result {
+                println!("{}", e);
+                std::process::exit(1);
+            }
         }
     }
 }
 
+fn open_raw(path: &str) -> Result<Cartridge, LoadError> {
+    let rom = util::file::read_file(path).map_err(|e| LoadError::io(path, e))?;
+    cartridge::create_cartridge_from_raw(&rom)
+}
+
+#[cfg(feature = "gui")]
+fn run_gui(nes: &mut NES, rom_path: &str, sample_rate: Option<u32>, battery: &mut BatterySave) -> Result<(), String> {
+    cnese::gfx::main::run(nes, rom_path, sample_rate, battery)
+}
+
+#[cfg(not(feature = "gui"))]
+fn run_gui(_nes: &mut NES, _rom_path: &str, _sample_rate: Option<u32>, _battery: &mut BatterySave) -> Result<(), String> {
+    Err("cnese was built without the gui feature, run it with --headless".to_string())
+}
+
+fn parse_address(text: &str) -> Result<u16, String> {
+    u16::from_str_radix(text.trim_start_matches('$'), 16)
+        .map_err(|_| format!("Invalid address '{}'", text))
+}
+
+// --break ADDR, --break-if CONDITION, --watch START[-END] and --watch-ppu REGISTER can be given any number of times,
+// --break-nmi and --break-irq once
+fn add_breakpoints(args: &[String], debugger: &mut Debugger) -> Result<(), String> {
+    for (i, arg) in args.iter().enumerate() {
+        let value = || args.get(i + 1).ok_or(format!("{} expects a value", arg));
+
+        match arg.as_str() {
+            "--break" => {
+                debugger.add_breakpoint(BreakKind::Pc(parse_address(value()?)?), None);
+            }
+            "--break-if" => {
+                debugger.add_breakpoint(BreakKind::Instruction, Some(Condition::parse(value()?)?));
+            }
+            "--watch" => {
+                let range = value()?;
+                let (start, end) = match range.find('-') {
+                    Some(index) => (parse_address(&range[..index])?, parse_address(&range[index + 1..])?),
+                    None => (parse_address(range)?, parse_address(range)?),
+                };
+
+                debugger.add_breakpoint(BreakKind::Watch { start, end, access: ACCESS_READ | ACCESS_WRITE }, None);
+            }
+            "--watch-ppu" => {
+                let register = (parse_address(value()?)? % 8) as u8;
+                debugger.add_breakpoint(BreakKind::PpuRegister { register, access: ACCESS_READ | ACCESS_WRITE }, None);
+            }
+            "--break-nmi" => {
+                debugger.add_breakpoint(BreakKind::Nmi, None);
+            }
+            "--break-irq" => {
+                debugger.add_breakpoint(BreakKind::Irq, None);
+            }
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+// --frames N, --cycles N, --until-pc ADDR, --screenshot FILE, --dump-ram FILE and --wav FILE
+fn parse_headless_options(args: &[String]) -> Result<HeadlessOptions, String> {
+    let value = |flag: &str| -> Result<Option<&String>, String> {
+        match args.iter().position(|arg| arg == flag) {
+            Some(i) => args.get(i + 1).map(Some).ok_or(format!("{} expects a value", flag)),
+            None => Ok(None),
+        }
+    };
+    let number = |flag: &str| -> Result<Option<u64>, String> {
+        match value(flag)? {
+            Some(text) => text.parse::<u64>().map(Some).map_err(|_| format!("Invalid number '{}' for {}", text, flag)),
+            None => Ok(None),
+        }
+    };
+
+    Ok(HeadlessOptions {
+        frames: number("--frames")?,
+        cycles: number("--cycles")?,
+        until_pc: value("--until-pc")?.map(|text| parse_address(text)).transpose()?,
+        screenshot_path: value("--screenshot")?.cloned(),
+        ram_dump_path: value("--dump-ram")?.cloned(),
+        wav_path: value("--wav")?.cloned(),
+        sample_rate: parse_sample_rate(args)?,
+    })
+}
+
+// --sample-rate 44100 or 48000, for both the audio device and --wav
+fn parse_sample_rate(args: &[String]) -> Result<u32, String> {
+    match args.iter().position(|arg| arg == "--sample-rate") {
+        Some(i) => output::parse_sample_rate(args.get(i + 1).ok_or("--sample-rate expects a value")?),
+        None => Ok(DEFAULT_SAMPLE_RATE),
+    }
+}
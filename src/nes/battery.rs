@@ -0,0 +1,147 @@
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use crate::nes::nes::NES;
+use crate::util::file;
+
+pub const DEFAULT_SAVE_INTERVAL_SECONDS: u64 = 30;
+
+/// Keeps the battery backed PRG-RAM of a cartridge in a .sav file next to the rom. The file is loaded at startup,
+/// written on exit and in between every interval if the RAM has changed, so a crash loses at most that much progress.
+pub struct BatterySave {
+    path: String,
+    // None only writes on exit
+    interval: Option<Duration>,
+    last_write: Instant,
+    // What is in the file, nothing is written while the RAM still matches it
+    saved: Vec<u8>,
+}
+
+impl BatterySave {
+    pub fn new(rom_path: &str, interval: Option<Duration>) -> BatterySave {
+        BatterySave {
+            path: sav_path(rom_path),
+            interval,
+            last_write: Instant::now(),
+            saved: Vec::new(),
+        }
+    }
+
+    pub fn get_path(&self) -> &str {
+        &self.path
+    }
+
+    // Does nothing for cartridges without a battery, or when there is no save yet
+    pub fn load(&mut self, nes: &mut NES) -> Result<(), String> {
+        if nes.get_cartridge().get_battery_ram().is_none() || !Path::new(&self.path).exists() {
+            return Ok(());
+        }
+
+        let data = std::fs::read(&self.path).map_err(|e| format!("Unable to read {}: {}", self.path, e))?;
+        nes.get_cartridge_mut().load_battery_ram(&data).map_err(|e| format!("{}: {}", self.path, e))?;
+        self.saved = data;
+
+        Ok(())
+    }
+
+    // Called once per frame, writes the RAM when the interval has passed
+    pub fn update(&mut self, nes: &NES) -> Result<(), String> {
+        match self.interval {
+            Some(interval) if self.last_write.elapsed() >= interval => self.write(nes),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn write(&mut self, nes: &NES) -> Result<(), String> {
+        self.last_write = Instant::now();
+
+        match nes.get_cartridge().get_battery_ram() {
+            Some(ram) if ram != self.saved.as_slice() => {
+                file::write_file_atomic(&self.path, ram)?;
+                self.saved = ram.to_vec();
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+// game.nes saves to game.sav
+fn sav_path(rom_path: &str) -> String {
+    Path::new(rom_path).with_extension("sav").to_string_lossy().into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BatterySave, sav_path};
+    use crate::nes::cartridge::cartridge;
+    use crate::nes::nes::NES;
+    use crate::nes::rominfo::RomInfo;
+    use crate::nes::ines;
+
+    fn setup(battery: bool) -> NES {
+        let prg = vec![0; 0x4000];
+        let info = RomInfo { battery, ..RomInfo::default() };
+        let cartridge = cartridge::create_cartridge_from_ines(&info, &prg, &[]).unwrap();
+        NES::new(cartridge)
+    }
+
+    #[test]
+    fn test_sav_path() {
+        assert_eq!("roms/game.sav", sav_path("roms/game.nes"));
+    }
+
+    #[test]
+    fn test_write_and_load() {
+        let dir = std::env::temp_dir().join(format!("cnese_battery_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let rom_path = dir.join("game.nes").to_string_lossy().into_owned();
+
+        let mut nes = setup(true);
+        nes.get_cartridge_mut().write_prg(0x6000, 0x42);
+        let mut battery = BatterySave::new(&rom_path, None);
+        battery.write(&nes).unwrap();
+        assert!(!std::path::Path::new(&format!("{}.tmp", battery.get_path())).exists());
+
+        let mut nes = setup(true);
+        BatterySave::new(&rom_path, None).load(&mut nes).unwrap();
+        assert_eq!(0x42, nes.get_databus().peek(0x6000));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_no_battery() {
+        let nes = setup(false);
+        assert!(nes.get_cartridge().get_battery_ram().is_none());
+
+        // Nothing to write, so the path is never touched
+        let mut battery = BatterySave::new("/nonexistent/game.nes", None);
+        assert!(battery.write(&nes).is_ok());
+    }
+
+    #[test]
+    fn test_trainer_after_load() {
+        let dir = std::env::temp_dir().join(format!("cnese_trainer_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let rom_path = dir.join("game.nes").to_string_lossy().into_owned();
+
+        // A save from an earlier run, with the game's own data around the trainer
+        let mut nes = setup(true);
+        nes.get_cartridge_mut().write_prg(0x6000, 0x42);
+        nes.get_cartridge_mut().write_prg(0x7000, 0x11);
+        BatterySave::new(&rom_path, None).write(&nes).unwrap();
+
+        let info = RomInfo { battery: true, trainer: true, trainer_data: vec![0xAA; 0x200], ..RomInfo::default() };
+        let prg = vec![0; 0x4000];
+        let mut nes = NES::new(cartridge::create_cartridge_from_ines(&info, &prg, &[]).unwrap());
+        BatterySave::new(&rom_path, None).load(&mut nes).unwrap();
+        ines::load_trainer(nes.get_cartridge_mut(), &info);
+
+        assert_eq!(0x42, nes.get_databus().peek(0x6000));
+        assert_eq!(0xAA, nes.get_databus().peek(0x7000));
+        assert_eq!(0xAA, nes.get_databus().peek(0x71FF));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,67 @@
+use super::chr::ChrMemory;
+use super::discrete::{DiscreteMapper, LatchBoard};
+use crate::ppu::nametable::Mirroring;
+
+const PRG_BANK_SIZE: usize = 0x8000;
+
+const PRG_BANK_MASK: u8 = 0x0F;
+const SINGLE_SCREEN_UPPER_MASK: u8 = 0x10;
+
+#[derive(Clone)]
+pub struct AxRom {
+    /*
+    CPU $8000-$FFFF: 32 KB switchable PRG ROM bank
+    PPU $0000-$1FFF: 8 KB of CHR-RAM
+    Writes to $8000-$FFFF select the bank in bits 0-3 and the single screen nametable in bit 4
+    AOROM has bus conflicts, ANROM and AN1ROM don't
+    */
+
+    board: LatchBoard,
+}
+
+impl AxRom {
+    pub fn new(ines_prg_vec: Vec<&[u8]>, chr: ChrMemory, bus_conflicts: bool) -> AxRom {
+        AxRom {
+            board: LatchBoard::new(ines_prg_vec, chr, bus_conflicts),
+        }
+    }
+}
+
+impl DiscreteMapper for AxRom {
+    fn get_board(&self) -> &LatchBoard { &self.board }
+    fn get_board_mut(&mut self) -> &mut LatchBoard { &mut self.board }
+
+    fn get_prg_bank(&self, _address: u16) -> (usize, usize) {
+        (PRG_BANK_SIZE, (self.board.get_latch() & PRG_BANK_MASK) as usize)
+    }
+
+    fn get_latch_mirroring(&self) -> Option<Mirroring> {
+        if self.board.get_latch() & SINGLE_SCREEN_UPPER_MASK > 0 {
+            Some(Mirroring::SingleScreenUpper)
+        } else {
+            Some(Mirroring::SingleScreenLower)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AxRom;
+    use crate::nes::cartridge::cartridge::CartridgeTrait;
+    use crate::nes::cartridge::chr::ChrMemory;
+    use crate::ppu::nametable::Mirroring;
+
+    #[test]
+    fn test_banks_and_mirroring() {
+        let prg: Vec<Vec<u8>> = (0..8).map(|bank| vec![bank as u8 / 2; 0x4000]).collect();
+        let mut axrom = AxRom::new(prg.iter().map(|bank| bank.as_slice()).collect(), ChrMemory::new(vec![], 0), false);
+
+        assert_eq!(0, axrom.read_prg(0xFFFF));
+        assert!(matches!(axrom.get_mirroring(), Some(Mirroring::SingleScreenLower)));
+
+        axrom.write_prg(0x8000, 0x13);
+        assert_eq!(3, axrom.read_prg(0x8000));
+        assert_eq!(3, axrom.read_prg(0xFFFF));
+        assert!(matches!(axrom.get_mirroring(), Some(Mirroring::SingleScreenUpper)));
+    }
+}
@@ -1,13 +1,27 @@
 use super::nrom::NRom;
+use super::mmc1::Mmc1;
+use super::mmc3::{Mmc3, IrqRevision};
+use super::uxrom::UxRom;
+use super::cnrom::CnRom;
+use super::axrom::AxRom;
+use super::gxrom::GxRom;
+use super::colordreams::ColorDreams;
 use super::frogrom::FrogRom;
-use crate::nes::cartridge::cartridge::Mirroring::{Horizontal, Vertical};
+use super::chr::ChrMemory;
 use crate::ppu::nametable::Mirroring;
+use crate::nes::rominfo::RomInfo;
+use crate::nes::loaderror::LoadError;
+use crate::nes::savestate::{StateWriter, StateReader};
 
 pub const CARTRIDGE_OFFSET: u16 = 0x4020;
 pub const CARTRIDGE_MAX_SIZE: usize = 0x10000 - CARTRIDGE_OFFSET as usize;
 
+// PRG-RAM is mapped from $6000, with the first bank there at power on
+const PRG_RAM_ADDRESS: u16 = 0x6000;
+const TRAINER_ADDRESS: u16 = 0x7000;
 
-pub trait CartridgeTrait {
+
+pub trait CartridgeTrait: Send {
     fn read_prg(&self, address: u16) -> u8;
     fn write_prg(&mut self, address: u16, data: u8);
 
@@ -16,12 +30,48 @@ pub trait CartridgeTrait {
     fn write_chr(&mut self, address: u16, data: u8);
 
     fn get_instruction_offset(&self) -> u16;
+
+    // Once per cpu cycle, for mappers that time the writes they see
+    fn tick_cpu(&mut self) {}
+
+    // Once per ppu dot with the address on the ppu bus, for mappers that count scanlines by watching it
+    fn notify_ppu_address(&mut self, _address: u16) {}
+
+    // True while the mapper pulls the cpu IRQ line low
+    fn get_irq_signal(&self) -> bool { false }
+
+    // The PRG-RAM a battery keeps while the console is off, for mappers that have any
+    fn get_prg_ram(&self) -> Option<&[u8]> { None }
+    fn get_prg_ram_mut(&mut self) -> Option<&mut [u8]> { None }
+
+    // Mappers that control the nametable mirroring override the one from the header
+    fn get_mirroring(&self) -> Option<Mirroring> { None }
+
+    // Saves whatever the mapper can change at runtime: RAM, bank registers, IRQ counters..
+    fn save_state(&self, writer: &mut StateWriter);
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), String>;
+
+    // Cartridges are boxed as trait objects, so they can't derive Clone themselves
+    fn box_clone(&self) -> Box<dyn CartridgeTrait>;
 }
 
 pub struct Cartridge {
     implementation: Box<dyn CartridgeTrait>,
     instruction_offset: u16,
     mirroring: Mirroring,
+    // Flag 6 of the iNES header, the PRG-RAM is kept in a .sav file
+    battery: bool,
+}
+
+impl Clone for Cartridge {
+    fn clone(&self) -> Cartridge {
+        Cartridge {
+            implementation: self.implementation.box_clone(),
+            instruction_offset: self.instruction_offset,
+            mirroring: self.mirroring,
+            battery: self.battery,
+        }
+    }
 }
 
 impl Cartridge {
@@ -31,6 +81,7 @@ impl Cartridge {
             implementation: cartridge,
             instruction_offset,
             mirroring,
+            battery: false,
         }
     }
 
@@ -52,24 +103,129 @@ impl Cartridge {
         self.implementation.write_chr(address, data);
     }
 
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        self.implementation.save_state(writer);
+    }
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<(), String> {
+        self.implementation.load_state(reader)
+    }
+
+    pub fn tick_cpu(&mut self) {
+        self.implementation.tick_cpu();
+    }
+
+    pub fn notify_ppu_address(&mut self, address: u16) {
+        self.implementation.notify_ppu_address(address);
+    }
+
+    pub fn get_irq_signal(&self) -> bool {
+        self.implementation.get_irq_signal()
+    }
+
+    pub fn set_battery(&mut self, battery: bool) {
+        self.battery = battery;
+    }
+
+    // The RAM to keep in the .sav file, None without a battery
+    pub fn get_battery_ram(&self) -> Option<&[u8]> {
+        if self.battery { self.implementation.get_prg_ram() } else { None }
+    }
+
+    // Copies an iNES trainer to $7000, false when the board has no PRG-RAM there
+    pub fn load_trainer(&mut self, trainer: &[u8]) -> bool {
+        let offset = (TRAINER_ADDRESS - PRG_RAM_ADDRESS) as usize;
+
+        match self.implementation.get_prg_ram_mut() {
+            Some(ram) if ram.len() >= offset + trainer.len() => {
+                ram[offset..offset + trainer.len()].copy_from_slice(trainer);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn load_battery_ram(&mut self, data: &[u8]) -> Result<(), String> {
+        let ram = match self.implementation.get_prg_ram_mut() {
+            Some(ram) if self.battery => ram,
+            _ => return Err("The cartridge has no battery backed RAM".to_string()),
+        };
+
+        if ram.len() != data.len() {
+            return Err(format!("Expected {} bytes of battery backed RAM, got {}", ram.len(), data.len()));
+        }
+
+        ram.copy_from_slice(data);
+        Ok(())
+    }
+
     pub fn get_instruction_offset(&self) -> u16 { self.instruction_offset }
-    pub fn get_mirroring(&self) -> Mirroring { self.mirroring }
+    pub fn get_mirroring(&self) -> Mirroring {
+        self.implementation.get_mirroring().unwrap_or(self.mirroring)
+    }
 }
 
-pub fn create_cartridge_from_ines(mapper: u8, prg_rom: Vec<&[u8]>,
-                                  chr_rom: Vec<&[u8]>,
-                                  mirroring: u8) -> Result<Cartridge, String> {
-    match mapper {
-        0 => Ok(Cartridge::new(Box::new(NRom::new(prg_rom, chr_rom[0])),
-                               if mirroring == 0 { Mirroring::Horizontal } else { Mirroring::Vertical })),
-        _ => Err(format!("Unsupported mapper: {}", mapper))
+// Builds the mapper the header asks for. Without CHR-ROM the cartridge gets the CHR-RAM of the header,
+// and RAM sizes of 0 leave it to the mapper.
+pub fn create_cartridge_from_ines(info: &RomInfo, prg_rom: &[u8], chr_rom: &[u8]) -> Result<Cartridge, LoadError> {
+    if prg_rom.is_empty() {
+        return Err(LoadError::NoPrgRom);
     }
+
+    let chr = ChrMemory::new(vec![chr_rom], info.get_total_chr_ram_size());
+    let prg_rom = vec![prg_rom];
+    let prg_ram_size = info.get_total_prg_ram_size();
+    let mirroring = info.mirroring;
+
+    // Submapper 1 of the discrete mappers marks boards without bus conflicts, 2 boards with them
+    let bus_conflicts = |default: bool| match info.submapper {
+        1 => false,
+        2 => true,
+        _ => default,
+    };
+
+    // Submapper 4 of MMC3 is the MMC3A, which raises the scanline IRQ the NEC way
+    let irq_revision = if info.submapper == 4 { IrqRevision::Nec } else { IrqRevision::Sharp };
+
+    let mut cartridge = match info.mapper {
+        0 => Cartridge::new(Box::new(NRom::new(prg_rom, chr)), mirroring),
+        1 => Cartridge::new(Box::new(Mmc1::new(prg_rom, chr, prg_ram_size)), mirroring),
+        2 => Cartridge::new(Box::new(UxRom::new(prg_rom, chr, bus_conflicts(true))), mirroring),
+        3 => Cartridge::new(Box::new(CnRom::new(prg_rom, chr, bus_conflicts(true))), mirroring),
+        4 => Cartridge::new(Box::new(Mmc3::new(prg_rom, chr, irq_revision)), mirroring),
+        // Most AxROM games are on ANROM boards, which don't have bus conflicts
+        7 => Cartridge::new(Box::new(AxRom::new(prg_rom, chr, bus_conflicts(false))), mirroring),
+        11 => Cartridge::new(Box::new(ColorDreams::new(prg_rom, chr, bus_conflicts(true))), mirroring),
+        66 => Cartridge::new(Box::new(GxRom::new(prg_rom, chr, bus_conflicts(true))), mirroring),
+        _ => return Err(LoadError::UnsupportedMapper(info.mapper))
+    };
+
+    cartridge.set_battery(info.battery);
+
+    Ok(cartridge)
 }
 
-pub fn create_cartridge_from_raw(data: &[u8]) -> Result<Cartridge, String> {
+pub fn create_cartridge_from_raw(data: &[u8]) -> Result<Cartridge, LoadError> {
+    if data.len() != CARTRIDGE_MAX_SIZE {
+        return Err(LoadError::SizeMismatch { expected: CARTRIDGE_MAX_SIZE, actual: data.len() });
+    }
+
     Ok(Cartridge::new(Box::new(FrogRom::new(data)),
                       Mirroring::Horizontal))
 }
 
 
 
+
+#[cfg(test)]
+mod tests {
+    use super::{create_cartridge_from_raw, CARTRIDGE_MAX_SIZE};
+    use crate::nes::loaderror::LoadError;
+
+    #[test]
+    fn test_raw_size() {
+        assert!(create_cartridge_from_raw(&vec![0; CARTRIDGE_MAX_SIZE]).is_ok());
+
+        let error = create_cartridge_from_raw(&vec![0; 0x100]).err().unwrap();
+        assert!(matches!(error, LoadError::SizeMismatch { expected: CARTRIDGE_MAX_SIZE, actual: 0x100 }));
+    }
+}
@@ -0,0 +1,127 @@
+use crate::nes::savestate::{StateWriter, StateReader};
+
+// What the ppu sees at $0000-$1FFF, smaller memories are mirrored to fill it
+pub const CHR_WINDOW_SIZE: usize = 0x2000;
+// Boards without CHR-ROM have this much CHR-RAM, unless an NES 2.0 header says otherwise
+pub const DEFAULT_CHR_RAM_SIZE: usize = 0x2000;
+
+/// The pattern table memory of a cartridge. It is the CHR-ROM of the image, or writable CHR-RAM when the image has none.
+/// Mappers switch banks by reading at an offset into it.
+#[derive(Clone)]
+pub struct ChrMemory {
+    data: Vec<u8>,
+    // The size the cartridge has, data is at least CHR_WINDOW_SIZE with the mirrors filled in
+    size: usize,
+    ram: bool,
+}
+
+impl ChrMemory {
+    // Uses the banks of the image as ROM, or allocates ram_size bytes of RAM if there are none
+    pub fn new(chr_rom: Vec<&[u8]>, ram_size: usize) -> ChrMemory {
+        let rom: Vec<u8> = chr_rom.concat();
+
+        let (data, ram) = if rom.is_empty() {
+            let size = if ram_size == 0 { DEFAULT_CHR_RAM_SIZE } else { ram_size };
+            (vec![0; size], true)
+        } else {
+            (rom, false)
+        };
+
+        let size = data.len();
+        let mut chr = ChrMemory { data, size, ram };
+        chr._fill_mirrors();
+        chr
+    }
+
+    fn _fill_mirrors(&mut self) {
+        while self.data.len() < CHR_WINDOW_SIZE {
+            let mirror = self.data[..self.size].to_vec();
+            self.data.extend_from_slice(&mirror);
+        }
+        self.data.truncate(self.size.max(CHR_WINDOW_SIZE));
+    }
+
+    pub fn is_ram(&self) -> bool {
+        self.ram
+    }
+
+    pub fn get_size(&self) -> usize {
+        self.size
+    }
+
+    pub fn read(&self, offset: usize) -> u8 {
+        self.data[offset % self.data.len()]
+    }
+
+    pub fn read_slice(&self, offset: usize, len: usize) -> &[u8] {
+        let start = offset % self.data.len();
+        &self.data[start..start + len]
+    }
+
+    // Writes to ROM are ignored
+    pub fn write(&mut self, offset: usize, data: u8) {
+        if !self.ram {
+            return;
+        }
+
+        let mut address = offset % self.size;
+        while address < self.data.len() {
+            self.data[address] = data;
+            address += self.size;
+        }
+    }
+
+    // Only RAM can change, so ROM saves nothing
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        if self.ram {
+            writer.write_bytes(&self.data[..self.size]);
+        }
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<(), String> {
+        if self.ram {
+            reader.read_bytes(&mut self.data[..self.size])?;
+            self.data.truncate(self.size);
+            self._fill_mirrors();
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ChrMemory;
+
+    #[test]
+    fn test_rom() {
+        let bank = vec![7; 0x2000];
+        let mut chr = ChrMemory::new(vec![&bank, &vec![9; 0x2000]], 0);
+        assert!(!chr.is_ram());
+        assert_eq!(0x4000, chr.get_size());
+        assert_eq!(9, chr.read(0x2000));
+
+        chr.write(0, 1);
+        assert_eq!(7, chr.read(0));
+    }
+
+    #[test]
+    fn test_ram() {
+        let mut chr = ChrMemory::new(vec![], 0);
+        assert!(chr.is_ram());
+        assert_eq!(0x2000, chr.get_size());
+        chr.write(0x1FFF, 3);
+        assert_eq!(3, chr.read(0x1FFF));
+        assert_eq!(&[0, 3], chr.read_slice(0x1FFE, 2));
+    }
+
+    #[test]
+    fn test_small_memory_is_mirrored() {
+        let mut chr = ChrMemory::new(vec![], 0x800);
+        assert_eq!(0x800, chr.get_size());
+        chr.write(0x1001, 5);
+        assert_eq!(5, chr.read(0x0001));
+        assert_eq!(5, chr.read(0x1801));
+        assert_eq!(&[5], chr.read_slice(0x0801, 1));
+    }
+}
@@ -0,0 +1,60 @@
+use super::chr::ChrMemory;
+use super::discrete::{DiscreteMapper, LatchBoard};
+
+// 16 or 32 KB of PRG-ROM without banking, like NROM
+const PRG_ROM_SIZE: usize = 0x8000;
+
+#[derive(Clone)]
+pub struct CnRom {
+    /*
+    CPU $8000-$FFFF: 16 or 32 KB PRG ROM, a 16 KB image is mirrored
+    PPU $0000-$1FFF: 8 KB switchable CHR ROM bank
+    Writes to $8000-$FFFF select the CHR bank
+    */
+
+    board: LatchBoard,
+}
+
+impl CnRom {
+    pub fn new(ines_prg_vec: Vec<&[u8]>, chr: ChrMemory, bus_conflicts: bool) -> CnRom {
+        CnRom {
+            board: LatchBoard::new(ines_prg_vec, chr, bus_conflicts),
+        }
+    }
+}
+
+impl DiscreteMapper for CnRom {
+    fn get_board(&self) -> &LatchBoard { &self.board }
+    fn get_board_mut(&mut self) -> &mut LatchBoard { &mut self.board }
+
+    fn get_prg_bank(&self, _address: u16) -> (usize, usize) {
+        (PRG_ROM_SIZE, 0)
+    }
+
+    fn get_chr_bank(&self) -> usize {
+        self.board.get_latch() as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CnRom;
+    use crate::nes::cartridge::cartridge::CartridgeTrait;
+    use crate::nes::cartridge::chr::ChrMemory;
+
+    #[test]
+    fn test_chr_banks() {
+        let prg = vec![0xFF; 0x4000];
+        let chr: Vec<Vec<u8>> = (0..4).map(|bank| vec![bank as u8; 0x2000]).collect();
+        let mut cnrom = CnRom::new(vec![&prg], ChrMemory::new(chr.iter().map(|bank| bank.as_slice()).collect(), 0), true);
+
+        assert_eq!(0xFF, cnrom.read_prg(0xC000));
+        cnrom.write_prg(0x8000, 3);
+        assert_eq!(3, cnrom.read_chr(0x0000));
+        assert_eq!(&[3, 3], cnrom.read_chr_slice(0x1FFE, 2));
+
+        // Bank 5 wraps to bank 1
+        cnrom.write_prg(0x8000, 5);
+        assert_eq!(1, cnrom.read_chr(0x1000));
+    }
+}
@@ -0,0 +1,58 @@
+use super::chr::ChrMemory;
+use super::discrete::{DiscreteMapper, LatchBoard};
+
+const PRG_BANK_SIZE: usize = 0x8000;
+
+const PRG_BANK_MASK: u8 = 0x03;
+const CHR_BANK_SHIFT: u8 = 4;
+
+#[derive(Clone)]
+pub struct ColorDreams {
+    /*
+    CPU $8000-$FFFF: 32 KB switchable PRG ROM bank
+    PPU $0000-$1FFF: 8 KB switchable CHR ROM bank
+    Writes to $8000-$FFFF select the PRG bank in bits 0-1 and the CHR bank in bits 4-7
+    */
+
+    board: LatchBoard,
+}
+
+impl ColorDreams {
+    pub fn new(ines_prg_vec: Vec<&[u8]>, chr: ChrMemory, bus_conflicts: bool) -> ColorDreams {
+        ColorDreams {
+            board: LatchBoard::new(ines_prg_vec, chr, bus_conflicts),
+        }
+    }
+}
+
+impl DiscreteMapper for ColorDreams {
+    fn get_board(&self) -> &LatchBoard { &self.board }
+    fn get_board_mut(&mut self) -> &mut LatchBoard { &mut self.board }
+
+    fn get_prg_bank(&self, _address: u16) -> (usize, usize) {
+        (PRG_BANK_SIZE, (self.board.get_latch() & PRG_BANK_MASK) as usize)
+    }
+
+    fn get_chr_bank(&self) -> usize {
+        (self.board.get_latch() >> CHR_BANK_SHIFT) as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ColorDreams;
+    use crate::nes::cartridge::cartridge::CartridgeTrait;
+    use crate::nes::cartridge::chr::ChrMemory;
+
+    #[test]
+    fn test_banks() {
+        let prg: Vec<Vec<u8>> = (0..8).map(|bank| vec![bank as u8 / 2; 0x4000]).collect();
+        let chr: Vec<Vec<u8>> = (0..16).map(|bank| vec![bank as u8; 0x2000]).collect();
+        let mut color_dreams = ColorDreams::new(prg.iter().map(|bank| bank.as_slice()).collect(),
+                                                ChrMemory::new(chr.iter().map(|bank| bank.as_slice()).collect(), 0), false);
+
+        color_dreams.write_prg(0x8000, 0xA3);
+        assert_eq!(3, color_dreams.read_prg(0xFFFF));
+        assert_eq!(10, color_dreams.read_chr(0x0000));
+    }
+}
@@ -0,0 +1,106 @@
+use std::boxed::Box;
+use super::cartridge::CartridgeTrait;
+use super::chr::ChrMemory;
+use super::prg::PrgRom;
+use crate::ppu::nametable::Mirroring;
+use crate::nes::savestate::{StateWriter, StateReader};
+
+const PRG_ROM_START: u16 = 0x8000;
+const CHR_BANK_SIZE: usize = 0x2000;
+
+/// What the discrete logic boards (UxROM, CNROM, AxROM, GxROM, Color Dreams) have in common: PRG-ROM, 8 KB banks of
+/// CHR memory and a single register latching every write to $8000-$FFFF. They only differ in how they decode it.
+#[derive(Clone)]
+pub struct LatchBoard {
+    prg_rom: PrgRom,
+    chr: ChrMemory,
+    latch: u8,
+    bus_conflicts: bool,
+}
+
+impl LatchBoard {
+    pub fn new(ines_prg_vec: Vec<&[u8]>, chr: ChrMemory, bus_conflicts: bool) -> LatchBoard {
+        LatchBoard {
+            prg_rom: PrgRom::new(ines_prg_vec),
+            chr,
+            latch: 0,
+            bus_conflicts,
+        }
+    }
+
+    pub fn get_latch(&self) -> u8 { self.latch }
+
+    pub fn get_prg_bank_count(&self, bank_size: usize) -> usize {
+        self.prg_rom.get_bank_count(bank_size)
+    }
+}
+
+pub trait DiscreteMapper: Clone + Send + 'static {
+    fn get_board(&self) -> &LatchBoard;
+    fn get_board_mut(&mut self) -> &mut LatchBoard;
+
+    // The bank size and bank of the PRG-ROM window at address, $8000-$FFFF
+    fn get_prg_bank(&self, address: u16) -> (usize, usize);
+
+    // The 8 KB CHR bank, boards with CHR-RAM don't switch it
+    fn get_chr_bank(&self) -> usize { 0 }
+
+    fn get_latch_mirroring(&self) -> Option<Mirroring> { None }
+}
+
+fn chr_offset<T: DiscreteMapper>(mapper: &T, address: u16) -> usize {
+    mapper.get_chr_bank() * CHR_BANK_SIZE + address as usize
+}
+
+impl<T: DiscreteMapper> CartridgeTrait for T {
+    fn read_prg(&self, address: u16) -> u8 {
+        if address >= PRG_ROM_START {
+            let (bank_size, bank) = self.get_prg_bank(address);
+            self.get_board().prg_rom.read(bank_size, bank, address)
+        } else {
+            0
+        }
+    }
+
+    fn write_prg(&mut self, address: u16, data: u8) {
+        if address >= PRG_ROM_START {
+            let (bank_size, bank) = self.get_prg_bank(address);
+            let board = self.get_board_mut();
+            board.latch = board.prg_rom.bus_conflict(bank_size, bank, address, data, board.bus_conflicts);
+        }
+    }
+
+    fn read_chr(&self, address: u16) -> u8 {
+        self.get_board().chr.read(chr_offset(self, address))
+    }
+
+    fn read_chr_slice(&self, address: u16, len: usize) -> &[u8] {
+        self.get_board().chr.read_slice(chr_offset(self, address), len)
+    }
+
+    fn write_chr(&mut self, address: u16, data: u8) {
+        let offset = chr_offset(self, address);
+        self.get_board_mut().chr.write(offset, data);
+    }
+
+    fn get_mirroring(&self) -> Option<Mirroring> {
+        self.get_latch_mirroring()
+    }
+
+    fn save_state(&self, writer: &mut StateWriter) {
+        self.get_board().chr.save_state(writer);
+        writer.write_u8(self.get_board().latch);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), String> {
+        let board = self.get_board_mut();
+        board.chr.load_state(reader)?;
+        board.latch = reader.read_u8()?;
+
+        Ok(())
+    }
+
+    fn box_clone(&self) -> Box<dyn CartridgeTrait> { Box::new(self.clone()) }
+
+    fn get_instruction_offset(&self) -> u16 { PRG_ROM_START }
+}
@@ -1,9 +1,15 @@
 use std::boxed::Box;
 use super::cartridge::CartridgeTrait;
+use crate::nes::savestate::{StateWriter, StateReader};
 use super::cartridge::{CARTRIDGE_OFFSET, CARTRIDGE_MAX_SIZE};
 
+const CHR_SIZE: usize = 0x2000;
+
+#[derive(Clone)]
 pub struct FrogRom {
     rom: Box<[u8; CARTRIDGE_MAX_SIZE]>,
+    // Raw images have no graphics, the ppu sees blank pattern tables
+    chr: Box<[u8; CHR_SIZE]>,
 }
 
 impl FrogRom {
@@ -12,7 +18,8 @@ impl FrogRom {
 
         rom.copy_from_slice(filerom);
         FrogRom {
-            rom
+            rom,
+            chr: Box::new([0; CHR_SIZE]),
         }
     }
 }
@@ -26,17 +33,25 @@ impl CartridgeTrait for FrogRom {
         unimplemented!()
     }
 
-    fn read_chr(&self, _address: u16) -> u8 {
-        unimplemented!()
+    fn read_chr(&self, address: u16) -> u8 {
+        self.chr[address as usize % CHR_SIZE]
     }
 
-    fn read_chr_slice(&self, _address: u16, _len: usize) -> &[u8] {
-        unimplemented!()
+    fn read_chr_slice(&self, address: u16, len: usize) -> &[u8] {
+        let start = address as usize;
+        &self.chr[start..start + len]
     }
 
     fn write_chr(&mut self, address: u16, data: u8) {
         unimplemented!()
     }
 
+    // Nothing in a raw image can change
+    fn save_state(&self, _writer: &mut StateWriter) {}
+
+    fn load_state(&mut self, _reader: &mut StateReader) -> Result<(), String> { Ok(()) }
+
+    fn box_clone(&self) -> Box<dyn CartridgeTrait> { Box::new(self.clone()) }
+
     fn get_instruction_offset(&self) -> u16 { CARTRIDGE_OFFSET }
 }
@@ -0,0 +1,61 @@
+use super::chr::ChrMemory;
+use super::discrete::{DiscreteMapper, LatchBoard};
+
+const PRG_BANK_SIZE: usize = 0x8000;
+
+const CHR_BANK_MASK: u8 = 0x03;
+const PRG_BANK_MASK: u8 = 0x30;
+const PRG_BANK_SHIFT: u8 = 4;
+
+#[derive(Clone)]
+pub struct GxRom {
+    /*
+    CPU $8000-$FFFF: 32 KB switchable PRG ROM bank
+    PPU $0000-$1FFF: 8 KB switchable CHR ROM bank
+    Writes to $8000-$FFFF select the PRG bank in bits 4-5 and the CHR bank in bits 0-1
+    */
+
+    board: LatchBoard,
+}
+
+impl GxRom {
+    pub fn new(ines_prg_vec: Vec<&[u8]>, chr: ChrMemory, bus_conflicts: bool) -> GxRom {
+        GxRom {
+            board: LatchBoard::new(ines_prg_vec, chr, bus_conflicts),
+        }
+    }
+}
+
+impl DiscreteMapper for GxRom {
+    fn get_board(&self) -> &LatchBoard { &self.board }
+    fn get_board_mut(&mut self) -> &mut LatchBoard { &mut self.board }
+
+    fn get_prg_bank(&self, _address: u16) -> (usize, usize) {
+        (PRG_BANK_SIZE, ((self.board.get_latch() & PRG_BANK_MASK) >> PRG_BANK_SHIFT) as usize)
+    }
+
+    fn get_chr_bank(&self) -> usize {
+        (self.board.get_latch() & CHR_BANK_MASK) as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::GxRom;
+    use crate::nes::cartridge::cartridge::CartridgeTrait;
+    use crate::nes::cartridge::chr::ChrMemory;
+
+    #[test]
+    fn test_banks() {
+        // The 32 KB banks hold 0x32 with their number in bits 2-3
+        let prg: Vec<Vec<u8>> = (0..8).map(|bank| vec![0x32 | (bank as u8 / 2) << 2; 0x4000]).collect();
+        let chr: Vec<Vec<u8>> = (0..4).map(|bank| vec![bank as u8; 0x2000]).collect();
+        let mut gxrom = GxRom::new(prg.iter().map(|bank| bank.as_slice()).collect(),
+                                   ChrMemory::new(chr.iter().map(|bank| bank.as_slice()).collect(), 0), true);
+
+        // 0x23 & 0x32 selects PRG bank 2 and CHR bank 2
+        gxrom.write_prg(0x8000, 0x23);
+        assert_eq!(0x3A, gxrom.read_prg(0xC000));
+        assert_eq!(2, gxrom.read_chr(0x1000));
+    }
+}
@@ -0,0 +1,399 @@
+use std::boxed::Box;
+use super::cartridge::CartridgeTrait;
+use super::chr::ChrMemory;
+use super::prg::PrgRom;
+use crate::ppu::nametable::Mirroring;
+use crate::nes::savestate::{StateWriter, StateReader};
+
+use crate::nes::ines;
+
+const DEFAULT_PRG_RAM_SIZE: usize = 0x2000;
+const PRG_RAM_BANK_SIZE: usize = 0x2000;
+const PRG_BANK_SIZE: usize = ines::PRG_ROM_CHUNK_SIZE;
+const CHR_BANK_SIZE: usize = 0x1000;
+
+const PRG_RAM_START: u16 = 0x6000;
+const PRG_RAM_END: u16 = 0x7FFF;
+const PRG_ROM_START: u16 = 0x8000;
+const PRG_ROM_END: u16 = 0xFFFF;
+const PRG_ROM_FIXED_START: u16 = 0xC000;
+
+// Writes with bit 7 set clear the shift register, the fifth write of bit 0 loads the register picked by A13-A14
+const SHIFT_RESET_MASK: u8 = 0x80;
+const SHIFT_REGISTER_WRITES: u8 = 5;
+
+const CONTROL_MIRRORING_MASK: u8 = 0x03;
+const CONTROL_PRG_MODE_MASK: u8 = 0x0C;
+const CONTROL_PRG_MODE_SHIFT: u8 = 2;
+const CONTROL_CHR_4K_MASK: u8 = 0x10;
+
+const PRG_BANK_MASK: u8 = 0x0F;
+const PRG_RAM_DISABLE_MASK: u8 = 0x10;
+
+// SUROM and SXROM use the CHR bank register for more PRG and PRG-RAM
+const CHR_BANK_PRG_256K_MASK: u8 = 0x10;
+const PRG_BANKS_PER_256K: usize = 0x10;
+
+// The second write of a read-modify-write instruction lands on the next cycle and is ignored
+const MIN_CYCLES_BETWEEN_WRITES: u8 = 2;
+
+#[derive(Clone)]
+pub struct Mmc1 {
+    /*
+    CPU $6000-$7FFF: 8 KB PRG RAM bank, banked on SXROM (32 KB) and SOROM (16 KB)
+    CPU $8000-$BFFF: 16 KB PRG ROM bank, either switchable or fixed to the first bank
+    CPU $C000-$FFFF: 16 KB PRG ROM bank, either fixed to the last bank or switchable
+    PPU $0000-$0FFF: 4 KB switchable CHR bank
+    PPU $1000-$1FFF: 4 KB switchable CHR bank
+    */
+
+    prg_ram: Vec<u8>,
+    prg_rom: PrgRom,
+    chr: ChrMemory,
+
+    shift_register: u8,
+    shift_count: u8,
+    control: u8,
+    chr_bank_0: u8,
+    chr_bank_1: u8,
+    prg_bank: u8,
+
+    cycles_since_write: u8,
+}
+
+impl Mmc1 {
+    // prg_ram_size 0 is the usual 8 KB
+    pub fn new(ines_prg_vec: Vec<&[u8]>, chr: ChrMemory, prg_ram_size: usize) -> Mmc1 {
+        let prg_ram_size = if prg_ram_size == 0 { DEFAULT_PRG_RAM_SIZE } else { prg_ram_size };
+
+        Mmc1 {
+            prg_ram: vec![0; prg_ram_size.max(PRG_RAM_BANK_SIZE)],
+            prg_rom: PrgRom::new(ines_prg_vec),
+            chr,
+            shift_register: 0,
+            shift_count: 0,
+            // Boots with the last bank fixed at $C000, so the reset vector is always there
+            control: CONTROL_PRG_MODE_MASK,
+            chr_bank_0: 0,
+            chr_bank_1: 0,
+            prg_bank: 0,
+            cycles_since_write: MIN_CYCLES_BETWEEN_WRITES,
+        }
+    }
+
+    fn _write_register(&mut self, address: u16, data: u8) {
+        if self.cycles_since_write < MIN_CYCLES_BETWEEN_WRITES {
+            return;
+        }
+
+        if data & SHIFT_RESET_MASK > 0 {
+            self.shift_register = 0;
+            self.shift_count = 0;
+            self.control |= CONTROL_PRG_MODE_MASK;
+            return;
+        }
+
+        self.shift_register |= (data & 1) << self.shift_count;
+        self.shift_count += 1;
+
+        if self.shift_count == SHIFT_REGISTER_WRITES {
+            let value = self.shift_register;
+            match address {
+                0x8000..=0x9FFF => self.control = value,
+                0xA000..=0xBFFF => self.chr_bank_0 = value,
+                0xC000..=0xDFFF => self.chr_bank_1 = value,
+                _ => self.prg_bank = value,
+            }
+
+            self.shift_register = 0;
+            self.shift_count = 0;
+        }
+    }
+
+    fn _prg_bank_count(&self) -> usize {
+        self.prg_rom.get_bank_count(PRG_BANK_SIZE)
+    }
+
+    // The 256 KB half of a 512 KB SUROM/SXROM image that is mapped in
+    fn _prg_outer_bank(&self) -> usize {
+        if self._prg_bank_count() > PRG_BANKS_PER_256K && self.chr_bank_0 & CHR_BANK_PRG_256K_MASK > 0 {
+            PRG_BANKS_PER_256K
+        } else {
+            0
+        }
+    }
+
+    // The 16 KB bank mapped at the address
+    fn _prg_bank(&self, address: u16) -> usize {
+        let outer = self._prg_outer_bank();
+        let bank = (self.prg_bank & PRG_BANK_MASK) as usize;
+        let upper = address >= PRG_ROM_FIXED_START;
+
+        let bank = match (self.control & CONTROL_PRG_MODE_MASK) >> CONTROL_PRG_MODE_SHIFT {
+            // 32 KB mode ignores the low bit
+            0 | 1 => (bank & !1) | upper as usize,
+            // First bank fixed at $8000
+            2 => if upper { bank } else { 0 },
+            // Last bank fixed at $C000
+            _ => if upper { PRG_BANKS_PER_256K - 1 } else { bank },
+        };
+
+        outer | bank
+    }
+
+    fn _prg_ram_enabled(&self) -> bool {
+        self.prg_bank & PRG_RAM_DISABLE_MASK == 0
+    }
+
+    // SXROM selects one of four 8 KB banks with bits 2-3 of the CHR bank register, SOROM one of two with bit 3
+    fn _prg_ram_offset(&self, address: u16) -> usize {
+        let bank = match self.prg_ram.len() / PRG_RAM_BANK_SIZE {
+            1 => 0,
+            2 => (self.chr_bank_0 >> 3) & 1,
+            _ => (self.chr_bank_0 >> 2) & 3,
+        } as usize;
+
+        (bank * PRG_RAM_BANK_SIZE + (address - PRG_RAM_START) as usize) % self.prg_ram.len()
+    }
+
+    // In 8 KB mode the low bit of the first register is ignored and the second register is unused
+    fn _chr_offset(&self, address: u16) -> usize {
+        let upper = address as usize >= CHR_BANK_SIZE;
+
+        let bank = if self.control & CONTROL_CHR_4K_MASK > 0 {
+            if upper { self.chr_bank_1 } else { self.chr_bank_0 }
+        } else {
+            (self.chr_bank_0 & !1) | upper as u8
+        };
+
+        bank as usize * CHR_BANK_SIZE + (address as usize % CHR_BANK_SIZE)
+    }
+}
+
+impl CartridgeTrait for Mmc1 {
+    fn read_prg(&self, address: u16) -> u8 {
+        match address {
+            PRG_RAM_START..=PRG_RAM_END if self._prg_ram_enabled() => {
+                self.prg_ram[self._prg_ram_offset(address)]
+            }
+            PRG_ROM_START..=PRG_ROM_END => {
+                self.prg_rom.read(PRG_BANK_SIZE, self._prg_bank(address), address)
+            }
+            // Open bus without the RAM
+            _ => 0
+        }
+    }
+
+    fn write_prg(&mut self, address: u16, data: u8) {
+        match address {
+            PRG_RAM_START..=PRG_RAM_END if self._prg_ram_enabled() => {
+                let offset = self._prg_ram_offset(address);
+                self.prg_ram[offset] = data;
+            }
+            PRG_ROM_START..=PRG_ROM_END => {
+                self._write_register(address, data);
+                self.cycles_since_write = 0;
+            }
+            _ => {}
+        }
+    }
+
+    fn read_chr(&self, address: u16) -> u8 {
+        self.chr.read(self._chr_offset(address))
+    }
+
+    // Slices don't cross the 4 KB banks
+    fn read_chr_slice(&self, address: u16, len: usize) -> &[u8] {
+        self.chr.read_slice(self._chr_offset(address), len)
+    }
+
+    fn write_chr(&mut self, address: u16, data: u8) {
+        let offset = self._chr_offset(address);
+        self.chr.write(offset, data);
+    }
+
+    fn tick_cpu(&mut self) {
+        self.cycles_since_write = self.cycles_since_write.saturating_add(1);
+    }
+
+    fn get_mirroring(&self) -> Option<Mirroring> {
+        Some(match self.control & CONTROL_MIRRORING_MASK {
+            0 => Mirroring::SingleScreenLower,
+            1 => Mirroring::SingleScreenUpper,
+            2 => Mirroring::Vertical,
+            _ => Mirroring::Horizontal,
+        })
+    }
+
+    fn get_prg_ram(&self) -> Option<&[u8]> { Some(&self.prg_ram) }
+    fn get_prg_ram_mut(&mut self) -> Option<&mut [u8]> { Some(&mut self.prg_ram) }
+
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bytes(&self.prg_ram);
+        self.chr.save_state(writer);
+        writer.write_u8(self.shift_register);
+        writer.write_u8(self.shift_count);
+        writer.write_u8(self.control);
+        writer.write_u8(self.chr_bank_0);
+        writer.write_u8(self.chr_bank_1);
+        writer.write_u8(self.prg_bank);
+        writer.write_u8(self.cycles_since_write);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), String> {
+        reader.read_bytes(&mut self.prg_ram)?;
+        self.chr.load_state(reader)?;
+        self.shift_register = reader.read_u8()?;
+        self.shift_count = reader.read_u8()? % SHIFT_REGISTER_WRITES;
+        self.control = reader.read_u8()?;
+        self.chr_bank_0 = reader.read_u8()?;
+        self.chr_bank_1 = reader.read_u8()?;
+        self.prg_bank = reader.read_u8()?;
+        self.cycles_since_write = reader.read_u8()?;
+
+        Ok(())
+    }
+
+    fn box_clone(&self) -> Box<dyn CartridgeTrait> { Box::new(self.clone()) }
+
+    fn get_instruction_offset(&self) -> u16 { PRG_ROM_START }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Mmc1;
+    use crate::nes::cartridge::cartridge::CartridgeTrait;
+    use crate::nes::cartridge::chr::ChrMemory;
+    use crate::ppu::nametable::Mirroring;
+
+    // Every 16 KB bank is filled with its number, every 4 KB CHR bank too
+    fn setup(prg_banks: usize, chr_banks: usize, prg_ram_size: usize) -> Mmc1 {
+        let prg: Vec<Vec<u8>> = (0..prg_banks).map(|bank| vec![bank as u8; 0x4000]).collect();
+        let chr: Vec<u8> = (0..chr_banks).flat_map(|bank| vec![bank as u8; 0x1000]).collect();
+        let chr = if chr_banks == 0 { ChrMemory::new(vec![], 0) } else { ChrMemory::new(vec![&chr], 0) };
+
+        Mmc1::new(prg.iter().map(|bank| bank.as_slice()).collect(), chr, prg_ram_size)
+    }
+
+    // Five writes through the serial port, low bit first
+    fn write_register(mmc1: &mut Mmc1, address: u16, value: u8) {
+        for i in 0..5 {
+            mmc1.write_prg(address, (value >> i) & 1);
+            mmc1.tick_cpu();
+            mmc1.tick_cpu();
+        }
+    }
+
+    #[test]
+    fn test_power_on_fixes_last_bank() {
+        let mmc1 = setup(8, 2, 0);
+        assert_eq!(0, mmc1.read_prg(0x8000));
+        assert_eq!(7, mmc1.read_prg(0xFFFC));
+    }
+
+    #[test]
+    fn test_prg_modes() {
+        let mut mmc1 = setup(8, 2, 0);
+
+        write_register(&mut mmc1, 0xE000, 3);
+        assert_eq!(3, mmc1.read_prg(0x8000));
+        assert_eq!(7, mmc1.read_prg(0xC000));
+
+        // First bank fixed
+        write_register(&mut mmc1, 0x8000, 0x08);
+        assert_eq!(0, mmc1.read_prg(0x8000));
+        assert_eq!(3, mmc1.read_prg(0xC000));
+
+        // 32 KB
+        write_register(&mut mmc1, 0x8000, 0x00);
+        assert_eq!(2, mmc1.read_prg(0x8000));
+        assert_eq!(3, mmc1.read_prg(0xC000));
+    }
+
+    #[test]
+    fn test_reset_and_consecutive_writes() {
+        let mut mmc1 = setup(8, 2, 0);
+
+        mmc1.write_prg(0xE000, 1);
+        mmc1.tick_cpu();
+        mmc1.tick_cpu();
+        mmc1.write_prg(0xE000, 0x80);
+        mmc1.tick_cpu();
+        mmc1.tick_cpu();
+        write_register(&mut mmc1, 0xE000, 5);
+        assert_eq!(5, mmc1.read_prg(0x8000));
+
+        // Only the first write of a read-modify-write counts
+        write_register(&mut mmc1, 0xE000, 0);
+        mmc1.write_prg(0xE000, 1);
+        mmc1.tick_cpu();
+        mmc1.write_prg(0xE000, 1);
+        mmc1.tick_cpu();
+        mmc1.tick_cpu();
+        for _ in 0..4 {
+            mmc1.write_prg(0xE000, 0);
+            mmc1.tick_cpu();
+            mmc1.tick_cpu();
+        }
+        assert_eq!(1, mmc1.read_prg(0x8000));
+    }
+
+    #[test]
+    fn test_chr_banks() {
+        let mut mmc1 = setup(2, 8, 0);
+
+        write_register(&mut mmc1, 0xA000, 5);
+        assert_eq!(4, mmc1.read_chr(0x0000));
+        assert_eq!(5, mmc1.read_chr(0x1000));
+
+        write_register(&mut mmc1, 0x8000, 0x1C);
+        write_register(&mut mmc1, 0xC000, 2);
+        assert_eq!(5, mmc1.read_chr(0x0000));
+        assert_eq!(2, mmc1.read_chr(0x1FFF));
+        assert_eq!(&[2, 2], mmc1.read_chr_slice(0x1000, 2));
+    }
+
+    #[test]
+    fn test_mirroring() {
+        let mut mmc1 = setup(2, 2, 0);
+
+        for (value, mirroring) in [(0, Mirroring::SingleScreenLower), (1, Mirroring::SingleScreenUpper),
+                                   (2, Mirroring::Vertical), (3, Mirroring::Horizontal)] {
+            write_register(&mut mmc1, 0x8000, 0x0C | value);
+            assert_eq!(mirroring as u8, mmc1.get_mirroring().unwrap() as u8);
+        }
+    }
+
+    #[test]
+    fn test_prg_ram_enable() {
+        let mut mmc1 = setup(2, 2, 0);
+        mmc1.write_prg(0x6000, 0x42);
+        assert_eq!(0x42, mmc1.read_prg(0x6000));
+
+        write_register(&mut mmc1, 0xE000, 0x10);
+        mmc1.write_prg(0x6001, 0x42);
+        assert_eq!(0, mmc1.read_prg(0x6000));
+
+        write_register(&mut mmc1, 0xE000, 0x00);
+        assert_eq!(0x42, mmc1.read_prg(0x6000));
+        assert_eq!(0, mmc1.read_prg(0x6001));
+    }
+
+    #[test]
+    fn test_surom_and_sxrom() {
+        // 512 KB PRG with CHR-RAM and 32 KB of PRG-RAM
+        let mut mmc1 = setup(32, 0, 0x8000);
+        assert_eq!(15, mmc1.read_prg(0xC000));
+
+        write_register(&mut mmc1, 0xA000, 0x10);
+        assert_eq!(31, mmc1.read_prg(0xC000));
+        assert_eq!(16, mmc1.read_prg(0x8000));
+
+        mmc1.write_prg(0x6000, 1);
+        write_register(&mut mmc1, 0xA000, 0x1C);
+        assert_eq!(0, mmc1.read_prg(0x6000));
+        mmc1.write_prg(0x6000, 4);
+        write_register(&mut mmc1, 0xA000, 0x10);
+        assert_eq!(1, mmc1.read_prg(0x6000));
+    }
+}
@@ -0,0 +1,417 @@
+use std::boxed::Box;
+use super::cartridge::CartridgeTrait;
+use super::chr::ChrMemory;
+use super::prg::PrgRom;
+use crate::ppu::nametable::Mirroring;
+use crate::nes::savestate::{StateWriter, StateReader};
+
+const PRG_RAM_SIZE: usize = 0x2000;
+const PRG_BANK_SIZE: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x0400;
+
+const PRG_RAM_START: u16 = 0x6000;
+const PRG_RAM_END: u16 = 0x7FFF;
+const PRG_ROM_START: u16 = 0x8000;
+const PRG_ROM_END: u16 = 0xFFFF;
+
+const BANK_SELECT_REGISTER_MASK: u8 = 0x07;
+const BANK_SELECT_PRG_MODE_MASK: u8 = 0x40;
+const BANK_SELECT_CHR_INVERSION_MASK: u8 = 0x80;
+
+const MIRRORING_HORIZONTAL_MASK: u8 = 0x01;
+const PRG_RAM_ENABLE_MASK: u8 = 0x80;
+const PRG_RAM_WRITE_PROTECT_MASK: u8 = 0x40;
+
+// The scanline counter is clocked when A12 rises after staying low for about three cpu cycles.
+// That filters out the short lows between the fetches of a scanline.
+const A12_MASK: u16 = 0x1000;
+const A12_LOW_MIN_DOTS: u8 = 9;
+
+/// When the scanline counter raises the IRQ. The Sharp MMC3B/MMC3C does it on every clock that leaves the counter at 0,
+/// the NEC MMC3A only when it gets there by counting down or by a reload requested through $C001.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum IrqRevision {
+    Sharp,
+    Nec,
+}
+
+#[derive(Clone)]
+pub struct Mmc3 {
+    /*
+    CPU $6000-$7FFF: 8 KB PRG RAM bank, enabled and write protected through $A001
+    CPU $8000-$9FFF: 8 KB switchable PRG ROM bank, or the second last bank
+    CPU $A000-$BFFF: 8 KB switchable PRG ROM bank
+    CPU $C000-$DFFF: the second last 8 KB bank, or switchable
+    CPU $E000-$FFFF: 8 KB PRG ROM bank, fixed to the last bank
+    PPU $0000-$07FF: 2 KB switchable CHR bank, or four 1 KB banks with the halves inverted
+    PPU $0800-$0FFF: 2 KB switchable CHR bank
+    PPU $1000-$1FFF: four 1 KB switchable CHR banks
+    */
+
+    prg_ram: Box<[u8; PRG_RAM_SIZE]>,
+    prg_rom: PrgRom,
+    chr: ChrMemory,
+
+    bank_select: u8,
+    // R0-R1 are the 2 KB CHR banks, R2-R5 the 1 KB ones, R6-R7 the PRG banks
+    bank_registers: [u8; 8],
+    // None until the game picks one, the header decides until then
+    mirroring: Option<Mirroring>,
+    prg_ram_protect: u8,
+
+    irq_revision: IrqRevision,
+    irq_latch: u8,
+    irq_counter: u8,
+    irq_reload: bool,
+    irq_enabled: bool,
+    irq_pending: bool,
+
+    a12_low_dots: u8,
+}
+
+impl Mmc3 {
+    pub fn new(ines_prg_vec: Vec<&[u8]>, chr: ChrMemory, irq_revision: IrqRevision) -> Mmc3 {
+        Mmc3 {
+            prg_ram: Box::new([0; PRG_RAM_SIZE]),
+            prg_rom: PrgRom::new(ines_prg_vec),
+            chr,
+            bank_select: 0,
+            bank_registers: [0; 8],
+            mirroring: None,
+            // Games that never touch $A001 still expect their RAM to work
+            prg_ram_protect: PRG_RAM_ENABLE_MASK,
+            irq_revision,
+            irq_latch: 0,
+            irq_counter: 0,
+            irq_reload: false,
+            irq_enabled: false,
+            irq_pending: false,
+            a12_low_dots: 0,
+        }
+    }
+
+    // The registers are picked by the address range and whether the address is even or odd
+    fn _write_register(&mut self, address: u16, data: u8) {
+        let even = address & 1 == 0;
+
+        match address {
+            0x8000..=0x9FFF if even => self.bank_select = data,
+            0x8000..=0x9FFF => {
+                let register = (self.bank_select & BANK_SELECT_REGISTER_MASK) as usize;
+                self.bank_registers[register] = data;
+            }
+            0xA000..=0xBFFF if even => {
+                self.mirroring = Some(if data & MIRRORING_HORIZONTAL_MASK > 0 { Mirroring::Horizontal } else { Mirroring::Vertical });
+            }
+            0xA000..=0xBFFF => self.prg_ram_protect = data,
+            0xC000..=0xDFFF if even => self.irq_latch = data,
+            0xC000..=0xDFFF => {
+                self.irq_counter = 0;
+                self.irq_reload = true;
+            }
+            // Disabling also acknowledges the pending IRQ
+            _ if even => {
+                self.irq_enabled = false;
+                self.irq_pending = false;
+            }
+            _ => self.irq_enabled = true,
+        }
+    }
+
+    fn _clock_irq_counter(&mut self) {
+        let counted_down = self.irq_counter > 0 && !self.irq_reload;
+        let reloaded = self.irq_reload;
+
+        if self.irq_counter == 0 || self.irq_reload {
+            self.irq_counter = self.irq_latch;
+            self.irq_reload = false;
+        } else {
+            self.irq_counter -= 1;
+        }
+
+        let fire = match self.irq_revision {
+            IrqRevision::Sharp => self.irq_counter == 0,
+            IrqRevision::Nec => self.irq_counter == 0 && (counted_down || reloaded),
+        };
+
+        if fire && self.irq_enabled {
+            self.irq_pending = true;
+        }
+    }
+
+    // The 8 KB bank mapped at the address
+    fn _prg_bank(&self, address: u16) -> usize {
+        let last = self.prg_rom.get_bank_count(PRG_BANK_SIZE) - 1;
+        let inverted = self.bank_select & BANK_SELECT_PRG_MODE_MASK > 0;
+
+        match (address - PRG_ROM_START) as usize / PRG_BANK_SIZE {
+            0 if inverted => last.saturating_sub(1),
+            0 => self.bank_registers[6] as usize,
+            1 => self.bank_registers[7] as usize,
+            2 if inverted => self.bank_registers[6] as usize,
+            2 => last.saturating_sub(1),
+            _ => last,
+        }
+    }
+
+    // The 1 KB bank mapped at the address. The 2 KB banks ignore the low bit of their register.
+    fn _chr_bank(&self, address: u16) -> usize {
+        let mut slot = address as usize / CHR_BANK_SIZE;
+        if self.bank_select & BANK_SELECT_CHR_INVERSION_MASK > 0 {
+            slot ^= 4;
+        }
+
+        match slot {
+            0 | 1 => (self.bank_registers[0] & !1) as usize | slot,
+            2 | 3 => (self.bank_registers[1] & !1) as usize | (slot & 1),
+            _ => self.bank_registers[slot - 2] as usize,
+        }
+    }
+
+    fn _chr_offset(&self, address: u16) -> usize {
+        self._chr_bank(address) * CHR_BANK_SIZE + (address as usize % CHR_BANK_SIZE)
+    }
+}
+
+impl CartridgeTrait for Mmc3 {
+    fn read_prg(&self, address: u16) -> u8 {
+        match address {
+            PRG_RAM_START..=PRG_RAM_END if self.prg_ram_protect & PRG_RAM_ENABLE_MASK > 0 => {
+                self.prg_ram[(address - PRG_RAM_START) as usize]
+            }
+            PRG_ROM_START..=PRG_ROM_END => {
+                self.prg_rom.read(PRG_BANK_SIZE, self._prg_bank(address), address)
+            }
+            // Open bus without the RAM
+            _ => 0
+        }
+    }
+
+    fn write_prg(&mut self, address: u16, data: u8) {
+        match address {
+            // Enabled and not write protected
+            PRG_RAM_START..=PRG_RAM_END if self.prg_ram_protect & (PRG_RAM_ENABLE_MASK | PRG_RAM_WRITE_PROTECT_MASK) == PRG_RAM_ENABLE_MASK => {
+                self.prg_ram[(address - PRG_RAM_START) as usize] = data;
+            }
+            PRG_ROM_START..=PRG_ROM_END => self._write_register(address, data),
+            _ => {}
+        }
+    }
+
+    fn read_chr(&self, address: u16) -> u8 {
+        self.chr.read(self._chr_offset(address))
+    }
+
+    // Slices don't cross the 1 KB banks
+    fn read_chr_slice(&self, address: u16, len: usize) -> &[u8] {
+        self.chr.read_slice(self._chr_offset(address), len)
+    }
+
+    fn write_chr(&mut self, address: u16, data: u8) {
+        let offset = self._chr_offset(address);
+        self.chr.write(offset, data);
+    }
+
+    fn notify_ppu_address(&mut self, address: u16) {
+        if address & A12_MASK == 0 {
+            self.a12_low_dots = self.a12_low_dots.saturating_add(1);
+            return;
+        }
+
+        if self.a12_low_dots >= A12_LOW_MIN_DOTS {
+            self._clock_irq_counter();
+        }
+        self.a12_low_dots = 0;
+    }
+
+    fn get_irq_signal(&self) -> bool {
+        self.irq_pending
+    }
+
+    fn get_mirroring(&self) -> Option<Mirroring> {
+        self.mirroring
+    }
+
+    fn get_prg_ram(&self) -> Option<&[u8]> { Some(&*self.prg_ram) }
+    fn get_prg_ram_mut(&mut self) -> Option<&mut [u8]> { Some(&mut *self.prg_ram) }
+
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bytes(&*self.prg_ram);
+        self.chr.save_state(writer);
+        writer.write_u8(self.bank_select);
+        writer.write_bytes(&self.bank_registers);
+        writer.write_u8(match self.mirroring {
+            None => 0,
+            Some(Mirroring::Vertical) => 1,
+            Some(_) => 2,
+        });
+        writer.write_u8(self.prg_ram_protect);
+        writer.write_u8(self.irq_latch);
+        writer.write_u8(self.irq_counter);
+        writer.write_bool(self.irq_reload);
+        writer.write_bool(self.irq_enabled);
+        writer.write_bool(self.irq_pending);
+        writer.write_u8(self.a12_low_dots);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), String> {
+        reader.read_bytes(&mut *self.prg_ram)?;
+        self.chr.load_state(reader)?;
+        self.bank_select = reader.read_u8()?;
+        reader.read_bytes(&mut self.bank_registers)?;
+        self.mirroring = match reader.read_u8()? {
+            0 => None,
+            1 => Some(Mirroring::Vertical),
+            _ => Some(Mirroring::Horizontal),
+        };
+        self.prg_ram_protect = reader.read_u8()?;
+        self.irq_latch = reader.read_u8()?;
+        self.irq_counter = reader.read_u8()?;
+        self.irq_reload = reader.read_bool()?;
+        self.irq_enabled = reader.read_bool()?;
+        self.irq_pending = reader.read_bool()?;
+        self.a12_low_dots = reader.read_u8()?;
+
+        Ok(())
+    }
+
+    fn box_clone(&self) -> Box<dyn CartridgeTrait> { Box::new(self.clone()) }
+
+    fn get_instruction_offset(&self) -> u16 { PRG_ROM_START }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Mmc3, IrqRevision};
+    use crate::nes::cartridge::cartridge::CartridgeTrait;
+    use crate::nes::cartridge::chr::ChrMemory;
+
+    // Every 8 KB PRG bank and 1 KB CHR bank is filled with its number
+    fn setup(irq_revision: IrqRevision) -> Mmc3 {
+        let prg: Vec<u8> = (0..16).flat_map(|bank| vec![bank as u8; 0x2000]).collect();
+        let chr: Vec<u8> = (0..32).flat_map(|bank| vec![bank as u8; 0x0400]).collect();
+        Mmc3::new(vec![&prg], ChrMemory::new(vec![&chr], 0), irq_revision)
+    }
+
+    // One scanline of A12 activity: low through the background fetches, high for the sprites
+    fn scanline(mmc3: &mut Mmc3) {
+        for _ in 0..256 {
+            mmc3.notify_ppu_address(0x0000);
+        }
+        for _ in 256..320 {
+            mmc3.notify_ppu_address(0x1000);
+        }
+    }
+
+    #[test]
+    fn test_prg_banks() {
+        let mut mmc3 = setup(IrqRevision::Sharp);
+        mmc3.write_prg(0x8000, 6);
+        mmc3.write_prg(0x8001, 3);
+        mmc3.write_prg(0x8000, 7);
+        mmc3.write_prg(0x8001, 5);
+
+        assert_eq!(3, mmc3.read_prg(0x8000));
+        assert_eq!(5, mmc3.read_prg(0xA000));
+        assert_eq!(14, mmc3.read_prg(0xC000));
+        assert_eq!(15, mmc3.read_prg(0xE000));
+
+        mmc3.write_prg(0x8000, 0x40);
+        assert_eq!(14, mmc3.read_prg(0x8000));
+        assert_eq!(3, mmc3.read_prg(0xC000));
+        assert_eq!(15, mmc3.read_prg(0xFFFF));
+    }
+
+    #[test]
+    fn test_chr_banks() {
+        let mut mmc3 = setup(IrqRevision::Sharp);
+        for (register, bank) in [(0, 3), (1, 8), (2, 20), (3, 21), (4, 22), (5, 23)] {
+            mmc3.write_prg(0x8000, register);
+            mmc3.write_prg(0x8001, bank);
+        }
+
+        assert_eq!(2, mmc3.read_chr(0x0000));
+        assert_eq!(3, mmc3.read_chr(0x0400));
+        assert_eq!(9, mmc3.read_chr(0x0C00));
+        assert_eq!(20, mmc3.read_chr(0x1000));
+        assert_eq!(23, mmc3.read_chr(0x1FFF));
+
+        // Inverted, the 1 KB banks go to $0000
+        mmc3.write_prg(0x8000, 0x80);
+        assert_eq!(20, mmc3.read_chr(0x0000));
+        assert_eq!(2, mmc3.read_chr(0x1000));
+        assert_eq!(&[9, 9], mmc3.read_chr_slice(0x1FFE, 2));
+    }
+
+    #[test]
+    fn test_mirroring_and_prg_ram() {
+        let mut mmc3 = setup(IrqRevision::Sharp);
+        assert!(mmc3.get_mirroring().is_none());
+        mmc3.write_prg(0xA000, 1);
+        assert!(matches!(mmc3.get_mirroring(), Some(crate::ppu::nametable::Mirroring::Horizontal)));
+
+        mmc3.write_prg(0x6000, 0x42);
+        assert_eq!(0x42, mmc3.read_prg(0x6000));
+
+        // Write protected
+        mmc3.write_prg(0xA001, 0xC0);
+        mmc3.write_prg(0x6000, 0x11);
+        assert_eq!(0x42, mmc3.read_prg(0x6000));
+
+        // Disabled
+        mmc3.write_prg(0xA001, 0x00);
+        assert_eq!(0, mmc3.read_prg(0x6000));
+    }
+
+    #[test]
+    fn test_scanline_irq() {
+        let mut mmc3 = setup(IrqRevision::Sharp);
+        mmc3.write_prg(0xC000, 2);
+        mmc3.write_prg(0xC001, 0);
+        mmc3.write_prg(0xE001, 0);
+
+        // Reloads to 2, then counts down
+        scanline(&mut mmc3);
+        scanline(&mut mmc3);
+        assert!(!mmc3.get_irq_signal());
+        scanline(&mut mmc3);
+        assert!(mmc3.get_irq_signal());
+
+        mmc3.write_prg(0xE000, 0);
+        assert!(!mmc3.get_irq_signal());
+    }
+
+    #[test]
+    fn test_short_a12_lows_are_filtered() {
+        let mut mmc3 = setup(IrqRevision::Sharp);
+        mmc3.write_prg(0xC000, 0);
+        mmc3.write_prg(0xE001, 0);
+
+        for _ in 0..4 {
+            mmc3.notify_ppu_address(0x0000);
+        }
+        mmc3.notify_ppu_address(0x1000);
+        assert!(!mmc3.get_irq_signal());
+    }
+
+    #[test]
+    fn test_irq_revisions() {
+        // A latch of 0 fires on every scanline on Sharp, on NEC only once after the reload
+        let mut sharp = setup(IrqRevision::Sharp);
+        let mut nec = setup(IrqRevision::Nec);
+
+        for mmc3 in [&mut sharp, &mut nec] {
+            mmc3.write_prg(0xC000, 0);
+            mmc3.write_prg(0xC001, 0);
+            mmc3.write_prg(0xE001, 0);
+            scanline(mmc3);
+            assert!(mmc3.get_irq_signal());
+            mmc3.write_prg(0xE000, 0);
+            mmc3.write_prg(0xE001, 0);
+            scanline(mmc3);
+        }
+
+        assert!(sharp.get_irq_signal());
+        assert!(!nec.get_irq_signal());
+    }
+}
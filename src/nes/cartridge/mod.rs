@@ -1,3 +1,13 @@
 pub mod cartridge;
+pub mod chr;
+pub mod prg;
 mod nrom;
+mod mmc1;
+mod mmc3;
+mod uxrom;
+mod cnrom;
+mod axrom;
+mod gxrom;
+mod colordreams;
+mod discrete;
 mod frogrom;
\ No newline at end of file
@@ -1,9 +1,11 @@
 use std::boxed::Box;
 use super::cartridge::CartridgeTrait;
-
-use crate::nes::ines;
+use super::chr::ChrMemory;
+use super::prg::PrgRom;
+use crate::nes::savestate::{StateWriter, StateReader};
 
 const PRG_RAM_SIZE: usize = 0x2000;
+// The whole 32 KB window is one bank, a 16 KB image is mirrored into it
 const PRG_ROM_SIZE: usize = 0x8000;
 
 const PRG_RAM_START: u16 = 0x6000;
@@ -11,8 +13,7 @@ const PRG_RAM_END: u16 = 0x7FFF;
 const PRG_ROM_START: u16 = 0x8000;
 const PRG_ROM_END: u16 = 0xFFFF;
 
-const CHR_ROM_SIZE: usize = 0x2000;
-
+#[derive(Clone)]
 pub struct NRom {
     /*
     CPU $6000-$7FFF: Family Basic only: PRG RAM, mirrored as necessary to fill entire 8 KiB window, write protectable with an external switch
@@ -21,34 +22,17 @@ pub struct NRom {
     */
 
     prg_ram: Box<[u8; PRG_RAM_SIZE]>,
-    prg_rom: Box<[u8; PRG_ROM_SIZE]>,
-    chr_rom: Box<[u8; CHR_ROM_SIZE]>
+    prg_rom: PrgRom,
+    // 8 KB of CHR-ROM, or CHR-RAM
+    chr: ChrMemory,
 }
 
 impl NRom {
-    pub fn new(ines_prg_vec: Vec<&[u8]>, ines_chr_rom: &[u8]) -> NRom {
-        let prg_ram = Box::new([0 as u8; PRG_RAM_SIZE]);
-        let mut prg_rom = Box::new([0 as u8; PRG_ROM_SIZE]);
-        let mut chr_rom = Box::new([0 as u8; CHR_ROM_SIZE]);
-
-        match ines_prg_vec.len() {
-            1 => {
-                prg_rom[0..ines::PRG_ROM_CHUNK_SIZE].copy_from_slice(&ines_prg_vec[0]);
-                prg_rom[ines::PRG_ROM_CHUNK_SIZE..PRG_ROM_SIZE].copy_from_slice(&ines_prg_vec[0]);
-            }
-            2 => {
-                prg_rom[0..ines::PRG_ROM_CHUNK_SIZE].copy_from_slice(&ines_prg_vec[0]);
-                prg_rom[ines::PRG_ROM_CHUNK_SIZE..PRG_ROM_SIZE].copy_from_slice(&ines_prg_vec[1]);
-            }
-            _ => unreachable!()
-        }
-
-        chr_rom.copy_from_slice(ines_chr_rom);
-
+    pub fn new(ines_prg_vec: Vec<&[u8]>, chr: ChrMemory) -> NRom {
         NRom {
-            prg_ram,
-            prg_rom,
-            chr_rom
+            prg_ram: Box::new([0u8; PRG_RAM_SIZE]),
+            prg_rom: PrgRom::new(ines_prg_vec),
+            chr,
         }
     }
 }
@@ -60,7 +44,7 @@ impl CartridgeTrait for NRom {
                 self.prg_ram[(address - PRG_RAM_START) as usize]
             }
             PRG_ROM_START..=PRG_ROM_END => {
-                self.prg_rom[(address - PRG_ROM_START) as usize]
+                self.prg_rom.read(PRG_ROM_SIZE, 0, address)
             }
             _ => unreachable!()
         }
@@ -71,25 +55,38 @@ impl CartridgeTrait for NRom {
             PRG_RAM_START..=PRG_RAM_END => {
                 self.prg_ram[(address - PRG_RAM_START) as usize] = data;
             }
-            PRG_ROM_START..=PRG_ROM_END => {
-                self.prg_rom[(address - PRG_ROM_START) as usize] = data;
-            }
+            // ROM, nothing to switch
+            PRG_ROM_START..=PRG_ROM_END => {}
             _ => unreachable!()
         }
     }
 
     fn read_chr(&self, address: u16) -> u8 {
-        self.chr_rom[address as usize]
+        self.chr.read(address as usize)
     }
 
     fn read_chr_slice(&self, address: u16, len: usize) -> &[u8] {
-        let start = address as usize;
-        &self.chr_rom[start..start + len]
+        self.chr.read_slice(address as usize, len)
     }
 
     fn write_chr(&mut self, address: u16, data: u8) {
-        // self.chr_rom[address as usize] = data;
+        self.chr.write(address as usize, data);
+    }
+
+    fn get_prg_ram(&self) -> Option<&[u8]> { Some(&*self.prg_ram) }
+    fn get_prg_ram_mut(&mut self) -> Option<&mut [u8]> { Some(&mut *self.prg_ram) }
+
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bytes(&*self.prg_ram);
+        self.chr.save_state(writer);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), String> {
+        reader.read_bytes(&mut *self.prg_ram)?;
+        self.chr.load_state(reader)
     }
 
+    fn box_clone(&self) -> Box<dyn CartridgeTrait> { Box::new(self.clone()) }
+
     fn get_instruction_offset(&self) -> u16 { PRG_ROM_START }
 }
@@ -0,0 +1,62 @@
+/// The PRG-ROM of a cartridge, all the 16 KB banks of the image in one piece. Mappers read it through
+/// windows of their own bank size, bank numbers past the end wrap around like the unconnected address lines do.
+#[derive(Clone)]
+pub struct PrgRom {
+    data: Vec<u8>,
+}
+
+impl PrgRom {
+    pub fn new(prg_rom: Vec<&[u8]>) -> PrgRom {
+        PrgRom { data: prg_rom.concat() }
+    }
+
+    pub fn get_size(&self) -> usize {
+        self.data.len()
+    }
+
+    // The number of bank_size banks, at least 1 when the image is smaller than a bank
+    pub fn get_bank_count(&self, bank_size: usize) -> usize {
+        (self.data.len() / bank_size).max(1)
+    }
+
+    // Reads at address within the bank_size window of the bank. Smaller images are mirrored to fill the window.
+    pub fn read(&self, bank_size: usize, bank: usize, address: u16) -> u8 {
+        let bank = bank % self.get_bank_count(bank_size);
+        self.data[(bank * bank_size + address as usize % bank_size) % self.data.len()]
+    }
+
+    // Boards without a gate in front of the ROM get a bus conflict on register writes: the ROM drives the byte at the
+    // address while the cpu drives the value, and a 0 on either side wins. Returns what the register latches.
+    pub fn bus_conflict(&self, bank_size: usize, bank: usize, address: u16, data: u8, enabled: bool) -> u8 {
+        if enabled { data & self.read(bank_size, bank, address) } else { data }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PrgRom;
+
+    #[test]
+    fn test_banks() {
+        let prg = PrgRom::new(vec![&[1; 0x4000], &[2; 0x4000], &[3; 0x4000]]);
+        assert_eq!(3, prg.get_bank_count(0x4000));
+        assert_eq!(2, prg.read(0x4000, 1, 0x8000));
+        // Past the last bank
+        assert_eq!(1, prg.read(0x4000, 3, 0xC000));
+    }
+
+    #[test]
+    fn test_small_image_is_mirrored() {
+        let prg = PrgRom::new(vec![&[7; 0x4000]]);
+        assert_eq!(1, prg.get_bank_count(0x8000));
+        assert_eq!(7, prg.read(0x8000, 0, 0xFFFF));
+    }
+
+    #[test]
+    fn test_bus_conflict() {
+        let prg = PrgRom::new(vec![&[0x0F; 0x4000], &[0xF0; 0x4000]]);
+        assert_eq!(0x05, prg.bus_conflict(0x4000, 0, 0x8000, 0x35, true));
+        assert_eq!(0x30, prg.bus_conflict(0x4000, 1, 0x8000, 0x35, true));
+        assert_eq!(0x35, prg.bus_conflict(0x4000, 1, 0x8000, 0x35, false));
+    }
+}
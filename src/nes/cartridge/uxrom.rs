@@ -0,0 +1,76 @@
+use super::chr::ChrMemory;
+use super::discrete::{DiscreteMapper, LatchBoard};
+
+use crate::nes::ines;
+
+const PRG_BANK_SIZE: usize = ines::PRG_ROM_CHUNK_SIZE;
+
+const PRG_ROM_FIXED_START: u16 = 0xC000;
+
+#[derive(Clone)]
+pub struct UxRom {
+    /*
+    CPU $8000-$BFFF: 16 KB switchable PRG ROM bank
+    CPU $C000-$FFFF: 16 KB PRG ROM bank, fixed to the last bank
+    PPU $0000-$1FFF: 8 KB of CHR-RAM, or CHR-ROM on a few boards
+    Writes to $8000-$FFFF select the bank, UNROM uses 3 bits and UOROM 4, more are kept for bigger images
+    */
+
+    board: LatchBoard,
+}
+
+impl UxRom {
+    pub fn new(ines_prg_vec: Vec<&[u8]>, chr: ChrMemory, bus_conflicts: bool) -> UxRom {
+        UxRom {
+            board: LatchBoard::new(ines_prg_vec, chr, bus_conflicts),
+        }
+    }
+}
+
+impl DiscreteMapper for UxRom {
+    fn get_board(&self) -> &LatchBoard { &self.board }
+    fn get_board_mut(&mut self) -> &mut LatchBoard { &mut self.board }
+
+    fn get_prg_bank(&self, address: u16) -> (usize, usize) {
+        if address >= PRG_ROM_FIXED_START {
+            (PRG_BANK_SIZE, self.board.get_prg_bank_count(PRG_BANK_SIZE) - 1)
+        } else {
+            (PRG_BANK_SIZE, self.board.get_latch() as usize)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::UxRom;
+    use crate::nes::cartridge::cartridge::CartridgeTrait;
+    use crate::nes::cartridge::chr::ChrMemory;
+
+    fn setup(bus_conflicts: bool) -> UxRom {
+        let prg: Vec<Vec<u8>> = (0..8).map(|bank| vec![bank as u8; 0x4000]).collect();
+        UxRom::new(prg.iter().map(|bank| bank.as_slice()).collect(), ChrMemory::new(vec![], 0), bus_conflicts)
+    }
+
+    #[test]
+    fn test_banks() {
+        let mut uxrom = setup(false);
+        assert_eq!(0, uxrom.read_prg(0x8000));
+        assert_eq!(7, uxrom.read_prg(0xC000));
+
+        uxrom.write_prg(0x8000, 5);
+        assert_eq!(5, uxrom.read_prg(0xBFFF));
+        assert_eq!(7, uxrom.read_prg(0xFFFF));
+    }
+
+    #[test]
+    fn test_bus_conflicts() {
+        let mut uxrom = setup(true);
+
+        // The fixed bank holds 7 everywhere, 6 & 7 goes through
+        uxrom.write_prg(0xC000, 6);
+        assert_eq!(6, uxrom.read_prg(0x8000));
+        // Bank 6 holds 6, 5 & 6 = 4
+        uxrom.write_prg(0x8000, 5);
+        assert_eq!(4, uxrom.read_prg(0x8000));
+    }
+}
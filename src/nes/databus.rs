@@ -3,6 +3,11 @@ use std::rc::Rc;
 
 use super::super::nes::cartridge::cartridge::Cartridge;
 use crate::ppu::ppu::Ppu;
+use crate::apu::apu;
+use crate::apu::apu::Apu;
+use crate::debugger::debugger::BusAccess;
+use crate::nes::savestate::{StateWriter, StateReader};
+use crate::nes::region::Region;
 
 pub const CARTRIDGE_SPACE_START: u16 = 0x4020;
 
@@ -10,16 +15,28 @@ const INTERNAL_RAM_START: u16 = 0x0000;
 const INTERNAL_RAM_END: u16 = 0x1FFF;
 
 const NES_PPU_REGISTER_START:u16 = 0x2000;
-const NES_PPU_REGISTER_END:u16 = 0x2007;
+const NES_PPU_REGISTER_END:u16 = 0x3FFF;
 
 const NES_APU_IO_REGISTERS_START:u16 = 0x4000;
 const NES_APU_IO_REGISTERS_END:u16 = 0x4017;
 
+const OAMDMA: u16 = 0x4014;
+
 const NES_APU_IO_TEST_MODE_START:u16 = 0x4018;
 const NES_APU_IO_TEST_MODE_END:u16 = 0x401F;
 
 const RAM_SIZE: usize = 0x0800;
 
+// Cycles the cpu is halted for each sample byte the DMC reads
+const DMC_STALL_CYCLES: u16 = 4;
+// A DMC read in the middle of OAM DMA takes one of its get cycles, and the put cycle after it goes unused
+const DMC_STALL_CYCLES_DURING_OAM_DMA: u16 = 2;
+
+// OAM DMA reads and writes 256 bytes after a halt cycle, plus an alignment cycle when it starts on an odd cycle
+const OAM_DMA_CYCLES: u16 = 513;
+const OAM_DMA_PAGE_SIZE: u16 = 0x100;
+const OAMDATA: u16 = 0x2004;
+
 pub const END: u16 = 0xFFFF;
 
 /*
@@ -43,38 +60,237 @@ $FFFE, $FFFF ... IRQ (Interrupt Request) vector
 */
 
 
+// What the ppu did while the bus was clocked
+#[derive(Clone, Copy, Default)]
+pub struct PpuEvents {
+    pub frame_done: bool,
+    pub scanline_done: bool,
+    pub vblank_started: bool,
+}
+
+// An OAM DMA transfer in progress. Every cpu cycle is a get (even) or put (odd) cycle: gets read the next byte of
+// the page, puts write it to OAMDATA.
+#[derive(Clone, Copy)]
+struct OamDma {
+    // The next byte to read
+    address: u16,
+    // Read on a get cycle, waiting for the next put cycle
+    data: Option<u8>,
+    // The first cycle only halts the cpu
+    halted: bool,
+}
+
+// The bus owns everything the cpu can reach, the ppu borrows the cartridge from it
+#[derive(Clone)]
 pub struct NesDatabus {
+    region: Region,
     ram: Box<[u8; RAM_SIZE]>,
-    cartridge: *mut Cartridge,
-    ppu: *mut Ppu
+    cartridge: Cartridge,
+    ppu: Ppu,
+    apu: Apu,
+
+    ppu_events: PpuEvents,
+    // DMA cycles the cpu has not been stalled for yet
+    stall_cycles: u16,
+    // The page written to $4014, until the cpu starts stalling for the transfer
+    oam_dma_page: Option<u8>,
+    oam_dma: Option<OamDma>,
+    cycle_count: u64,
+    // Master cycles the cpu has run ahead of the ppu, PAL has a dot left over every few cpu cycles
+    ppu_master_cycles: i64,
 
+    // Reads and writes are only recorded while the debugger watches the bus
+    recording_accesses: bool,
+    accesses: Vec<BusAccess>,
 }
 
 impl NesDatabus {
-    pub fn new(cartridge: *mut Cartridge, ppu: *mut Ppu) -> NesDatabus {
+    pub fn new(cartridge: Cartridge) -> NesDatabus {
         let ram = [0 as u8; RAM_SIZE];
 
         NesDatabus {
+            region: Region::Ntsc,
             ram: Box::new(ram),
+            ppu: Ppu::new(cartridge.get_mirroring()),
+            apu: Apu::new(),
             cartridge,
-            ppu,
+            ppu_events: PpuEvents::default(),
+            stall_cycles: 0,
+            oam_dma_page: None,
+            oam_dma: None,
+            cycle_count: 0,
+            ppu_master_cycles: 0,
+            recording_accesses: false,
+            accesses: Vec::new(),
+        }
+    }
+
+    pub fn set_region(&mut self, region: Region) {
+        self.region = region;
+        self.ppu.set_region(region);
+        self.apu.set_region(region);
+        self.ppu_master_cycles = 0;
+    }
+
+    pub fn get_region(&self) -> Region { self.region }
+    pub fn get_ppu(&self) -> &Ppu { &self.ppu }
+    pub fn get_cartridge(&self) -> &Cartridge { &self.cartridge }
+    pub fn get_cartridge_mut(&mut self) -> &mut Cartridge { &mut self.cartridge }
+    pub fn get_apu(&self) -> &Apu { &self.apu }
+    pub fn get_apu_mut(&mut self) -> &mut Apu { &mut self.apu }
+
+    // The interrupt line is low while any device pulls it
+    pub fn get_irq_signal(&self) -> bool {
+        self.apu.get_irq_signal() || self.cartridge.get_irq_signal()
+    }
+
+    // Returns the cycles the cpu has to stall for DMA since the last call.
+    // Called once the instruction has finished, OAM DMA starts on the next cycle and runs for as long as the cpu is
+    // stalled: 513 cycles when it starts on a get cycle, one more to align when it starts on a put cycle.
+    pub fn take_stall_cycles(&mut self) -> u16 {
+        if let Some(page) = self.oam_dma_page.take() {
+            self.oam_dma = Some(OamDma { address: (page as u16) << 8, data: None, halted: false });
+            self.stall_cycles += OAM_DMA_CYCLES + (self.cycle_count % 2) as u16;
+        }
+
+        std::mem::take(&mut self.stall_cycles)
+    }
+
+    // Number of cpu cycles the bus has been clocked
+    pub fn get_cycle_count(&self) -> u64 {
+        self.cycle_count
+    }
+
+    pub fn set_recording_accesses(&mut self, recording: bool) {
+        if !recording {
+            self.accesses.clear();
         }
+        self.recording_accesses = recording;
     }
 
-    // TODO move to apu/io controller
+    pub fn get_accesses(&self) -> &[BusAccess] {
+        &self.accesses
+    }
+
+    pub fn clear_accesses(&mut self) {
+        self.accesses.clear();
+    }
+
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u8(self.region.to_u8());
+        writer.write_bytes(&*self.ram);
+        writer.write_u64(self.cycle_count);
+        writer.write_i64(self.ppu_master_cycles);
+        writer.write_bool(self.oam_dma_page.is_some());
+        writer.write_u8(self.oam_dma_page.unwrap_or(0));
+        writer.write_bool(self.oam_dma.is_some());
+        if let Some(dma) = self.oam_dma {
+            writer.write_u16(dma.address);
+            writer.write_bool(dma.data.is_some());
+            writer.write_u8(dma.data.unwrap_or(0));
+            writer.write_bool(dma.halted);
+        }
+        self.ppu.save_state(writer);
+        self.apu.save_state(writer);
+
+        // Mappers save a different amount of state, the length tells a state from another mapper apart
+        let mut cartridge = StateWriter::new_block();
+        self.cartridge.save_state(&mut cartridge);
+        writer.write_block(cartridge);
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<(), String> {
+        // The ppu and apu check their positions against the region, so it goes first
+        self.set_region(Region::read_state(reader)?);
+        reader.read_bytes(&mut *self.ram)?;
+        self.cycle_count = reader.read_u64()?;
+        self.ppu_master_cycles = reader.read_i64()?.clamp(0, self.region.get_master_cycles_per_ppu_dot() - 1);
+        let page_written = reader.read_bool()?;
+        let page = reader.read_u8()?;
+        self.oam_dma_page = Some(page).filter(|_| page_written);
+        self.oam_dma = if reader.read_bool()? {
+            let address = reader.read_u16()?;
+            let has_data = reader.read_bool()?;
+            let data = reader.read_u8()?;
+            Some(OamDma { address, data: Some(data).filter(|_| has_data), halted: reader.read_bool()? })
+        } else {
+            None
+        };
+        self.ppu.load_state(reader)?;
+        self.apu.load_state(reader)?;
+
+        let cartridge = &mut self.cartridge;
+        reader.read_block(|block| cartridge.load_state(block))?;
+        self.ppu.set_mirroring(self.cartridge.get_mirroring());
+
+        self.ppu_events = PpuEvents::default();
+        self.stall_cycles = 0;
+        self.accesses.clear();
+
+        Ok(())
+    }
+
+    // Returns what the ppu did since the last call
+    pub fn take_ppu_events(&mut self) -> PpuEvents {
+        std::mem::take(&mut self.ppu_events)
+    }
+
+    // One cycle of the DMA units. The DMC gets the next get cycle when it needs a byte, OAM DMA the rest.
+    fn _tick_dma(&mut self) {
+        let dmc_address = self.apu.get_dmc_fetch_address();
+        let get_cycle = self.cycle_count & 1 == 0;
+
+        let mut dma = match self.oam_dma {
+            Some(dma) => dma,
+            None => {
+                if let Some(address) = dmc_address {
+                    let data = self._read(address);
+                    self.apu.fill_dmc_sample_buffer(data);
+                    self.stall_cycles += DMC_STALL_CYCLES;
+                }
+                return;
+            }
+        };
+
+        if !dma.halted {
+            dma.halted = true;
+        } else if get_cycle {
+            if let Some(address) = dmc_address {
+                let data = self._read(address);
+                self.apu.fill_dmc_sample_buffer(data);
+                self.stall_cycles += DMC_STALL_CYCLES_DURING_OAM_DMA;
+            } else if dma.data.is_none() {
+                dma.data = Some(self._read(dma.address));
+            }
+        } else if let Some(data) = dma.data.take() {
+            // Put cycles without a byte only align the transfer
+            self.ppu.write_register(&mut self.cartridge, OAMDATA, data);
+            dma.address = dma.address.wrapping_add(1);
+
+            if dma.address & (OAM_DMA_PAGE_SIZE - 1) == 0 {
+                self.oam_dma = None;
+                return;
+            }
+        }
+
+        self.oam_dma = Some(dma);
+    }
+
+    // TODO move to io controller
     fn _write_apu_io(&mut self, address: u16, data: u8) {
-        if address == 0x4014 {
-            println!("OAMDMA")
+        if address == OAMDMA {
+            self.oam_dma_page = Some(data);
         } else if address == 0x4016 {
             println!("CONTROLLER POLL {}", data)
+        } else {
+            self.apu.write_register(address, data);
         }
     }
 
-    // TODO move to apu/io controller
-    fn _read_apu_io(&self, address: u16) -> u8 {
-        if address == 0x4015 {
-            // TODO APU
-            return 0x0;
+    // TODO move to io controller
+    fn _read_apu_io(&mut self, address: u16) -> u8 {
+        if address == apu::STATUS {
+            return self.apu.read_status();
         }
 
         if address == 0x4016 {
@@ -86,19 +302,27 @@ impl NesDatabus {
             return 0x0;
         }
 
-        println!("Crash _read_apu_io {:02x}", address);
-        unreachable!();
+        // The remaining registers are write-only
+        0x0
     }
-}
 
-impl crate::cpu::databus::Databus for NesDatabus {
-    fn read(&self, address: u16) -> u8 {
+    fn _peek_apu_io(&self, address: u16) -> u8 {
+        if address == apu::STATUS {
+            return self.apu.peek_status();
+        }
+
+        // TODO controllers
+        0x0
+    }
+
+    // A read with its side effects, but without recording it for the debugger
+    fn _read(&mut self, address: u16) -> u8 {
         match address {
             INTERNAL_RAM_START..=INTERNAL_RAM_END => {
                 self.ram[address as usize % RAM_SIZE]
             }
-            NES_PPU_REGISTER_START..=NES_PPU_REGISTER_END => unsafe {
-                (*self.ppu).read_register(address)
+            NES_PPU_REGISTER_START..=NES_PPU_REGISTER_END => {
+                self.ppu.read_register(&self.cartridge, address)
             }
             NES_APU_IO_REGISTERS_START..=NES_APU_IO_REGISTERS_END => {
                 self._read_apu_io(address)
@@ -107,43 +331,94 @@ impl crate::cpu::databus::Databus for NesDatabus {
                 println!("Test mode");
                 0
             }
-            CARTRIDGE_SPACE_START..=END => unsafe {
-                (*self.cartridge).read_prg(address)
-            }
-            _ => {
-                println!("CRASH Databus::read {:02x}", address);
-                unreachable!()
+            CARTRIDGE_SPACE_START..=END => {
+                self.cartridge.read_prg(address)
             }
         }
     }
+}
+
+impl crate::cpu::databus::Databus for NesDatabus {
+    fn read(&mut self, address: u16) -> u8 {
+        let data = self._read(address);
+
+        if self.recording_accesses {
+            self.accesses.push(BusAccess { address, data, write: false });
+        }
+
+        data
+    }
 
-    fn read_u16(&self, address: u16) -> u16 {
+    fn read_u16(&mut self, address: u16) -> u16 {
         let lo = self.read(address);
-        let hi = self.read(address + 1);
+        let hi = self.read(address.wrapping_add(1));
 
         ((hi as u16) << 8) + lo as u16
     }
 
+    fn peek(&self, address: u16) -> u8 {
+        match address {
+            INTERNAL_RAM_START..=INTERNAL_RAM_END => {
+                self.ram[address as usize % RAM_SIZE]
+            }
+            NES_PPU_REGISTER_START..=NES_PPU_REGISTER_END => {
+                self.ppu.peek_register(address)
+            }
+            NES_APU_IO_REGISTERS_START..=NES_APU_IO_REGISTERS_END => {
+                self._peek_apu_io(address)
+            }
+            NES_APU_IO_TEST_MODE_START..=NES_APU_IO_TEST_MODE_END => 0,
+            CARTRIDGE_SPACE_START..=END => {
+                self.cartridge.read_prg(address)
+            }
+        }
+    }
+
+    fn tick(&mut self) {
+        // The ppu runs three dots per cpu cycle, or 3.2 on PAL
+        self.cycle_count += 1;
+        self.ppu_master_cycles += self.region.get_master_cycles_per_cpu_cycle();
+
+        while self.ppu_master_cycles >= self.region.get_master_cycles_per_ppu_dot() {
+            self.ppu_master_cycles -= self.region.get_master_cycles_per_ppu_dot();
+
+            let scanline = self.ppu.get_scanline();
+            let vblank = self.ppu.is_vblank();
+
+            self.ppu_events.frame_done |= self.ppu.tick(&self.cartridge);
+            self.cartridge.notify_ppu_address(self.ppu.get_address_bus());
+            self.ppu_events.scanline_done |= self.ppu.get_scanline() != scanline;
+            self.ppu_events.vblank_started |= !vblank && self.ppu.is_vblank();
+        }
+
+        self.apu.tick();
+        self.cartridge.tick_cpu();
+
+        self._tick_dma();
+    }
+
 
     fn write(&mut self, address: u16, data: u8) {
+        if self.recording_accesses {
+            self.accesses.push(BusAccess { address, data, write: true });
+        }
+
         match address {
             INTERNAL_RAM_START..=INTERNAL_RAM_END => {
                 self.ram[address as usize % RAM_SIZE] = data;
             }
-            CARTRIDGE_SPACE_START..=END => unsafe {
-                (*self.cartridge).write_prg(address, data);
+            CARTRIDGE_SPACE_START..=END => {
+                self.cartridge.write_prg(address, data);
+                // The mapper may have switched the nametable mirroring
+                self.ppu.set_mirroring(self.cartridge.get_mirroring());
             }
-            NES_PPU_REGISTER_START..=NES_PPU_REGISTER_END => unsafe {
-                (*self.ppu).write_register(address, data);
+            NES_PPU_REGISTER_START..=NES_PPU_REGISTER_END => {
+                self.ppu.write_register(&mut self.cartridge, address, data);
             }
             NES_APU_IO_REGISTERS_START..=NES_APU_IO_REGISTERS_END => {
                 self._write_apu_io(address, data);
             }
-
-            _ => {
-                println!("CRASH: write ${:04X} = ${:02X}", address, data);
-                unreachable!()
-            }
+            NES_APU_IO_TEST_MODE_START..=NES_APU_IO_TEST_MODE_END => {}
         }
     }
 
@@ -0,0 +1,326 @@
+use crate::nes::rominfo::{RomInfo, TimingMode};
+use crate::ppu::nametable::Mirroring;
+use crate::util::checksum;
+
+/*
+A game database has one game per line, as whitespace separated key=value pairs. Everything after a # is a comment,
+on a line with a game it is taken as the game's name.
+
+crc32=XXXXXXXX      CRC-32 of PRG-ROM and CHR-ROM, without header and trainer
+sha1=XXXX...        SHA-1 of the same, 40 hex digits
+mapper=N
+submapper=N
+mirroring=M         horizontal, vertical or four
+battery=B           0 or 1
+region=R            ntsc, pal, dendy or multi
+prg_ram=N           Sizes in bytes
+prg_nvram=N
+chr_ram=N
+chr_nvram=N
+
+A game needs a crc32 or a sha1, when it has both both must match. Everything else is optional and left as the header
+says when missing.
+*/
+
+const EMBEDDED_DB: &str = include_str!("gamedb.txt");
+
+/// What the database knows about one game. Fields that are None are trusted from the header.
+#[derive(Clone, Default, Debug)]
+pub struct GameEntry {
+    pub name: String,
+
+    pub crc32: Option<u32>,
+    pub sha1: Option<[u8; 20]>,
+
+    pub mapper: Option<u16>,
+    pub submapper: Option<u8>,
+    pub mirroring: Option<Mirroring>,
+    pub four_screen: Option<bool>,
+    pub battery: Option<bool>,
+    pub timing_mode: Option<TimingMode>,
+    pub prg_ram_size: Option<usize>,
+    pub prg_nvram_size: Option<usize>,
+    pub chr_ram_size: Option<usize>,
+    pub chr_nvram_size: Option<usize>,
+}
+
+impl GameEntry {
+    pub fn parse(line: &str) -> Result<Option<GameEntry>, String> {
+        let (fields, name) = match line.find('#') {
+            Some(index) => (&line[..index], line[index + 1..].trim()),
+            None => (line, ""),
+        };
+
+        if fields.trim().is_empty() {
+            return Ok(None);
+        }
+
+        let mut entry = GameEntry { name: name.to_string(), ..GameEntry::default() };
+
+        for field in fields.split_whitespace() {
+            let (key, value) = match field.find('=') {
+                Some(index) => (&field[..index], &field[index + 1..]),
+                None => return Err(format!("Expected key=value, got '{}'", field)),
+            };
+
+            let number = || value.parse::<usize>().map_err(|_| format!("Invalid {} '{}'", key, value));
+
+            match key {
+                "crc32" => {
+                    entry.crc32 = Some(u32::from_str_radix(value, 16).map_err(|_| format!("Invalid crc32 '{}'", value))?);
+                }
+                "sha1" => entry.sha1 = Some(parse_sha1(value)?),
+                "mapper" => entry.mapper = Some(number()? as u16),
+                "submapper" => entry.submapper = Some(number()? as u8),
+                "mirroring" => {
+                    let (mirroring, four_screen) = match value {
+                        "horizontal" => (Mirroring::Horizontal, false),
+                        "vertical" => (Mirroring::Vertical, false),
+                        "four" => (Mirroring::Vertical, true),
+                        _ => return Err(format!("Unknown mirroring '{}', expected one of: horizontal, vertical, four", value)),
+                    };
+                    entry.mirroring = Some(mirroring);
+                    entry.four_screen = Some(four_screen);
+                }
+                "battery" => entry.battery = Some(number()? != 0),
+                "region" => {
+                    entry.timing_mode = Some(match value {
+                        "ntsc" => TimingMode::Ntsc,
+                        "pal" => TimingMode::Pal,
+                        "dendy" => TimingMode::Dendy,
+                        "multi" => TimingMode::MultiRegion,
+                        _ => return Err(format!("Unknown region '{}', expected one of: ntsc, pal, dendy, multi", value)),
+                    });
+                }
+                "prg_ram" => entry.prg_ram_size = Some(number()?),
+                "prg_nvram" => entry.prg_nvram_size = Some(number()?),
+                "chr_ram" => entry.chr_ram_size = Some(number()?),
+                "chr_nvram" => entry.chr_nvram_size = Some(number()?),
+                _ => return Err(format!("Unknown key '{}'", key)),
+            }
+        }
+
+        if entry.crc32.is_none() && entry.sha1.is_none() {
+            return Err("A game needs a crc32 or a sha1".to_string());
+        }
+
+        Ok(Some(entry))
+    }
+
+    // sha1 is only worked out when an entry has one
+    fn matches(&self, crc32: u32, sha1: &mut impl FnMut() -> [u8; 20]) -> bool {
+        self.crc32.iter().all(|value| *value == crc32) && self.sha1.iter().all(|value| *value == sha1())
+    }
+
+    // Overwrites what the header got wrong, and describes each change
+    pub fn apply(&self, info: &mut RomInfo) -> Vec<String> {
+        let mut corrections = Vec::new();
+
+        fn correct<T: PartialEq + std::fmt::Debug>(corrections: &mut Vec<String>, name: &str, field: &mut T, value: Option<T>) {
+            if let Some(value) = value {
+                if *field != value {
+                    corrections.push(format!("{}: {:?} -> {:?}", name, field, value));
+                    *field = value;
+                }
+            }
+        }
+
+        correct(&mut corrections, "Mapper", &mut info.mapper, self.mapper);
+        correct(&mut corrections, "Submapper", &mut info.submapper, self.submapper);
+        correct(&mut corrections, "Mirroring", &mut info.mirroring, self.mirroring);
+        correct(&mut corrections, "Four screen", &mut info.four_screen, self.four_screen);
+        correct(&mut corrections, "Battery", &mut info.battery, self.battery);
+        correct(&mut corrections, "TV-system", &mut info.timing_mode, self.timing_mode);
+        correct(&mut corrections, "PRG-RAM size", &mut info.prg_ram_size, self.prg_ram_size);
+        correct(&mut corrections, "PRG-NVRAM size", &mut info.prg_nvram_size, self.prg_nvram_size);
+        correct(&mut corrections, "CHR-RAM size", &mut info.chr_ram_size, self.chr_ram_size);
+        correct(&mut corrections, "CHR-NVRAM size", &mut info.chr_nvram_size, self.chr_nvram_size);
+
+        corrections
+    }
+}
+
+fn parse_sha1(text: &str) -> Result<[u8; 20], String> {
+    let invalid = || format!("Invalid sha1 '{}', expected 40 hex digits", text);
+
+    if text.len() != 40 || !text.is_ascii() {
+        return Err(invalid());
+    }
+
+    let mut sha1 = [0; 20];
+    for (i, byte) in sha1.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&text[i * 2..i * 2 + 2], 16).map_err(|_| invalid())?;
+    }
+
+    Ok(sha1)
+}
+
+/// Known games whose dumps often come with a wrong header, keyed by the checksums of their PRG-ROM and CHR-ROM.
+#[derive(Clone, Default)]
+pub struct GameDb {
+    entries: Vec<GameEntry>,
+}
+
+impl GameDb {
+    // The database built into cnese
+    pub fn embedded() -> GameDb {
+        let mut db = GameDb::default();
+        db.merge(EMBEDDED_DB).expect("The embedded game database is invalid");
+        db
+    }
+
+    // Adds the games of a database file, they take precedence over the ones already there.
+    // Nothing is added if any line is invalid.
+    pub fn merge(&mut self, text: &str) -> Result<(), String> {
+        let mut entries = Vec::new();
+
+        for (number, line) in text.lines().enumerate() {
+            match GameEntry::parse(line) {
+                Ok(Some(entry)) => entries.push(entry),
+                Ok(None) => {}
+                Err(e) => return Err(format!("Line {}: {}", number + 1, e)),
+            }
+        }
+
+        self.entries.extend(entries);
+        Ok(())
+    }
+
+    pub fn merge_file(&mut self, path: &str) -> Result<(), String> {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("Unable to read {}: {}", path, e))?;
+        self.merge(&text).map_err(|e| format!("{}: {}", path, e))
+    }
+
+    pub fn get_len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn find(&self, prg_rom: &[u8], chr_rom: &[u8]) -> Option<&GameEntry> {
+        let crc32 = checksum::crc32_update(checksum::crc32(prg_rom), chr_rom);
+        // Most entries only have a CRC-32, the SHA-1 is hashed the first time one needs it
+        let mut sha1 = None;
+        let mut get_sha1 = || *sha1.get_or_insert_with(|| checksum::sha1_parts(&[prg_rom, chr_rom]));
+
+        // The last one wins, so merged files override the embedded games
+        self.entries.iter().rev().find(|entry| entry.matches(crc32, &mut get_sha1))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{GameDb, GameEntry};
+    use crate::nes::ines;
+    use crate::nes::rominfo::{RomInfo, TimingMode};
+    use crate::ppu::nametable::Mirroring;
+    use crate::util::checksum;
+
+    const ZELDA_CRC32: u32 = 0x3FE2_72FB;
+
+    // Sets the last 4 bytes so the CRC-32 of data becomes target, by running the CRC backwards through them
+    fn forge_crc32(data: &mut [u8], target: u32) {
+        let table: Vec<u32> = (0..256u32).map(|mut crc| {
+            for _i in 0..8 {
+                crc = if crc & 1 > 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+            }
+            crc
+        }).collect();
+
+        // The last 4 table indexes decide the whole register, the top byte of each entry tells which one it was
+        let mut indexes = [0; 4];
+        let mut crc = !target;
+        for index in indexes.iter_mut().rev() {
+            *index = table.iter().position(|entry| entry >> 24 == crc >> 24).unwrap();
+            crc = (crc ^ table[*index]) << 8;
+        }
+
+        let start = data.len() - 4;
+        let mut crc = !checksum::crc32(&data[..start]);
+        for (byte, index) in data[start..].iter_mut().zip(indexes) {
+            *byte = (crc as usize ^ index) as u8;
+            crc = (crc >> 8) ^ table[index];
+        }
+    }
+
+    #[test]
+    fn test_embedded() {
+        let db = GameDb::embedded();
+        assert!(db.get_len() > 0);
+
+        // The Legend of Zelda as a plain NROM, without the battery
+        let header = [0x4e, 0x45, 0x53, 0x1a, 8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        let mut prg_rom: Vec<u8> = (0..0x20000).map(|i| (i * 7) as u8).collect();
+        forge_crc32(&mut prg_rom, ZELDA_CRC32);
+        assert_eq!(ZELDA_CRC32, checksum::crc32(&prg_rom));
+
+        let mut info = ines::parse_header(&header).unwrap();
+        let entry = db.find(&prg_rom, &[]).unwrap();
+        assert_eq!("Legend of Zelda, The (USA)", entry.name);
+
+        let corrections = entry.apply(&mut info);
+        assert_eq!(vec![
+            "Mapper: 0 -> 1".to_string(),
+            "Battery: false -> true".to_string(),
+            "PRG-NVRAM size: 0 -> 8192".to_string(),
+        ], corrections);
+        assert_eq!(1, info.mapper);
+        assert!(info.battery);
+        assert_eq!(0x2000, info.get_total_prg_ram_size());
+        // Left as the header says
+        assert_eq!(Mirroring::Horizontal, info.mirroring);
+
+        // Some other game
+        prg_rom[0] ^= 1;
+        assert!(db.find(&prg_rom, &[]).is_none());
+    }
+
+    #[test]
+    fn test_parse() {
+        assert!(GameEntry::parse("  # Only a comment").unwrap().is_none());
+        assert!(GameEntry::parse("").unwrap().is_none());
+
+        let entry = GameEntry::parse("crc32=CBF43926 mapper=1 mirroring=four region=pal prg_nvram=8192 # Test game")
+            .unwrap().unwrap();
+        assert_eq!("Test game", entry.name);
+        assert_eq!(Some(0xCBF4_3926), entry.crc32);
+        assert_eq!(Some(1), entry.mapper);
+        assert_eq!(Some(true), entry.four_screen);
+        assert_eq!(Some(TimingMode::Pal), entry.timing_mode);
+        assert_eq!(Some(0x2000), entry.prg_nvram_size);
+        assert_eq!(None, entry.battery);
+
+        assert!(GameEntry::parse("mapper=1").is_err());
+        assert!(GameEntry::parse("crc32=CBF43926 mapper").is_err());
+        assert!(GameEntry::parse("crc32=CBF43926 colour=blue").is_err());
+        assert!(GameEntry::parse("sha1=1234").is_err());
+    }
+
+    #[test]
+    fn test_find_and_apply() {
+        let prg = b"12345";
+        let chr = b"6789";
+        let sha1: String = checksum::sha1(b"123456789").iter().map(|byte| format!("{:02x}", byte)).collect();
+
+        let mut db = GameDb::default();
+        db.merge("crc32=CBF43926 mapper=4 battery=1\n").unwrap();
+        assert!(db.find(b"1234", chr).is_none());
+
+        let mut info = RomInfo { mapper: 1, mirroring: Mirroring::Vertical, ..RomInfo::default() };
+        let corrections = db.find(prg, chr).unwrap().apply(&mut info);
+        assert_eq!(4, info.mapper);
+        assert!(info.battery);
+        // Not in the entry, left alone
+        assert_eq!(Mirroring::Vertical, info.mirroring);
+        assert_eq!(vec!["Mapper: 1 -> 4".to_string(), "Battery: false -> true".to_string()], corrections);
+
+        // A later file wins, and a sha1 that doesn't match rules the entry out
+        db.merge(&format!("crc32=CBF43926 sha1={} mapper=2", sha1)).unwrap();
+        db.merge(&format!("crc32=CBF43926 sha1={} mapper=3", "0".repeat(40))).unwrap();
+        assert_eq!(Some(2), db.find(prg, chr).unwrap().mapper);
+
+        // A file with an error adds none of its games
+        let len = db.get_len();
+        assert!(db.merge("crc32=CBF43926 mapper=5\nmapper=1").unwrap_err().starts_with("Line 2"));
+        assert_eq!(len, db.get_len());
+        assert_eq!(Some(2), db.find(prg, chr).unwrap().mapper);
+    }
+}
@@ -2,6 +2,11 @@ use crate::util::file;
 
 use super::cartridge::cartridge;
 use crate::nes::cartridge::cartridge::Cartridge;
+use crate::nes::rominfo::{RomInfo, ConsoleType, TimingMode};
+use crate::nes::loaderror::LoadError;
+use crate::nes::gamedb::GameDb;
+use crate::nes::cartridge::chr::DEFAULT_CHR_RAM_SIZE;
+use crate::ppu::nametable::Mirroring;
 /*
 An iNES file consists of the following sections, in order:
 
@@ -44,64 +49,466 @@ const FLAGS_6_TRAINER_MASK: u8 = 4;
 const FLAGS_6_IGNORE_MIRRORING_MASK: u8 = 8;
 
 const FLAGS_7_OFFSET: usize = 7;
-// const FLAGS_8_OFFSET: usize = 8;
-// const FLAGS_9_OFFSET: usize = 9;
+const FLAGS_7_VS_SYSTEM: u8 = 1;
+const FLAGS_7_PLAYCHOICE_10: u8 = 2;
+const FLAGS_7_CONSOLE_TYPE_MASK: u8 = 0x03;
+const FLAGS_7_CONSOLE_TYPE_EXTENDED: u8 = 3;
+// Bits 2-3 are 2 in an NES 2.0 header
+const FLAGS_7_NES_2_MASK: u8 = 0x0C;
+const FLAGS_7_NES_2: u8 = 0x08;
 
-const FLAGS_10_OFFSET: usize = 7;
+const FLAGS_8_OFFSET: usize = 8;
+const FLAGS_8_PRG_RAM_UNIT: usize = 0x2000;
+const FLAGS_9_OFFSET: usize = 9;
+const FLAGS_9_PAL_MASK: u8 = 1;
+
+const FLAGS_10_OFFSET: usize = 10;
 const FLAGS_10_TV_SYSTEM_MASK:u8 = 0x3;
+const FLAGS_10_TV_SYSTEM_PAL: u8 = 2;
+
+// Bytes 8-10 are only trusted when the padding is clean, a name in there overwrites them too
+const PADDING_START: usize = 11;
+// An iNES header with anything in bytes 12-15 is an old one where a ripper's name overwrote byte 7 as well
+const DIRTY_PADDING_START: usize = 12;
+
+/*
+NES 2.0 uses the padding:
+
+8: Mapper bits 8-11 in the low nibble, submapper in the high nibble
+9: PRG-ROM size MSB in the low nibble, CHR-ROM size MSB in the high nibble
+10: PRG-RAM shift count in the low nibble, PRG-NVRAM shift count in the high nibble
+11: CHR-RAM shift count in the low nibble, CHR-NVRAM shift count in the high nibble
+12: CPU/PPU timing mode in bits 0-1
+13: Vs. System type, or the extended console type in the low nibble
+14: Number of miscellaneous roms
+15: Default expansion device in bits 0-5
+*/
+const NES_2_MAPPER_OFFSET: usize = 8;
+const NES_2_ROM_SIZE_MSB_OFFSET: usize = 9;
+const NES_2_PRG_RAM_OFFSET: usize = 10;
+const NES_2_CHR_RAM_OFFSET: usize = 11;
+const NES_2_TIMING_OFFSET: usize = 12;
+const NES_2_TIMING_MASK: u8 = 0x03;
+const NES_2_EXTENDED_CONSOLE_TYPE_OFFSET: usize = 13;
+const NES_2_EXPANSION_DEVICE_OFFSET: usize = 15;
+const NES_2_EXPANSION_DEVICE_MASK: u8 = 0x3F;
+
+// With an MSB nibble of $F the LSB byte is EEEEEEMM, the size is 2^E * (MM * 2 + 1) bytes
+const NES_2_EXPONENT_NOTATION: u8 = 0x0F;
+
+// The RAM sizes are 64 << n bytes, 0 means none
+fn nes_2_ram_size(shift: u8) -> usize {
+    match shift {
+        0 => 0,
+        shift => 64 << shift,
+    }
+}
+
+fn nes_2_rom_size(lsb: u8, msb: u8, unit: usize) -> usize {
+    if msb == NES_2_EXPONENT_NOTATION {
+        let exponent = (lsb >> 2) as u32;
+        let multiplier = (lsb & 0x03) as usize * 2 + 1;
+        2usize.saturating_pow(exponent).saturating_mul(multiplier)
+    } else {
+        (((msb as usize) << 8) | lsb as usize) * unit
+    }
+}
+
+/// Reads the 16 byte header of an iNES or NES 2.0 file.
+pub fn parse_header(header: &[u8]) -> Result<RomInfo, LoadError> {
+    if header.len() >= INES_PREFIX.len() && header[0..INES_PREFIX.len()] != INES_PREFIX {
+        return Err(LoadError::BadMagic);
+    }
+    section(header, 0, HEADER_SIZE, "header")?;
+
+    let flags_6 = header[FLAGS_6_OFFSET];
+    let nes_2 = header[FLAGS_7_OFFSET] & FLAGS_7_NES_2_MASK == FLAGS_7_NES_2;
+    let dirty = !nes_2 && (header[FLAGS_7_OFFSET] & FLAGS_7_NES_2_MASK != 0 ||
+        header[DIRTY_PADDING_START..HEADER_SIZE].iter().any(|byte| *byte != 0));
+    // Bytes 8-10 of iNES also need byte 11 to be clean
+    let clean_padding = header[PADDING_START..HEADER_SIZE].iter().all(|byte| *byte == 0);
+    let flags_7 = if dirty { 0 } else { header[FLAGS_7_OFFSET] };
+
+    let mut info = RomInfo {
+        nes_2,
+        mapper: ((flags_7 & 0xF0) | (flags_6 >> 4)) as u16,
+        prg_rom_size: header[PRG_ROM_CHUNK_COUNT_OFFSET] as usize * PRG_ROM_CHUNK_SIZE,
+        chr_rom_size: header[CHR_ROM_SIZE_OFFSET] as usize * CHR_ROM_CHUNK_SIZE,
+        mirroring: if flags_6 & FLAGS_6_MIRRORING_MASK > 0 { Mirroring::Vertical } else { Mirroring::Horizontal },
+        four_screen: flags_6 & FLAGS_6_IGNORE_MIRRORING_MASK > 0,
+        battery: flags_6 & FLAGS_6_BATTERY_MASK > 0,
+        trainer: flags_6 & FLAGS_6_TRAINER_MASK > 0,
+        console_type: match flags_7 & FLAGS_7_CONSOLE_TYPE_MASK {
+            FLAGS_7_VS_SYSTEM => ConsoleType::VsSystem,
+            FLAGS_7_PLAYCHOICE_10 => ConsoleType::Playchoice10,
+            FLAGS_7_CONSOLE_TYPE_EXTENDED if nes_2 => ConsoleType::Extended(header[NES_2_EXTENDED_CONSOLE_TYPE_OFFSET] & 0x0F),
+            _ => ConsoleType::Nes,
+        },
+        ..RomInfo::default()
+    };
 
+    if nes_2 {
+        info.mapper |= ((header[NES_2_MAPPER_OFFSET] & 0x0F) as u16) << 8;
+        info.submapper = header[NES_2_MAPPER_OFFSET] >> 4;
 
-pub fn open_ines(path: &String) -> Result<(Cartridge), String> {
-    let file_data = file::read_file(path);
+        let size_msb = header[NES_2_ROM_SIZE_MSB_OFFSET];
+        info.prg_rom_size = nes_2_rom_size(header[PRG_ROM_CHUNK_COUNT_OFFSET], size_msb & 0x0F, PRG_ROM_CHUNK_SIZE);
+        info.chr_rom_size = nes_2_rom_size(header[CHR_ROM_SIZE_OFFSET], size_msb >> 4, CHR_ROM_CHUNK_SIZE);
 
-    let header = &file_data[0..HEADER_SIZE];
+        info.prg_ram_size = nes_2_ram_size(header[NES_2_PRG_RAM_OFFSET] & 0x0F);
+        info.prg_nvram_size = nes_2_ram_size(header[NES_2_PRG_RAM_OFFSET] >> 4);
+        info.chr_ram_size = nes_2_ram_size(header[NES_2_CHR_RAM_OFFSET] & 0x0F);
+        info.chr_nvram_size = nes_2_ram_size(header[NES_2_CHR_RAM_OFFSET] >> 4);
+
+        info.timing_mode = match header[NES_2_TIMING_OFFSET] & NES_2_TIMING_MASK {
+            0 => TimingMode::Ntsc,
+            1 => TimingMode::Pal,
+            2 => TimingMode::MultiRegion,
+            _ => TimingMode::Dendy,
+        };
+        info.expansion_device = header[NES_2_EXPANSION_DEVICE_OFFSET] & NES_2_EXPANSION_DEVICE_MASK;
+    } else if clean_padding {
+        // The battery backs the whole PRG-RAM. 0 is the 8 KB most boards have, left to the mapper.
+        let prg_ram_size = header[FLAGS_8_OFFSET] as usize * FLAGS_8_PRG_RAM_UNIT;
+        if info.battery {
+            info.prg_nvram_size = prg_ram_size;
+        } else {
+            info.prg_ram_size = prg_ram_size;
+        }
+
+        // Anything not marked as PAL, including the dual compatible values, runs as NTSC
+        let pal = header[FLAGS_9_OFFSET] & FLAGS_9_PAL_MASK > 0 ||
+            header[FLAGS_10_OFFSET] & FLAGS_10_TV_SYSTEM_MASK == FLAGS_10_TV_SYSTEM_PAL;
+        info.timing_mode = if pal { TimingMode::Pal } else { TimingMode::Ntsc };
+    }
+
+    Ok(info)
+}
 
-    let ines_prefix = &header[0..4];
-    if !ines_prefix.eq(&INES_PREFIX) {
-        return Err(format!("Not a valid iNES file"));
+// The len bytes at offset, or how much of them is missing
+fn section<'a>(data: &'a [u8], offset: usize, len: usize, name: &'static str) -> Result<&'a [u8], LoadError> {
+    let available = data.len().saturating_sub(offset);
+    if available < len {
+        return Err(LoadError::Truncated { section: name, expected: len, actual: available });
     }
 
-    let prg_size = header[PRG_ROM_CHUNK_COUNT_OFFSET];
-    let chr_size = header[CHR_ROM_SIZE_OFFSET];
-    let mirroring= header[FLAGS_6_OFFSET] & FLAGS_6_MIRRORING_MASK;
-    let battery_ram = (header[FLAGS_6_OFFSET] & FLAGS_6_BATTERY_MASK) > 0;
-    let trainer_present = (header[FLAGS_6_OFFSET] & FLAGS_6_TRAINER_MASK) > 0;
-    let ignore_mirroring = header[FLAGS_6_OFFSET] & FLAGS_6_IGNORE_MIRRORING_MASK > 0;
-    let mapper = (header[FLAGS_7_OFFSET] & 0xF0) + (header[FLAGS_6_OFFSET] >> 4);
-    let tv_system = header[FLAGS_10_OFFSET] & FLAGS_10_TV_SYSTEM_MASK;
+    Ok(&data[offset..offset + len])
+}
 
-    let mut offset = if trainer_present { TRAINER_SIZE + HEADER_SIZE } else { HEADER_SIZE };
+/// Builds the cartridge from the contents of an iNES or NES 2.0 file. Anything after the CHR-ROM is ignored.
+/// A game found in the database gets the header fields it has corrected.
+pub fn load_ines(file_data: &[u8], db: &GameDb) -> Result<(Cartridge, RomInfo), LoadError> {
+    let mut info = parse_header(file_data)?;
 
-    let mut prg_rom_vec = Vec::new();
-    for _i in 0..prg_size {
-        prg_rom_vec.push(&file_data[offset..offset + PRG_ROM_CHUNK_SIZE]);
-        offset += PRG_ROM_CHUNK_SIZE;
+    let mut offset = HEADER_SIZE;
+    if info.trainer {
+        info.trainer_data = section(file_data, offset, TRAINER_SIZE, "trainer")?.to_vec();
+        offset += TRAINER_SIZE;
     }
+    let prg_rom = section(file_data, offset, info.prg_rom_size, "PRG-ROM")?;
+    let chr_rom = section(file_data, offset + info.prg_rom_size, info.chr_rom_size, "CHR-ROM")?;
 
-    let mut chr_rom_vec = Vec::new();
-    for _i in 0..chr_size {
-        chr_rom_vec.push(&file_data[offset..offset + CHR_ROM_CHUNK_SIZE]);
-        offset += CHR_ROM_CHUNK_SIZE;
+    if let Some(entry) = db.find(prg_rom, chr_rom) {
+        let corrections = entry.apply(&mut info);
+        if !corrections.is_empty() {
+            println!("Corrected the header of {} from the game database:", if entry.name.is_empty() { "the game" } else { &entry.name });
+            for correction in corrections {
+                println!("  {}", correction);
+            }
+        }
     }
 
+    let cartridge = cartridge::create_cartridge_from_ines(&info, prg_rom, chr_rom)?;
+
+    Ok((cartridge, info))
+}
+
+/// Copies the trainer of the rom into PRG-RAM. Done after the battery RAM is loaded, which would overwrite it.
+pub fn load_trainer(cartridge: &mut Cartridge, info: &RomInfo) {
+    if info.trainer && !cartridge.load_trainer(&info.trainer_data) {
+        println!("Warning: the trainer is ignored, mapper {} has no PRG-RAM at $7000-$71FF", info.mapper);
+    }
+}
+
+pub fn open_ines(path: &str, db: &GameDb) -> Result<(Cartridge, RomInfo), LoadError> {
+    let file_data = file::read_file(path).map_err(|e| LoadError::io(path, e))?;
+    let (cartridge, info) = load_ines(&file_data, db)?;
+
     #[cfg(debug_assertions)] {
-        println!("Parsed iNES: {}", path);
+        println!("Parsed {}: {}", if info.nes_2 { "NES 2.0" } else { "iNES" }, path);
         println!("===========================");
 
-        println!("PRG size: {} kb", prg_size as usize * PRG_ROM_CHUNK_SIZE);
-        println!("CHR size: {}", chr_size as usize * CHR_ROM_CHUNK_SIZE);
+        println!("PRG size: {}", info.prg_rom_size);
+        println!("CHR size: {}", info.chr_rom_size);
+        if info.chr_rom_size == 0 {
+            let chr_ram_size = info.get_total_chr_ram_size();
+            println!("CHR-RAM size: {}", if chr_ram_size == 0 { DEFAULT_CHR_RAM_SIZE } else { chr_ram_size });
+        }
+
+        if info.get_total_prg_ram_size() > 0 {
+            println!("PRG-RAM size: {} ({} battery backed)", info.get_total_prg_ram_size(), info.prg_nvram_size);
+        }
 
-        println!("Mirroring: {}", mirroring);
-        println!("Battery present: {}", battery_ram);
-        println!("Trainer present: {}", trainer_present);
-        println!("Ignore mirroring: {}", ignore_mirroring);
-        println!("Mapper: {}", mapper);
-        println!("TV-system: {}", tv_system);
+        println!("Mirroring: {:?}", info.mirroring);
+        println!("Battery present: {}", info.battery);
+        println!("Trainer present: {}", info.trainer);
+        println!("Ignore mirroring: {}", info.four_screen);
+        println!("Mapper: {}", info.mapper);
+        if info.submapper > 0 {
+            println!("Submapper: {}", info.submapper);
+        }
+        println!("Console: {:?}", info.console_type);
+        println!("TV-system: {:?}", info.timing_mode);
+        if info.expansion_device > 0 {
+            println!("Expansion device: {}", info.expansion_device);
+        }
         println!("===========================");
 
     }
 
-    let cartridge = cartridge::create_cartridge_from_ines(mapper, prg_rom_vec, chr_rom_vec, mirroring)?;
+    Ok((cartridge, info))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_header, load_ines, load_trainer, HEADER_SIZE, TRAINER_SIZE, PRG_ROM_CHUNK_SIZE, CHR_ROM_CHUNK_SIZE};
+    use crate::nes::loaderror::LoadError;
+    use crate::nes::gamedb::GameDb;
+    use crate::util::checksum;
+    use crate::nes::region::Region;
+    use crate::nes::rominfo::{ConsoleType, TimingMode};
+    use crate::ppu::nametable::Mirroring;
+
+    fn region(header: &[u8]) -> Region {
+        parse_header(header).unwrap().get_region()
+    }
+
+    #[test]
+    fn test_header_region() {
+        let mut header = [0x4e, 0x45, 0x53, 0x1a, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        assert_eq!(Region::Ntsc, region(&header));
+
+        header[9] = 1;
+        assert_eq!(Region::Pal, region(&header));
+
+        header[9] = 0;
+        header[10] = 2;
+        assert_eq!(Region::Pal, region(&header));
+
+        // Dual compatible
+        header[10] = 3;
+        assert_eq!(Region::Ntsc, region(&header));
+
+        // A name written over the padding
+        header[10] = 2;
+        header[12] = b'D';
+        assert_eq!(Region::Ntsc, region(&header));
+    }
+
+    #[test]
+    fn test_nes_2_region() {
+        let mut header = [0x4e, 0x45, 0x53, 0x1a, 1, 1, 0, 0x08, 0, 0, 0, 0, 1, 0, 0, 0];
+        assert_eq!(Region::Pal, region(&header));
+        header[12] = 2;
+        assert_eq!(TimingMode::MultiRegion, parse_header(&header).unwrap().timing_mode);
+        assert_eq!(Region::Ntsc, region(&header));
+        header[12] = 3;
+        assert_eq!(Region::Dendy, region(&header));
+    }
+
+    #[test]
+    fn test_chr_ram_size() {
+        let mut header = [0x4e, 0x45, 0x53, 0x1a, 1, 0, 0, 0, 0, 0, 0, 0x07, 0, 0, 0, 0];
+        // Byte 11 is padding in iNES
+        assert_eq!(0, parse_header(&header).unwrap().get_total_chr_ram_size());
 
-    Ok(cartridge)
+        header[7] = 0x08;
+        assert_eq!(0x2000, parse_header(&header).unwrap().chr_ram_size);
+        header[11] = 0x09;
+        assert_eq!(0x8000, parse_header(&header).unwrap().chr_ram_size);
+        header[11] = 0x00;
+        assert_eq!(0, parse_header(&header).unwrap().get_total_chr_ram_size());
+        header[11] = 0x70;
+        assert_eq!(0x2000, parse_header(&header).unwrap().chr_nvram_size);
+    }
+
+    #[test]
+    fn test_prg_ram_size() {
+        let mut header = [0x4e, 0x45, 0x53, 0x1a, 32, 0, 0x10, 0, 0, 0, 0x07, 0, 0, 0, 0, 0];
+        assert_eq!(0, parse_header(&header).unwrap().get_total_prg_ram_size());
+
+        // SXROM: 32 KB, all battery backed
+        header[7] = 0x08;
+        header[10] = 0x90;
+        let info = parse_header(&header).unwrap();
+        assert_eq!(0x8000, info.prg_nvram_size);
+        assert_eq!(0x8000, info.get_total_prg_ram_size());
+        // SOROM: 8 KB volatile and 8 KB battery backed
+        header[10] = 0x77;
+        assert_eq!(0x4000, parse_header(&header).unwrap().get_total_prg_ram_size());
+
+        // iNES counts 8 KB units in byte 8
+        let header = [0x4e, 0x45, 0x53, 0x1a, 32, 0, 0x12, 0, 4, 0, 0, 0, 0, 0, 0, 0];
+        assert_eq!(0x8000, parse_header(&header).unwrap().prg_nvram_size);
+    }
+
+    #[test]
+    fn test_mapper() {
+        let mut header = [0x4e, 0x45, 0x53, 0x1a, 8, 0, 0x41, 0x40, 0, 0, 0, 0, 0, 0, 0, 0];
+        let info = parse_header(&header).unwrap();
+        assert_eq!(0x44, info.mapper);
+        assert_eq!(Mirroring::Vertical, info.mirroring);
+        assert!(!info.nes_2);
+
+        // "DiskDude!" over bytes 7-15, only the low nibble of flags 6 counts
+        header[7..16].copy_from_slice(b"DiskDude!");
+        assert_eq!(4, parse_header(&header).unwrap().mapper);
+
+        // NES 2.0: mapper bits 8-11 and the submapper in byte 8
+        let header = [0x4e, 0x45, 0x53, 0x1a, 8, 0, 0x41, 0x48, 0x31, 0, 0, 0, 0, 0, 0, 0x02];
+        let info = parse_header(&header).unwrap();
+        assert!(info.nes_2);
+        assert_eq!(0x144, info.mapper);
+        assert_eq!(3, info.submapper);
+        assert_eq!(2, info.expansion_device);
+    }
+
+    #[test]
+    fn test_nes_2_rom_sizes() {
+        // 0x102 16 KB PRG banks, 0x201 8 KB CHR banks
+        let mut header = [0x4e, 0x45, 0x53, 0x1a, 0x02, 0x01, 0, 0x08, 0, 0x21, 0, 0, 0, 0, 0, 0];
+        let info = parse_header(&header).unwrap();
+        assert_eq!(0x102 * 0x4000, info.prg_rom_size);
+        assert_eq!(0x201 * 0x2000, info.chr_rom_size);
+
+        // Exponent-multiplier: 2^4 * 3 bytes of PRG, 2^10 * 1 of CHR
+        header[9] = 0xFF;
+        header[4] = (4 << 2) | 1;
+        header[5] = 10 << 2;
+        let info = parse_header(&header).unwrap();
+        assert_eq!(48, info.prg_rom_size);
+        assert_eq!(1024, info.chr_rom_size);
+    }
+
+    #[test]
+    fn test_console_type() {
+        let mut header = [0x4e, 0x45, 0x53, 0x1a, 1, 1, 0, 0x01, 0, 0, 0, 0, 0, 0, 0, 0];
+        assert_eq!(ConsoleType::VsSystem, parse_header(&header).unwrap().console_type);
+
+        header[7] = 0x0B;
+        header[13] = 0x03;
+        assert_eq!(ConsoleType::Extended(3), parse_header(&header).unwrap().console_type);
+    }
+
+    #[test]
+    fn test_not_ines() {
+        assert!(matches!(parse_header(b"NES\x00 not a rom.."), Err(LoadError::BadMagic)));
+    }
+
+    // 1 bank of PRG-ROM and 1 of CHR-ROM
+    fn image(flags_6: u8) -> Vec<u8> {
+        let mut data = vec![0x4e, 0x45, 0x53, 0x1a, 1, 1, flags_6, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        data.resize(HEADER_SIZE + PRG_ROM_CHUNK_SIZE + CHR_ROM_CHUNK_SIZE, 0);
+        data
+    }
+
+    fn load_error(data: &[u8]) -> LoadError {
+        load_ines(data, &GameDb::default()).err().expect("the image should not load")
+    }
+
+    #[test]
+    fn test_load() {
+        let (_, info) = load_ines(&image(0), &GameDb::default()).unwrap();
+        assert_eq!(PRG_ROM_CHUNK_SIZE, info.prg_rom_size);
+
+        // A title after the CHR-ROM
+        let mut data = image(0);
+        data.extend_from_slice(b"Some title");
+        assert!(load_ines(&data, &GameDb::default()).is_ok());
+    }
+
+    #[test]
+    fn test_truncated() {
+        assert!(matches!(load_error(&[]), LoadError::Truncated { section: "header", expected: 16, actual: 0 }));
+        assert!(matches!(load_error(b"NES\x1a\x01"), LoadError::Truncated { section: "header", .. }));
+
+        let data = image(0);
+        assert!(matches!(load_error(&data[..HEADER_SIZE + 0x100]),
+                         LoadError::Truncated { section: "PRG-ROM", expected: PRG_ROM_CHUNK_SIZE, actual: 0x100 }));
+        assert!(matches!(load_error(&data[..data.len() - 1]),
+                         LoadError::Truncated { section: "CHR-ROM", actual: 0x1FFF, .. }));
+
+        // The header says there is a trainer, but the file doesn't have room for it
+        let data = image(0x04);
+        assert!(matches!(load_error(&data[..HEADER_SIZE + 0x10]), LoadError::Truncated { section: "trainer", .. }));
+        assert!(matches!(load_error(&data), LoadError::Truncated { section: "CHR-ROM", actual, .. } if actual == CHR_ROM_CHUNK_SIZE - TRAINER_SIZE));
+    }
+
+    #[test]
+    fn test_corrupted_header() {
+        let mut data = image(0);
+        data[0] = b'M';
+        assert!(matches!(load_error(&data), LoadError::BadMagic));
+
+        // Mapper 15 isn't supported
+        let mut data = image(0xF0);
+        assert!(matches!(load_error(&data), LoadError::UnsupportedMapper(15)));
+
+        data[6] = 0;
+        data[4] = 0;
+        assert!(matches!(load_error(&data), LoadError::NoPrgRom));
+
+        // An NES 2.0 PRG-ROM size far beyond the file
+        let mut data = image(0);
+        data[7] = 0x08;
+        data[9] = 0x0F;
+        data[4] = 0xFF;
+        assert!(matches!(load_error(&data), LoadError::Truncated { section: "PRG-ROM", .. }));
+    }
+
+    #[test]
+    fn test_game_db() {
+        // Mapper 15 in the header, the database knows better
+        let mut data = image(0xF0);
+        data[HEADER_SIZE] = 0x42;
+        let crc32 = checksum::crc32(&data[HEADER_SIZE..]);
+
+        let mut db = GameDb::default();
+        db.merge(&format!("crc32={:08X} mapper=0 mirroring=vertical battery=1 region=pal", crc32)).unwrap();
+
+        let (cartridge, info) = load_ines(&data, &db).unwrap();
+        assert_eq!(0, info.mapper);
+        assert_eq!(Mirroring::Vertical, info.mirroring);
+        assert_eq!(Region::Pal, info.get_region());
+        assert!(cartridge.get_battery_ram().is_some());
+
+        // Another game doesn't match
+        data[HEADER_SIZE] = 0x43;
+        assert!(matches!(load_error(&data), LoadError::UnsupportedMapper(15)));
+    }
+
+    #[test]
+    fn test_trainer() {
+        let mut data = image(0x04);
+        let trainer: Vec<u8> = (0..TRAINER_SIZE).map(|i| i as u8).collect();
+        data.splice(HEADER_SIZE..HEADER_SIZE, trainer.iter().cloned());
+        data[HEADER_SIZE + TRAINER_SIZE] = 0x42;
+
+        let (mut cartridge, info) = load_ines(&data, &GameDb::default()).unwrap();
+        assert_eq!(trainer, info.trainer_data);
+        assert_eq!(0, cartridge.read_prg(0x71FF));
+
+        load_trainer(&mut cartridge, &info);
+        assert_eq!(0x00, cartridge.read_prg(0x7000));
+        assert_eq!(0xFF, cartridge.read_prg(0x71FF));
+        assert_eq!(0, cartridge.read_prg(0x7200));
+        // The PRG-ROM starts after the trainer
+        assert_eq!(0x42, cartridge.read_prg(0x8000));
+
+        // UxROM has no RAM for it, the game still loads
+        data[6] = 0x24;
+        let (mut cartridge, info) = load_ines(&data, &GameDb::default()).unwrap();
+        assert_eq!(trainer, info.trainer_data);
+        load_trainer(&mut cartridge, &info);
+    }
 }
@@ -0,0 +1,38 @@
+use std::fmt;
+
+/// Why a rom image could not be turned into a cartridge.
+#[derive(Debug)]
+pub enum LoadError {
+    Io { path: String, error: std::io::Error },
+    // The file ends before the section the header promises, e.g. a cut off download
+    Truncated { section: &'static str, expected: usize, actual: usize },
+    // No "NES\x1A" at the start of the file
+    BadMagic,
+    UnsupportedMapper(u16),
+    // Raw images have to fill the whole cartridge space
+    SizeMismatch { expected: usize, actual: usize },
+    NoPrgRom,
+}
+
+impl LoadError {
+    pub fn io(path: &str, error: std::io::Error) -> LoadError {
+        LoadError::Io { path: path.to_string(), error }
+    }
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadError::Io { path, error } => write!(f, "Unable to read {}: {}", path, error),
+            LoadError::Truncated { section, expected, actual } => {
+                write!(f, "The file is truncated, the {} needs {} bytes but only {} are left", section, expected, actual)
+            }
+            LoadError::BadMagic => write!(f, "Not an iNES file, the header doesn't start with NES$1A"),
+            LoadError::UnsupportedMapper(mapper) => write!(f, "Unsupported mapper: {}", mapper),
+            LoadError::SizeMismatch { expected, actual } => {
+                write!(f, "Expected an image of {} bytes, got {}", expected, actual)
+            }
+            LoadError::NoPrgRom => write!(f, "The image has no PRG-ROM"),
+        }
+    }
+}
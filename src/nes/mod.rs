@@ -1,5 +1,11 @@
 pub mod nes;
 pub mod cartridge;
 pub mod ines;
+pub mod savestate;
+pub mod region;
+pub mod rominfo;
+pub mod loaderror;
+pub mod gamedb;
+pub mod battery;
 
 mod databus;
@@ -1,77 +1,251 @@
 use std::cell::{RefCell, Ref};
 use std::rc::Rc;
 
-use crate::nes::databus::{NesDatabus, END};
-use crate::cpu::cpu::Cpu;
+use crate::nes::databus::{NesDatabus, PpuEvents, END};
+use crate::cpu::cpu::{Cpu, CpuError, IllegalOpcodePolicy};
 use crate::nes::cartridge::cartridge::Cartridge;
 use crate::ppu::ppu::Ppu;
+use crate::apu::apu::Apu;
 use crate::cpu::databus::Databus;
 use crate::cpu::instruction;
 use crate::cpu::instruction::Instruction;
+use crate::debugger::debugger::{Debugger, BreakHit};
+use crate::nes::savestate::{StateWriter, StateReader};
+use crate::nes::region::Region;
 
+/// Why a stepping call returned.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum StopReason {
+    /// The cpu finished an instruction.
+    InstructionDone,
+    /// The ppu moved on to the next scanline.
+    ScanlineDone,
+    /// The ppu entered vblank.
+    VblankStarted,
+    /// The ppu finished the visible part of the frame.
+    FrameDone,
+    /// The requested number of master cycles have run.
+    CyclesDone,
+    /// The cpu is jammed and will not finish another instruction until reset.
+    CpuHalted,
+    /// A breakpoint or watchpoint hit.
+    Break(BreakHit),
+}
+
+/// The outcome of a stepping call.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Step {
+    /// Number of cpu cycles the bus was clocked, the ppu ran three dots for each of them (3.2 on PAL).
+    /// A cycle accurate cpu clocks the bus for a whole instruction at once, so this can overshoot the stop condition.
+    pub cycles: u64,
+    pub reason: StopReason,
+}
+
+/// A whole console: the cpu and the bus, which owns the ram, the ppu and the cartridge.
+/// It holds no references to anything outside of itself, so it can be cloned or moved to another thread.
+#[derive(Clone)]
 pub struct NES {
     cpu: Cpu,
-    ppu: Box<Ppu>,
     databus: NesDatabus,
-    cartridge: Box<Cartridge>,
+    debugger: Debugger,
+
+    // Master cycles requested by tick_master_cycles that have not been run yet, negative when it ran ahead
+    master_cycle_balance: i64,
+    // Set by set_irq_lo, the cpu sees it together with the IRQs of the apu and cartridge
+    external_irq: bool,
 
     _actual_framerate: u32,
 }
 
 impl NES {
     pub fn new(cartridge : Cartridge) -> NES {
-        let mut cartridge = Box::new(cartridge);
-        let cartridge_ptr: *mut Cartridge = &mut *cartridge;
-
-        let mut ppu = Box::new(Ppu::new(cartridge_ptr));
-        let ppu_ptr: *mut Ppu = &mut *ppu;
-
         NES {
             cpu: Cpu::new(),
-            ppu,
-            databus: NesDatabus::new(cartridge_ptr, ppu_ptr),
-            cartridge,
+            databus: NesDatabus::new(cartridge),
+            debugger: Debugger::new(),
+            master_cycle_balance: 0,
+            external_irq: false,
             _actual_framerate: 0,
         }
     }
 
-    pub fn tick(&mut self) -> bool {
-        self.cpu.tick(&mut self.databus);
+    // Runs a single cpu cycle, a break is reported by the debugger but doesn't stop anything
+    pub fn tick(&mut self) -> Result<bool, CpuError> {
+        let frame_done = self._tick()?.frame_done;
+        self.debugger.take_break();
+
+        Ok(frame_done)
+    }
+
+    // Steps one cpu instruction with the ppu kept in lockstep
+    pub fn tick_cpu_instruction(&mut self) -> Result<Step, CpuError> {
+        let start = self.databus.get_cycle_count();
+
+        loop {
+            self._tick()?;
+
+            if let Some(hit) = self.debugger.take_break() {
+                return Ok(self._step_since(start, StopReason::Break(hit)));
+            }
+            if self.cpu.is_halted() {
+                return Ok(self._step_since(start, StopReason::CpuHalted));
+            }
+            if self.cpu.is_at_instruction_boundary() {
+                return Ok(self._step_since(start, StopReason::InstructionDone));
+            }
+        }
+    }
+
+    // Steps until the ppu has finished the current scanline
+    pub fn tick_scanline(&mut self) -> Result<Step, CpuError> {
+        self._tick_until(StopReason::ScanlineDone, |events| events.scanline_done)
+    }
+
+    // Steps until the ppu enters vblank
+    pub fn tick_vblank(&mut self) -> Result<Step, CpuError> {
+        self._tick_until(StopReason::VblankStarted, |events| events.vblank_started)
+    }
+
+    // Steps until the ppu has finished the visible part of the frame
+    pub fn tick_frame(&mut self) -> Result<Step, CpuError> {
+        self._tick_until(StopReason::FrameDone, |events| events.frame_done)
+    }
+
+    // Runs the given number of master cycles, see Region for how many a cpu cycle takes. Whatever does not add up
+    // to a whole cpu cycle, or was run ahead by a cycle accurate instruction, is settled on the next call.
+    pub fn tick_master_cycles(&mut self, master_cycles: u64) -> Result<Step, CpuError> {
+        let start = self.databus.get_cycle_count();
+        let master_cycles_per_cpu_cycle = self.get_region().get_master_cycles_per_cpu_cycle();
+        self.master_cycle_balance += master_cycles as i64;
+
+        while self.master_cycle_balance >= master_cycles_per_cpu_cycle {
+            let cycle_count = self.databus.get_cycle_count();
+            self._tick()?;
+            let cycles = (self.databus.get_cycle_count() - cycle_count) as i64;
+
+            self.master_cycle_balance -= cycles * master_cycles_per_cpu_cycle;
+
+            if let Some(hit) = self.debugger.take_break() {
+                return Ok(self._step_since(start, StopReason::Break(hit)));
+            }
+        }
+
+        Ok(self._step_since(start, StopReason::CyclesDone))
+    }
+
+    fn _tick_until<F: Fn(&PpuEvents) -> bool>(&mut self, reason: StopReason, stop: F) -> Result<Step, CpuError> {
+        let start = self.databus.get_cycle_count();
 
-        let mut frame_done = false;
+        loop {
+            let events = self._tick()?;
 
-        frame_done |= self.ppu.tick();
-        frame_done |= self.ppu.tick();
-        frame_done |= self.ppu.tick();
+            if let Some(hit) = self.debugger.take_break() {
+                return Ok(self._step_since(start, StopReason::Break(hit)));
+            }
+            if stop(&events) {
+                return Ok(self._step_since(start, reason));
+            }
+        }
+    }
+
+    fn _step_since(&self, start_cycle_count: u64, reason: StopReason) -> Step {
+        Step { cycles: self.databus.get_cycle_count() - start_cycle_count, reason }
+    }
 
-        if self.ppu.get_nmi_signal() {
+    fn _tick(&mut self) -> Result<PpuEvents, CpuError> {
+        self.databus.set_recording_accesses(self.debugger.is_watching_bus());
+        self.cpu.tick(&mut self.databus)?;
+
+        if self.debugger.is_active() && !self.cpu.is_halted() && self.cpu.is_at_instruction_boundary() {
+            self.debugger.check(self.cpu.get_state(), self.cpu.get_last_instruction(), self.databus.get_accesses());
+            self.databus.clear_accesses();
+        }
+
+        let events = self.databus.take_ppu_events();
+        self.cpu.stall(self.databus.take_stall_cycles());
+
+        if self.external_irq || self.databus.get_irq_signal() {
+            self.cpu.set_irq_lo();
+        } else {
+            self.cpu.set_irq_hi();
+        }
+
+        if self.databus.get_ppu().get_nmi_signal() {
             self.cpu.set_nmi_lo();
         } else {
             self.cpu.set_nmi_hi();
         }
 
-        frame_done
+        Ok(events)
+    }
+
+    // Snapshots the whole machine. Debugger breakpoints and settings such as the illegal opcode policy are not included.
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut writer = StateWriter::new();
+
+        self.cpu.save_state(&mut writer);
+        self.databus.save_state(&mut writer);
+        writer.write_i64(self.master_cycle_balance);
+
+        writer.into_bytes()
+    }
+
+    // Restores a snapshot made by save_state of the same game. Nothing is changed if the state can't be loaded.
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), String> {
+        let mut reader = StateReader::new(data)?;
+        let mut cpu = self.cpu.clone();
+        let mut databus = self.databus.clone();
+
+        cpu.load_state(&mut reader)?;
+        databus.load_state(&mut reader)?;
+        let master_cycle_balance = reader.read_i64()?;
+        reader.finish()?;
+
+        self.cpu = cpu;
+        self.databus = databus;
+        self.master_cycle_balance = master_cycle_balance;
+
+        Ok(())
     }
 
-    pub fn tick_cpu_instruction(&mut self) {
-        // TODO
-        // let cycles = self.cpu.tick_instruction(&mut self.databus);
-        // for _i in 0..cycles * 3 {
-        //     self.ppu.tick();
-        // }
+    // Switches the clocks and timing, meant to be called before reset
+    pub fn set_region(&mut self, region: Region) {
+        self.databus.set_region(region);
+        self.master_cycle_balance = 0;
     }
+    pub fn get_region(&self) -> Region { self.databus.get_region() }
 
     pub fn get_databus(&self) -> &dyn Databus { &self.databus }
 
-    pub fn get_ppu(&self) -> &Ppu {
-        &self.ppu
+    pub fn get_ppu(&self) -> &Ppu { self.databus.get_ppu() }
+    pub fn get_apu(&self) -> &Apu { self.databus.get_apu() }
+
+    // Returns the audio samples produced since the last call, at the cpu clock rate of the region
+    pub fn take_audio_samples(&mut self) -> Vec<f32> {
+        self.databus.get_apu_mut().take_samples()
     }
+    pub fn get_cartridge(&self) -> &Cartridge { self.databus.get_cartridge() }
+    pub fn get_cartridge_mut(&mut self) -> &mut Cartridge { self.databus.get_cartridge_mut() }
     pub fn get_cpu(&self) -> &Cpu { &self.cpu }
-    pub fn reset(&mut self) { self.cpu.reset(&self.databus); }
-    pub fn set_irq_lo(&mut self) { self.cpu.set_irq_lo(); }
-    pub fn set_irq_hi(&mut self) { self.cpu.set_irq_hi(); }
+    pub fn get_debugger(&self) -> &Debugger { &self.debugger }
+    pub fn get_debugger_mut(&mut self) -> &mut Debugger { &mut self.debugger }
+    pub fn reset(&mut self) { self.cpu.reset(&mut self.databus); }
+    pub fn set_irq_lo(&mut self) {
+        self.external_irq = true;
+        self.cpu.set_irq_lo();
+    }
+    pub fn set_irq_hi(&mut self) {
+        self.external_irq = false;
+        self.cpu.set_irq_hi();
+    }
     pub fn set_nmi_hi(&mut self) { self.cpu.set_nmi_hi(); }
     pub fn set_nmi_lo(&mut self) { self.cpu.set_nmi_lo(); }
+    pub fn set_illegal_opcode_policy(&mut self, policy: IllegalOpcodePolicy) {
+        self.cpu.set_illegal_opcode_policy(policy);
+    }
+    pub fn set_cycle_accurate(&mut self, cycle_accurate: bool) {
+        self.cpu.set_cycle_accurate(cycle_accurate);
+    }
     pub fn get_actual_framerate(&self) -> u32 { self._actual_framerate }
 
     pub fn set_actual_framerate(&mut self, frames_dropped: u32) {
@@ -79,13 +253,13 @@ impl NES {
     }
 
     pub fn deassemble_prg(&self) -> (Vec<Instruction>, u16) {
-        let start_address = self.cartridge.get_instruction_offset();
+        let start_address = self.get_cartridge().get_instruction_offset();
 
         let mut instructions: Vec<Instruction> = Vec::new();
         let mut i = start_address;
 
         while i < (END - 3) {
-            let instruction = instruction::decode_instruction(&self.databus, i);
+            let instruction = instruction::peek_instruction(&self.databus, i);
             i += instruction.get_size() as u16;
             instructions.push(instruction);
         }
@@ -94,3 +268,444 @@ impl NES {
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::{NES, StopReason};
+    use crate::nes::region::Region;
+    use crate::cpu::databus::Databus;
+    use crate::nes::cartridge::cartridge;
+    use crate::nes::ines;
+    use crate::nes::databus::NesDatabus;
+    use crate::nes::rominfo::RomInfo;
+    use crate::debugger::debugger::{BreakKind, BusAccess, ACCESS_WRITE};
+
+    // NOP, JMP $8000
+    const LOOP_PROGRAM: [u8; 4] = [0xea, 0x4c, 0x00, 0x80];
+
+    fn setup(program: &[u8]) -> NES {
+        let mut prg_rom = vec![0; ines::PRG_ROM_CHUNK_SIZE];
+        prg_rom[..program.len()].copy_from_slice(program);
+        // NMI handler at $8100 doing RTI
+        prg_rom[0x0100] = 0x40;
+        prg_rom[0x3ffa] = 0x00;
+        prg_rom[0x3ffb] = 0x81;
+        // IRQ handler at $8200 doing INC $11, LDA $4015 (acknowledging the frame IRQ), RTI
+        prg_rom[0x0200..0x0206].copy_from_slice(&[0xe6, 0x11, 0xad, 0x15, 0x40, 0x40]);
+        prg_rom[0x3ffe] = 0x00;
+        prg_rom[0x3fff] = 0x82;
+        // Reset vector at $FFFC
+        prg_rom[0x3ffc] = 0x00;
+        prg_rom[0x3ffd] = 0x80;
+        let chr_rom = vec![0; 0x2000];
+
+        let cartridge = cartridge::create_cartridge_from_ines(&RomInfo::default(), &prg_rom, &chr_rom).unwrap();
+        let mut nes = NES::new(cartridge);
+        nes.reset();
+        nes
+    }
+
+    #[test]
+    fn test_tick_cpu_instruction() {
+        let mut nes = setup(&LOOP_PROGRAM);
+
+        let nop = nes.tick_cpu_instruction().unwrap();
+        assert_eq!(StopReason::InstructionDone, nop.reason);
+        assert_eq!(2, nop.cycles);
+
+        let jmp = nes.tick_cpu_instruction().unwrap();
+        assert_eq!(3, jmp.cycles);
+        assert_eq!(0x8000, nes.get_cpu().get_state().get_pc());
+        assert_eq!(5, nes.get_cpu().get_cycle_count());
+    }
+
+    #[test]
+    fn test_tick_cpu_instruction_halted() {
+        // JAM
+        let mut nes = setup(&[0x02]);
+
+        let step = nes.tick_cpu_instruction().unwrap();
+        assert_eq!(StopReason::CpuHalted, step.reason);
+        assert_eq!(1, step.cycles);
+    }
+
+    #[test]
+    fn test_tick_scanline() {
+        let mut nes = setup(&LOOP_PROGRAM);
+        nes.set_cycle_accurate(false);
+        nes.tick_scanline().unwrap();
+
+        for _i in 0..300 {
+            let scanline = nes.get_ppu().get_scanline();
+            let step = nes.tick_scanline().unwrap();
+
+            assert_eq!(StopReason::ScanlineDone, step.reason);
+            // 341 dots at three dots per cpu cycle
+            assert!(step.cycles == 113 || step.cycles == 114);
+            assert_eq!((scanline + 1) % 262, nes.get_ppu().get_scanline());
+        }
+    }
+
+    #[test]
+    fn test_tick_scanline_cycle_accurate() {
+        let mut nes = setup(&LOOP_PROGRAM);
+
+        for _i in 0..300 {
+            let scanline = nes.get_ppu().get_scanline();
+            nes.tick_scanline().unwrap();
+
+            // Stops at the end of the instruction that crossed into the next scanline
+            assert_eq!((scanline + 1) % 262, nes.get_ppu().get_scanline());
+            assert!(nes.get_ppu().get_scanline_cycle() <= 3 * 3);
+        }
+    }
+
+    #[test]
+    fn test_tick_vblank_and_frame() {
+        let mut nes = setup(&LOOP_PROGRAM);
+
+        let step = nes.tick_vblank().unwrap();
+        assert_eq!(StopReason::VblankStarted, step.reason);
+        assert!(nes.get_ppu().is_vblank());
+        assert_eq!(241, nes.get_ppu().get_scanline());
+
+        nes.tick_frame().unwrap();
+        let step = nes.tick_frame().unwrap();
+        assert_eq!(StopReason::FrameDone, step.reason);
+        assert!(step.cycles == 29780 || step.cycles == 29781);
+    }
+
+    #[test]
+    fn test_region_timing() {
+        // Cpu cycles per frame, and the scanline vblank starts on
+        for (region, cycles, vblank_scanline) in [(Region::Pal, 33247.5, 241), (Region::Dendy, 35464.0, 291)].iter() {
+            let mut nes = setup(&LOOP_PROGRAM);
+            nes.set_cycle_accurate(false);
+            nes.set_region(*region);
+
+            nes.tick_vblank().unwrap();
+            assert_eq!(*vblank_scanline, nes.get_ppu().get_scanline());
+
+            nes.tick_frame().unwrap();
+            let mut total = 0;
+            for _i in 0..10 {
+                total += nes.tick_frame().unwrap().cycles;
+            }
+            assert!((total as f64 - cycles * 10.0).abs() < 1.0, "{:?} ran {} cycles", region, total);
+
+            for _i in 0..400 {
+                let scanline = nes.get_ppu().get_scanline();
+                nes.tick_scanline().unwrap();
+                assert_eq!((scanline + 1) % 312, nes.get_ppu().get_scanline());
+            }
+        }
+    }
+
+    #[test]
+    fn test_tick_master_cycles() {
+        let mut nes = setup(&LOOP_PROGRAM);
+
+        let cycle = Region::Ntsc.get_master_cycles_per_cpu_cycle() as u64;
+
+        // Runs the two cycle NOP
+        let step = nes.tick_master_cycles(cycle + 6).unwrap();
+        assert_eq!(StopReason::CyclesDone, step.reason);
+        assert_eq!(2, step.cycles);
+
+        // The NOP ran half a cycle ahead
+        assert_eq!(0, nes.tick_master_cycles(6).unwrap().cycles);
+        assert_eq!(3, nes.tick_master_cycles(3 * cycle).unwrap().cycles);
+
+        nes.set_cycle_accurate(false);
+        assert_eq!(5, nes.tick_master_cycles(5 * cycle).unwrap().cycles);
+    }
+
+    #[test]
+    fn test_pc_breakpoint() {
+        let mut nes = setup(&LOOP_PROGRAM);
+        let id = nes.get_debugger_mut().add_breakpoint(BreakKind::Pc(0x8001), None);
+
+        let step = nes.tick_frame().unwrap();
+        match step.reason {
+            StopReason::Break(hit) => {
+                assert_eq!(id, hit.id);
+                assert_eq!(0x8001, hit.pc);
+            }
+            _ => panic!("Expected a break, got {:?}", step.reason),
+        }
+        assert_eq!(0x8001, nes.get_cpu().get_state().get_pc());
+
+        // Resuming executes the instruction at the breakpoint
+        assert_eq!(StopReason::InstructionDone, nes.tick_cpu_instruction().unwrap().reason);
+        assert!(matches!(nes.tick_cpu_instruction().unwrap().reason, StopReason::Break(_)));
+        assert_eq!(2, nes.get_debugger().get_breakpoints()[0].get_hit_count());
+    }
+
+    #[test]
+    fn test_write_watchpoint() {
+        // LDX #$05, LDA #$40, STA $0300, JMP $8000
+        let mut nes = setup(&[0xa2, 0x05, 0xa9, 0x40, 0x8d, 0x00, 0x03, 0x4c, 0x00, 0x80]);
+        nes.get_debugger_mut().add_breakpoint(BreakKind::Watch { start: 0x0300, end: 0x03ff, access: ACCESS_WRITE }, None);
+
+        // Stops after the store, with the access the bus recorded
+        match nes.tick_frame().unwrap().reason {
+            StopReason::Break(hit) => {
+                assert_eq!(Some(BusAccess { address: 0x0300, data: 0x40, write: true }), hit.access);
+                assert_eq!(0x8007, hit.pc);
+            }
+            reason => panic!("Expected a break, got {:?}", reason),
+        }
+    }
+
+    #[test]
+    fn test_nmi_breakpoint() {
+        // LDA #$80, STA $2000, JMP $8005
+        let mut nes = setup(&[0xa9, 0x80, 0x8d, 0x00, 0x20, 0x4c, 0x05, 0x80]);
+        nes.get_debugger_mut().add_breakpoint(BreakKind::Nmi, None);
+
+        nes.tick_vblank().unwrap();
+        let step = nes.tick_frame().unwrap();
+        assert!(matches!(step.reason, StopReason::Break(_)));
+        assert_eq!(0x8100, nes.get_cpu().get_state().get_pc());
+    }
+
+    // INC $10, JMP $8000
+    const COUNTER_PROGRAM: [u8; 5] = [0xe6, 0x10, 0x4c, 0x00, 0x80];
+
+    fn assert_send_clone<T: Send + Clone>() {}
+
+    #[test]
+    fn test_send_clone() {
+        assert_send_clone::<NES>();
+    }
+
+    #[test]
+    fn test_clone_runs_independently() {
+        let mut nes = setup(&COUNTER_PROGRAM);
+        nes.tick_frame().unwrap();
+
+        let mut clone = nes.clone();
+        nes.tick_frame().unwrap();
+        nes.tick_frame().unwrap();
+        assert_ne!(nes.get_databus().peek(0x10), clone.get_databus().peek(0x10));
+
+        clone.tick_frame().unwrap();
+        clone.tick_frame().unwrap();
+        assert_eq!(nes.get_databus().peek(0x10), clone.get_databus().peek(0x10));
+        assert_eq!(nes.get_cpu().get_cycle_count(), clone.get_cpu().get_cycle_count());
+        assert_eq!(nes.get_cpu().get_state().get_pc(), clone.get_cpu().get_state().get_pc());
+        assert_eq!(nes.get_ppu().get_framebuffer(), clone.get_ppu().get_framebuffer());
+    }
+
+    #[test]
+    fn test_run_on_other_thread() {
+        let nes = setup(&COUNTER_PROGRAM);
+        let mut expected = nes.clone();
+
+        let handle = std::thread::spawn(move || {
+            let mut nes = nes;
+            nes.tick_frame().unwrap();
+            nes
+        });
+        let nes = handle.join().unwrap();
+
+        expected.tick_frame().unwrap();
+        assert_eq!(expected.get_databus().peek(0x10), nes.get_databus().peek(0x10));
+        assert_ne!(0, nes.get_databus().peek(0x10));
+    }
+
+    #[test]
+    fn test_save_and_load_state() {
+        // INC $10, LDA #$80, STA $2000 (NMI on), STA $0200, JMP $8000
+        let program = [0xe6, 0x10, 0xa9, 0x80, 0x8d, 0x00, 0x20, 0x8d, 0x00, 0x02, 0x4c, 0x00, 0x80];
+        let mut nes = setup(&program);
+        nes.tick_frame().unwrap();
+        nes.tick_scanline().unwrap();
+
+        let state = nes.save_state();
+
+        nes.tick_frame().unwrap();
+        nes.tick_cpu_instruction().unwrap();
+        let expected = nes.save_state();
+
+        let mut other = setup(&program);
+        other.load_state(&state).unwrap();
+        assert_eq!(state, other.save_state());
+
+        other.tick_frame().unwrap();
+        other.tick_cpu_instruction().unwrap();
+        assert_eq!(expected, other.save_state());
+        assert_eq!(nes.get_databus().peek(0x10), other.get_databus().peek(0x10));
+    }
+
+    #[test]
+    fn test_load_invalid_state() {
+        let mut nes = setup(&LOOP_PROGRAM);
+        nes.tick_frame().unwrap();
+        let before = nes.save_state();
+
+        let mut state = before.clone();
+        state.truncate(state.len() - 1);
+        assert!(nes.load_state(&state).is_err());
+
+        state = before.clone();
+        state.push(0);
+        assert!(nes.load_state(&state).is_err());
+
+        // Version
+        state = before.clone();
+        state[4] = 0xff;
+        assert!(nes.load_state(&state).is_err());
+
+        // A failed load leaves the machine as it was
+        assert_eq!(before, nes.save_state());
+    }
+
+    #[test]
+    fn test_apu_frame_irq() {
+        // CLI, JMP $8001
+        let mut nes = setup(&[0x58, 0x4c, 0x01, 0x80]);
+        nes.get_debugger_mut().add_breakpoint(BreakKind::Irq, None);
+
+        let step = nes.tick_master_cycles(30_000 * Region::Ntsc.get_master_cycles_per_cpu_cycle() as u64).unwrap();
+        assert!(matches!(step.reason, StopReason::Break(_)));
+        assert_eq!(0x8200, nes.get_cpu().get_state().get_pc());
+
+        // The handler acknowledges it, so there is one IRQ per four step sequence
+        nes.get_debugger_mut().remove_breakpoint(1);
+        nes.tick_master_cycles((3 * 29830 + 15000) * Region::Ntsc.get_master_cycles_per_cpu_cycle() as u64).unwrap();
+        assert_eq!(4, nes.get_databus().peek(0x11));
+    }
+
+    #[test]
+    fn test_dmc_stalls_cpu() {
+        // LDA #$10, STA $4015 (play a one byte sample), NOP..
+        let mut program = vec![0xa9, 0x10, 0x8d, 0x15, 0x40];
+        program.extend_from_slice(&[0xea; 8]);
+        program.extend_from_slice(&LOOP_PROGRAM);
+        let mut nes = setup(&program);
+        nes.set_cycle_accurate(false);
+
+        nes.tick_cpu_instruction().unwrap();
+        nes.tick_cpu_instruction().unwrap();
+
+        let cycles: u64 = (0..8).map(|_i| nes.tick_cpu_instruction().unwrap().cycles).sum();
+        assert_eq!(8 * 2 + 4, cycles);
+    }
+
+    // The cpu cycle count once STA $4014 has finished, and the cycles of the instruction after it besides its own 2,
+    // which is the stall
+    fn oam_dma_stall(prefix: &[u8], cycle_accurate: bool) -> (u32, u64) {
+        // LDA #$AB, STA $0200, LDA #$CD, STA $02FF, LDA #$02, STA $4014, NOP
+        let mut program = prefix.to_vec();
+        program.extend_from_slice(&[0xa9, 0xab, 0x8d, 0x00, 0x02, 0xa9, 0xcd, 0x8d, 0xff, 0x02,
+            0xa9, 0x02, 0x8d, 0x14, 0x40, 0xea]);
+        program.extend_from_slice(&LOOP_PROGRAM);
+        let mut nes = setup(&program);
+        nes.set_cycle_accurate(cycle_accurate);
+
+        for _i in 0..prefix.len() / 2 + 6 {
+            nes.tick_cpu_instruction().unwrap();
+        }
+        let write_cycle = nes.get_cpu().get_cycle_count();
+
+        let start = nes.get_cpu().get_cycle_count();
+        nes.tick_cpu_instruction().unwrap();
+        assert_eq!(0x8000 + program.len() as u16 - LOOP_PROGRAM.len() as u16, nes.get_cpu().get_state().get_pc());
+        assert_eq!(0xab, nes.get_ppu().get_oam()[0]);
+        assert_eq!(0xcd, nes.get_ppu().get_oam()[0xff]);
+
+        (write_cycle, (nes.get_cpu().get_cycle_count() - start) as u64 - 2)
+    }
+
+    #[test]
+    fn test_oam_dma() {
+        for cycle_accurate in [true, false].iter() {
+            // LDA #, STA, LDA #, STA, LDA #, STA $4014 take 18 cycles, the DMA starts on a get cycle
+            assert_eq!((18, 513), oam_dma_stall(&[], *cycle_accurate));
+            // LDA $00 makes it 21, starting on a put cycle takes one more cycle to align the reads
+            assert_eq!((21, 514), oam_dma_stall(&[0xa5, 0x00], *cycle_accurate));
+        }
+    }
+
+    // Fills page 2 with 0x80 ^ offset
+    fn fill_oam_dma_page(bus: &mut NesDatabus) {
+        for offset in 0..0x100 {
+            bus.write(0x0200 + offset, 0x80 ^ offset as u8);
+        }
+    }
+
+    #[test]
+    fn test_oam_dma_transfer() {
+        let mut nes = setup(&LOOP_PROGRAM);
+        let bus = &mut nes.databus;
+        fill_oam_dma_page(bus);
+
+        bus.write(0x4014, 0x02);
+        let stall = bus.take_stall_cycles();
+        // Nothing is copied before the cpu stalls
+        assert_eq!(0, bus.get_ppu().get_oam()[0]);
+
+        // The halt cycle, and the alignment cycle if any
+        for _i in 0..stall - 512 {
+            bus.tick();
+        }
+        assert_eq!(0, bus.get_ppu().get_oam()[0]);
+
+        // A get and a put per byte
+        bus.tick();
+        assert_eq!(0, bus.get_ppu().get_oam()[0]);
+        bus.tick();
+        assert_eq!(0x80, bus.get_ppu().get_oam()[0]);
+        assert_eq!(0, bus.get_ppu().get_oam()[1]);
+
+        for _i in 0..510 {
+            bus.tick();
+        }
+        assert_eq!(0x7f, bus.get_ppu().get_oam()[0xff]);
+        assert_eq!(0, bus.take_stall_cycles());
+    }
+
+    #[test]
+    fn test_dmc_during_oam_dma() {
+        let mut nes = setup(&LOOP_PROGRAM);
+        let bus = &mut nes.databus;
+        fill_oam_dma_page(bus);
+
+        // A one byte sample is fetched on the next cycle
+        bus.write(0x4015, 0x10);
+        bus.tick();
+        assert_eq!(4, bus.take_stall_cycles());
+
+        // Let it play, so that the buffer is empty again
+        for _i in 0..8 * 428 {
+            bus.tick();
+        }
+        assert_eq!(0, bus.take_stall_cycles());
+
+        bus.write(0x4014, 0x02);
+        let stall = bus.take_stall_cycles();
+        assert!(stall == 513 || stall == 514);
+
+        // The DMC takes a get cycle in the middle of the transfer, and the put cycle after it is lost
+        for _i in 0..stall / 2 {
+            bus.tick();
+        }
+        bus.write(0x4015, 0x10);
+        let mut extra = 0;
+        for _i in stall / 2..stall {
+            bus.tick();
+            extra += bus.take_stall_cycles();
+        }
+        assert_eq!(2, extra);
+        assert!(bus.get_apu().get_dmc_fetch_address().is_none());
+
+        // So the last byte only arrives in the extra cycles
+        assert_eq!(0, bus.get_ppu().get_oam()[0xff]);
+        for _i in 0..extra {
+            bus.tick();
+        }
+        assert_eq!(0x7f, bus.get_ppu().get_oam()[0xff]);
+        assert_eq!(0, bus.take_stall_cycles());
+    }
+}
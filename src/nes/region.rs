@@ -0,0 +1,110 @@
+use crate::nes::savestate::StateReader;
+
+/// The console variant a game was made for. It sets the clocks, the length of a frame and the apu rate tables.
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub enum Region {
+    #[default]
+    Ntsc,
+    Pal,
+    // The famiclones sold in Russia: a PAL frame, but the cpu clocked close to NTSC speed
+    Dendy,
+}
+
+impl Region {
+    // --region ntsc, pal or dendy
+    pub fn parse(name: &str) -> Result<Region, String> {
+        match name.to_ascii_lowercase().as_str() {
+            "ntsc" => Ok(Region::Ntsc),
+            "pal" => Ok(Region::Pal),
+            "dendy" => Ok(Region::Dendy),
+            _ => Err(format!("Unknown region '{}', expected one of: ntsc, pal, dendy", name)),
+        }
+    }
+
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            Region::Ntsc => "NTSC",
+            Region::Pal => "PAL",
+            Region::Dendy => "Dendy",
+        }
+    }
+
+    // The cpu and the ppu both divide the master clock, by 12 and 4 on NTSC. PAL runs 3.2 dots per cpu cycle.
+    pub fn get_master_cycles_per_cpu_cycle(&self) -> i64 {
+        match self {
+            Region::Ntsc => 12,
+            Region::Pal => 16,
+            Region::Dendy => 15,
+        }
+    }
+
+    pub fn get_master_cycles_per_ppu_dot(&self) -> i64 {
+        match self {
+            Region::Ntsc => 4,
+            Region::Pal | Region::Dendy => 5,
+        }
+    }
+
+    // In Hz, the apu produces a sample at this rate too
+    pub fn get_cpu_clock_rate(&self) -> u32 {
+        match self {
+            Region::Ntsc => 1_789_773,
+            Region::Pal => 1_662_607,
+            Region::Dendy => 1_773_448,
+        }
+    }
+
+    pub fn get_frame_rate(&self) -> f64 {
+        match self {
+            Region::Ntsc => 60.0988,
+            Region::Pal | Region::Dendy => 50.0070,
+        }
+    }
+
+    pub fn get_scanline_count(&self) -> u16 {
+        match self {
+            Region::Ntsc => 262,
+            Region::Pal | Region::Dendy => 312,
+        }
+    }
+
+    // The extra lines of a PAL frame all go to vblank. Dendy keeps the NTSC vblank and idles after the picture instead.
+    pub fn get_vblank_start_scanline(&self) -> u16 {
+        match self {
+            Region::Ntsc | Region::Pal => 241,
+            Region::Dendy => 291,
+        }
+    }
+
+    // Only the NTSC ppu skips a dot on odd frames
+    pub fn skips_odd_frame_dot(&self) -> bool {
+        *self == Region::Ntsc
+    }
+
+    // The PAL ppu has the red and green emphasis bits of PPUMASK the other way around
+    pub fn swaps_red_green_emphasis(&self) -> bool {
+        *self == Region::Pal
+    }
+
+    // The frame counter and the noise and DMC periods are slower on PAL, Dendy uses the NTSC ones
+    pub fn has_pal_apu(&self) -> bool {
+        *self == Region::Pal
+    }
+
+    pub fn to_u8(&self) -> u8 {
+        match self {
+            Region::Ntsc => 0,
+            Region::Pal => 1,
+            Region::Dendy => 2,
+        }
+    }
+
+    pub fn read_state(reader: &mut StateReader) -> Result<Region, String> {
+        match reader.read_u8()? {
+            0 => Ok(Region::Ntsc),
+            1 => Ok(Region::Pal),
+            2 => Ok(Region::Dendy),
+            value => Err(format!("Invalid region in save state: {}", value)),
+        }
+    }
+}
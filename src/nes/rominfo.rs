@@ -0,0 +1,98 @@
+use crate::nes::region::Region;
+use crate::ppu::nametable::Mirroring;
+
+/// The system the rom was made for, from flags 7 and, for the extended types, byte 13 of an NES 2.0 header.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ConsoleType {
+    Nes,
+    VsSystem,
+    Playchoice10,
+    // Famiclones, Vs. Dual System and the like, the value is the extended console type
+    Extended(u8),
+}
+
+/// The CPU/PPU timing of an NES 2.0 header. iNES only knows NTSC and PAL.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum TimingMode {
+    Ntsc,
+    Pal,
+    // Runs on any console
+    MultiRegion,
+    Dendy,
+}
+
+/// What the header of an iNES or NES 2.0 file says about the cartridge. Fields an iNES header doesn't have are 0,
+/// and a RAM size of 0 leaves it to the mapper.
+#[derive(Clone, Debug)]
+pub struct RomInfo {
+    pub nes_2: bool,
+
+    pub mapper: u16,
+    pub submapper: u8,
+
+    // In bytes
+    pub prg_rom_size: usize,
+    pub chr_rom_size: usize,
+    pub prg_ram_size: usize,
+    pub prg_nvram_size: usize,
+    pub chr_ram_size: usize,
+    pub chr_nvram_size: usize,
+
+    pub mirroring: Mirroring,
+    // The cartridge brings the memory for all four nametables, the mirroring bit means nothing then
+    pub four_screen: bool,
+    pub battery: bool,
+    pub trainer: bool,
+    // The 512 bytes that go to $7000, empty without a trainer. Only filled in when loading the whole file,
+    // ines::load_trainer installs them
+    pub trainer_data: Vec<u8>,
+
+    pub console_type: ConsoleType,
+    pub timing_mode: TimingMode,
+    // The controller or other device plugged in by default, 0 is unspecified
+    pub expansion_device: u8,
+}
+
+impl Default for RomInfo {
+    fn default() -> RomInfo {
+        RomInfo {
+            nes_2: false,
+            mapper: 0,
+            submapper: 0,
+            prg_rom_size: 0,
+            chr_rom_size: 0,
+            prg_ram_size: 0,
+            prg_nvram_size: 0,
+            chr_ram_size: 0,
+            chr_nvram_size: 0,
+            mirroring: Mirroring::Horizontal,
+            four_screen: false,
+            battery: false,
+            trainer: false,
+            trainer_data: Vec::new(),
+            console_type: ConsoleType::Nes,
+            timing_mode: TimingMode::Ntsc,
+            expansion_device: 0,
+        }
+    }
+}
+
+impl RomInfo {
+    // Multi-region games run as NTSC
+    pub fn get_region(&self) -> Region {
+        match self.timing_mode {
+            TimingMode::Ntsc | TimingMode::MultiRegion => Region::Ntsc,
+            TimingMode::Pal => Region::Pal,
+            TimingMode::Dendy => Region::Dendy,
+        }
+    }
+
+    // Volatile and battery backed together, mappers don't tell them apart
+    pub fn get_total_prg_ram_size(&self) -> usize {
+        self.prg_ram_size + self.prg_nvram_size
+    }
+
+    pub fn get_total_chr_ram_size(&self) -> usize {
+        self.chr_ram_size + self.chr_nvram_size
+    }
+}
@@ -0,0 +1,224 @@
+// Save states are a flat little endian blob. Every part of the machine writes its fields in a fixed
+// order and reads them back in the same order, so any change to what is saved must bump VERSION.
+
+const MAGIC: &[u8; 4] = b"CNSS";
+pub const VERSION: u16 = 12;
+
+pub struct StateWriter {
+    data: Vec<u8>,
+}
+
+impl Default for StateWriter {
+    fn default() -> StateWriter {
+        StateWriter::new()
+    }
+}
+
+impl StateWriter {
+    pub fn new() -> StateWriter {
+        let mut writer = StateWriter { data: Vec::new() };
+        writer.write_bytes(MAGIC);
+        writer.write_u16(VERSION);
+        writer
+    }
+
+    // A writer without the header, for blocks that are embedded in another state
+    pub fn new_block() -> StateWriter {
+        StateWriter { data: Vec::new() }
+    }
+
+    pub fn write_u8(&mut self, value: u8) {
+        self.data.push(value);
+    }
+
+    pub fn write_bool(&mut self, value: bool) {
+        self.write_u8(value as u8);
+    }
+
+    pub fn write_u16(&mut self, value: u16) {
+        self.write_bytes(&value.to_le_bytes());
+    }
+
+    pub fn write_u32(&mut self, value: u32) {
+        self.write_bytes(&value.to_le_bytes());
+    }
+
+    pub fn write_u64(&mut self, value: u64) {
+        self.write_bytes(&value.to_le_bytes());
+    }
+
+    pub fn write_i64(&mut self, value: i64) {
+        self.write_bytes(&value.to_le_bytes());
+    }
+
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        self.data.extend_from_slice(bytes);
+    }
+
+    // Writes the block with its length in front, so that the reader can check it was read whole
+    pub fn write_block(&mut self, block: StateWriter) {
+        self.write_u32(block.data.len() as u32);
+        self.write_bytes(&block.data);
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+pub struct StateReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(data: &'a [u8]) -> Result<StateReader<'a>, String> {
+        let mut reader = StateReader { data, position: 0 };
+
+        let mut magic = [0; 4];
+        reader.read_bytes(&mut magic).map_err(|_| "Not a save state".to_string())?;
+        if &magic != MAGIC {
+            return Err("Not a save state".to_string());
+        }
+
+        let version = reader.read_u16()?;
+        if version != VERSION {
+            return Err(format!("Unsupported save state version {}, expected {}", version, VERSION));
+        }
+
+        Ok(reader)
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, String> {
+        let mut bytes = [0; 1];
+        self.read_bytes(&mut bytes)?;
+        Ok(bytes[0])
+    }
+
+    pub fn read_bool(&mut self) -> Result<bool, String> {
+        Ok(self.read_u8()? != 0)
+    }
+
+    pub fn read_u16(&mut self) -> Result<u16, String> {
+        let mut bytes = [0; 2];
+        self.read_bytes(&mut bytes)?;
+        Ok(u16::from_le_bytes(bytes))
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32, String> {
+        let mut bytes = [0; 4];
+        self.read_bytes(&mut bytes)?;
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    pub fn read_u64(&mut self) -> Result<u64, String> {
+        let mut bytes = [0; 8];
+        self.read_bytes(&mut bytes)?;
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    pub fn read_i64(&mut self) -> Result<i64, String> {
+        let mut bytes = [0; 8];
+        self.read_bytes(&mut bytes)?;
+        Ok(i64::from_le_bytes(bytes))
+    }
+
+    // Fills the whole target
+    pub fn read_bytes(&mut self, target: &mut [u8]) -> Result<(), String> {
+        let end = self.position + target.len();
+        if end > self.data.len() {
+            return Err("Save state is truncated".to_string());
+        }
+
+        target.copy_from_slice(&self.data[self.position..end]);
+        self.position = end;
+
+        Ok(())
+    }
+
+    // Reads a block written by StateWriter::write_block
+    pub fn read_block<F>(&mut self, read: F) -> Result<(), String>
+        where F: FnOnce(&mut StateReader) -> Result<(), String> {
+        let len = self.read_u32()? as usize;
+        let end = self.position + len;
+        if end > self.data.len() {
+            return Err("Save state is truncated".to_string());
+        }
+
+        let mut block = StateReader { data: &self.data[self.position..end], position: 0 };
+        read(&mut block)?;
+        block.finish()?;
+
+        self.position = end;
+
+        Ok(())
+    }
+
+    // Fails if anything is left unread, which means the state was written by something else
+    pub fn finish(&self) -> Result<(), String> {
+        if self.position != self.data.len() {
+            return Err(format!("Save state has {} unexpected bytes at the end", self.data.len() - self.position));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{StateWriter, StateReader};
+
+    #[test]
+    fn test_round_trip() {
+        let mut writer = StateWriter::new();
+        writer.write_u8(0x12);
+        writer.write_bool(true);
+        writer.write_u16(0x3456);
+        writer.write_u64(u64::MAX - 1);
+        writer.write_i64(-12);
+
+        let mut block = StateWriter::new_block();
+        block.write_u32(0xdeadbeef);
+        writer.write_block(block);
+
+        let data = writer.into_bytes();
+        let mut reader = StateReader::new(&data).unwrap();
+        assert_eq!(0x12, reader.read_u8().unwrap());
+        assert!(reader.read_bool().unwrap());
+        assert_eq!(0x3456, reader.read_u16().unwrap());
+        assert_eq!(u64::MAX - 1, reader.read_u64().unwrap());
+        assert_eq!(-12, reader.read_i64().unwrap());
+        reader.read_block(|block| {
+            assert_eq!(0xdeadbeef, block.read_u32()?);
+            Ok(())
+        }).unwrap();
+        reader.finish().unwrap();
+    }
+
+    #[test]
+    fn test_invalid_data() {
+        assert!(StateReader::new(b"CN").is_err());
+        assert!(StateReader::new(b"NOPE\x01\x00").is_err());
+        assert!(StateReader::new(b"CNSS\xff\x00").is_err());
+
+        let mut writer = StateWriter::new();
+        writer.write_u16(1);
+        let data = writer.into_bytes();
+
+        let mut reader = StateReader::new(&data[..data.len() - 1]).unwrap();
+        assert!(reader.read_u16().is_err());
+
+        let mut reader = StateReader::new(&data).unwrap();
+        reader.read_u8().unwrap();
+        assert!(reader.finish().is_err());
+
+        // A block must be read whole
+        let mut writer = StateWriter::new();
+        let mut block = StateWriter::new_block();
+        block.write_u16(1);
+        writer.write_block(block);
+        let data = writer.into_bytes();
+
+        let mut reader = StateReader::new(&data).unwrap();
+        assert!(reader.read_block(|block| block.read_u8().map(|_| ())).is_err());
+    }
+}
@@ -1,3 +1,5 @@
 pub mod ppu;
 pub mod nametable;
-mod register;
\ No newline at end of file
+mod register;
+mod sprite;
+mod vram_address;
\ No newline at end of file
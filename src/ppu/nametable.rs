@@ -1,4 +1,4 @@
-use crate::ppu::nametable::Mirroring::{Horizontal, Vertical};
+use crate::nes::savestate::{StateWriter, StateReader};
 
 pub const START_ADDRESS: u16 = 0x2000;
 pub const END_ADDRESS: u16 = 0x2FFF;
@@ -7,6 +7,7 @@ pub const MIRROR_END_ADDRESS:u16 = 0x3EFF;
 
 const NAMETABLE_SIZE: usize = 0x400;
 
+#[derive(Clone)]
 pub struct NametableMemory {
     memory: [u8; NAMETABLE_SIZE * 2],
     mirroring: Mirroring,
@@ -29,9 +30,20 @@ impl NametableMemory {
             Mirroring::Vertical => {
                 (address % 0x800) as usize
             }
+            Mirroring::SingleScreenLower => {
+                (address % 0x400) as usize
+            }
+            Mirroring::SingleScreenUpper => {
+                (address % 0x400) as usize + NAMETABLE_SIZE
+            }
         }
     }
 
+    // Mappers like MMC1 switch the mirroring at runtime
+    pub fn set_mirroring(&mut self, mirroring: Mirroring) {
+        self.mirroring = mirroring;
+    }
+
     pub fn read(&self, address: u16) -> u8 {
         match address {
             START_ADDRESS..=END_ADDRESS => {
@@ -52,19 +64,31 @@ impl NametableMemory {
             _ =>  unreachable!()
         }
     }
+
+    // The mirroring comes from the cartridge, only the memory itself is saved
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bytes(&self.memory);
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<(), String> {
+        reader.read_bytes(&mut self.memory)
+    }
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, PartialEq, Debug)]
 pub enum Mirroring {
     Horizontal = 0,
     Vertical = 1,
+    // All four nametables show the same 1 KB, the first or the second of the ppu's memory
+    SingleScreenLower = 2,
+    SingleScreenUpper = 3,
 }
 
 
 #[cfg(test)]
 #[test]
 fn test_horizontal() {
-    let mem = NametableMemory::new(Horizontal);
+    let mem = NametableMemory::new(Mirroring::Horizontal);
 
     assert_eq!(mem._calc_address(0x2000), mem._calc_address(0x2400));
     assert_eq!(mem._calc_address(0x2001), mem._calc_address(0x2401));
@@ -85,7 +109,7 @@ fn test_horizontal() {
 
 #[test]
 fn test_vertical() {
-    let mem = NametableMemory::new(Vertical);
+    let mem = NametableMemory::new(Mirroring::Vertical);
 
     assert_eq!(mem._calc_address(0x2000), mem._calc_address(0x2800));
     assert_eq!(mem._calc_address(0x2001), mem._calc_address(0x2801));
@@ -104,3 +128,18 @@ fn test_vertical() {
     assert_ne!(mem._calc_address(0x2bff), mem._calc_address(0x2fff));
 }
 
+
+#[test]
+fn test_single_screen() {
+    let mut mem = NametableMemory::new(Mirroring::SingleScreenLower);
+
+    assert_eq!(mem._calc_address(0x2000), mem._calc_address(0x2400));
+    assert_eq!(mem._calc_address(0x2000), mem._calc_address(0x2800));
+    assert_eq!(mem._calc_address(0x2001), mem._calc_address(0x2c01));
+    let lower = mem._calc_address(0x2000);
+
+    mem.set_mirroring(Mirroring::SingleScreenUpper);
+    assert_eq!(mem._calc_address(0x2000), mem._calc_address(0x2400));
+    assert_eq!(mem._calc_address(0x23ff), mem._calc_address(0x2fff));
+    assert_ne!(lower, mem._calc_address(0x2000));
+}
@@ -1,52 +1,96 @@
 use crate::nes::cartridge::cartridge::Cartridge;
 use super::register;
-use crate::ppu::register::{PpuStatus, PpuCtrl, PpuStatusTrait, PpuCtrlTrait};
+use crate::ppu::register::{PpuStatus, PpuCtrl, PpuMask, PpuStatusTrait, PpuCtrlTrait, PpuMaskTrait};
 use crate::ppu::nametable;
-use crate::ppu::nametable::NametableMemory;
+use crate::ppu::vram_address::{VramAddress, VramAddressTrait, VRAM_ADDRESS_MASK};
+use crate::ppu::sprite::{self, SpriteSlot, OAM_SIZE, SECONDARY_OAM_SIZE, SPRITES_PER_SCANLINE};
+use crate::ppu::nametable::{NametableMemory, Mirroring};
+use crate::nes::savestate::{StateWriter, StateReader};
+use crate::nes::region::Region;
 
 const PATTERN_TABLE_SIZE: usize = 0x1000;
 
-const SCANLINE_PRE_RENDER: u16 = 261;
+// The pre-render scanline is the last one of the frame and vblank ends right before it, how many lines that
+// takes depends on the region
 const SCANLINE_VISIBLE_START: u16 = 0;
 const SCANLINE_VISIBLE_END: u16 = 239;
 const SCANLINE_POST_RENDER: u16 = 240;
-const SCANLINE_VBLANK_START: u16 = 241;
-const SCANLINE_VBLANK_END: u16 = 260;
 
 const SCANLINE_CYCLE_COUNT: u16 = 341;
 
 const PIXEL_OUTPUT_CYCLE_OFFSET: u16 = 1;
 
+const SPRITE_EVALUATION_CYCLE: u16 = 65;
+const SPRITE_FETCH_START_CYCLE: u16 = 257;
+const SPRITE_FETCH_END_CYCLE: u16 = 320;
+// OAMDATA reads return this while secondary OAM is being cleared
+const SECONDARY_OAM_CLEAR_END_CYCLE: u16 = 64;
+// Fetched for the empty sprite slots
+const SPRITE_DUMMY_TILE: u8 = 0xFF;
+
+// Sprites use the upper half of palette RAM
+const SPRITE_PALETTE_OFFSET: u8 = 0x10;
+
 pub const FRAMEBUFFER_WIDTH: usize = 256;
 pub const FRAMEBUFFER_HEIGHT: usize = 240;
 const FRAMEBUFFER_SIZE: usize = FRAMEBUFFER_WIDTH * FRAMEBUFFER_HEIGHT;
 
+// A framebuffer pixel is the 6 bit color index with the PPUMASK emphasis bits above it
+pub const PIXEL_COLOR_MASK: u16 = 0x3F;
+pub const PIXEL_EMPHASIS_SHIFT: u16 = 6;
+const PIXEL_MASK: u16 = 0x1FF;
+
+// Greyscale keeps only the brightness of a color, the column with the greys
+const GREYSCALE_MASK: u8 = 0x30;
+
 const PALETTE_RAM_SIZE : usize = 0x20;
 const PALETTE_START_ADDRESS: u16 = 0x3F00;
 const PALETTE_END_ADDRESS: u16 = 0x3FFF;
+// Palette RAM only has 6 bits per entry, a color index into the system palette
+const PALETTE_ENTRY_MASK: u8 = 0x3F;
 
 const PATTERN_TABLE_START : u16 = 0;
 const PATTERN_TABLE_END :u16 = 0x1FFF;
 
 const VRAM_SIZE:u16 = 0x4000;
 
+/// The picture processing unit. It doesn't own the cartridge, the bus lends it the cartridge
+/// for every access that can reach the pattern tables.
+#[derive(Clone)]
 pub struct Ppu {
-    cartridge_ptr: *mut Cartridge,
+    region: Region,
+
     ppuctrl: PpuCtrl,
-    ppumask: u8,
+    ppumask: PpuMask,
     ppustatus: PpuStatus,
 
     oamaddr: u8,
-    // TODO oamdata
-
-    ppuscroll: u16,
-    ppuaddr: u16,
-    latch: Option<u8>,
+    oam: [u8; OAM_SIZE],
+    // The sprites found for the next scanline
+    secondary_oam: [u8; SECONDARY_OAM_SIZE],
+    secondary_oam_count: usize,
+    secondary_oam_sprite_zero: bool,
+    sprite_overflow_cycle: Option<u16>,
+    // The sprites fetched for the current scanline
+    sprites: [SpriteSlot; SPRITES_PER_SCANLINE],
+    sprite_count: usize,
+    sprite_zero_on_scanline: bool,
+
+    // The current and temporary vram address, fine X scroll and the write toggle shared by PPUSCROLL and PPUADDR
+    v: VramAddress,
+    t: VramAddress,
+    fine_x: u8,
+    w: bool,
+
+    // The last address the ppu put on its bus, mappers like MMC3 watch A12 of it
+    address_bus: u16,
 
     nametable_memory: NametableMemory,
     palette_ram: [u8; PALETTE_RAM_SIZE],
 
     vram_read_buffer :u8,
+    // The last value written to a register, returned when reading a write-only register
+    open_bus: u8,
 
     // rendering variables
     scanline: u16,
@@ -54,10 +98,7 @@ pub struct Ppu {
 
     framecount: u64,
 
-    framebuffer: [u8; FRAMEBUFFER_SIZE],
-
-    v_horizontal: u8,
-    v_vertical:u8,
+    framebuffer: Box<[u16; FRAMEBUFFER_SIZE]>,
 
     _tmp_nt_byte :u8,
     _tmp_at_byte:u8,
@@ -66,33 +107,51 @@ pub struct Ppu {
 
     bg_pattern_lo_shift: u16,
     bg_pattern_hi_shift: u16,
+    // The palette of the tiles in the pattern shift registers, one bit of it repeated for every pixel
+    bg_attribute_lo_shift: u16,
+    bg_attribute_hi_shift: u16,
+}
 
-
+// The palette RAM entry for an address in $3F00-$3FFF. Entry 0 of each sprite palette ($3F10, $3F14, $3F18 and $3F1C)
+// is the same as the one of the background palette below it.
+fn palette_ram_index(address: u16) -> usize {
+    let index = address as usize % PALETTE_RAM_SIZE;
+    if index & 0x13 == 0x10 {
+        index & 0x0F
+    } else {
+        index
+    }
 }
 
 impl Ppu {
-    pub fn new(cartridge_ptr: *mut Cartridge) -> Ppu {
-        let cartridge = unsafe { &*cartridge_ptr };
-
+    pub fn new(mirroring: Mirroring) -> Ppu {
         Ppu {
-            cartridge_ptr,
+            region: Region::Ntsc,
             ppuctrl: 0,
             ppumask: 0,
             ppustatus: 0,
             oamaddr: 0,
-            ppuscroll: 0,
-            ppuaddr: 0,
-            latch: None,
-            nametable_memory: NametableMemory::new(cartridge.get_mirroring()),
+            oam: [0; OAM_SIZE],
+            secondary_oam: [0xFF; SECONDARY_OAM_SIZE],
+            secondary_oam_count: 0,
+            secondary_oam_sprite_zero: false,
+            sprite_overflow_cycle: None,
+            sprites: [SpriteSlot::default(); SPRITES_PER_SCANLINE],
+            sprite_count: 0,
+            sprite_zero_on_scanline: false,
+            v: 0,
+            t: 0,
+            fine_x: 0,
+            w: false,
+            address_bus: 0,
+            nametable_memory: NametableMemory::new(mirroring),
             palette_ram: [0; PALETTE_RAM_SIZE],
             vram_read_buffer: 0,
-            scanline: SCANLINE_PRE_RENDER,
+            open_bus: 0,
+            scanline: Region::Ntsc.get_scanline_count() - 1,
             scanline_cycle: 0,
             framecount: 0,
-            framebuffer: [0; FRAMEBUFFER_SIZE],
-
-            v_horizontal : 2,
-            v_vertical : 30,
+            framebuffer: Box::new([0; FRAMEBUFFER_SIZE]),
 
             _tmp_nt_byte : 0,
             _tmp_at_byte : 0,
@@ -101,13 +160,34 @@ impl Ppu {
 
             bg_pattern_lo_shift: 0,
             bg_pattern_hi_shift: 0,
+            bg_attribute_lo_shift: 0,
+            bg_attribute_hi_shift: 0,
         }
     }
 
-    pub fn write_register(&mut self, address: u16, data: u8) {
+    // Starts over on the pre-render scanline when the frame of the new region is shorter
+    pub fn set_region(&mut self, region: Region) {
+        self.region = region;
+        if self.scanline > self._pre_render_scanline() {
+            self.scanline = self._pre_render_scanline();
+        }
+    }
+
+    pub fn set_mirroring(&mut self, mirroring: Mirroring) {
+        self.nametable_memory.set_mirroring(mirroring);
+    }
+
+    fn _pre_render_scanline(&self) -> u16 {
+        self.region.get_scanline_count() - 1
+    }
+
+    pub fn write_register(&mut self, cartridge: &mut Cartridge, address: u16, data: u8) {
+        self.open_bus = data;
+
         match address % register::REGISTER_SIZE {
             register::PPUCTRL_OFFSET => {
                 self.ppuctrl = data;
+                self.t.set_nametable((self.ppuctrl.base_nametable_addr() - nametable::START_ADDRESS) / 0x400);
             }
             register::PPUMASK_OFFSET => {
                 self.ppumask = data;
@@ -125,7 +205,7 @@ impl Ppu {
                 self._write_ppuaddr(data);
             }
             register::PPUDATA_OFFSET => {
-                self._write_ppudata(data);
+                self._write_ppudata(cartridge, data);
             }
             _ => {
                 println!("CRASH ppu::write_register ${:04X} = ${:02X}", address, data);
@@ -133,211 +213,324 @@ impl Ppu {
             }
         }
     }
-    pub fn read_register(&mut self, address: u16) -> u8 {
+    pub fn read_register(&mut self, cartridge: &Cartridge, address: u16) -> u8 {
         match address % register::REGISTER_SIZE {
             register::PPUSTATUS_OFFSET => {
-                let status = self.ppustatus;
+                let status = self.ppustatus.with_open_bus(self.open_bus);
                 self.ppustatus.clear_vblank();
-                self.latch = None;
+                self.w = false;
 
                 status
             }
-            register::PPUDATA_OFFSET => {
-                self._read_ppudata()
+            register::OAMDATA_OFFSET => {
+                self._read_oamdata()
             }
-            _ => {
-                println!("CRASH ppu::read_register ${:04X}", address);
-                unreachable!()
+            register::PPUDATA_OFFSET => {
+                self._read_ppudata(cartridge)
             }
+            _ => self.open_bus
+        }
+    }
+
+    // Reads a register without the side effects of read_register, for debug views
+    pub fn peek_register(&self, address: u16) -> u8 {
+        match address % register::REGISTER_SIZE {
+            register::PPUSTATUS_OFFSET => self.ppustatus.with_open_bus(self.open_bus),
+            register::OAMDATA_OFFSET => self._read_oamdata(),
+            register::PPUDATA_OFFSET => self.vram_read_buffer,
+            _ => self.open_bus
+        }
+    }
+
+    fn _is_rendering_scanline(&self) -> bool {
+        self.ppumask.is_rendering_enabled() &&
+            (self.scanline <= SCANLINE_VISIBLE_END || self.scanline == self._pre_render_scanline())
+    }
+
+    fn _read_oamdata(&self) -> u8 {
+        if self._is_rendering_scanline() && self.scanline != self._pre_render_scanline() &&
+            (1..=SECONDARY_OAM_CLEAR_END_CYCLE).contains(&self.scanline_cycle) {
+            return 0xFF;
+        }
+
+        let data = self.oam[self.oamaddr as usize];
+        if self.oamaddr % 4 == 2 {
+            data & !sprite::ATTRIBUTE_UNIMPLEMENTED_MASK
+        } else {
+            data
         }
     }
 
     fn _write_oamdata(&mut self, data: u8)  {
-        // TODO write oamdata;
-        // println!("writing oamdata");
+        if self._is_rendering_scanline() {
+            // The write is lost, and the address skips to the next sprite
+            self.oamaddr = self.oamaddr.wrapping_add(4);
+            return;
+        }
+
+        self.oam[self.oamaddr as usize] = data;
+        self.oamaddr = self.oamaddr.wrapping_add(1);
     }
 
 
     fn _write_ppuaddr(&mut self, data: u8) {
-        match self.latch {
-            Some(hi) => {
-                self.ppuaddr = (((hi as u16) << 8) + data as u16) % VRAM_SIZE;
-                self.latch = None;
-            }
-            None => {
-                self.latch = Some(data);
-            }
+        if self.w {
+            self.t.set_address_lo(data);
+            self.v = self.t;
+            self.address_bus = self._vram_address();
+        } else {
+            self.t.set_address_hi(data);
         }
+        self.w = !self.w;
     }
 
     fn _write_ppuscroll(&mut self, data: u8) {
-        match self.latch {
-            Some(hi) => {
-                self.ppuscroll = ((hi as u16) << 8) + data as u16;
-            }
-            None => {
-                self.latch = Some(data);
-            }
+        if self.w {
+            self.t.set_y_scroll(data);
+        } else {
+            self.t.set_coarse_x(data);
+            self.fine_x = data & 0b111;
+        }
+        self.w = !self.w;
+    }
+
+    // PPUDATA addresses the ppu memory through v
+    fn _vram_address(&self) -> u16 {
+        self.v % VRAM_SIZE
+    }
+
+    // While rendering v is busy scrolling, and an access bumps both coarse X and Y instead
+    fn _increment_vram_address(&mut self) {
+        if self._is_rendering_scanline() {
+            self.v.increment_x();
+            self.v.increment_y();
+        } else {
+            self.v = (self.v + self.ppuctrl.vram_address_increment()) & VRAM_ADDRESS_MASK;
         }
     }
 
-    fn _read_ppudata(&mut self) -> u8 {
+    fn _read_ppudata(&mut self, cartridge: &Cartridge) -> u8 {
         let mut return_value = self.vram_read_buffer;
+        let address = self._vram_address();
+        self.address_bus = address;
 
-        match self.ppuaddr {
-            PATTERN_TABLE_START..=PATTERN_TABLE_END =>  unsafe {
-                self.vram_read_buffer = (&mut *self.cartridge_ptr).read_chr(self.ppuaddr);
+        match address {
+            PATTERN_TABLE_START..=PATTERN_TABLE_END => {
+                self.vram_read_buffer = cartridge.read_chr(address);
             }
             nametable::START_ADDRESS..=nametable::END_ADDRESS => {
-                self.vram_read_buffer = self.nametable_memory.read(self.ppuaddr);
+                self.vram_read_buffer = self.nametable_memory.read(address);
             }
             nametable::MIRROR_START_ADDRESS..=nametable::MIRROR_END_ADDRESS => {
-                let mirrored_address = self.ppuaddr - 0x1000;
+                let mirrored_address = address - 0x1000;
                 self.vram_read_buffer = self.nametable_memory.read(mirrored_address);
 
             }
             PALETTE_START_ADDRESS..=PALETTE_END_ADDRESS => {
-                let mirrored_address = self.ppuaddr - 0x1000;
+                let mirrored_address = address - 0x1000;
 
-                return_value = self.palette_ram[(mirrored_address as usize % PALETTE_RAM_SIZE)];
+                // Not buffered, the top two bits are open bus
+                return_value = self.palette_ram[palette_ram_index(address)] | (self.open_bus & !PALETTE_ENTRY_MASK);
                 self.vram_read_buffer = self.nametable_memory.read(mirrored_address);
             }
             _ => {
-                println!("_read_ppudata {:04x}", self.ppuaddr);
+                println!("_read_ppudata {:04x}", address);
                 unreachable!()
             }
         }
 
-        self.ppuaddr += self.ppuctrl.vram_address_increment();
-        if self.ppuaddr > VRAM_SIZE {
-            self.ppuaddr = self.ppuaddr % VRAM_SIZE;
-        }
+        self._increment_vram_address();
 
         return_value
     }
 
-    fn _write_ppudata(&mut self, data: u8) {
-        match self.ppuaddr {
-            PATTERN_TABLE_START..=PATTERN_TABLE_END => unsafe {
-                (&mut *self.cartridge_ptr).write_chr(self.ppuaddr, data);
+    fn _write_ppudata(&mut self, cartridge: &mut Cartridge, data: u8) {
+        let address = self._vram_address();
+        self.address_bus = address;
+
+        match address {
+            PATTERN_TABLE_START..=PATTERN_TABLE_END => {
+                cartridge.write_chr(address, data);
             }
             nametable::START_ADDRESS..=nametable::END_ADDRESS => {
-               self.nametable_memory.write(self.ppuaddr, data);
+               self.nametable_memory.write(address, data);
             }
             nametable::MIRROR_START_ADDRESS..=nametable::MIRROR_END_ADDRESS => {
-                let mirrored_address = self.ppuaddr - 0x1000;
+                let mirrored_address = address - 0x1000;
                 self.nametable_memory.write(mirrored_address, data);
             }
             PALETTE_START_ADDRESS..=PALETTE_END_ADDRESS => {
-                self.palette_ram[self.ppuaddr as usize % PALETTE_RAM_SIZE];
+                self.palette_ram[palette_ram_index(address)] = data & PALETTE_ENTRY_MASK;
             }
             _ => {
-                println!("_write_ppudata {:04x}", self.ppuaddr);
+                println!("_write_ppudata {:04x}", address);
                 unreachable!()
             }
         }
 
-        self.ppuaddr += self.ppuctrl.vram_address_increment();
-        if self.ppuaddr > VRAM_SIZE {
-            self.ppuaddr = self.ppuaddr % VRAM_SIZE;
-        }
+        self._increment_vram_address();
     }
 
     pub fn _output_framebuffer_pixel(&mut self) {
         match self.scanline {
             SCANLINE_VISIBLE_START..=SCANLINE_VISIBLE_END => {
                 if self.scanline_cycle >= PIXEL_OUTPUT_CYCLE_OFFSET &&
-                    self.scanline_cycle < (FRAMEBUFFER_WIDTH as u16 - PIXEL_OUTPUT_CYCLE_OFFSET) {
-                    let pixel = self.scanline as usize * FRAMEBUFFER_WIDTH + self.scanline_cycle as usize - PIXEL_OUTPUT_CYCLE_OFFSET as usize;
-                    self.framebuffer[pixel] = self._next_pixel_value();
+                    self.scanline_cycle < FRAMEBUFFER_WIDTH as u16 + PIXEL_OUTPUT_CYCLE_OFFSET {
+                    let x = self.scanline_cycle - PIXEL_OUTPUT_CYCLE_OFFSET;
+                    let pixel = self.scanline as usize * FRAMEBUFFER_WIDTH + x as usize;
+
+                    let bg_pixel = self._next_pixel_value();
+                    let palette_address = self._compose_pixel(x, bg_pixel) as u16;
+                    self.framebuffer[pixel] = self._pixel_color(palette_address);
                 }
             }
             _ => {}
         }
     }
 
+    // The framebuffer value for a palette RAM address, with greyscale and emphasis applied
+    fn _pixel_color(&self, palette_address: u16) -> u16 {
+        // With rendering disabled the backdrop is shown, unless v points into palette RAM. Then that color is.
+        let address = if !self.ppumask.is_rendering_enabled() && self._vram_address() >= PALETTE_START_ADDRESS {
+            self._vram_address()
+        } else {
+            palette_address
+        };
+
+        let mut color = self.palette_ram[palette_ram_index(address)];
+        if self.ppumask.is_greyscale() {
+            color &= GREYSCALE_MASK;
+        }
+
+        let mut emphasis = self.ppumask.emphasis() as u16;
+        if self.region.swaps_red_green_emphasis() {
+            emphasis = (emphasis & 0b100) | ((emphasis & 0b01) << 1) | ((emphasis & 0b10) >> 1);
+        }
+
+        color as u16 | emphasis << PIXEL_EMPHASIS_SHIFT
+    }
+
+    // Picks the background or the first opaque sprite, and returns its palette RAM address.
+    // Transparent pixels are 0, the backdrop color.
+    fn _compose_pixel(&mut self, x: u16, bg_pixel: u8) -> u8 {
+        let show_sprite = self.ppumask.show_sprites() && (x >= 8 || self.ppumask.show_sprites_leftmost());
+        let show_bg = self.ppumask.show_bg() && (x >= 8 || self.ppumask.show_bg_leftmost());
+        let bg_pixel = if show_bg { bg_pixel } else { 0 };
+
+        let sprite = if show_sprite {
+            self.sprites[..self.sprite_count].iter()
+                .enumerate()
+                .map(|(i, slot)| (i, slot, slot.pixel(x)))
+                .find(|(_i, _slot, pixel)| *pixel > 0)
+        } else {
+            None
+        };
+
+        match sprite {
+            Some((i, slot, sprite_pixel)) => {
+                // Sprite 0 hit ignores the priority bit, but never happens on the last pixel
+                if i == 0 && self.sprite_zero_on_scanline && bg_pixel > 0 && x != FRAMEBUFFER_WIDTH as u16 - 1 {
+                    self.ppustatus.set_sprite_0_hit();
+                }
+
+                if bg_pixel > 0 && slot.is_behind_background() {
+                    bg_pixel
+                } else {
+                    SPRITE_PALETTE_OFFSET | (slot.palette() << 2) | sprite_pixel
+                }
+            }
+            None => bg_pixel
+        }
+    }
+
+    // The palette RAM address of the next background pixel, 0 if it is transparent
     pub fn _next_pixel_value(&mut self) -> u8 {
-        let lo = (self.bg_pattern_lo_shift >> 15) as u8;
-        let hi = (self.bg_pattern_hi_shift >> 15) as u8;
+        // Fine X selects which of the two tiles in the shift registers the pixel comes from
+        let bit = 15 - self.fine_x as u16;
+        let lo = ((self.bg_pattern_lo_shift >> bit) & 1) as u8;
+        let hi = ((self.bg_pattern_hi_shift >> bit) & 1) as u8;
+        let palette_lo = ((self.bg_attribute_lo_shift >> bit) & 1) as u8;
+        let palette_hi = ((self.bg_attribute_hi_shift >> bit) & 1) as u8;
 
         self.bg_pattern_lo_shift <<= 1;
         self.bg_pattern_hi_shift <<= 1;
+        self.bg_attribute_lo_shift <<= 1;
+        self.bg_attribute_hi_shift <<= 1;
 
+        let pixel = (hi << 1) + lo;
+        if pixel == 0 {
+            return 0;
+        }
 
-        (hi << 1)+ lo
+        (palette_hi << 3) | (palette_lo << 2) | pixel
     }
 
-    fn _prerender_scanline(&mut self) {
-        self._process_scanline();
+    fn _prerender_scanline(&mut self, cartridge: &Cartridge) {
+        self._process_scanline(cartridge);
 
         match self.scanline_cycle {
             1 => {
                 self.ppustatus.clear_vblank();
-                // TODO clear sprite overflow
-            }
-            280..=304 => {
-                self.v_vertical = 0;
+                self.ppustatus.clear_sprite_flags();
+                self.secondary_oam_count = 0;
+                self.sprite_overflow_cycle = None;
             }
+            280..=304 if self.ppumask.is_rendering_enabled() => self.v.copy_vertical(self.t),
 
             _ => {}
         }
 
     }
 
-    fn _inc_v_horizontal(&mut self) {
-        self.v_horizontal += 1;
-        if self.v_horizontal > 32 {
-            self.v_horizontal = 0;
-        }
-    }
-
-    fn _inc_v_vertical(&mut self) {
-        self.v_vertical += 1;
-        if self.v_vertical >= 240 {
-            self.v_vertical = 0;
-        }
-    }
-
-    fn _reset_v_horizontal(&mut self) {
-        self.v_horizontal = 0;
-    }
-
     fn _fetch_nt_byte(&mut self) {
-        let fetch_addr = (self.v_horizontal as u16 + ((self.v_vertical / 8)as u16 * 32)) + 0x2000;
-
-        // println!("v.x={} v.y={} scanline={} cycle={}, fetch_addr={:04x}", self.v_horizontal , self.v_vertical, self.scanline, self.scanline_cycle, fetch_addr);
-        self._tmp_nt_byte = self.nametable_memory.read(fetch_addr);
-
-        if self._tmp_nt_byte > 0 {
-            // println!("nt byte {}   scanline={} cycle={}", self._tmp_nt_byte, self.scanline, self.scanline_cycle);
-        }
+        self.address_bus = self.v.nametable_fetch_addr();
+        self._tmp_nt_byte = self.nametable_memory.read(self.address_bus);
     }
 
-     fn _fetch_bg_lo_byte(&mut self) {
-        let addr = (self._tmp_nt_byte as u16 * 16) + (self.v_vertical % 8) as u16;
-         self._tmp_pt_lo = unsafe { (*self.cartridge_ptr).read_chr(addr + self.ppuctrl.bg_pattern_table_addr()) };
-         // TODO patterntable offset
+    // Keeps the two bits of the attribute byte for the 2x2 tiles the fetched tile is in
+    fn _fetch_at_byte(&mut self) {
+        self.address_bus = self.v.attribute_fetch_addr();
+        let attribute = self.nametable_memory.read(self.address_bus);
+        let shift = ((self.v.coarse_y() & 2) << 1) | (self.v.coarse_x() & 2);
+        self._tmp_at_byte = (attribute >> shift) & 0b11;
     }
 
+    fn _fetch_bg_lo_byte(&mut self, cartridge: &Cartridge) {
+        let addr = (self._tmp_nt_byte as u16 * 16) + self.v.fine_y();
+        self.address_bus = addr + self.ppuctrl.bg_pattern_table_addr();
+        self._tmp_pt_lo = cartridge.read_chr(self.address_bus);
+    }
 
-    fn _fetch_bg_hi_byte(&mut self) {
-        let addr = (self._tmp_nt_byte as u16 * 16 + (self.v_vertical % 8) as u16) + 8;
 
-        self._tmp_pt_hi = unsafe { (*self.cartridge_ptr).read_chr(addr + self.ppuctrl.bg_pattern_table_addr()) };
-        // TODO patterntable offset
+    fn _fetch_bg_hi_byte(&mut self, cartridge: &Cartridge) {
+        let addr = (self._tmp_nt_byte as u16 * 16 + self.v.fine_y()) + 8;
+        self.address_bus = addr + self.ppuctrl.bg_pattern_table_addr();
+        self._tmp_pt_hi = cartridge.read_chr(self.address_bus);
     }
 
     fn _reload_shift_registers(&mut self) {
         self.bg_pattern_lo_shift = (self.bg_pattern_lo_shift & 0xFF00) | self._tmp_pt_lo as u16;
         self.bg_pattern_hi_shift = (self.bg_pattern_hi_shift & 0xFF00) | self._tmp_pt_hi as u16;
+
+        let palette_lo = if self._tmp_at_byte & 0b01 > 0 { 0xFF } else { 0 };
+        let palette_hi = if self._tmp_at_byte & 0b10 > 0 { 0xFF } else { 0 };
+        self.bg_attribute_lo_shift = (self.bg_attribute_lo_shift & 0xFF00) | palette_lo;
+        self.bg_attribute_hi_shift = (self.bg_attribute_hi_shift & 0xFF00) | palette_hi;
     }
 
     fn _shift_byte(&mut self) {
         self.bg_pattern_lo_shift <<= 8;
         self.bg_pattern_hi_shift <<= 8;
+        self.bg_attribute_lo_shift <<= 8;
+        self.bg_attribute_hi_shift <<= 8;
     }
 
-    fn _process_scanline(&mut self) {
+    // The background fetches, and the scrolling of v along with them. Nothing happens with rendering disabled.
+    fn _process_scanline(&mut self, cartridge: &Cartridge) {
+        if !self.ppumask.is_rendering_enabled() {
+            return;
+        }
+
         let cycle_mod = self.scanline_cycle % 8;
 
         match self.scanline_cycle {
@@ -349,45 +542,42 @@ impl Ppu {
                 }
 
                 match cycle_mod {
-                    0 => { self._inc_v_horizontal(); }
-                    1 => { self._fetch_nt_byte();
-
-
-                    }
-                    3 => {
-                        // Attribute
-                    }
+                    0 => { self.v.increment_x(); }
+                    1 => { self._fetch_nt_byte(); }
+                    3 => { self._fetch_at_byte(); }
                     5 => {
                         // Pattern lo
-                        self._fetch_bg_lo_byte();
+                        self._fetch_bg_lo_byte(cartridge);
                     }
                     7 => {
                         // Pattern hi
-                        self._fetch_bg_hi_byte();
+                        self._fetch_bg_hi_byte(cartridge);
                     }
                     _ => {}
                 }
                 if self.scanline_cycle == 256 {
-                    self._inc_v_vertical();
+                    self.v.increment_y();
                 }
 
             }
             257 => {
-                self._reset_v_horizontal();
+                self.v.copy_horizontal(self.t);
             }
 
             321 => { self._fetch_nt_byte(); }
-            325 => { self._fetch_bg_lo_byte(); }
-            327 => { self._fetch_bg_hi_byte(); }
-            328 => { self._inc_v_horizontal(); }
+            323 => { self._fetch_at_byte(); }
+            325 => { self._fetch_bg_lo_byte(cartridge); }
+            327 => { self._fetch_bg_hi_byte(cartridge); }
+            328 => { self.v.increment_x(); }
             329 => {
                 self._fetch_nt_byte();
                 self._reload_shift_registers();
                 self._shift_byte();
             }
-            333 => { self._fetch_bg_lo_byte(); }
-            335 => { self._fetch_bg_hi_byte(); }
-            336 => { self._inc_v_horizontal(); }
+            331 => { self._fetch_at_byte(); }
+            333 => { self._fetch_bg_lo_byte(cartridge); }
+            335 => { self._fetch_bg_hi_byte(cartridge); }
+            336 => { self.v.increment_x(); }
 
             337 => { self._reload_shift_registers();}
             338..=340 => {
@@ -397,26 +587,123 @@ impl Ppu {
         }
     }
 
+    fn _process_sprites(&mut self, cartridge: &Cartridge) {
+        if !self.ppumask.is_rendering_enabled() {
+            if self.scanline_cycle == SPRITE_FETCH_START_CYCLE {
+                self.sprite_count = 0;
+            }
+            return;
+        }
+
+        let visible = self.scanline <= SCANLINE_VISIBLE_END;
+
+        match self.scanline_cycle {
+            SPRITE_EVALUATION_CYCLE if visible => {
+                let evaluation = sprite::evaluate(&self.oam, &mut self.secondary_oam, self.scanline, self.ppuctrl.sprite_height());
+                self.secondary_oam_count = evaluation.count;
+                self.secondary_oam_sprite_zero = evaluation.sprite_zero;
+                self.sprite_overflow_cycle = evaluation.overflow_dot;
+            }
+            SPRITE_FETCH_START_CYCLE..=SPRITE_FETCH_END_CYCLE => {
+                self.oamaddr = 0;
+
+                let slot = ((self.scanline_cycle - SPRITE_FETCH_START_CYCLE) / 8) as usize;
+                match self.scanline_cycle % 8 {
+                    1 if slot == 0 => {
+                        self.address_bus = self.v.nametable_fetch_addr();
+                        // Nothing was evaluated on the pre-render scanline, so no sprites are drawn on the first line
+                        self.sprite_count = if visible { self.secondary_oam_count } else { 0 };
+                        self.sprite_zero_on_scanline = self.secondary_oam_sprite_zero;
+                    }
+                    // The unused nametable fetches of the sprite slots, they only show on the address bus
+                    1 | 3 => { self.address_bus = self.v.nametable_fetch_addr(); }
+                    5 => { self._fetch_sprite_lo_byte(cartridge, slot); }
+                    7 => { self._fetch_sprite_hi_byte(cartridge, slot); }
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+
+        if visible && Some(self.scanline_cycle) == self.sprite_overflow_cycle {
+            self.ppustatus.set_sprite_overflow();
+        }
+    }
+
+    fn _sprite_pattern_addr(&self, slot: usize) -> u16 {
+        let height = self.ppuctrl.sprite_height();
+
+        if slot >= self.sprite_count {
+            return if height == 16 {
+                0x1000 + (SPRITE_DUMMY_TILE & 0xFE) as u16 * 16
+            } else {
+                self.ppuctrl.sprite_pattern_table_addr() + SPRITE_DUMMY_TILE as u16 * 16
+            };
+        }
+
+        let entry = &self.secondary_oam[slot * 4..slot * 4 + 4];
+        let mut row = (self.scanline - entry[0] as u16) % height;
+        if entry[2] & sprite::ATTRIBUTE_FLIP_VERTICAL_MASK > 0 {
+            row = height - 1 - row;
+        }
+
+        // 8x16 sprites take the pattern table from bit 0 of the tile number, the bottom half is the next tile
+        let (table, tile) = if height == 16 {
+            ((entry[1] & 1) as u16 * 0x1000, (entry[1] & 0xFE) as u16 + row / 8)
+        } else {
+            (self.ppuctrl.sprite_pattern_table_addr(), entry[1] as u16)
+        };
+
+        table + tile * 16 + row % 8
+    }
+
+    fn _fetch_sprite_lo_byte(&mut self, cartridge: &Cartridge, slot: usize) {
+        self.address_bus = self._sprite_pattern_addr(slot);
+        let pattern = cartridge.read_chr(self.address_bus);
+
+        if slot < self.sprite_count {
+            let attributes = self.secondary_oam[slot * 4 + 2];
+            self.sprites[slot] = SpriteSlot {
+                x: self.secondary_oam[slot * 4 + 3],
+                attributes,
+                pattern_lo: if attributes & sprite::ATTRIBUTE_FLIP_HORIZONTAL_MASK > 0 { pattern.reverse_bits() } else { pattern },
+                pattern_hi: 0,
+            };
+        }
+    }
+
+    fn _fetch_sprite_hi_byte(&mut self, cartridge: &Cartridge, slot: usize) {
+        self.address_bus = self._sprite_pattern_addr(slot) + 8;
+        let pattern = cartridge.read_chr(self.address_bus);
+
+        if slot < self.sprite_count {
+            let sprite = &mut self.sprites[slot];
+            sprite.pattern_hi = if sprite.attributes & sprite::ATTRIBUTE_FLIP_HORIZONTAL_MASK > 0 { pattern.reverse_bits() } else { pattern };
+        }
+    }
+
     fn _vblank_scanline(&mut self) {
-        if self.scanline_cycle == 1 {
+        if self.scanline == self.region.get_vblank_start_scanline() && self.scanline_cycle == 1 {
             self.ppustatus.set_vblank();
         }
     }
 
-    pub fn tick(&mut self) -> bool {
+    pub fn tick(&mut self, cartridge: &Cartridge) -> bool {
         match self.scanline {
             // Pre-render scanline
-            SCANLINE_PRE_RENDER => {
-                self._prerender_scanline();
+            scanline if scanline == self._pre_render_scanline() => {
+                self._prerender_scanline(cartridge);
+                self._process_sprites(cartridge);
             }
             SCANLINE_VISIBLE_START..=SCANLINE_VISIBLE_END => {
-                self._process_scanline();
+                self._process_scanline(cartridge);
+                self._process_sprites(cartridge);
             }
-            SCANLINE_POST_RENDER => {}
-            SCANLINE_VBLANK_START..=SCANLINE_VBLANK_END => {
+            scanline if scanline >= self.region.get_vblank_start_scanline() => {
                 self._vblank_scanline();
             }
-            _ => { unreachable!() }
+            // Post-render, idle
+            _ => {}
         }
 
         self._output_framebuffer_pixel();
@@ -434,7 +721,9 @@ impl Ppu {
         self.scanline_cycle += 1;
 
         if self.scanline_cycle == SCANLINE_CYCLE_COUNT {
-            if self.scanline == SCANLINE_PRE_RENDER && self.framecount % 2 == 1 {
+            // The NTSC ppu skips the first dot of odd frames while rendering
+            if self.scanline == self._pre_render_scanline() && self.framecount % 2 == 1 &&
+                self.region.skips_odd_frame_dot() && self.ppumask.is_rendering_enabled() {
                 self.scanline_cycle = 1;
             } else {
                 self.scanline_cycle = 0;
@@ -442,16 +731,14 @@ impl Ppu {
             self.scanline += 1;
         }
 
-        if self.scanline > SCANLINE_PRE_RENDER {
+        if self.scanline > self._pre_render_scanline() {
             self.scanline = 0;
             self.framecount += 1;
-
-            self.v_vertical = 0; // TODO
         }
     }
 
 
-    pub fn patterntable_to_texture_data(&self, pattern_table_index: u8) -> [u8; 16384] {
+    pub fn patterntable_to_texture_data(&self, cartridge: &Cartridge, pattern_table_index: u8) -> [u8; 16384] {
         const PATTERN_TABLE_TILE_COUNT: usize = 256;
         const PATTERN_TABLE_TILE_WIDTH: usize = 8;
         const PATTERN_TABLE_TILE_HEIGHT: usize = 8;
@@ -461,17 +748,19 @@ impl Ppu {
         const TEXTURE_DATA_WIDTH: usize = 128; // 128 x 128 values
         const TEXTURE_TILE_COUNT_WIDTH: usize = TEXTURE_DATA_WIDTH / PATTERN_TABLE_TILE_WIDTH;
 
-        let cartridge = unsafe { &mut *self.cartridge_ptr };
-        let data = cartridge.read_chr_slice(pattern_table_index as u16 * PATTERN_TABLE_SIZE as u16, PATTERN_TABLE_SIZE);
+        let table_address = pattern_table_index as u16 * PATTERN_TABLE_SIZE as u16;
 
         let mut target = [0; PATTERN_TABLE_TILE_COUNT * PATTERN_TABLE_TILE_WIDTH * PATTERN_TABLE_TILE_HEIGHT];
 
         // For every tile
         for i in 0..PATTERN_TABLE_TILE_COUNT {
+            // Read tile by tile, mappers can bank the pattern table in pieces as small as 1 KB
+            let data = cartridge.read_chr_slice(table_address + (i * PATTERN_TABLE_BYTES_PER_TILE) as u16, PATTERN_TABLE_BYTES_PER_TILE);
+
             // For every line in pattern
             for j in 0..PATTERN_TABLE_TILE_HEIGHT {
-                let lo_plane = data[i * PATTERN_TABLE_BYTES_PER_TILE + j];
-                let hi_plane = data[i * PATTERN_TABLE_BYTES_PER_TILE + j + PATTERN_TABLE_PLANE_SIZE];
+                let lo_plane = data[j];
+                let hi_plane = data[j + PATTERN_TABLE_PLANE_SIZE];
 
                 // For every pixel in line
                 for k in 0..PATTERN_TABLE_TILE_WIDTH {
@@ -491,10 +780,9 @@ impl Ppu {
     }
 
 
-    fn _read_memory(&self, address: u16) -> u8 {
+    fn _read_memory(&self, cartridge: &Cartridge, address: u16) -> u8 {
         match address {
             0..=0x1FFF => {
-                let cartridge = unsafe { &mut *self.cartridge_ptr };
                 cartridge.read_chr(address)
             }
             0x2000..=0x2FFF => {
@@ -537,6 +825,110 @@ impl Ppu {
         }
     }
 
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u8(self.ppuctrl);
+        writer.write_u8(self.ppumask);
+        writer.write_u8(self.ppustatus);
+
+        writer.write_u8(self.oamaddr);
+        writer.write_bytes(&self.oam);
+        writer.write_bytes(&self.secondary_oam);
+        writer.write_u8(self.secondary_oam_count as u8);
+        writer.write_bool(self.secondary_oam_sprite_zero);
+        writer.write_u16(self.sprite_overflow_cycle.unwrap_or(0));
+        for sprite in self.sprites.iter() {
+            sprite.save_state(writer);
+        }
+        writer.write_u8(self.sprite_count as u8);
+        writer.write_bool(self.sprite_zero_on_scanline);
+
+        writer.write_u16(self.v);
+        writer.write_u16(self.t);
+        writer.write_u8(self.fine_x);
+        writer.write_bool(self.w);
+        writer.write_u16(self.address_bus);
+
+        self.nametable_memory.save_state(writer);
+        writer.write_bytes(&self.palette_ram);
+
+        writer.write_u8(self.vram_read_buffer);
+        writer.write_u8(self.open_bus);
+
+        writer.write_u16(self.scanline);
+        writer.write_u16(self.scanline_cycle);
+        writer.write_u64(self.framecount);
+        for pixel in self.framebuffer.iter() {
+            writer.write_u16(*pixel);
+        }
+
+        writer.write_u8(self._tmp_nt_byte);
+        writer.write_u8(self._tmp_at_byte);
+        writer.write_u8(self._tmp_pt_lo);
+        writer.write_u8(self._tmp_pt_hi);
+
+        writer.write_u16(self.bg_pattern_lo_shift);
+        writer.write_u16(self.bg_pattern_hi_shift);
+        writer.write_u16(self.bg_attribute_lo_shift);
+        writer.write_u16(self.bg_attribute_hi_shift);
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<(), String> {
+        self.ppuctrl = reader.read_u8()?;
+        self.ppumask = reader.read_u8()?;
+        self.ppustatus = reader.read_u8()?;
+
+        self.oamaddr = reader.read_u8()?;
+        reader.read_bytes(&mut self.oam)?;
+        reader.read_bytes(&mut self.secondary_oam)?;
+        self.secondary_oam_count = (reader.read_u8()? as usize).min(SPRITES_PER_SCANLINE);
+        self.secondary_oam_sprite_zero = reader.read_bool()?;
+        // Overflow is never set on dot 0
+        self.sprite_overflow_cycle = Some(reader.read_u16()?).filter(|cycle| *cycle > 0);
+        for sprite in self.sprites.iter_mut() {
+            sprite.load_state(reader)?;
+        }
+        self.sprite_count = (reader.read_u8()? as usize).min(SPRITES_PER_SCANLINE);
+        self.sprite_zero_on_scanline = reader.read_bool()?;
+
+        self.v = reader.read_u16()? & VRAM_ADDRESS_MASK;
+        self.t = reader.read_u16()? & VRAM_ADDRESS_MASK;
+        self.fine_x = reader.read_u8()? & 0b111;
+        self.w = reader.read_bool()?;
+        self.address_bus = reader.read_u16()? % VRAM_SIZE;
+
+        self.nametable_memory.load_state(reader)?;
+        reader.read_bytes(&mut self.palette_ram)?;
+        for entry in self.palette_ram.iter_mut() {
+            *entry &= PALETTE_ENTRY_MASK;
+        }
+
+        self.vram_read_buffer = reader.read_u8()?;
+        self.open_bus = reader.read_u8()?;
+
+        self.scanline = reader.read_u16()?;
+        self.scanline_cycle = reader.read_u16()?;
+        self.framecount = reader.read_u64()?;
+        for pixel in self.framebuffer.iter_mut() {
+            *pixel = reader.read_u16()? & PIXEL_MASK;
+        }
+
+        self._tmp_nt_byte = reader.read_u8()?;
+        self._tmp_at_byte = reader.read_u8()? & 0b11;
+        self._tmp_pt_lo = reader.read_u8()?;
+        self._tmp_pt_hi = reader.read_u8()?;
+
+        self.bg_pattern_lo_shift = reader.read_u16()?;
+        self.bg_pattern_hi_shift = reader.read_u16()?;
+        self.bg_attribute_lo_shift = reader.read_u16()?;
+        self.bg_attribute_hi_shift = reader.read_u16()?;
+
+        if self.scanline > self._pre_render_scanline() || self.scanline_cycle >= SCANLINE_CYCLE_COUNT {
+            return Err(format!("Invalid ppu position in save state: scanline {} cycle {}", self.scanline, self.scanline_cycle));
+        }
+
+        Ok(())
+    }
+
     pub fn get_ppuctrl(&self) -> u8 {
         self.ppuctrl
     }
@@ -553,21 +945,598 @@ impl Ppu {
         self.oamaddr
     }
 
-    pub fn get_ppuscroll(&self) -> u16 {
-        self.ppuscroll
+    pub fn get_oam(&self) -> &[u8] {
+        &self.oam
+    }
+
+    pub fn get_v(&self) -> u16 {
+        self.v
+    }
+
+    pub fn get_t(&self) -> u16 {
+        self.t
+    }
+
+    pub fn get_fine_x(&self) -> u8 {
+        self.fine_x
+    }
+
+    pub fn get_address_bus(&self) -> u16 {
+        self.address_bus
+    }
+
+    pub fn get_write_toggle(&self) -> bool {
+        self.w
     }
 
     pub fn get_nmi_signal(&self) -> bool {
         self.ppuctrl.generate_nmi() && self.ppustatus.is_vblank()
     }
 
-    pub fn get_ppuaddr(&self) -> u16 {
-        self.ppuaddr
+    pub fn is_vblank(&self) -> bool {
+        self.ppustatus.is_vblank()
+    }
+
+    pub fn is_sprite_0_hit(&self) -> bool {
+        self.ppustatus.is_sprite_0_hit()
     }
 
-    pub fn get_framebuffer(&self) -> &[u8] {
-        &self.framebuffer
+    pub fn is_sprite_overflow(&self) -> bool {
+        self.ppustatus.is_sprite_overflow()
+    }
+
+    pub fn get_scanline(&self) -> u16 {
+        self.scanline
+    }
+
+    pub fn get_scanline_cycle(&self) -> u16 {
+        self.scanline_cycle
+    }
+
+    pub fn get_framebuffer(&self) -> &[u16] {
+        &self.framebuffer[..]
     }
 
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::Ppu;
+    use crate::nes::cartridge::cartridge::{self, Cartridge};
+    use crate::ppu::nametable::Mirroring;
+    use crate::nes::rominfo::RomInfo;
+
+    const PPUCTRL: u16 = 0x2000;
+    const PPUMASK: u16 = 0x2001;
+    const PPUSTATUS: u16 = 0x2002;
+    const OAMADDR: u16 = 0x2003;
+    const OAMDATA: u16 = 0x2004;
+    const PPUSCROLL: u16 = 0x2005;
+    const PPUADDR: u16 = 0x2006;
+    const PPUDATA: u16 = 0x2007;
+
+    // Both layers, including the leftmost 8 pixels
+    const SHOW_ALL: u8 = 0x1E;
+
+    // Tile 1 is all pixel value 1, tile 2 all value 3 and tile 3 only has its top left pixel set.
+    // Every palette RAM entry holds its own address, so the framebuffer shows which entry a pixel used.
+    fn setup() -> (Ppu, Cartridge) {
+        setup_with_mirroring(Mirroring::Horizontal)
+    }
+
+    fn setup_with_mirroring(mirroring: Mirroring) -> (Ppu, Cartridge) {
+        let mut chr = vec![0; 0x2000];
+        for row in 0..8 {
+            chr[0x10 + row] = 0xFF;
+            chr[0x20 + row] = 0xFF;
+            chr[0x28 + row] = 0xFF;
+        }
+        chr[0x30] = 0x80;
+        let prg = vec![0; 0x4000];
+
+        let info = RomInfo { mirroring, ..RomInfo::default() };
+        let mut cartridge = cartridge::create_cartridge_from_ines(&info, &prg, &chr).unwrap();
+        let mut ppu = Ppu::new(mirroring);
+
+        // The sprite palettes first, so the mirrored entries end up with the background addresses
+        for address in [0x3F10, 0x3F00].iter() {
+            ppu.write_register(&mut cartridge, PPUADDR, (address >> 8) as u8);
+            ppu.write_register(&mut cartridge, PPUADDR, *address as u8);
+            for i in 0..0x10 {
+                ppu.write_register(&mut cartridge, PPUDATA, *address as u8 + i);
+            }
+        }
+        set_scroll(&mut ppu, &mut cartridge, 0, 0, 0);
+
+        (ppu, cartridge)
+    }
+
+    fn fill_nametable(ppu: &mut Ppu, cartridge: &mut Cartridge, tile: u8) {
+        fill_nametable_at(ppu, cartridge, 0x20, tile);
+    }
+
+    fn fill_nametable_at(ppu: &mut Ppu, cartridge: &mut Cartridge, address_hi: u8, tile: u8) {
+        ppu.write_register(cartridge, PPUADDR, address_hi);
+        ppu.write_register(cartridge, PPUADDR, 0x00);
+        for _i in 0..0x3C0 {
+            ppu.write_register(cartridge, PPUDATA, tile);
+        }
+    }
+
+    fn write_vram(ppu: &mut Ppu, cartridge: &mut Cartridge, address: u16, data: &[u8]) {
+        ppu.write_register(cartridge, PPUADDR, (address >> 8) as u8);
+        ppu.write_register(cartridge, PPUADDR, address as u8);
+        for byte in data.iter() {
+            ppu.write_register(cartridge, PPUDATA, *byte);
+        }
+    }
+
+    fn read_vram(ppu: &mut Ppu, cartridge: &mut Cartridge, address: u16) -> u8 {
+        ppu.write_register(cartridge, PPUADDR, (address >> 8) as u8);
+        ppu.write_register(cartridge, PPUADDR, address as u8);
+        ppu.read_register(cartridge, PPUDATA)
+    }
+
+    fn write_sprite(ppu: &mut Ppu, cartridge: &mut Cartridge, index: u8, sprite: [u8; 4]) {
+        ppu.write_register(cartridge, OAMADDR, index * 4);
+        for byte in sprite.iter() {
+            ppu.write_register(cartridge, OAMDATA, *byte);
+        }
+    }
+
+    // Hides every sprite below the screen
+    fn clear_oam(ppu: &mut Ppu, cartridge: &mut Cartridge) {
+        ppu.write_register(cartridge, OAMADDR, 0);
+        for _i in 0..256 {
+            ppu.write_register(cartridge, OAMDATA, 0xFF);
+        }
+    }
+
+    fn set_scroll(ppu: &mut Ppu, cartridge: &mut Cartridge, nametable: u8, x: u8, y: u8) {
+        ppu.read_register(cartridge, PPUSTATUS);
+        ppu.write_register(cartridge, PPUCTRL, nametable);
+        ppu.write_register(cartridge, PPUSCROLL, x);
+        ppu.write_register(cartridge, PPUSCROLL, y);
+    }
+
+    fn run_until(ppu: &mut Ppu, cartridge: &Cartridge, scanline: u16, cycle: u16) {
+        while ppu.get_scanline() != scanline || ppu.get_scanline_cycle() != cycle {
+            ppu.tick(cartridge);
+        }
+    }
+
+    fn pixel(ppu: &Ppu, x: usize, y: usize) -> u16 {
+        ppu.get_framebuffer()[y * super::FRAMEBUFFER_WIDTH + x]
+    }
+
+    #[test]
+    fn test_sprite_rendering() {
+        let (mut ppu, mut cartridge) = setup();
+        clear_oam(&mut ppu, &mut cartridge);
+        // Palette 2, in front of the empty background
+        write_sprite(&mut ppu, &mut cartridge, 5, [40, 2, 0x02, 100]);
+        // Flipped both ways, a single pixel at the bottom right
+        write_sprite(&mut ppu, &mut cartridge, 6, [60, 3, 0xC0, 20]);
+        ppu.write_register(&mut cartridge, PPUMASK, SHOW_ALL);
+
+        run_until(&mut ppu, &cartridge, 240, 0);
+
+        // Sprites are drawn one line below their Y
+        assert_eq!(0, pixel(&ppu, 100, 40));
+        assert_eq!(0x10 | 2 << 2 | 3, pixel(&ppu, 100, 41));
+        assert_eq!(0x10 | 2 << 2 | 3, pixel(&ppu, 107, 48));
+        assert_eq!(0, pixel(&ppu, 108, 48));
+        assert_eq!(0, pixel(&ppu, 100, 49));
+
+        assert_eq!(0x11, pixel(&ppu, 27, 68));
+        assert_eq!(0, pixel(&ppu, 20, 61));
+    }
+
+    #[test]
+    fn test_sprite_8x16() {
+        let (mut ppu, mut cartridge) = setup();
+        clear_oam(&mut ppu, &mut cartridge);
+        // Tile 2 selects the first pattern table, the bottom half is tile 3
+        write_sprite(&mut ppu, &mut cartridge, 0, [10, 2, 0x00, 30]);
+        ppu.write_register(&mut cartridge, PPUCTRL, 0x20);
+        ppu.write_register(&mut cartridge, PPUMASK, SHOW_ALL);
+
+        run_until(&mut ppu, &cartridge, 240, 0);
+
+        assert_eq!(0x13, pixel(&ppu, 30, 11));
+        assert_eq!(0x13, pixel(&ppu, 37, 18));
+        assert_eq!(0x11, pixel(&ppu, 30, 19));
+        assert_eq!(0, pixel(&ppu, 31, 19));
+        assert_eq!(0, pixel(&ppu, 30, 27));
+    }
+
+    #[test]
+    fn test_sprite_priority() {
+        let (mut ppu, mut cartridge) = setup();
+        fill_nametable(&mut ppu, &mut cartridge, 1);
+        clear_oam(&mut ppu, &mut cartridge);
+        // Behind the background, then a lower priority sprite in front of it on the same pixels
+        write_sprite(&mut ppu, &mut cartridge, 1, [50, 2, 0x20, 60]);
+        write_sprite(&mut ppu, &mut cartridge, 2, [50, 2, 0x01, 60]);
+        ppu.write_register(&mut cartridge, PPUMASK, SHOW_ALL);
+
+        run_until(&mut ppu, &cartridge, 240, 0);
+
+        // The first opaque sprite decides, even when it is behind the background
+        assert_eq!(1, pixel(&ppu, 64, 55));
+    }
+
+    #[test]
+    fn test_sprite_0_hit() {
+        let (mut ppu, mut cartridge) = setup();
+        fill_nametable(&mut ppu, &mut cartridge, 1);
+        clear_oam(&mut ppu, &mut cartridge);
+        write_sprite(&mut ppu, &mut cartridge, 0, [30, 3, 0x00, 50]);
+        ppu.write_register(&mut cartridge, PPUMASK, SHOW_ALL);
+
+        run_until(&mut ppu, &cartridge, 31, 50);
+        assert_eq!(0, ppu.read_register(&cartridge, PPUSTATUS) & 0x40);
+        ppu.tick(&cartridge);
+        ppu.tick(&cartridge);
+        assert_eq!(0x40, ppu.read_register(&cartridge, PPUSTATUS) & 0x40);
+        assert!(ppu.is_sprite_0_hit());
+
+        // Stays set until the pre-render scanline
+        run_until(&mut ppu, &cartridge, 261, 1);
+        assert_eq!(0x40, ppu.read_register(&cartridge, PPUSTATUS) & 0x40);
+        ppu.tick(&cartridge);
+        assert_eq!(0, ppu.read_register(&cartridge, PPUSTATUS) & 0x40);
+    }
+
+    #[test]
+    fn test_no_sprite_0_hit() {
+        let (mut ppu, mut cartridge) = setup();
+        fill_nametable(&mut ppu, &mut cartridge, 1);
+        clear_oam(&mut ppu, &mut cartridge);
+        // At x 255 there is no hit
+        write_sprite(&mut ppu, &mut cartridge, 0, [30, 1, 0x00, 255]);
+        ppu.write_register(&mut cartridge, PPUMASK, SHOW_ALL);
+        run_until(&mut ppu, &cartridge, 240, 0);
+        assert_eq!(0, ppu.read_register(&cartridge, PPUSTATUS) & 0x40);
+
+        // Nor with the background hidden
+        write_sprite(&mut ppu, &mut cartridge, 0, [30, 1, 0x00, 50]);
+        ppu.write_register(&mut cartridge, PPUMASK, 0x14);
+        run_until(&mut ppu, &cartridge, 240, 0);
+        assert_eq!(0, ppu.read_register(&cartridge, PPUSTATUS) & 0x40);
+
+        // Nor in the leftmost pixels when they are clipped
+        write_sprite(&mut ppu, &mut cartridge, 0, [30, 1, 0x00, 0]);
+        ppu.write_register(&mut cartridge, PPUMASK, 0x18);
+        run_until(&mut ppu, &cartridge, 240, 0);
+        assert_eq!(0, ppu.read_register(&cartridge, PPUSTATUS) & 0x40);
+    }
+
+    #[test]
+    fn test_sprite_overflow() {
+        let (mut ppu, mut cartridge) = setup();
+        clear_oam(&mut ppu, &mut cartridge);
+        for i in 0..8 {
+            write_sprite(&mut ppu, &mut cartridge, i, [100, 1, 0, i * 10]);
+        }
+        ppu.write_register(&mut cartridge, PPUMASK, SHOW_ALL);
+
+        run_until(&mut ppu, &cartridge, 240, 0);
+        assert_eq!(0, ppu.read_register(&cartridge, PPUSTATUS) & 0x20);
+
+        // A ninth sprite on the line, wait for the pre-render scanline to see it set during the next frame
+        ppu.write_register(&mut cartridge, PPUMASK, 0);
+        write_sprite(&mut ppu, &mut cartridge, 8, [100, 1, 0, 90]);
+        ppu.write_register(&mut cartridge, PPUMASK, SHOW_ALL);
+        run_until(&mut ppu, &cartridge, 0, 0);
+        run_until(&mut ppu, &cartridge, 100, 65 + 8 * 8);
+        assert_eq!(0, ppu.read_register(&cartridge, PPUSTATUS) & 0x20);
+        ppu.tick(&cartridge);
+        assert_eq!(0x20, ppu.read_register(&cartridge, PPUSTATUS) & 0x20);
+        assert!(ppu.is_sprite_overflow());
+
+        run_until(&mut ppu, &cartridge, 0, 0);
+        assert_eq!(0, ppu.read_register(&cartridge, PPUSTATUS) & 0x20);
+    }
+
+    #[test]
+    fn test_oamdata() {
+        let (mut ppu, mut cartridge) = setup();
+        write_sprite(&mut ppu, &mut cartridge, 0, [1, 2, 0xFF, 4]);
+
+        ppu.write_register(&mut cartridge, OAMADDR, 0);
+        assert_eq!(1, ppu.read_register(&cartridge, OAMDATA));
+        // Reading doesn't increment the address
+        assert_eq!(1, ppu.read_register(&cartridge, OAMDATA));
+
+        // The unimplemented attribute bits read back as 0
+        ppu.write_register(&mut cartridge, OAMADDR, 2);
+        assert_eq!(0xE3, ppu.read_register(&cartridge, OAMDATA));
+    }
+
+    #[test]
+    fn test_scroll_registers() {
+        let (mut ppu, mut cartridge) = setup();
+
+        ppu.write_register(&mut cartridge, PPUCTRL, 0x03);
+        assert_eq!(0x0C00, ppu.get_t());
+        ppu.read_register(&cartridge, PPUSTATUS);
+        assert!(!ppu.get_write_toggle());
+
+        ppu.write_register(&mut cartridge, PPUSCROLL, 0x7D);
+        assert_eq!(0x0C0F, ppu.get_t());
+        assert_eq!(5, ppu.get_fine_x());
+        assert!(ppu.get_write_toggle());
+
+        ppu.write_register(&mut cartridge, PPUSCROLL, 0x5E);
+        assert_eq!(0x6D6F, ppu.get_t());
+        assert!(!ppu.get_write_toggle());
+
+        // PPUADDR shares the toggle, and only the second write reaches v
+        let v = ppu.get_v();
+        ppu.write_register(&mut cartridge, PPUADDR, 0x3D);
+        assert_eq!(0x3D6F, ppu.get_t());
+        assert_eq!(v, ppu.get_v());
+        ppu.write_register(&mut cartridge, PPUADDR, 0xF0);
+        assert_eq!(0x3DF0, ppu.get_t());
+        assert_eq!(0x3DF0, ppu.get_v());
+
+        // PPUDATA increments v
+        ppu.write_register(&mut cartridge, PPUDATA, 0);
+        assert_eq!(0x3DF1, ppu.get_v());
+        ppu.write_register(&mut cartridge, PPUCTRL, 0x04);
+        ppu.write_register(&mut cartridge, PPUDATA, 0);
+        assert_eq!(0x3E11, ppu.get_v());
+    }
+
+    #[test]
+    fn test_horizontal_scroll() {
+        let (mut ppu, mut cartridge) = setup_with_mirroring(Mirroring::Vertical);
+        fill_nametable_at(&mut ppu, &mut cartridge, 0x20, 0);
+        fill_nametable_at(&mut ppu, &mut cartridge, 0x24, 1);
+        clear_oam(&mut ppu, &mut cartridge);
+
+        // Halfway into the first nametable and 4 pixels more
+        set_scroll(&mut ppu, &mut cartridge, 0, 132, 0);
+        ppu.write_register(&mut cartridge, PPUMASK, SHOW_ALL);
+        run_until(&mut ppu, &cartridge, 0, 0);
+        run_until(&mut ppu, &cartridge, 240, 0);
+
+        for y in [0, 100, 239].iter() {
+            assert_eq!(0, pixel(&ppu, 123, *y));
+            assert_eq!(1, pixel(&ppu, 124, *y));
+            assert_eq!(1, pixel(&ppu, 255, *y));
+        }
+
+        // The nametable select wraps around to the first one
+        set_scroll(&mut ppu, &mut cartridge, 1, 132, 0);
+        run_until(&mut ppu, &cartridge, 0, 0);
+        run_until(&mut ppu, &cartridge, 240, 0);
+        assert_eq!(1, pixel(&ppu, 123, 10));
+        assert_eq!(0, pixel(&ppu, 124, 10));
+    }
+
+    #[test]
+    fn test_vertical_scroll() {
+        let (mut ppu, mut cartridge) = setup();
+        fill_nametable_at(&mut ppu, &mut cartridge, 0x20, 0);
+        fill_nametable_at(&mut ppu, &mut cartridge, 0x28, 1);
+        clear_oam(&mut ppu, &mut cartridge);
+
+        // Row 29 is followed by the next nametable down
+        set_scroll(&mut ppu, &mut cartridge, 0, 0, 203);
+        ppu.write_register(&mut cartridge, PPUMASK, SHOW_ALL);
+        run_until(&mut ppu, &cartridge, 0, 0);
+        run_until(&mut ppu, &cartridge, 240, 0);
+
+        assert_eq!(0, pixel(&ppu, 10, 36));
+        assert_eq!(1, pixel(&ppu, 10, 37));
+        assert_eq!(1, pixel(&ppu, 10, 239));
+    }
+
+    #[test]
+    fn test_split_screen() {
+        let (mut ppu, mut cartridge) = setup_with_mirroring(Mirroring::Vertical);
+        fill_nametable_at(&mut ppu, &mut cartridge, 0x20, 0);
+        fill_nametable_at(&mut ppu, &mut cartridge, 0x24, 1);
+        clear_oam(&mut ppu, &mut cartridge);
+
+        set_scroll(&mut ppu, &mut cartridge, 0, 0, 0);
+        ppu.write_register(&mut cartridge, PPUMASK, SHOW_ALL);
+        run_until(&mut ppu, &cartridge, 0, 0);
+
+        // Changing the horizontal scroll during a scanline takes effect from the next one
+        run_until(&mut ppu, &cartridge, 100, 100);
+        set_scroll(&mut ppu, &mut cartridge, 1, 0, 0);
+        run_until(&mut ppu, &cartridge, 240, 0);
+
+        assert_eq!(0, pixel(&ppu, 200, 100));
+        assert_eq!(1, pixel(&ppu, 200, 101));
+        assert_eq!(1, pixel(&ppu, 0, 239));
+    }
+
+    #[test]
+    fn test_palette_mirrors() {
+        let (mut ppu, mut cartridge) = setup();
+
+        // Entry 0 of the sprite palettes is shared with the background palettes, in both directions
+        write_vram(&mut ppu, &mut cartridge, 0x3F10, &[0x2A]);
+        assert_eq!(0x2A, read_vram(&mut ppu, &mut cartridge, 0x3F00) & 0x3F);
+        write_vram(&mut ppu, &mut cartridge, 0x3F0C, &[0x15]);
+        assert_eq!(0x15, read_vram(&mut ppu, &mut cartridge, 0x3F1C) & 0x3F);
+
+        // The others are not
+        write_vram(&mut ppu, &mut cartridge, 0x3F11, &[0x30]);
+        assert_eq!(0x01, read_vram(&mut ppu, &mut cartridge, 0x3F01) & 0x3F);
+
+        // Palette RAM repeats up to $3FFF
+        assert_eq!(0x30, read_vram(&mut ppu, &mut cartridge, 0x3FF1) & 0x3F);
+
+        // Only six bits are stored, the others read back from the open bus
+        write_vram(&mut ppu, &mut cartridge, 0x3F02, &[0xFF]);
+        ppu.write_register(&mut cartridge, PPUADDR, 0x3F);
+        ppu.write_register(&mut cartridge, PPUADDR, 0x02);
+        assert_eq!(0x02 | 0x3F, ppu.read_register(&cartridge, PPUDATA));
+        ppu.write_register(&mut cartridge, PPUADDR, 0x3F);
+        ppu.write_register(&mut cartridge, PPUADDR, 0x02);
+        ppu.write_register(&mut cartridge, PPUADDR, 0x3F);
+        ppu.write_register(&mut cartridge, PPUADDR, 0xC2);
+        assert_eq!(0xC0 | 0x3F, ppu.read_register(&cartridge, PPUDATA));
+    }
+
+    #[test]
+    fn test_attributes() {
+        let (mut ppu, mut cartridge) = setup();
+        fill_nametable(&mut ppu, &mut cartridge, 1);
+        // The top left 4x4 tiles get palettes 0 to 3 in the quadrants, the 4x4 tiles right of them palette 1
+        write_vram(&mut ppu, &mut cartridge, 0x23C0, &[0b11_10_01_00, 0b01_01_01_01]);
+        clear_oam(&mut ppu, &mut cartridge);
+        set_scroll(&mut ppu, &mut cartridge, 0, 0, 0);
+        ppu.write_register(&mut cartridge, PPUMASK, SHOW_ALL);
+
+        run_until(&mut ppu, &cartridge, 0, 0);
+        run_until(&mut ppu, &cartridge, 240, 0);
+
+        assert_eq!(0x01, pixel(&ppu, 15, 15));
+        assert_eq!(0x05, pixel(&ppu, 16, 15));
+        assert_eq!(0x09, pixel(&ppu, 15, 16));
+        assert_eq!(0x0D, pixel(&ppu, 31, 31));
+        assert_eq!(0x05, pixel(&ppu, 32, 0));
+        assert_eq!(0x05, pixel(&ppu, 63, 31));
+        assert_eq!(0x01, pixel(&ppu, 64, 0));
+        assert_eq!(0x01, pixel(&ppu, 0, 32));
+    }
+
+    #[test]
+    fn test_backdrop_color() {
+        let (mut ppu, mut cartridge) = setup();
+        clear_oam(&mut ppu, &mut cartridge);
+        // Transparent pixels use $3F00, whatever their palette
+        write_vram(&mut ppu, &mut cartridge, 0x23C0, &[0xFF; 0x40]);
+        write_vram(&mut ppu, &mut cartridge, 0x3F00, &[0x21]);
+        set_scroll(&mut ppu, &mut cartridge, 0, 0, 0);
+        ppu.write_register(&mut cartridge, PPUMASK, SHOW_ALL);
+
+        run_until(&mut ppu, &cartridge, 0, 0);
+        run_until(&mut ppu, &cartridge, 240, 0);
+
+        assert_eq!(0x21, pixel(&ppu, 0, 0));
+        assert_eq!(0x21, pixel(&ppu, 200, 200));
+    }
+
+    #[test]
+    fn test_left_clipping() {
+        let (mut ppu, mut cartridge) = setup();
+        fill_nametable(&mut ppu, &mut cartridge, 1);
+        clear_oam(&mut ppu, &mut cartridge);
+        write_sprite(&mut ppu, &mut cartridge, 1, [50, 2, 0x00, 4]);
+        set_scroll(&mut ppu, &mut cartridge, 0, 0, 0);
+        ppu.write_register(&mut cartridge, PPUMASK, 0x18);
+
+        run_until(&mut ppu, &cartridge, 0, 0);
+        run_until(&mut ppu, &cartridge, 240, 0);
+
+        // Both layers are hidden in the leftmost 8 pixels
+        assert_eq!(0, pixel(&ppu, 7, 10));
+        assert_eq!(1, pixel(&ppu, 8, 10));
+        assert_eq!(0, pixel(&ppu, 7, 51));
+        assert_eq!(0x13, pixel(&ppu, 8, 51));
+
+        // Only the background
+        ppu.write_register(&mut cartridge, PPUMASK, 0x1C);
+        run_until(&mut ppu, &cartridge, 0, 0);
+        run_until(&mut ppu, &cartridge, 240, 0);
+        assert_eq!(0, pixel(&ppu, 7, 10));
+        assert_eq!(0x13, pixel(&ppu, 7, 51));
+    }
+
+    #[test]
+    fn test_layer_enable() {
+        let (mut ppu, mut cartridge) = setup();
+        fill_nametable(&mut ppu, &mut cartridge, 1);
+        clear_oam(&mut ppu, &mut cartridge);
+        write_sprite(&mut ppu, &mut cartridge, 1, [50, 2, 0x00, 100]);
+        set_scroll(&mut ppu, &mut cartridge, 0, 0, 0);
+
+        // Sprites only
+        ppu.write_register(&mut cartridge, PPUMASK, 0x14);
+        run_until(&mut ppu, &cartridge, 0, 0);
+        run_until(&mut ppu, &cartridge, 240, 0);
+        assert_eq!(0, pixel(&ppu, 10, 10));
+        assert_eq!(0x13, pixel(&ppu, 100, 51));
+
+        // Background only
+        ppu.write_register(&mut cartridge, PPUMASK, 0x0A);
+        run_until(&mut ppu, &cartridge, 0, 0);
+        run_until(&mut ppu, &cartridge, 240, 0);
+        assert_eq!(1, pixel(&ppu, 10, 10));
+        assert_eq!(1, pixel(&ppu, 100, 51));
+    }
+
+    #[test]
+    fn test_rendering_disabled() {
+        let (mut ppu, mut cartridge) = setup();
+        fill_nametable(&mut ppu, &mut cartridge, 1);
+        set_scroll(&mut ppu, &mut cartridge, 0, 0, 0);
+        write_vram(&mut ppu, &mut cartridge, 0x3F00, &[0x21]);
+        ppu.write_register(&mut cartridge, PPUADDR, 0x20);
+        ppu.write_register(&mut cartridge, PPUADDR, 0x00);
+
+        // v isn't touched, and the backdrop color is shown
+        run_until(&mut ppu, &cartridge, 0, 0);
+        run_until(&mut ppu, &cartridge, 240, 0);
+        assert_eq!(0x2000, ppu.get_v());
+        assert_eq!(0x21, pixel(&ppu, 10, 10));
+
+        // Unless v points into palette RAM
+        ppu.write_register(&mut cartridge, PPUADDR, 0x3F);
+        ppu.write_register(&mut cartridge, PPUADDR, 0x05);
+        run_until(&mut ppu, &cartridge, 0, 0);
+        run_until(&mut ppu, &cartridge, 240, 0);
+        assert_eq!(0x05, pixel(&ppu, 10, 10));
+    }
+
+    #[test]
+    fn test_greyscale_and_emphasis() {
+        let (mut ppu, mut cartridge) = setup();
+        fill_nametable(&mut ppu, &mut cartridge, 1);
+        clear_oam(&mut ppu, &mut cartridge);
+        write_vram(&mut ppu, &mut cartridge, 0x3F01, &[0x2A]);
+        set_scroll(&mut ppu, &mut cartridge, 0, 0, 0);
+
+        // Greyscale and blue
+        ppu.write_register(&mut cartridge, PPUMASK, SHOW_ALL | 0x81);
+        run_until(&mut ppu, &cartridge, 0, 0);
+        run_until(&mut ppu, &cartridge, 240, 0);
+        assert_eq!(0x20 | 0b100 << super::PIXEL_EMPHASIS_SHIFT, pixel(&ppu, 10, 10));
+
+        // Red and green
+        ppu.write_register(&mut cartridge, PPUMASK, SHOW_ALL | 0x60);
+        run_until(&mut ppu, &cartridge, 0, 0);
+        run_until(&mut ppu, &cartridge, 240, 0);
+        assert_eq!(0x2A | 0b011 << super::PIXEL_EMPHASIS_SHIFT, pixel(&ppu, 10, 10));
+    }
+
+    #[test]
+    fn test_chr_ram() {
+        let (mut ppu, _rom_cartridge) = setup();
+        let prg = vec![0; 0x4000];
+        let mut cartridge = cartridge::create_cartridge_from_ines(&RomInfo::default(), &prg, &[]).unwrap();
+
+        // Tile 1 drawn through PPUDATA, pixel value 2 everywhere
+        let mut tile = [0; 16];
+        tile[8..].copy_from_slice(&[0xFF; 8]);
+        write_vram(&mut ppu, &mut cartridge, 0x0010, &tile);
+        assert_eq!(0xFF, cartridge.read_chr(0x0018));
+
+        fill_nametable(&mut ppu, &mut cartridge, 1);
+        clear_oam(&mut ppu, &mut cartridge);
+        set_scroll(&mut ppu, &mut cartridge, 0, 0, 0);
+        ppu.write_register(&mut cartridge, PPUMASK, SHOW_ALL);
+        run_until(&mut ppu, &cartridge, 0, 0);
+        run_until(&mut ppu, &cartridge, 240, 0);
+
+        assert_eq!(2, pixel(&ppu, 100, 100));
+    }
+}
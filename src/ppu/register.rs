@@ -30,9 +30,9 @@ const PPUCTRL_GENERATE_NMI_MASK: u8 = 1 << 7;
 pub trait PpuCtrlTrait {
     fn base_nametable_addr(&self) -> u16;
     fn vram_address_increment(&self) -> u16;
-    fn sprite_pattern_table_addr(&self) -> u8;
+    fn sprite_pattern_table_addr(&self) -> u16;
     fn bg_pattern_table_addr(&self) -> u16;
-    fn sprite_size(&self) -> u8;
+    fn sprite_height(&self) -> u16;
     fn ppu_master_slave_select(&self) -> u8;
     fn generate_nmi(&self) -> bool;
 }
@@ -40,7 +40,7 @@ pub type PpuCtrl = u8;
 
 impl PpuCtrlTrait for PpuCtrl {
     fn base_nametable_addr(&self) -> u16 {
-        unimplemented!()
+        0x2000 + (*self & PPUCTRL_BASE_NAMETABLE_MASK) as u16 * 0x400
     }
 
     fn vram_address_increment(&self) -> u16 {
@@ -51,8 +51,12 @@ impl PpuCtrlTrait for PpuCtrl {
         }
     }
 
-    fn sprite_pattern_table_addr(&self) -> u8 {
-        unimplemented!()
+    fn sprite_pattern_table_addr(&self) -> u16 {
+        if (*self & PPUCTRL_SPRITE_PATTERN_TABLE_ADDR_MASK) > 0 {
+            0x1000
+        } else {
+            0x0
+        }
     }
 
     fn bg_pattern_table_addr(&self) -> u16 {
@@ -63,8 +67,12 @@ impl PpuCtrlTrait for PpuCtrl {
         }
     }
 
-    fn sprite_size(&self) -> u8 {
-        unimplemented!()
+    fn sprite_height(&self) -> u16 {
+        if (*self & PPUCTRL_SPRITE_SIZE_MASK) > 0 {
+            16
+        } else {
+            8
+        }
     }
 
     fn ppu_master_slave_select(&self) -> u8 {
@@ -90,14 +98,57 @@ pub const PPUMASK_OFFSET: u16 = 0x1;
 // ||+------- Emphasize red
 // |+-------- Emphasize green
 // +--------- Emphasize blue
-const PPUMASK_GREYSCALE_MASK: usize = 1 << 0;
-const PPUMASK_SHOW_BG_LEFTMOST_MASK: usize = 1 << 1;
-const PPUMASK_SHOW_SPRITES_LEFTMOST_MASK: usize = 1 << 2;
-const PPUMASK_SHOW_BG_MASK: usize = 1 << 3;
-const PPUMASK_SHOW_SPRITES_MASK: usize = 1 << 4;
-const PPUMASK_EMPHASIZE_RED_MASK: usize = 1 << 5;
-const PPUMASK_EMPHASIZE_GREEN_MASK: usize = 1 << 6;
-const PPUMASK_EMPHASIZE_BLUE_MASK: usize = 1 << 7;
+const PPUMASK_GREYSCALE_MASK: u8 = 1 << 0;
+const PPUMASK_SHOW_BG_LEFTMOST_MASK: u8 = 1 << 1;
+const PPUMASK_SHOW_SPRITES_LEFTMOST_MASK: u8 = 1 << 2;
+const PPUMASK_SHOW_BG_MASK: u8 = 1 << 3;
+const PPUMASK_SHOW_SPRITES_MASK: u8 = 1 << 4;
+const PPUMASK_EMPHASIZE_RED_MASK: u8 = 1 << 5;
+const PPUMASK_EMPHASIZE_GREEN_MASK: u8 = 1 << 6;
+const PPUMASK_EMPHASIZE_BLUE_MASK: u8 = 1 << 7;
+
+pub trait PpuMaskTrait {
+    fn show_bg(&self) -> bool;
+    fn show_sprites(&self) -> bool;
+    fn show_bg_leftmost(&self) -> bool;
+    fn show_sprites_leftmost(&self) -> bool;
+    fn is_rendering_enabled(&self) -> bool;
+    fn is_greyscale(&self) -> bool;
+    fn emphasis(&self) -> u8;
+}
+pub type PpuMask = u8;
+
+impl PpuMaskTrait for PpuMask {
+    fn show_bg(&self) -> bool {
+        (*self & PPUMASK_SHOW_BG_MASK) > 0
+    }
+
+    fn show_sprites(&self) -> bool {
+        (*self & PPUMASK_SHOW_SPRITES_MASK) > 0
+    }
+
+    fn show_bg_leftmost(&self) -> bool {
+        (*self & PPUMASK_SHOW_BG_LEFTMOST_MASK) > 0
+    }
+
+    fn show_sprites_leftmost(&self) -> bool {
+        (*self & PPUMASK_SHOW_SPRITES_LEFTMOST_MASK) > 0
+    }
+
+    // The ppu only fetches and evaluates sprites while either layer is shown
+    fn is_rendering_enabled(&self) -> bool {
+        self.show_bg() || self.show_sprites()
+    }
+
+    fn is_greyscale(&self) -> bool {
+        (*self & PPUMASK_GREYSCALE_MASK) > 0
+    }
+
+    // The three emphasis bits, red in bit 0
+    fn emphasis(&self) -> u8 {
+        (*self & (PPUMASK_EMPHASIZE_RED_MASK | PPUMASK_EMPHASIZE_GREEN_MASK | PPUMASK_EMPHASIZE_BLUE_MASK)) >> 5
+    }
+}
 
 
 // PPUSTATUS (read)
@@ -126,11 +177,18 @@ pub const PPUSTATUS_OFFSET: u16 = 0x2;
 const PPUSTATUS_SPRITE_OVERFLOW_MASK: u8 = 1 << 5;
 const PPUSTATUS_SPRITE_0_MASK: u8 = 1 << 6;
 const PPUSTATUS_VBLANK_MASK: u8 = 1 << 7;
+const PPUSTATUS_FLAGS_MASK: u8 = PPUSTATUS_SPRITE_OVERFLOW_MASK | PPUSTATUS_SPRITE_0_MASK | PPUSTATUS_VBLANK_MASK;
 
 pub trait PpuStatusTrait {
     fn set_vblank(&mut self);
     fn clear_vblank(&mut self);
     fn is_vblank(&self) -> bool;
+    fn set_sprite_0_hit(&mut self);
+    fn is_sprite_0_hit(&self) -> bool;
+    fn set_sprite_overflow(&mut self);
+    fn is_sprite_overflow(&self) -> bool;
+    fn clear_sprite_flags(&mut self);
+    fn with_open_bus(&self, open_bus: u8) -> u8;
 }
 pub type PpuStatus = u8;
 
@@ -146,6 +204,31 @@ impl PpuStatusTrait for PpuStatus {
     fn is_vblank(&self) -> bool {
         (*self & PPUSTATUS_VBLANK_MASK) > 0
     }
+
+    fn set_sprite_0_hit(&mut self) {
+        *self |= PPUSTATUS_SPRITE_0_MASK;
+    }
+
+    fn is_sprite_0_hit(&self) -> bool {
+        (*self & PPUSTATUS_SPRITE_0_MASK) > 0
+    }
+
+    fn set_sprite_overflow(&mut self) {
+        *self |= PPUSTATUS_SPRITE_OVERFLOW_MASK;
+    }
+
+    fn is_sprite_overflow(&self) -> bool {
+        (*self & PPUSTATUS_SPRITE_OVERFLOW_MASK) > 0
+    }
+
+    fn clear_sprite_flags(&mut self) {
+        *self &= !(PPUSTATUS_SPRITE_0_MASK | PPUSTATUS_SPRITE_OVERFLOW_MASK);
+    }
+
+    // The low five bits aren't driven, they keep what was last written to a register
+    fn with_open_bus(&self, open_bus: u8) -> u8 {
+        (*self & PPUSTATUS_FLAGS_MASK) | (open_bus & !PPUSTATUS_FLAGS_MASK)
+    }
 }
 
 // OAMADDR (write)
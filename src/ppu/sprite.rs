@@ -0,0 +1,209 @@
+use crate::nes::savestate::{StateWriter, StateReader};
+
+pub const OAM_SIZE: usize = 0x100;
+pub const SECONDARY_OAM_SIZE: usize = 0x20;
+pub const SPRITES_PER_SCANLINE: usize = 8;
+const BYTES_PER_SPRITE: usize = 4;
+const OAM_SPRITE_COUNT: usize = OAM_SIZE / BYTES_PER_SPRITE;
+
+// Byte 2 of a sprite in OAM
+// 76543210
+// ||||||||
+// ||||||++- Palette (4 to 7) of sprite
+// |||+++--- Unimplemented, read back as 0
+// ||+------ Priority (0: in front of background; 1: behind background)
+// |+------- Flip sprite horizontally
+// +-------- Flip sprite vertically
+pub const ATTRIBUTE_PALETTE_MASK: u8 = 0b11;
+pub const ATTRIBUTE_UNIMPLEMENTED_MASK: u8 = 0b1_1100;
+pub const ATTRIBUTE_PRIORITY_MASK: u8 = 1 << 5;
+pub const ATTRIBUTE_FLIP_HORIZONTAL_MASK: u8 = 1 << 6;
+pub const ATTRIBUTE_FLIP_VERTICAL_MASK: u8 = 1 << 7;
+
+// The dot evaluation starts at, and what it costs per sprite. Copying an in range sprite takes four reads and writes.
+const EVALUATION_START_DOT: u16 = 65;
+const EVALUATION_DOTS_IN_RANGE: u16 = 8;
+const EVALUATION_DOTS_OUT_OF_RANGE: u16 = 2;
+
+// The result of evaluating OAM for the next scanline
+#[derive(Debug, PartialEq)]
+pub struct Evaluation {
+    pub count: usize,
+    // Sprite 0 is in secondary OAM, and so in slot 0
+    pub sprite_zero: bool,
+    // The dot the overflow flag gets set on, if a ninth sprite was found
+    pub overflow_dot: Option<u16>,
+}
+
+// True if a sprite with the given OAM Y is drawn on the scanline after this one.
+// Sprites are drawn one line below their Y, which is why this compares against the current scanline.
+pub fn is_in_range(y: u8, scanline: u16, height: u16) -> bool {
+    scanline >= y as u16 && scanline - (y as u16) < height
+}
+
+// Copies the first eight sprites on the next scanline to secondary OAM. After that the hardware keeps looking for
+// a ninth sprite to set the overflow flag, but increments the byte index along with the sprite index, so it
+// compares tile numbers, attributes and X positions as if they were Y. This gives both false positives and negatives.
+pub fn evaluate(oam: &[u8; OAM_SIZE], secondary_oam: &mut [u8; SECONDARY_OAM_SIZE], scanline: u16, height: u16) -> Evaluation {
+    *secondary_oam = [0xFF; SECONDARY_OAM_SIZE];
+
+    let mut evaluation = Evaluation { count: 0, sprite_zero: false, overflow_dot: None };
+    let mut dot = EVALUATION_START_DOT;
+    let mut n = 0;
+
+    while n < OAM_SPRITE_COUNT && evaluation.count < SPRITES_PER_SCANLINE {
+        let sprite = &oam[n * BYTES_PER_SPRITE..(n + 1) * BYTES_PER_SPRITE];
+
+        // The Y is copied either way, but only kept if the sprite is in range
+        let target = evaluation.count * BYTES_PER_SPRITE;
+        secondary_oam[target] = sprite[0];
+
+        if is_in_range(sprite[0], scanline, height) {
+            secondary_oam[target..target + BYTES_PER_SPRITE].copy_from_slice(sprite);
+            evaluation.count += 1;
+            evaluation.sprite_zero |= n == 0;
+            dot += EVALUATION_DOTS_IN_RANGE;
+        } else {
+            dot += EVALUATION_DOTS_OUT_OF_RANGE;
+        }
+        n += 1;
+    }
+
+    let mut m = 0;
+    while n < OAM_SPRITE_COUNT {
+        if is_in_range(oam[n * BYTES_PER_SPRITE + m], scanline, height) {
+            evaluation.overflow_dot = Some(dot);
+            break;
+        }
+
+        n += 1;
+        m = (m + 1) % BYTES_PER_SPRITE;
+        dot += EVALUATION_DOTS_OUT_OF_RANGE;
+    }
+
+    evaluation
+}
+
+// A sprite fetched for the scanline being drawn
+#[derive(Clone, Copy, Default)]
+pub struct SpriteSlot {
+    pub x: u8,
+    pub attributes: u8,
+    // Already flipped, bit 7 is the leftmost pixel
+    pub pattern_lo: u8,
+    pub pattern_hi: u8,
+}
+
+impl SpriteSlot {
+    // The 2 bit pixel value at screen x, 0 is transparent
+    pub fn pixel(&self, x: u16) -> u8 {
+        if x < self.x as u16 || x >= self.x as u16 + 8 {
+            return 0;
+        }
+
+        let shift = 7 - (x - self.x as u16);
+        let lo = (self.pattern_lo >> shift) & 1;
+        let hi = (self.pattern_hi >> shift) & 1;
+
+        (hi << 1) | lo
+    }
+
+    pub fn palette(&self) -> u8 {
+        self.attributes & ATTRIBUTE_PALETTE_MASK
+    }
+
+    pub fn is_behind_background(&self) -> bool {
+        self.attributes & ATTRIBUTE_PRIORITY_MASK > 0
+    }
+
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u8(self.x);
+        writer.write_u8(self.attributes);
+        writer.write_u8(self.pattern_lo);
+        writer.write_u8(self.pattern_hi);
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<(), String> {
+        self.x = reader.read_u8()?;
+        self.attributes = reader.read_u8()?;
+        self.pattern_lo = reader.read_u8()?;
+        self.pattern_hi = reader.read_u8()?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{evaluate, Evaluation, SpriteSlot, OAM_SIZE, SECONDARY_OAM_SIZE};
+
+    fn oam_with_ys(ys: &[u8]) -> [u8; OAM_SIZE] {
+        // Everything else is off screen
+        let mut oam = [0xFF; OAM_SIZE];
+        for (i, y) in ys.iter().enumerate() {
+            oam[i * 4] = *y;
+            oam[i * 4 + 1] = i as u8;
+            oam[i * 4 + 2] = 0;
+            oam[i * 4 + 3] = 0;
+        }
+        oam
+    }
+
+    #[test]
+    fn test_evaluation() {
+        let oam = oam_with_ys(&[0xF0, 10, 3, 20, 17]);
+        let mut secondary = [0; SECONDARY_OAM_SIZE];
+
+        // Sprites at Y 3 and 10 are drawn on scanline 11, found while on scanline 10
+        let evaluation = evaluate(&oam, &mut secondary, 10, 8);
+        assert_eq!(Evaluation { count: 2, sprite_zero: false, overflow_dot: None }, evaluation);
+        assert_eq!([10, 1, 0, 0, 3, 2, 0, 0], secondary[..8]);
+        assert!(secondary[8..].iter().all(|byte| *byte == 0xFF));
+
+        // 8x16 reaches down to 3 + 15
+        let evaluation = evaluate(&oam, &mut secondary, 18, 16);
+        assert_eq!(3, evaluation.count);
+        assert_eq!([10, 3, 17, 0xFF], [secondary[0], secondary[4], secondary[8], secondary[12]]);
+
+        let evaluation = evaluate(&oam, &mut secondary, 0xF0, 8);
+        assert!(evaluation.sprite_zero);
+    }
+
+    #[test]
+    fn test_overflow() {
+        let mut secondary = [0; SECONDARY_OAM_SIZE];
+
+        // Eight sprites don't overflow
+        let oam = oam_with_ys(&[50; 8]);
+        assert_eq!(None, evaluate(&oam, &mut secondary, 50, 8).overflow_dot);
+
+        // Nine do, after eight copies
+        let oam = oam_with_ys(&[50; 9]);
+        let evaluation = evaluate(&oam, &mut secondary, 50, 8);
+        assert_eq!(8, evaluation.count);
+        assert_eq!(Some(65 + 8 * 8), evaluation.overflow_dot);
+
+        // The ninth sprite is checked by its Y, the tenth by its tile number, which is out of range here
+        let mut oam = oam_with_ys(&[50; 8]);
+        oam[8 * 4] = 0xFF;
+        oam[9 * 4] = 50;
+        oam[9 * 4 + 1] = 0xFF;
+        assert_eq!(None, evaluate(&oam, &mut secondary, 50, 8).overflow_dot);
+
+        // A tile number that happens to be in range sets it without a ninth sprite on the line
+        oam[9 * 4] = 0xFF;
+        oam[9 * 4 + 1] = 45;
+        assert_eq!(Some(65 + 8 * 8 + 2), evaluate(&oam, &mut secondary, 50, 8).overflow_dot);
+    }
+
+    #[test]
+    fn test_slot_pixel() {
+        let slot = SpriteSlot { x: 100, attributes: 0, pattern_lo: 0b1000_0001, pattern_hi: 0b1100_0000 };
+        assert_eq!(0, slot.pixel(99));
+        assert_eq!(3, slot.pixel(100));
+        assert_eq!(2, slot.pixel(101));
+        assert_eq!(0, slot.pixel(102));
+        assert_eq!(1, slot.pixel(107));
+        assert_eq!(0, slot.pixel(108));
+    }
+}
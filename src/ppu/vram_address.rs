@@ -0,0 +1,182 @@
+// The internal v and t registers. v is the address the ppu fetches from while rendering and that PPUDATA uses,
+// t holds the scroll and address writes until they are copied to v.
+//
+// yyy NN YYYYY XXXXX
+// ||| || ||||| +++++-- coarse X scroll
+// ||| || +++++-------- coarse Y scroll
+// ||| ++-------------- nametable select
+// +++----------------- fine Y scroll
+const COARSE_X_MASK: u16 = 0x001F;
+const COARSE_Y_MASK: u16 = 0x03E0;
+const NAMETABLE_X_MASK: u16 = 0x0400;
+const NAMETABLE_Y_MASK: u16 = 0x0800;
+const NAMETABLE_MASK: u16 = NAMETABLE_X_MASK | NAMETABLE_Y_MASK;
+const FINE_Y_MASK: u16 = 0x7000;
+
+const HORIZONTAL_MASK: u16 = COARSE_X_MASK | NAMETABLE_X_MASK;
+const VERTICAL_MASK: u16 = COARSE_Y_MASK | NAMETABLE_Y_MASK | FINE_Y_MASK;
+
+pub const VRAM_ADDRESS_MASK: u16 = 0x7FFF;
+
+const COARSE_Y_SHIFT: u16 = 5;
+const NAMETABLE_SHIFT: u16 = 10;
+const FINE_Y_SHIFT: u16 = 12;
+
+// The last row of tiles, the two rows after it hold the attribute table
+const COARSE_Y_LAST_ROW: u16 = 29;
+
+const NAMETABLE_START: u16 = 0x2000;
+const ATTRIBUTE_TABLE_OFFSET: u16 = 0x03C0;
+
+pub trait VramAddressTrait {
+    fn coarse_x(&self) -> u16;
+    fn coarse_y(&self) -> u16;
+    fn fine_y(&self) -> u16;
+
+    fn set_nametable(&mut self, nametable: u16);
+    // PPUSCROLL writes
+    fn set_coarse_x(&mut self, data: u8);
+    fn set_y_scroll(&mut self, data: u8);
+    // PPUADDR writes
+    fn set_address_hi(&mut self, data: u8);
+    fn set_address_lo(&mut self, data: u8);
+
+    fn nametable_fetch_addr(&self) -> u16;
+    fn attribute_fetch_addr(&self) -> u16;
+
+    fn increment_x(&mut self);
+    fn increment_y(&mut self);
+    fn copy_horizontal(&mut self, t: u16);
+    fn copy_vertical(&mut self, t: u16);
+}
+pub type VramAddress = u16;
+
+impl VramAddressTrait for VramAddress {
+    fn coarse_x(&self) -> u16 {
+        *self & COARSE_X_MASK
+    }
+
+    fn coarse_y(&self) -> u16 {
+        (*self & COARSE_Y_MASK) >> COARSE_Y_SHIFT
+    }
+
+    fn fine_y(&self) -> u16 {
+        (*self & FINE_Y_MASK) >> FINE_Y_SHIFT
+    }
+
+    fn set_nametable(&mut self, nametable: u16) {
+        *self = (*self & !NAMETABLE_MASK) | ((nametable << NAMETABLE_SHIFT) & NAMETABLE_MASK);
+    }
+
+    fn set_coarse_x(&mut self, data: u8) {
+        *self = (*self & !COARSE_X_MASK) | (data >> 3) as u16;
+    }
+
+    fn set_y_scroll(&mut self, data: u8) {
+        let coarse_y = ((data >> 3) as u16) << COARSE_Y_SHIFT;
+        let fine_y = ((data & 0b111) as u16) << FINE_Y_SHIFT;
+        *self = (*self & !(COARSE_Y_MASK | FINE_Y_MASK)) | coarse_y | fine_y;
+    }
+
+    // Only six bits are written, the top bit of the fifteen is cleared
+    fn set_address_hi(&mut self, data: u8) {
+        *self = (*self & 0x00FF) | (((data & 0x3F) as u16) << 8);
+    }
+
+    fn set_address_lo(&mut self, data: u8) {
+        *self = (*self & 0xFF00) | data as u16;
+    }
+
+    fn nametable_fetch_addr(&self) -> u16 {
+        NAMETABLE_START | (*self & 0x0FFF)
+    }
+
+    // One attribute byte covers 4x4 tiles
+    fn attribute_fetch_addr(&self) -> u16 {
+        NAMETABLE_START | ATTRIBUTE_TABLE_OFFSET | (*self & NAMETABLE_MASK) |
+            ((self.coarse_y() >> 2) << 3) | (self.coarse_x() >> 2)
+    }
+
+    // Moves to the next tile, over to the next horizontal nametable after the last column
+    fn increment_x(&mut self) {
+        if self.coarse_x() == COARSE_X_MASK {
+            *self &= !COARSE_X_MASK;
+            *self ^= NAMETABLE_X_MASK;
+        } else {
+            *self += 1;
+        }
+    }
+
+    // Moves to the next row of pixels. Coarse Y wraps to the next vertical nametable after row 29, but
+    // when it was set to 30 or 31 it runs through the attribute table and wraps in the same nametable.
+    fn increment_y(&mut self) {
+        if self.fine_y() < 7 {
+            *self += 1 << FINE_Y_SHIFT;
+            return;
+        }
+
+        *self &= !FINE_Y_MASK;
+        let coarse_y = match self.coarse_y() {
+            COARSE_Y_LAST_ROW => {
+                *self ^= NAMETABLE_Y_MASK;
+                0
+            }
+            31 => 0,
+            y => y + 1,
+        };
+        *self = (*self & !COARSE_Y_MASK) | (coarse_y << COARSE_Y_SHIFT);
+    }
+
+    fn copy_horizontal(&mut self, t: u16) {
+        *self = (*self & !HORIZONTAL_MASK) | (t & HORIZONTAL_MASK);
+    }
+
+    fn copy_vertical(&mut self, t: u16) {
+        *self = (*self & !VERTICAL_MASK) | (t & VERTICAL_MASK);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{VramAddress, VramAddressTrait};
+
+    #[test]
+    fn test_increment_x() {
+        let mut v: VramAddress = 30;
+        v.increment_x();
+        assert_eq!(31, v);
+        v.increment_x();
+        assert_eq!(0x0400, v);
+        v = 0x041F;
+        v.increment_x();
+        assert_eq!(0, v);
+    }
+
+    #[test]
+    fn test_increment_y() {
+        // Fine Y first
+        let mut v: VramAddress = 0x6000 | (5 << 5);
+        v.increment_y();
+        assert_eq!(0x7000 | (5 << 5), v);
+        v.increment_y();
+        assert_eq!(6 << 5, v);
+
+        // Row 29 wraps to the next nametable
+        let mut v: VramAddress = 0x7000 | (29 << 5);
+        v.increment_y();
+        assert_eq!(0x0800, v);
+
+        // Row 31 wraps in the same one
+        let mut v: VramAddress = 0x7800 | (31 << 5);
+        v.increment_y();
+        assert_eq!(0x0800, v);
+    }
+
+    #[test]
+    fn test_fetch_addresses() {
+        // Nametable 3, row 29, column 31
+        let v: VramAddress = 0x0C00 | (29 << 5) | 31;
+        assert_eq!(0x2FBF, v.nametable_fetch_addr());
+        assert_eq!(0x2FFF, v.attribute_fetch_addr());
+    }
+}
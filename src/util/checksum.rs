@@ -0,0 +1,140 @@
+const CRC32_POLYNOMIAL: u32 = 0xEDB8_8320;
+const ADLER32_MODULO: u32 = 65521;
+
+// CRC-32 as used by zlib, PNG and most ROM databases
+pub fn crc32(data: &[u8]) -> u32 {
+    crc32_update(0, data)
+}
+
+// Continues a CRC-32 over more data, start with a crc of 0
+pub fn crc32_update(crc: u32, data: &[u8]) -> u32 {
+    let mut crc = !crc;
+
+    for byte in data {
+        crc ^= *byte as u32;
+        for _i in 0..8 {
+            crc = if crc & 1 > 0 { (crc >> 1) ^ CRC32_POLYNOMIAL } else { crc >> 1 };
+        }
+    }
+
+    !crc
+}
+
+pub fn adler32(data: &[u8]) -> u32 {
+    let mut a: u32 = 1;
+    let mut b: u32 = 0;
+
+    for byte in data {
+        a = (a + *byte as u32) % ADLER32_MODULO;
+        b = (b + a) % ADLER32_MODULO;
+    }
+
+    (b << 16) | a
+}
+
+// SHA-1 as used by ROM databases to tell apart dumps with the same CRC-32
+pub fn sha1(data: &[u8]) -> [u8; 20] {
+    sha1_parts(&[data])
+}
+
+// SHA-1 of the parts one after another, without copying them together
+pub fn sha1_parts(parts: &[&[u8]]) -> [u8; 20] {
+    let mut h: [u32; 5] = [0x6745_2301, 0xEFCD_AB89, 0x98BA_DCFE, 0x1032_5476, 0xC3D2_E1F0];
+    let mut block = [0u8; 64];
+    let mut filled = 0;
+    let mut length: u64 = 0;
+
+    for byte in parts.iter().flat_map(|part| part.iter()) {
+        block[filled] = *byte;
+        filled += 1;
+        length += 1;
+        if filled == block.len() {
+            sha1_block(&mut h, &block);
+            filled = 0;
+        }
+    }
+
+    // A 1 bit, zeros up to 8 bytes before a block boundary, then the length in bits
+    block[filled] = 0x80;
+    filled += 1;
+    if filled > 56 {
+        block[filled..].iter_mut().for_each(|byte| *byte = 0);
+        sha1_block(&mut h, &block);
+        filled = 0;
+    }
+    block[filled..56].iter_mut().for_each(|byte| *byte = 0);
+    block[56..].copy_from_slice(&(length * 8).to_be_bytes());
+    sha1_block(&mut h, &block);
+
+    let mut digest = [0; 20];
+    for (i, value) in h.iter().enumerate() {
+        digest[i * 4..i * 4 + 4].copy_from_slice(&value.to_be_bytes());
+    }
+    digest
+}
+
+fn sha1_block(h: &mut [u32; 5], block: &[u8; 64]) {
+    let mut w = [0u32; 80];
+    for i in 0..16 {
+        w[i] = u32::from_be_bytes([block[i * 4], block[i * 4 + 1], block[i * 4 + 2], block[i * 4 + 3]]);
+    }
+    for i in 16..80 {
+        w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+    }
+
+    let [mut a, mut b, mut c, mut d, mut e] = *h;
+    for (i, word) in w.iter().enumerate() {
+        let (f, k) = match i {
+            0..=19 => ((b & c) | (!b & d), 0x5A82_7999),
+            20..=39 => (b ^ c ^ d, 0x6ED9_EBA1),
+            40..=59 => ((b & c) | (b & d) | (c & d), 0x8F1B_BCDC),
+            _ => (b ^ c ^ d, 0xCA62_C1D6),
+        };
+
+        let temp = a.rotate_left(5).wrapping_add(f).wrapping_add(e).wrapping_add(k).wrapping_add(*word);
+        e = d;
+        d = c;
+        c = b.rotate_left(30);
+        b = a;
+        a = temp;
+    }
+
+    for (value, added) in h.iter_mut().zip([a, b, c, d, e].iter()) {
+        *value = value.wrapping_add(*added);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{adler32, crc32, crc32_update, sha1, sha1_parts};
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(0, crc32(&[]));
+        assert_eq!(0xCBF4_3926, crc32(b"123456789"));
+        assert_eq!(crc32(b"123456789"), crc32_update(crc32(b"1234"), b"56789"));
+    }
+
+    #[test]
+    fn test_adler32() {
+        assert_eq!(1, adler32(&[]));
+        assert_eq!(0x11E6_0398, adler32(b"Wikipedia"));
+    }
+
+    fn hex(digest: &[u8]) -> String {
+        digest.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    #[test]
+    fn test_sha1() {
+        assert_eq!("da39a3ee5e6b4b0d3255bfef95601890afd80709", hex(&sha1(&[])));
+        assert_eq!("a9993e364706816aba3e25717850c26c9cd0d89d", hex(&sha1(b"abc")));
+        // Two blocks after padding
+        assert_eq!("84983e441c3bd26ebaae4aa1f95129e5e54670f1",
+                   hex(&sha1(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq")));
+
+        let data: Vec<u8> = (0..1000).map(|i| i as u8).collect();
+        assert_eq!(sha1(&data), sha1_parts(&[&data[..100], &data[100..]]));
+        assert_eq!(sha1(&data[..64]), sha1_parts(&[&data[..60], &[], &data[60..64]]));
+    }
+}
@@ -1,4 +1,21 @@
-pub fn read_file(path: &String) -> Vec<u8> {
-    std::fs::read(path).expect(format!("Unable to open file: {}", path).as_str())
+pub fn read_file(path: &str) -> std::io::Result<Vec<u8>> {
+    std::fs::read(path)
 }
 
+
+// Writes next to the target and renames it over, so a crash leaves either the old or the new file
+pub fn write_file_atomic(path: &str, data: &[u8]) -> Result<(), String> {
+    let tmp_path = format!("{}.tmp", path);
+
+    let write = || -> std::io::Result<()> {
+        let mut file = std::fs::File::create(&tmp_path)?;
+        std::io::Write::write_all(&mut file, data)?;
+        file.sync_all()?;
+        std::fs::rename(&tmp_path, path)
+    };
+
+    write().map_err(|e| {
+        let _ = std::fs::remove_file(&tmp_path);
+        format!("Unable to write {}: {}", path, e)
+    })
+}
@@ -0,0 +1,120 @@
+use std::fs;
+
+use super::checksum;
+
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+const PNG_COLOR_TYPE_RGB: u8 = 2;
+const DEFLATE_MAX_STORED_BLOCK: usize = 0xFFFF;
+
+// Writes 24 bit RGB pixel data as a binary PPM (P6)
+pub fn write_ppm(path: &str, width: usize, height: usize, rgb: &[u8]) -> Result<(), String> {
+    let mut data = format!("P6\n{} {}\n255\n", width, height).into_bytes();
+    data.extend_from_slice(rgb);
+
+    fs::write(path, data).map_err(|e| format!("Unable to write {}: {}", path, e))
+}
+
+// Writes 24 bit RGB pixel data as a PNG. The image data is stored without compression,
+// which keeps the encoder small at the cost of file size.
+pub fn write_png(path: &str, width: usize, height: usize, rgb: &[u8]) -> Result<(), String> {
+    fs::write(path, encode_png(width, height, rgb)).map_err(|e| format!("Unable to write {}: {}", path, e))
+}
+
+pub fn encode_png(width: usize, height: usize, rgb: &[u8]) -> Vec<u8> {
+    let mut header = Vec::new();
+    header.extend_from_slice(&(width as u32).to_be_bytes());
+    header.extend_from_slice(&(height as u32).to_be_bytes());
+    // Bit depth, color type, compression, filter and interlace method
+    header.extend_from_slice(&[8, PNG_COLOR_TYPE_RGB, 0, 0, 0]);
+
+    // Every scanline starts with its filter type, 0 is none
+    let mut scanlines = Vec::with_capacity(height * (width * 3 + 1));
+    for row in rgb.chunks(width * 3).take(height) {
+        scanlines.push(0);
+        scanlines.extend_from_slice(row);
+    }
+
+    let mut png = PNG_SIGNATURE.to_vec();
+    _write_png_chunk(&mut png, b"IHDR", &header);
+    _write_png_chunk(&mut png, b"IDAT", &_zlib_stored(&scanlines));
+    _write_png_chunk(&mut png, b"IEND", &[]);
+
+    png
+}
+
+fn _write_png_chunk(png: &mut Vec<u8>, chunk_type: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+
+    let crc_start = png.len();
+    png.extend_from_slice(chunk_type);
+    png.extend_from_slice(data);
+    let crc = checksum::crc32(&png[crc_start..]);
+
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+// A zlib stream made of uncompressed deflate blocks
+fn _zlib_stored(data: &[u8]) -> Vec<u8> {
+    // Deflate with a 32K window, no preset dictionary
+    let mut zlib = vec![0x78, 0x01];
+
+    let blocks: Vec<&[u8]> = data.chunks(DEFLATE_MAX_STORED_BLOCK).collect();
+    for (i, block) in blocks.iter().enumerate() {
+        let last_block = i == blocks.len() - 1;
+        let len = block.len() as u16;
+
+        zlib.push(if last_block { 1 } else { 0 });
+        zlib.extend_from_slice(&len.to_le_bytes());
+        zlib.extend_from_slice(&(!len).to_le_bytes());
+        zlib.extend_from_slice(block);
+    }
+    if blocks.is_empty() {
+        zlib.extend_from_slice(&[1, 0, 0, 0xFF, 0xFF]);
+    }
+
+    zlib.extend_from_slice(&checksum::adler32(data).to_be_bytes());
+    zlib
+}
+
+#[cfg(test)]
+mod tests {
+    use super::encode_png;
+    use crate::util::checksum;
+
+    #[test]
+    fn test_encode_png() {
+        let width = 200;
+        let height = 200;
+        let rgb: Vec<u8> = (0..width * height * 3).map(|i| i as u8).collect();
+
+        let png = encode_png(width, height, &rgb);
+
+        assert_eq!(&[0x89, b'P', b'N', b'G'], &png[0..4]);
+        assert_eq!(b"IHDR", &png[12..16]);
+        assert_eq!(&(width as u32).to_be_bytes(), &png[16..20]);
+        assert_eq!(b"IEND", &png[png.len() - 8..png.len() - 4]);
+
+        // The IDAT payload is the zlib stream of the filtered scanlines
+        let idat_len = u32::from_be_bytes([png[33], png[34], png[35], png[36]]) as usize;
+        assert_eq!(b"IDAT", &png[37..41]);
+        let zlib = &png[41..41 + idat_len];
+
+        let mut inflated = Vec::new();
+        let mut offset = 2;
+        loop {
+            let last_block = zlib[offset] == 1;
+            let len = u16::from_le_bytes([zlib[offset + 1], zlib[offset + 2]]) as usize;
+            assert_eq!(!(len as u16), u16::from_le_bytes([zlib[offset + 3], zlib[offset + 4]]));
+            inflated.extend_from_slice(&zlib[offset + 5..offset + 5 + len]);
+            offset += 5 + len;
+
+            if last_block {
+                break;
+            }
+        }
+
+        assert_eq!(height * (width * 3 + 1), inflated.len());
+        assert_eq!(&rgb[0..width * 3], &inflated[1..width * 3 + 1]);
+        assert_eq!(&checksum::adler32(&inflated).to_be_bytes(), &zlib[offset..offset + 4]);
+    }
+}
@@ -1 +1,3 @@
-pub mod file;
\ No newline at end of file
+pub mod file;
+pub mod checksum;
+pub mod image;